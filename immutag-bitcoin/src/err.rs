@@ -0,0 +1,56 @@
+pub use sv::util::Error as SvError;
+
+#[derive(Debug)]
+pub enum BitcoinError {
+    SvError(SvError),
+    Error(Error),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    InvalidKey,
+    InvalidPayload,
+    PayloadTooLarge,
+    UnsupportedLedger,
+    InvalidUtxo,
+    InsufficientFunds,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub details: String,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    pub fn new(msg: &str, kind: ErrorKind) -> Error {
+        Error {
+            details: msg.to_string(),
+            kind,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl From<SvError> for BitcoinError {
+    fn from(error: SvError) -> Self {
+        BitcoinError::SvError(error)
+    }
+}
+
+impl From<Error> for BitcoinError {
+    fn from(error: Error) -> Self {
+        BitcoinError::Error(error)
+    }
+}
@@ -0,0 +1,99 @@
+/*
+Signs and verifies messages with a collaborator's account key,
+`m / 44' / 0' / k'`, the way Bitcoin signs messages: the double SHA-256 of
+the message behind the `Bitcoin Signed Message:` magic. Signatures are DER
+and public keys compressed, both in hex.
+*/
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey, Signature};
+use sv::util::{sha256d, Hash256};
+
+use crate::err::{BitcoinError, SvError};
+
+const MAGIC: &[u8] = b"Bitcoin Signed Message:\n";
+
+/// `m / 44' / 0' / account'`
+pub fn account_path(account: u32) -> String {
+    format!("m/44'/0'/{}'", account)
+}
+
+/// The compressed public key of the account, in hex.
+pub fn account_public_key<T: AsRef<str>>(xpriv: T, account: u32) -> Result<String, BitcoinError> {
+    let key = crate::derive(xpriv.as_ref(), account_path(account).as_str())?;
+
+    Ok(hex::encode(&key.public_key()?[..]))
+}
+
+/// Signs `message` with the account key, and returns the signature and the
+/// public key to check it with.
+pub fn sign_message<T: AsRef<str>>(xpriv: T, account: u32, message: &[u8]) -> Result<(String, String), BitcoinError> {
+    let key = crate::derive(xpriv.as_ref(), account_path(account).as_str())?;
+    let secret_key = SecretKey::from_slice(&key.private_key()?).map_err(SvError::from)?;
+    let digest = Message::from_slice(&digest(message).0).map_err(SvError::from)?;
+
+    let mut signature = Secp256k1::signing_only().sign(&digest, &secret_key);
+    signature.normalize_s();
+
+    Ok((hex::encode(signature.serialize_der()), hex::encode(&key.public_key()?[..])))
+}
+
+/// Whether `signature` is the public key's over `message`. Anything that
+/// doesn't decode doesn't verify.
+pub fn verify_message<T: AsRef<str>>(public_key: T, message: &[u8], signature: T) -> bool {
+    let public_key = hex::decode(public_key.as_ref()).ok().and_then(|k| PublicKey::from_slice(&k).ok());
+    let signature = hex::decode(signature.as_ref()).ok().and_then(|s| Signature::from_der(&s).ok());
+    let digest = Message::from_slice(&digest(message).0).ok();
+
+    match (public_key, signature, digest) {
+        (Some(public_key), Some(signature), Some(digest)) => {
+            Secp256k1::verification_only().verify(&digest, &signature, &public_key).is_ok()
+        }
+        _ => false,
+    }
+}
+
+fn digest(message: &[u8]) -> Hash256 {
+    let mut data = Vec::new();
+    data.push(MAGIC.len() as u8);
+    data.extend_from_slice(MAGIC);
+    var_int(message.len() as u64, &mut data);
+    data.extend_from_slice(message);
+
+    sha256d(&data)
+}
+
+/// Bitcoin's variable length integer.
+fn var_int(n: u64, data: &mut Vec<u8>) {
+    if n < 0xfd {
+        data.push(n as u8);
+    } else if n <= 0xffff {
+        data.push(0xfd);
+        data.extend_from_slice(&(n as u16).to_le_bytes());
+    } else if n <= 0xffff_ffff {
+        data.push(0xfe);
+        data.extend_from_slice(&(n as u32).to_le_bytes());
+    } else {
+        data.push(0xff);
+        data.extend_from_slice(&n.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPRIV: &str = "xprv9s21ZrQH143K29TJGFSiEAAQM8SMBH2V6x5Aaf9bqvXftrs1v274STWWKfz8svukBLGEQgWqkgRhpt2CNFY89CFaqdsA3gicZeqexk2itxf";
+
+    #[test]
+    fn sign_and_verify() {
+        let message = b"alias: lemonade stand";
+        let (signature, public_key) = sign_message(XPRIV, 1, message).unwrap();
+        let other = account_public_key(XPRIV, 2).unwrap();
+
+        assert_eq!(public_key, account_public_key(XPRIV, 1).unwrap());
+        assert_eq!(public_key.len(), 66);
+        assert!(verify_message(public_key.as_str(), message, signature.as_str()));
+        assert!(!verify_message(public_key.as_str(), b"alias: lemonade", signature.as_str()));
+        assert!(!verify_message(other.as_str(), message, signature.as_str()));
+        assert!(!verify_message(public_key.as_str(), message, "00"));
+    }
+}
@@ -1,17 +1,29 @@
 extern crate sv;
+extern crate secp256k1;
 extern crate ring;
 extern crate hex;
 
+pub mod err;
+pub mod message;
+pub mod opreturn;
+pub mod tx;
+
+use sv::address::{addr_encode, AddressType};
 use sv::network::Network;
+use sv::util::hash160;
 use sv::wallet;
 use sv::wallet::Wordlist;
-use sv::wallet::ExtendedKey;
+use sv::wallet::{ExtendedKey, ExtendedKeyType};
 use ring::digest::SHA512;
 use ring::hmac;
 
+pub use err::{BitcoinError, ErrorKind};
 pub use sv::wallet as sv_wallet;
 pub use sv::network as sv_network;
 
+/// Purpose of the 'synthetic' file version branch, `m / 144,000'`.
+pub const FILE_VERSION_PURPOSE: u32 = 144_000;
+
 /// Maximum private key value (exclusive)
 const SECP256K1_CURVE_ORDER: [u8; 32] = [
     0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
@@ -65,6 +77,79 @@ pub fn mnemonic_to_xpriv(mnemonic: Vec<String>, wordlist: Wordlist) -> ExtendedK
     wallet::derive_extended_key(&m, "m").unwrap()
 }
 
+/// The master xpriv `mnemonic_to_xpriv` gives an English mnemonic, but an
+/// unknown word or a bad checksum is an error.
+pub fn mnemonic_xpriv<T: AsRef<str>>(mnemonic: T) -> Result<String, BitcoinError> {
+    let words: Vec<String> = mnemonic.as_ref().split_whitespace().map(|w| w.to_string()).collect();
+    if words.is_empty() {
+        let err = err::Error::new("the mnemonic is empty", ErrorKind::InvalidKey);
+        return Err(BitcoinError::from(err));
+    }
+    let data = wallet::mnemonic_decode(&words, &wallet::load_wordlist(Wordlist::English))?;
+    let master = master_private_key(&data);
+
+    Ok(wallet::derive_extended_key(&master, "m")?.encode())
+}
+
+/// Legacy (P2PKH) mainnet address of a key.
+pub fn key_addr(key: &ExtendedKey) -> Result<String, BitcoinError> {
+    let public_key = key.public_key()?;
+
+    Ok(addr_encode(&hash160(&public_key), AddressType::P2PKH, Network::Mainnet))
+}
+
+/// Derives `path` from a filesystem's master xpriv.
+pub fn derive<T: AsRef<str>>(xpriv: T, path: T) -> Result<ExtendedKey, BitcoinError> {
+    let master = ExtendedKey::decode(xpriv.as_ref())?;
+    if master.key_type()? != ExtendedKeyType::Private {
+        let err = err::Error::new("filesystem key must be an xpriv", ErrorKind::InvalidKey);
+        return Err(BitcoinError::from(err));
+    }
+
+    Ok(wallet::derive_extended_key(&master, path.as_ref())?)
+}
+
+/// The filesystem 'root', the first BIP-44 external address.
+pub fn filesys_addr<T: AsRef<str>>(xpriv: T) -> Result<String, BitcoinError> {
+    file_addr(xpriv, 0)
+}
+
+/// `m / 44' / 0' / 0' / 0 / index`
+pub fn file_addr<T: AsRef<str>>(xpriv: T, index: u32) -> Result<String, BitcoinError> {
+    let path = format!("m/44'/0'/0'/0/{}", index);
+    let key = derive(xpriv.as_ref(), path.as_ref())?;
+
+    key_addr(&key)
+}
+
+/// `m / 144,000' / file_index' / 0' / 0 / version`
+pub fn version_addr<T: AsRef<str>>(xpriv: T, file_index: u32, version: u32) -> Result<String, BitcoinError> {
+    branch_version_addr(xpriv, file_index, 0, version)
+}
+
+/// `m / 44' / 0' / 0' / 0 / file_index / branch'`, a hardened child of the
+/// file address. Branches count from 1.
+pub fn branch_addr<T: AsRef<str>>(xpriv: T, file_index: u32, branch: u32) -> Result<String, BitcoinError> {
+    let path = format!("m/44'/0'/0'/0/{}/{}'", file_index, branch);
+    let key = derive(xpriv.as_ref(), path.as_ref())?;
+
+    key_addr(&key)
+}
+
+/// `m / 144,000' / file_index' / branch' / 0 / version`. The file's own
+/// versions are branch 0.
+pub fn branch_version_addr<T: AsRef<str>>(
+    xpriv: T,
+    file_index: u32,
+    branch: u32,
+    version: u32,
+) -> Result<String, BitcoinError> {
+    let path = format!("m/{}'/{}'/{}'/0/{}", FILE_VERSION_PURPOSE, file_index, branch, version);
+    let key = derive(xpriv.as_ref(), path.as_ref())?;
+
+    key_addr(&key)
+}
+
 #[cfg(test)]
 mod  bitcoin {
     use super::{
@@ -76,9 +161,17 @@ mod  bitcoin {
         ring::hmac,
         is_private_key_valid,
         master_private_key,
-        mnemonic_to_xpriv
+        mnemonic_to_xpriv,
+        mnemonic_xpriv,
+        filesys_addr,
+        file_addr,
+        version_addr,
+        branch_addr,
+        branch_version_addr,
     };
 
+    const XPRIV: &str = "xprv9s21ZrQH143K29TJGFSiEAAQM8SMBH2V6x5Aaf9bqvXftrs1v274STWWKfz8svukBLGEQgWqkgRhpt2CNFY89CFaqdsA3gicZeqexk2itxf";
+
     #[test]
     fn test_mnemonic_to_xpriv() {
         //let m = ["legal".to_string(), "winner".to_string(), "thank".to_string(), "year".to_string(), "wave".to_string(), "sausage".to_string(), "worth".to_string(), "useful".to_string(), "legal".to_string(), "winner".to_string(), "thank".to_string(), "year".to_string(), "wave".to_string(), "sausage".to_string(), "worth".to_string(), "useful".to_string(), "legal".to_string(), "will".to_string()];
@@ -104,4 +197,30 @@ mod  bitcoin {
 
         assert_eq!(xpub_string, expected_xpub);
     }
+
+    #[test]
+    fn test_mnemonic_xpriv() {
+        let xpriv = mnemonic_xpriv("certain dust pave crane renew multiply stone stuff proud flee fancy knee").unwrap();
+
+        assert_eq!(xpriv, XPRIV);
+        assert!(mnemonic_xpriv("certain dust pave crane renew multiply stone stuff proud flee fancy fancy").is_err());
+        assert!(mnemonic_xpriv("certain dust pave crane renew multiply stone stuff proud flee fancy immutag").is_err());
+        assert!(mnemonic_xpriv(" ").is_err());
+    }
+
+    #[test]
+    fn test_file_addrs() {
+        assert_eq!(filesys_addr(XPRIV).unwrap(), "1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj");
+        assert_eq!(file_addr(XPRIV, 1).unwrap(), "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9");
+        assert_eq!(version_addr(XPRIV, 1, 0).unwrap(), "1QKr8NEjbhe9uW3u1wGVWTDavr5n3HZTx9");
+        assert!(file_addr("XPRIV", 1).is_err());
+    }
+
+    #[test]
+    fn test_branch_addrs() {
+        assert_eq!(branch_addr(XPRIV, 1, 1).unwrap(), "176MrXcWHpGxMuXg8v31bChp2huL8XuzjP");
+        assert_ne!(branch_addr(XPRIV, 1, 2).unwrap(), "176MrXcWHpGxMuXg8v31bChp2huL8XuzjP");
+        assert_eq!(branch_version_addr(XPRIV, 1, 0, 0).unwrap(), "1QKr8NEjbhe9uW3u1wGVWTDavr5n3HZTx9");
+        assert_eq!(branch_version_addr(XPRIV, 1, 1, 0).unwrap(), "18N3aRLGFxfhE7tFVz5tbyQeopgmgZXJ7o");
+    }
 }
@@ -0,0 +1,269 @@
+/*
+The records immutag puts in OP_RETURN outputs: pegs of version-store
+manifests, and forks of file versions onto branches.
+
+    magic "IMT" | format | type (| 0x80 if there's a parent) |
+    content addr length | content addr | target | parent
+
+The content address is a binary CID. A peg's target is its version, as a
+Bitcoin var int (CompactSize). A fork's target is the branch address.
+Addresses are the 20 byte hash of a mainnet P2PKH address.
+*/
+use sv::address::{addr_decode, addr_encode, AddressType};
+use sv::network::Network;
+use sv::util::Hash160;
+
+use crate::err::{BitcoinError, Error, ErrorKind};
+
+pub const MAGIC: &[u8] = b"IMT";
+pub const FORMAT_VERSION: u8 = 1;
+/// Largest OP_RETURN data nodes relay by default.
+pub const MAX_PAYLOAD_SIZE: usize = 80;
+
+const PEG: u8 = 1;
+const FORK: u8 = 2;
+const HAS_PARENT: u8 = 0x80;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Record {
+    /// A version-store manifest.
+    Peg { version: u64 },
+    /// A file version forked onto a branch.
+    Fork { branch_addr: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Payload {
+    pub record: Record,
+    pub content_addr: Vec<u8>,
+    pub parent: Option<String>,
+}
+
+impl Payload {
+    pub fn encode(&self) -> Result<Vec<u8>, BitcoinError> {
+        let mut payload = MAGIC.to_vec();
+        payload.push(FORMAT_VERSION);
+
+        let record_type = match self.record {
+            Record::Peg { .. } => PEG,
+            Record::Fork { .. } => FORK,
+        };
+        payload.push(if self.parent.is_some() { record_type | HAS_PARENT } else { record_type });
+
+        if self.content_addr.len() > u8::MAX as usize {
+            return Err(invalid("content address is too long"));
+        }
+        payload.push(self.content_addr.len() as u8);
+        payload.extend_from_slice(&self.content_addr);
+
+        match &self.record {
+            Record::Peg { version } => write_var_int(*version, &mut payload),
+            Record::Fork { branch_addr } => payload.extend_from_slice(&addr_hash(branch_addr)?.0),
+        }
+        if let Some(parent) = &self.parent {
+            payload.extend_from_slice(&addr_hash(parent)?.0);
+        }
+
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            let msg = format!(
+                "payload is {} bytes, more than the {} nodes relay",
+                payload.len(),
+                MAX_PAYLOAD_SIZE
+            );
+            return Err(BitcoinError::from(Error::new(&msg, ErrorKind::PayloadTooLarge)));
+        }
+
+        Ok(payload)
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<Payload, BitcoinError> {
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            let msg = format!("payload is {} bytes", payload.len());
+            return Err(BitcoinError::from(Error::new(&msg, ErrorKind::PayloadTooLarge)));
+        }
+        if !payload.starts_with(MAGIC) {
+            return Err(invalid("not an immutag payload"));
+        }
+
+        let mut pos = MAGIC.len();
+        let format = take(payload, &mut pos, 1)?[0];
+        if format != FORMAT_VERSION {
+            return Err(invalid(&format!("unsupported payload format {}", format)));
+        }
+        let record_type = take(payload, &mut pos, 1)?[0];
+        let len = take(payload, &mut pos, 1)?[0] as usize;
+        let content_addr = take(payload, &mut pos, len)?.to_vec();
+
+        let record = match record_type & !HAS_PARENT {
+            PEG => Record::Peg {
+                version: read_var_int(payload, &mut pos)?,
+            },
+            FORK => Record::Fork {
+                branch_addr: hash_addr(take(payload, &mut pos, 20)?),
+            },
+            other => return Err(invalid(&format!("unknown record type {}", other))),
+        };
+        let parent = if record_type & HAS_PARENT != 0 {
+            Some(hash_addr(take(payload, &mut pos, 20)?))
+        } else {
+            None
+        };
+
+        if pos != payload.len() {
+            return Err(invalid("trailing bytes in payload"));
+        }
+
+        Ok(Payload {
+            record,
+            content_addr,
+            parent,
+        })
+    }
+}
+
+fn take<'a>(payload: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], BitcoinError> {
+    if payload.len() < *pos + len {
+        return Err(invalid("truncated payload"));
+    }
+    let bytes = &payload[*pos..*pos + len];
+    *pos += len;
+
+    Ok(bytes)
+}
+
+/// Bitcoin's CompactSize.
+fn write_var_int(n: u64, bytes: &mut Vec<u8>) {
+    match n {
+        0..=0xfc => bytes.push(n as u8),
+        0xfd..=0xffff => {
+            bytes.push(0xfd);
+            bytes.extend_from_slice(&(n as u16).to_le_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            bytes.push(0xfe);
+            bytes.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        _ => {
+            bytes.push(0xff);
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+    }
+}
+
+fn read_var_int(payload: &[u8], pos: &mut usize) -> Result<u64, BitcoinError> {
+    let len = match take(payload, pos, 1)?[0] {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        n => return Ok(u64::from(n)),
+    };
+    let mut n = [0; 8];
+    n[..len].copy_from_slice(take(payload, pos, len)?);
+
+    Ok(u64::from_le_bytes(n))
+}
+
+fn addr_hash(addr: &str) -> Result<Hash160, BitcoinError> {
+    match addr_decode(addr, Network::Mainnet)? {
+        (hash, AddressType::P2PKH) => Ok(hash),
+        _ => Err(invalid("only P2PKH addresses can be in a payload")),
+    }
+}
+
+fn hash_addr(hash: &[u8]) -> String {
+    let mut hash160 = Hash160::default();
+    hash160.0.copy_from_slice(hash);
+
+    addr_encode(&hash160, AddressType::P2PKH, Network::Mainnet)
+}
+
+fn invalid(msg: &str) -> BitcoinError {
+    BitcoinError::from(Error::new(msg, ErrorKind::InvalidPayload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL
+    const CID_V0: &str = "122031c3d57080d8463a3c63b2923df5a1d40ad7a73eae5a14af584213e5f504ac33";
+
+    fn cid() -> Vec<u8> {
+        hex::decode(CID_V0).unwrap()
+    }
+
+    #[test]
+    fn fork_round_trip() {
+        let payload = Payload {
+            record: Record::Fork {
+                branch_addr: "176MrXcWHpGxMuXg8v31bChp2huL8XuzjP".to_string(),
+            },
+            content_addr: cid(),
+            parent: Some("1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9".to_string()),
+        };
+        let encoded = payload.encode().unwrap();
+
+        assert_eq!(&encoded[..5], b"IMT\x01\x82");
+        assert_eq!(encoded.len(), MAX_PAYLOAD_SIZE);
+        assert_eq!(Payload::decode(&encoded).unwrap(), payload);
+    }
+
+    #[test]
+    fn peg_round_trip() {
+        let payload = Payload {
+            record: Record::Peg { version: 300 },
+            content_addr: cid(),
+            parent: None,
+        };
+        let encoded = payload.encode().unwrap();
+
+        // 0xfd starts a 2 byte var int.
+        assert_eq!(&encoded[encoded.len() - 3..], &[0xfd, 0x2c, 0x01]);
+        assert_eq!(Payload::decode(&encoded).unwrap(), payload);
+    }
+
+    #[test]
+    fn too_large() {
+        let payload = Payload {
+            record: Record::Fork {
+                branch_addr: "176MrXcWHpGxMuXg8v31bChp2huL8XuzjP".to_string(),
+            },
+            // A CIDv1 is a couple of bytes longer.
+            content_addr: [&[0x01, 0x55][..], &cid()].concat(),
+            parent: Some("1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9".to_string()),
+        };
+
+        match payload.encode() {
+            Err(BitcoinError::Error(err)) => assert_eq!(err.kind, ErrorKind::PayloadTooLarge),
+            _ => panic!("expected the payload to be too large"),
+        }
+        assert!(Payload::decode(&[0; MAX_PAYLOAD_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn invalid_payloads() {
+        let payload = Payload {
+            record: Record::Peg { version: 1 },
+            content_addr: cid(),
+            parent: None,
+        };
+        let encoded = payload.encode().unwrap();
+
+        assert!(Payload::decode(b"OTHER").is_err());
+        assert!(Payload::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(Payload::decode(&[&encoded[..], &[0]].concat()).is_err());
+        assert!(Payload::decode(&[b"IMT\x02".to_vec(), encoded[4..].to_vec()].concat()).is_err());
+    }
+
+    #[test]
+    fn var_ints() {
+        for n in &[0, 0xfc, 0xfd, 0xffff, 0x1_0000, 0xffff_ffff, 0x1_0000_0000, u64::MAX] {
+            let mut bytes = Vec::new();
+            write_var_int(*n, &mut bytes);
+            let mut pos = 0;
+
+            assert_eq!(read_var_int(&bytes, &mut pos).unwrap(), *n);
+            assert_eq!(pos, bytes.len());
+        }
+    }
+}
@@ -0,0 +1,326 @@
+/*
+Builds and signs peg transactions offline. The UTXOs are supplied by the
+user and spent from the filesystem's BIP-44 keys; the transaction carries the
+OP_RETURN payload and sends whatever is left, less the fee, back to a change
+address on the internal chain. Nothing here talks to the network: the raw hex
+can be broadcast any way the user likes.
+*/
+use std::str::FromStr;
+
+use sv::messages::{OutPoint, Tx, TxIn, TxOut};
+use sv::script::op_codes::{OP_FALSE, OP_RETURN};
+use sv::script::Script;
+use sv::transaction::generate_signature;
+use sv::transaction::p2pkh::{create_pk_script, create_sig_script};
+use sv::transaction::sighash::{sighash, SigHashCache, SIGHASH_ALL, SIGHASH_FORKID};
+use sv::util::{hash160, Amount, Hash256, Serializable};
+
+use crate::err::{BitcoinError, Error, ErrorKind};
+use crate::opreturn::MAX_PAYLOAD_SIZE;
+
+/// Outputs worth less than this aren't relayed, so it goes to the fee.
+pub const DUST_LIMIT: u64 = 546;
+
+/// A signed P2PKH input: outpoint, a script of a ~72 byte signature and a
+/// compressed public key, and sequence.
+const P2PKH_INPUT_SIZE: usize = 36 + 1 + 107 + 4;
+const P2PKH_OUTPUT_SIZE: usize = 8 + 1 + 25;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ledger {
+    /// Signs with SIGHASH_FORKID (BIP-143 digests).
+    BitcoinSv,
+    /// Signs with the legacy digest.
+    Bitcoin,
+}
+
+impl Ledger {
+    pub fn sighash_type(self) -> u8 {
+        match self {
+            Ledger::BitcoinSv => SIGHASH_ALL | SIGHASH_FORKID,
+            Ledger::Bitcoin => SIGHASH_ALL,
+        }
+    }
+
+    /// The unspendable output carrying `payload`. BTC only relays a bare
+    /// `OP_RETURN`, BSV the `OP_FALSE OP_RETURN` form.
+    pub fn data_script(self, payload: &[u8]) -> Script {
+        let mut data = Script::new();
+        if self == Ledger::BitcoinSv {
+            data.append(OP_FALSE);
+        }
+        data.append(OP_RETURN);
+        data.append_data(payload);
+
+        data
+    }
+}
+
+impl FromStr for Ledger {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Ledger, BitcoinError> {
+        match s {
+            "bitcoinsv" => Ok(Ledger::BitcoinSv),
+            "bitcoin" => Ok(Ledger::Bitcoin),
+            _ => {
+                let msg = format!("unsupported ledger '{}'", s);
+                Err(BitcoinError::from(Error::new(&msg, ErrorKind::UnsupportedLedger)))
+            }
+        }
+    }
+}
+
+/// An output to spend, paid to `m / 44' / 0' / 0' / chain / index`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Utxo {
+    /// Transaction id, hex in the usual (reversed) order.
+    pub txid: String,
+    pub vout: u32,
+    pub satoshis: u64,
+    pub chain: u32,
+    pub index: u32,
+}
+
+/// `m / 44' / 0' / 0' / 1 / index`
+pub fn change_addr<T: AsRef<str>>(xpriv: T, index: u32) -> Result<String, BitcoinError> {
+    let path = format!("m/44'/0'/0'/1/{}", index);
+    let key = crate::derive(xpriv.as_ref(), path.as_ref())?;
+
+    crate::key_addr(&key)
+}
+
+/// Signs a transaction spending `utxos` into an OP_RETURN output of `payload`
+/// and change to `m / 44' / 0' / 0' / 1 / change_index`. The fee is
+/// `fee_per_kb` satoshis per 1000 bytes. Returns the raw transaction as hex.
+pub fn peg_tx<T: AsRef<str>>(
+    xpriv: T,
+    ledger: Ledger,
+    utxos: &[Utxo],
+    payload: &[u8],
+    change_index: u32,
+    fee_per_kb: u64,
+) -> Result<String, BitcoinError> {
+    let tx = build(xpriv.as_ref(), ledger, utxos, payload, change_index, fee_per_kb)?;
+    let mut raw = Vec::new();
+    tx.write(&mut raw).map_err(sv::util::Error::from)?;
+
+    Ok(hex::encode(raw))
+}
+
+fn build(
+    xpriv: &str,
+    ledger: Ledger,
+    utxos: &[Utxo],
+    payload: &[u8],
+    change_index: u32,
+    fee_per_kb: u64,
+) -> Result<Tx, BitcoinError> {
+    if utxos.is_empty() {
+        return Err(invalid_utxo("no UTXOs to spend"));
+    }
+    if payload.len() > MAX_PAYLOAD_SIZE {
+        let msg = format!("payload is {} bytes", payload.len());
+        return Err(BitcoinError::from(Error::new(&msg, ErrorKind::PayloadTooLarge)));
+    }
+
+    let mut keys = Vec::new();
+    let mut inputs = Vec::new();
+    for utxo in utxos {
+        let path = format!("m/44'/0'/0'/{}/{}", utxo.chain, utxo.index);
+        keys.push(crate::derive(xpriv, path.as_str())?);
+        let hash = Hash256::decode(&utxo.txid)
+            .map_err(|_| invalid_utxo(&format!("invalid txid '{}'", utxo.txid)))?;
+        inputs.push(TxIn {
+            prev_output: OutPoint { hash, index: utxo.vout },
+            sig_script: Script::new(),
+            sequence: 0xffff_ffff,
+        });
+    }
+
+    let data = ledger.data_script(payload);
+
+    let total: u64 = utxos.iter().map(|u| u.satoshis).sum();
+    let size = 4 + 1 + inputs.len() * P2PKH_INPUT_SIZE + 1
+        + (8 + 1 + data.0.len())
+        + P2PKH_OUTPUT_SIZE
+        + 4;
+    let fee = size as u64 * fee_per_kb / 1000;
+    if total < fee {
+        let msg = format!("{} satoshis doesn't cover the {} satoshi fee", total, fee);
+        return Err(BitcoinError::from(Error::new(&msg, ErrorKind::InsufficientFunds)));
+    }
+
+    let mut outputs = vec![TxOut {
+        amount: Amount(0),
+        pk_script: data,
+    }];
+    let change = total - fee;
+    if change >= DUST_LIMIT {
+        let change_key = crate::derive(xpriv, format!("m/44'/0'/0'/1/{}", change_index).as_str())?;
+        outputs.push(TxOut {
+            amount: Amount(change as i64),
+            pk_script: create_pk_script(&hash160(&change_key.public_key()?)),
+        });
+    }
+
+    let mut tx = Tx {
+        version: 1,
+        inputs,
+        outputs,
+        lock_time: 0,
+    };
+
+    let sighash_type = ledger.sighash_type();
+    let mut cache = SigHashCache::new();
+    for (n, (key, utxo)) in keys.iter().zip(utxos).enumerate() {
+        let public_key = key.public_key()?;
+        let pk_script = create_pk_script(&hash160(&public_key));
+        let amount = Amount(utxo.satoshis as i64);
+        let digest = sighash(&tx, n, &pk_script.0, amount, sighash_type, &mut cache)?;
+        let signature = generate_signature(&key.private_key()?, &digest, sighash_type)?;
+        tx.inputs[n].sig_script = create_sig_script(&signature, &public_key);
+    }
+
+    Ok(tx)
+}
+
+fn invalid_utxo(msg: &str) -> BitcoinError {
+    BitcoinError::from(Error::new(msg, ErrorKind::InvalidUtxo))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sv::script::op_codes::OP_CODESEPARATOR;
+    use sv::address::addr_decode;
+    use sv::network::Network;
+    use sv::script::TransactionChecker;
+
+    const XPRIV: &str = "xprv9s21ZrQH143K29TJGFSiEAAQM8SMBH2V6x5Aaf9bqvXftrs1v274STWWKfz8svukBLGEQgWqkgRhpt2CNFY89CFaqdsA3gicZeqexk2itxf";
+
+    fn utxos() -> Vec<Utxo> {
+        vec![
+            Utxo {
+                txid: "8d8e8f3c7c8b2d5c2f1c0b5e5d0d3b2a1f0e9d8c7b6a59483726150403020100".to_string(),
+                vout: 1,
+                satoshis: 10_000,
+                chain: 0,
+                index: 0,
+            },
+            Utxo {
+                txid: "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".to_string(),
+                vout: 0,
+                satoshis: 2_000,
+                chain: 1,
+                index: 3,
+            },
+        ]
+    }
+
+    /// Runs each input's script against the output it spends.
+    fn verify(tx: &Tx, utxos: &[Utxo], require_sighash_forkid: bool) -> Result<(), BitcoinError> {
+        let mut cache = SigHashCache::new();
+        for (n, utxo) in utxos.iter().enumerate() {
+            let path = format!("m/44'/0'/0'/{}/{}", utxo.chain, utxo.index);
+            let key = crate::derive(XPRIV, path.as_str())?;
+            let mut script = Script::new();
+            script.append_slice(&tx.inputs[n].sig_script.0);
+            script.append(OP_CODESEPARATOR);
+            script.append_slice(&create_pk_script(&hash160(&key.public_key()?)).0);
+
+            let mut checker = TransactionChecker {
+                tx,
+                sig_hash_cache: &mut cache,
+                input: n,
+                amount: Amount(utxo.satoshis as i64),
+                require_sighash_forkid,
+            };
+            script.eval(&mut checker)?;
+        }
+
+        Ok(())
+    }
+
+    fn decode(raw: &str) -> Tx {
+        Tx::read(&mut &hex::decode(raw).unwrap()[..]).unwrap()
+    }
+
+    #[test]
+    fn bitcoinsv_peg() {
+        let raw = peg_tx(XPRIV, Ledger::BitcoinSv, &utxos(), b"IMT\x01\x01", 0, 500).unwrap();
+        let tx = decode(&raw);
+
+        assert_eq!(raw, BITCOINSV_VECTOR);
+        assert!(verify(&tx, &utxos(), true).is_ok());
+        assert_eq!(tx.outputs[0].pk_script.0, b"\x00\x6a\x05IMT\x01\x01".to_vec());
+        assert_eq!(tx.outputs[1].amount.0, 12_000 - 178);
+        assert_eq!(change_addr(XPRIV, 0).unwrap(), "18MR5c5expn7jC7r8L6xnfnamZBoTHARgx");
+        assert_eq!(
+            tx.outputs[1].pk_script,
+            create_pk_script(&addr_decode("18MR5c5expn7jC7r8L6xnfnamZBoTHARgx", Network::Mainnet).unwrap().0)
+        );
+    }
+
+    #[test]
+    fn bitcoin_peg() {
+        let raw = peg_tx(XPRIV, Ledger::Bitcoin, &utxos(), b"IMT\x01\x01", 0, 500).unwrap();
+        let tx = decode(&raw);
+
+        assert_eq!(raw, BITCOIN_VECTOR);
+        assert!(verify(&tx, &utxos(), false).is_ok());
+        // A legacy signature doesn't commit to the amount, a forkid one does.
+        assert!(verify(&tx, &utxos(), true).is_err());
+        let forkid = decode(BITCOINSV_VECTOR);
+        let mut wrong_amount = utxos();
+        wrong_amount[0].satoshis += 1;
+        assert!(verify(&forkid, &wrong_amount, true).is_err());
+    }
+
+    #[test]
+    fn data_prefix() {
+        let bitcoinsv = decode(&peg_tx(XPRIV, Ledger::BitcoinSv, &utxos(), b"IMT", 0, 500).unwrap());
+        let bitcoin = decode(&peg_tx(XPRIV, Ledger::Bitcoin, &utxos(), b"IMT", 0, 500).unwrap());
+
+        assert_eq!(bitcoinsv.outputs[0].pk_script.0[..2], [OP_FALSE, OP_RETURN]);
+        assert_eq!(bitcoin.outputs[0].pk_script.0, b"\x6a\x03IMT".to_vec());
+    }
+
+    #[test]
+    fn dust_change() {
+        let utxo = Utxo {
+            satoshis: 700,
+            ..utxos()[0].clone()
+        };
+
+        let tx = decode(&peg_tx(XPRIV, Ledger::BitcoinSv, &[utxo], b"IMT", 0, 1000).unwrap());
+
+        assert_eq!(tx.outputs.len(), 1);
+    }
+
+    #[test]
+    fn invalid() {
+        let poor = Utxo {
+            satoshis: 10,
+            ..utxos()[0].clone()
+        };
+        let bad_txid = Utxo {
+            txid: "not hex".to_string(),
+            ..utxos()[0].clone()
+        };
+        let kind = |result: Result<String, BitcoinError>| match result {
+            Err(BitcoinError::Error(err)) => Some(err.kind),
+            _ => None,
+        };
+
+        assert_eq!(kind(peg_tx(XPRIV, Ledger::Bitcoin, &[poor], b"IMT", 0, 1000)), Some(ErrorKind::InsufficientFunds));
+        assert_eq!(kind(peg_tx(XPRIV, Ledger::Bitcoin, &[bad_txid], b"IMT", 0, 1000)), Some(ErrorKind::InvalidUtxo));
+        assert_eq!(kind(peg_tx(XPRIV, Ledger::Bitcoin, &[], b"IMT", 0, 1000)), Some(ErrorKind::InvalidUtxo));
+        assert_eq!(kind(peg_tx(XPRIV, Ledger::Bitcoin, &utxos(), &[0; 81], 0, 1000)), Some(ErrorKind::PayloadTooLarge));
+        assert!("bitcoincash".parse::<Ledger>().is_err());
+        assert_eq!("bitcoinsv".parse::<Ledger>().unwrap(), Ledger::BitcoinSv);
+    }
+
+    const BITCOINSV_VECTOR: &str = "0100000002000102030415263748596a7b8c9d0e1f2a3b0d5d5e0b1c2f5c2d8b7c3c8f8e8d010000006a47304402205fa2d0b4e607188cc5086faca2ec456140fefb8f4a7ebca897811c9fd611df5002200163ea17c662874c84d496e8171e6096d3842a41cec3d7b01f301b7c3171f41c41210260ca0f286dfa7e60c6489966a7b1c045b8e25dd4fc9cc4a2d69b6fd4d797b414ffffffffffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100000000006a473044022047e5ab7d4d8bd8ba4eb2caaad39ebec3578c65b0f5f366025fe02b30cfa6fa440220709fecdea441eef29251137b596d68df17f881382ef49f93ffb7f6897f8192a04121037ebe71e0bcc41a72d70cfa73eac00917697ab1f57040b61b7c8d2ac2942c6dc7ffffffff02000000000000000008006a05494d5401012e2e0000000000001976a91450a54cd91b887e361a5a8fb237d2b30fc943518e88ac00000000";
+    const BITCOIN_VECTOR: &str = "0100000002000102030415263748596a7b8c9d0e1f2a3b0d5d5e0b1c2f5c2d8b7c3c8f8e8d010000006b483045022100cf7b4b841367d0eb96ca70e0b299967cc66f8cdee3030635deb4172f7f54d04f02207f3ae334593ce541fd23d511ab0b0ba4170cd2e3348429c9c032bfb4ff23e99001210260ca0f286dfa7e60c6489966a7b1c045b8e25dd4fc9cc4a2d69b6fd4d797b414ffffffffffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100000000006a47304402200e9aa3f4c9650e5afc6a923b3674ca1c46149174ebdfe01ce6124c4d3b8c3feb02203ca61e8d40253c0a7a83c8d27ead7621c5a89ad74f901723596465cdefe84a560121037ebe71e0bcc41a72d70cfa73eac00917697ab1f57040b61b7c8d2ac2942c6dc7ffffffff020000000000000000076a05494d5401012e2e0000000000001976a91450a54cd91b887e361a5a8fb237d2b30fc943518e88ac00000000";
+}
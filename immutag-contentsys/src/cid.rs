@@ -0,0 +1,218 @@
+use data_encoding::BASE32_NOPAD;
+use ring::digest::{digest, SHA256};
+use rust_base58::{FromBase58, ToBase58};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::err::{ContentsysError, Error, ErrorKind};
+use crate::unixfs::{read_varint, write_varint};
+
+/// Multicodec for protobuf encoded merkledag nodes.
+pub const DAG_PB: u64 = 0x70;
+/// Multicodec for leaves that hold the file bytes as is.
+pub const RAW: u64 = 0x55;
+/// Multihash code for sha2-256.
+pub const SHA2_256: u64 = 0x12;
+
+/// Multibase prefix for lowercase, unpadded base32.
+const BASE32_PREFIX: char = 'b';
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CidVersion {
+    V0,
+    V1,
+}
+
+/// A content identifier, as found in ipfs.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Cid {
+    pub version: CidVersion,
+    pub codec: u64,
+    pub multihash: Vec<u8>,
+}
+
+impl Cid {
+    pub fn new_v0(multihash: Vec<u8>) -> Cid {
+        Cid {
+            version: CidVersion::V0,
+            codec: DAG_PB,
+            multihash,
+        }
+    }
+
+    pub fn new_v1(codec: u64, multihash: Vec<u8>) -> Cid {
+        Cid {
+            version: CidVersion::V1,
+            codec,
+            multihash,
+        }
+    }
+
+    /// Binary form, as embedded in dag-pb links.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self.version {
+            CidVersion::V0 => self.multihash.clone(),
+            CidVersion::V1 => {
+                let mut bytes = Vec::new();
+                write_varint(&mut bytes, 1);
+                write_varint(&mut bytes, self.codec);
+                bytes.extend_from_slice(&self.multihash);
+                bytes
+            }
+        }
+    }
+
+    /// Same content, but CIDv1. Useful for comparing addresses of different versions.
+    pub fn to_v1(&self) -> Cid {
+        Cid::new_v1(self.codec, self.multihash.clone())
+    }
+
+    /// CIDv0 is base58btc (`Qm...`), CIDv1 is multibase base32 (`b...`).
+    pub fn encode(&self) -> String {
+        match self.version {
+            CidVersion::V0 => self.multihash.to_base58(),
+            CidVersion::V1 => {
+                let encoded = BASE32_NOPAD.encode(&self.to_bytes()).to_lowercase();
+                format!("{}{}", BASE32_PREFIX, encoded)
+            }
+        }
+    }
+
+    pub fn decode<T: AsRef<str>>(cid: T) -> Result<Cid, ContentsysError> {
+        let cid = cid.as_ref();
+        if cid.len() == 46 && cid.starts_with("Qm") {
+            let multihash = cid.from_base58().map_err(|_| invalid("invalid base58 cid"))?;
+            check_multihash(&multihash)?;
+
+            Ok(Cid::new_v0(multihash))
+        } else if cid.starts_with(BASE32_PREFIX) {
+            let bytes = BASE32_NOPAD
+                .decode(cid[1..].to_uppercase().as_bytes())
+                .map_err(|_| invalid("invalid base32 cid"))?;
+
+            decode_v1(&bytes)
+        } else {
+            Err(invalid("unsupported multibase").into())
+        }
+    }
+
+    /// Reads the binary form. A bare multihash is CIDv0.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cid, ContentsysError> {
+        if bytes.first() == Some(&(SHA2_256 as u8)) {
+            check_multihash(bytes)?;
+
+            Ok(Cid::new_v0(bytes.to_vec()))
+        } else {
+            decode_v1(bytes)
+        }
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&self.encode())
+    }
+}
+
+impl FromStr for Cid {
+    type Err = ContentsysError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Cid::decode(s)
+    }
+}
+
+/// Wraps a sha2-256 digest of the data in a multihash.
+pub fn multihash_sha256(data: &[u8]) -> Vec<u8> {
+    let hash = digest(&SHA256, data);
+    let mut multihash = Vec::with_capacity(34);
+    write_varint(&mut multihash, SHA2_256);
+    write_varint(&mut multihash, hash.as_ref().len() as u64);
+    multihash.extend_from_slice(hash.as_ref());
+
+    multihash
+}
+
+fn decode_v1(bytes: &[u8]) -> Result<Cid, ContentsysError> {
+    let mut pos = 0;
+    let version = read_varint(bytes, &mut pos).ok_or_else(|| invalid("truncated cid"))?;
+    let codec = read_varint(bytes, &mut pos).ok_or_else(|| invalid("truncated cid"))?;
+    if version != 1 {
+        return Err(invalid("unsupported cid version").into());
+    }
+    let multihash = bytes[pos..].to_vec();
+    check_multihash(&multihash)?;
+
+    Ok(Cid::new_v1(codec, multihash))
+}
+
+fn check_multihash(multihash: &[u8]) -> Result<(), ContentsysError> {
+    let mut pos = 0;
+    let code = read_varint(multihash, &mut pos).ok_or_else(|| invalid("truncated multihash"))?;
+    let len = read_varint(multihash, &mut pos).ok_or_else(|| invalid("truncated multihash"))?;
+    if code != SHA2_256 {
+        let err = Error::new("only sha2-256 multihashes are supported", ErrorKind::UnsupportedCodec);
+        return Err(err.into());
+    }
+    if multihash.len() - pos != len as usize {
+        return Err(invalid("multihash digest has the wrong length").into());
+    }
+
+    Ok(())
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(msg, ErrorKind::InvalidCid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_v0() {
+        let encoded = "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH";
+        let cid: Cid = encoded.parse().unwrap();
+
+        assert_eq!(cid.version, CidVersion::V0);
+        assert_eq!(cid.codec, DAG_PB);
+        assert_eq!(cid.to_string(), encoded);
+    }
+
+    #[test]
+    fn round_trip_v1() {
+        let encoded = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+        let cid: Cid = encoded.parse().unwrap();
+
+        assert_eq!(cid.version, CidVersion::V1);
+        assert_eq!(cid.codec, RAW);
+        assert_eq!(cid.multihash, multihash_sha256(b""));
+        assert_eq!(cid.to_string(), encoded);
+    }
+
+    #[test]
+    fn v0_to_v1() {
+        let cid: Cid = "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH".parse().unwrap();
+        let v1 = cid.to_v1();
+
+        assert!(v1.to_string().starts_with("bafybei"));
+        assert_eq!(v1.to_string().parse::<Cid>().unwrap().multihash, cid.multihash);
+    }
+
+    #[test]
+    fn round_trip_bytes() {
+        let v0: Cid = "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH".parse().unwrap();
+        let v1: Cid = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku".parse().unwrap();
+
+        assert_eq!(Cid::from_bytes(&v0.to_bytes()).unwrap(), v0);
+        assert_eq!(Cid::from_bytes(&v1.to_bytes()).unwrap(), v1);
+        assert!(Cid::from_bytes(&v0.to_bytes()[..20]).is_err());
+    }
+
+    #[test]
+    fn invalid_cids() {
+        assert!("QmNotACid".parse::<Cid>().is_err());
+        assert!("zb2rhe5P4gXftAwvA4eXQ5HJwsER2owDyS9sKaQRRVQPn93bA".parse::<Cid>().is_err());
+        assert!("bafkrei".parse::<Cid>().is_err());
+    }
+}
@@ -0,0 +1,61 @@
+#[derive(Debug)]
+pub enum ContentsysError {
+    IoError(std::io::Error),
+    HttpError(Box<ureq::Error>),
+    Error(Error),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    InvalidCid,
+    UnsupportedCodec,
+    UnsupportedContentsys,
+    NotFound,
+    HashMismatch,
+    IpfsApi,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub details: String,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    pub fn new(msg: &str, kind: ErrorKind) -> Error {
+        Error {
+            details: msg.to_string(),
+            kind,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl From<std::io::Error> for ContentsysError {
+    fn from(error: std::io::Error) -> Self {
+        ContentsysError::IoError(error)
+    }
+}
+
+impl From<Box<ureq::Error>> for ContentsysError {
+    fn from(error: Box<ureq::Error>) -> Self {
+        ContentsysError::HttpError(error)
+    }
+}
+
+impl From<Error> for ContentsysError {
+    fn from(error: Error) -> Self {
+        ContentsysError::Error(error)
+    }
+}
@@ -0,0 +1,300 @@
+/*
+Keeps file versions on an ipfs node, over its HTTP RPC API. Every address the
+node hands back is checked against the one computed locally.
+*/
+use std::io::Read;
+
+use serde_json::Value;
+
+use crate::cid::{Cid, CidVersion};
+use crate::err::{ContentsysError, Error, ErrorKind};
+use crate::store::ContentStore;
+
+/// Where `ipfs daemon` serves the RPC API by default.
+pub const DEFAULT_IPFS_API: &str = "http://127.0.0.1:5001";
+
+const BOUNDARY: &str = "immutag-ipfs-boundary";
+
+pub struct IpfsStore {
+    api: String,
+    cid_version: CidVersion,
+}
+
+impl IpfsStore {
+    pub fn new<T: AsRef<str>>(api: T, cid_version: CidVersion) -> IpfsStore {
+        IpfsStore {
+            api: api.as_ref().trim_end_matches('/').to_string(),
+            cid_version,
+        }
+    }
+
+    fn request(&self, endpoint: &str) -> ureq::Request {
+        ureq::post(&format!("{}/api/v0/{}", self.api, endpoint))
+    }
+
+    fn send(&self, request: ureq::Request, body: &[u8]) -> Result<ureq::Response, ContentsysError> {
+        match request.send_bytes(body) {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(code, response)) => {
+                // The API explains itself in a JSON `Message`.
+                let body = response.into_string().unwrap_or_default();
+                let message = serde_json::from_str::<Value>(&body)
+                    .ok()
+                    .and_then(|v| v["Message"].as_str().map(|m| m.to_string()))
+                    .unwrap_or(body);
+                let msg = format!("ipfs api returned {}: {}", code, message);
+                Err(ContentsysError::from(Error::new(&msg, ErrorKind::IpfsApi)))
+            }
+            Err(err) => Err(ContentsysError::from(Box::new(err))),
+        }
+    }
+
+    fn json(&self, response: ureq::Response) -> Result<Value, ContentsysError> {
+        let body = response.into_string()?;
+        serde_json::from_str(&body).map_err(|e| {
+            let msg = format!("invalid ipfs api response: {}", e);
+            ContentsysError::from(Error::new(&msg, ErrorKind::IpfsApi))
+        })
+    }
+}
+
+/// A single `file` part, as `ipfs add` expects it.
+fn multipart(data: &[u8]) -> Vec<u8> {
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n",
+        BOUNDARY
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+    body
+}
+
+impl ContentStore for IpfsStore {
+    fn put(&self, data: &[u8]) -> Result<Cid, ContentsysError> {
+        let expected = crate::content_addr(data, self.cid_version);
+        let cid_version = match self.cid_version {
+            CidVersion::V0 => "0",
+            CidVersion::V1 => "1",
+        };
+        let request = self
+            .request("add")
+            .query("cid-version", cid_version)
+            .query("pin", "true")
+            .set("Content-Type", &format!("multipart/form-data; boundary={}", BOUNDARY));
+        let response = self.send(request, &multipart(data))?;
+        let added = self.json(response)?;
+
+        let hash = added["Hash"].as_str().unwrap_or_default();
+        if hash != expected.to_string() {
+            let msg = format!("ipfs added the content as '{}', expected {}", hash, expected);
+            return Err(ContentsysError::from(Error::new(&msg, ErrorKind::HashMismatch)));
+        }
+
+        Ok(expected)
+    }
+
+    fn get(&self, cid: &Cid) -> Result<Vec<u8>, ContentsysError> {
+        let request = self.request("cat").query("arg", &cid.to_string());
+        let mut data = Vec::new();
+        self.send(request, &[])?.into_reader().read_to_end(&mut data)?;
+
+        if &crate::content_addr(&data, cid.version) != cid {
+            let msg = format!("ipfs returned content that doesn't hash to {}", cid);
+            return Err(ContentsysError::from(Error::new(&msg, ErrorKind::HashMismatch)));
+        }
+
+        Ok(data)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool, ContentsysError> {
+        // Offline, so the node doesn't go looking for it on the network.
+        let request = self
+            .request("block/stat")
+            .query("arg", &cid.to_string())
+            .query("offline", "true");
+
+        match self.send(request, &[]) {
+            Ok(_) => Ok(true),
+            Err(ContentsysError::Error(ref err)) if err.kind == ErrorKind::IpfsApi => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn pin(&self, cid: &Cid) -> Result<(), ContentsysError> {
+        let request = self.request("pin/add").query("arg", &cid.to_string());
+        self.send(request, &[])?;
+
+        Ok(())
+    }
+
+    fn unpin(&self, cid: &Cid) -> Result<(), ContentsysError> {
+        let request = self.request("pin/rm").query("arg", &cid.to_string());
+        self.send(request, &[])?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use std::collections::{BTreeSet, HashMap};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[derive(Default)]
+    struct Node {
+        blocks: HashMap<String, Vec<u8>>,
+        pins: BTreeSet<String>,
+    }
+
+    /// Serves the few endpoints the store uses, like an ipfs node would.
+    /// A lying node hands back the wrong address on add.
+    fn mock_node(lying: bool) -> (String, Arc<Mutex<Node>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api = format!("http://{}", listener.local_addr().unwrap());
+        let node = Arc::new(Mutex::new(Node::default()));
+        let served = node.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                serve(stream.unwrap(), &served, lying);
+            }
+        });
+
+        (api, node)
+    }
+
+    fn serve(stream: TcpStream, node: &Arc<Mutex<Node>>, lying: bool) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            let header = header.to_lowercase();
+            if let Some(len) = header.strip_prefix("content-length:") {
+                content_length = len.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let target = request_line.split_whitespace().nth(1).unwrap();
+        let (endpoint, query) = target.split_at(target.find('?').unwrap_or(target.len()));
+        let params: HashMap<&str, &str> = query
+            .trim_start_matches('?')
+            .split('&')
+            .filter_map(|p| p.find('=').map(|pos| (&p[..pos], &p[pos + 1..])))
+            .collect();
+        let arg = params.get("arg").map(|a| a.to_string()).unwrap_or_default();
+        let mut node = node.lock().unwrap();
+
+        let (status, response) = match endpoint {
+            "/api/v0/add" => {
+                let start = body.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+                let end = body.len() - format!("\r\n--{}--\r\n", BOUNDARY).len();
+                let data = body[start..end].to_vec();
+                let version = match params.get("cid-version") {
+                    Some(&"1") => CidVersion::V1,
+                    _ => CidVersion::V0,
+                };
+                let mut hash = crate::content_addr(&data, version).to_string();
+                if lying {
+                    hash = crate::content_addr(b"something else", version).to_string();
+                }
+                node.pins.insert(hash.clone());
+                node.blocks.insert(hash.clone(), data);
+                (200, format!("{{\"Name\":\"file\",\"Hash\":\"{}\",\"Size\":\"0\"}}\n", hash).into_bytes())
+            }
+            "/api/v0/cat" | "/api/v0/block/stat" => match node.blocks.get(&arg) {
+                Some(data) if endpoint == "/api/v0/cat" => (200, data.clone()),
+                Some(_) => (200, format!("{{\"Key\":\"{}\"}}", arg).into_bytes()),
+                None => (500, b"{\"Message\":\"block was not found locally (offline)\"}".to_vec()),
+            },
+            "/api/v0/pin/add" => {
+                node.pins.insert(arg.clone());
+                (200, format!("{{\"Pins\":[\"{}\"]}}", arg).into_bytes())
+            }
+            "/api/v0/pin/rm" => {
+                if node.pins.remove(&arg) {
+                    (200, format!("{{\"Pins\":[\"{}\"]}}", arg).into_bytes())
+                } else {
+                    (500, b"{\"Message\":\"not pinned or pinned indirectly\"}".to_vec())
+                }
+            }
+            _ => (404, b"404 page not found".to_vec()),
+        };
+
+        let mut stream = stream;
+        let head = format!(
+            "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            response.len()
+        );
+        stream.write_all(head.as_bytes()).unwrap();
+        stream.write_all(&response).unwrap();
+    }
+
+    #[test]
+    fn put_get_pin() {
+        let (api, node) = mock_node(false);
+        let store = IpfsStore::new(api, CidVersion::V0);
+
+        let cid = store.put(b"foobar\n").unwrap();
+        let data = store.get(&cid).unwrap();
+        let has = store.has(&cid).unwrap();
+        let missing: Cid = "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH".parse().unwrap();
+        let has_missing = store.has(&missing).unwrap();
+        store.unpin(&cid).unwrap();
+        let unpinned = !node.lock().unwrap().pins.contains(&cid.to_string());
+        store.pin(&cid).unwrap();
+        let unpin_twice = store.unpin(&cid).and_then(|_| store.unpin(&cid));
+
+        assert_eq!(cid.to_string(), "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL");
+        assert_eq!(data, b"foobar\n".to_vec());
+        assert!(has);
+        assert!(!has_missing);
+        assert!(unpinned);
+        assert!(unpin_twice.is_err());
+    }
+
+    #[test]
+    fn put_v1() {
+        let (api, _) = mock_node(false);
+        let store = IpfsStore::new(api, CidVersion::V1);
+
+        let cid = store.put(b"hello world\n").unwrap();
+
+        assert_eq!(cid.to_string(), "bafkreifjjcie6lypi6ny7amxnfftagclbuxndqonfipmb64f2km2devei4");
+    }
+
+    #[test]
+    fn cid_mismatch() {
+        let (api, _) = mock_node(true);
+        let store = IpfsStore::new(api, CidVersion::V0);
+
+        let result = store.put(b"foobar\n");
+
+        match result {
+            Err(ContentsysError::Error(err)) => assert_eq!(err.kind, ErrorKind::HashMismatch),
+            _ => panic!("expected a hash mismatch"),
+        }
+    }
+
+    #[test]
+    fn node_unreachable() {
+        let store = IpfsStore::new("http://127.0.0.1:1", CidVersion::V0);
+
+        assert!(store.put(b"foobar\n").is_err());
+    }
+}
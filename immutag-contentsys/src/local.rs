@@ -0,0 +1,214 @@
+/*
+A blob store in `.immutag/<addr>/objects/`, so every committed version can be
+restored offline.
+*/
+use std::collections::BTreeSet;
+use std::fs::{create_dir_all, read, read_dir, read_to_string, remove_file, rename, write};
+use std::path::Path;
+
+use crate::cid::{Cid, CidVersion};
+use crate::err::{ContentsysError, Error, ErrorKind};
+use crate::store::ContentStore;
+
+const PINS: &str = "pins";
+
+pub struct LocalStore {
+    objects_path: String,
+    cid_version: CidVersion,
+}
+
+impl LocalStore {
+    /// Opens, or creates, the store of the filesystem at `filesys_path`.
+    pub fn open<T: AsRef<str>>(filesys_path: T) -> Result<LocalStore, ContentsysError> {
+        let objects_path = fixture::directorate(filesys_path.as_ref().to_string()) + "objects";
+
+        LocalStore::new(objects_path, CidVersion::V0)
+    }
+
+    pub fn new<T: AsRef<str>>(objects_path: T, cid_version: CidVersion) -> Result<LocalStore, ContentsysError> {
+        create_dir_all(objects_path.as_ref())?;
+
+        Ok(LocalStore {
+            objects_path: fixture::directorate(objects_path.as_ref().to_string()),
+            cid_version,
+        })
+    }
+
+    /// Deletes everything that isn't pinned and returns what was deleted.
+    pub fn gc(&self) -> Result<Vec<Cid>, ContentsysError> {
+        let pins = self.pins()?;
+        let mut removed = Vec::new();
+
+        for entry in read_dir(&self.objects_path)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if let Ok(cid) = Cid::decode(&name) {
+                if !pins.contains(&name) {
+                    remove_file(self.blob_path(&cid))?;
+                    removed.push(cid);
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn blob_path(&self, cid: &Cid) -> String {
+        self.objects_path.clone() + &cid.to_string()
+    }
+
+    fn pins_path(&self) -> String {
+        self.objects_path.clone() + PINS
+    }
+
+    fn pins(&self) -> Result<BTreeSet<String>, ContentsysError> {
+        if !Path::new(&self.pins_path()).exists() {
+            return Ok(BTreeSet::new());
+        }
+        let pins = read_to_string(self.pins_path())?
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        Ok(pins)
+    }
+
+    fn write_pins(&self, pins: &BTreeSet<String>) -> Result<(), ContentsysError> {
+        let mut data = String::new();
+        for pin in pins {
+            data.push_str(pin);
+            data.push('\n');
+        }
+        write(self.pins_path(), data)?;
+
+        Ok(())
+    }
+}
+
+impl ContentStore for LocalStore {
+    fn put(&self, data: &[u8]) -> Result<Cid, ContentsysError> {
+        let cid = crate::content_addr(data, self.cid_version);
+        let path = self.blob_path(&cid);
+
+        if !Path::new(&path).exists() {
+            // Written aside first, so a blob is either complete or missing.
+            let tmp_path = path.clone() + ".tmp";
+            write(&tmp_path, data)?;
+            rename(&tmp_path, &path)?;
+        }
+        self.pin(&cid)?;
+
+        Ok(cid)
+    }
+
+    fn get(&self, cid: &Cid) -> Result<Vec<u8>, ContentsysError> {
+        if !self.has(cid)? {
+            let msg = format!("{} isn't in the local store", cid);
+            return Err(ContentsysError::from(Error::new(&msg, ErrorKind::NotFound)));
+        }
+        let data = read(self.blob_path(cid))?;
+
+        if &crate::content_addr(&data, cid.version) != cid {
+            let msg = format!("local blob {} is corrupt", cid);
+            return Err(ContentsysError::from(Error::new(&msg, ErrorKind::HashMismatch)));
+        }
+
+        Ok(data)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool, ContentsysError> {
+        Ok(Path::new(&self.blob_path(cid)).exists())
+    }
+
+    fn pin(&self, cid: &Cid) -> Result<(), ContentsysError> {
+        let mut pins = self.pins()?;
+        if pins.insert(cid.to_string()) {
+            self.write_pins(&pins)?;
+        }
+
+        Ok(())
+    }
+
+    fn unpin(&self, cid: &Cid) -> Result<(), ContentsysError> {
+        let mut pins = self.pins()?;
+        if pins.remove(&cid.to_string()) {
+            self.write_pins(&pins)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::store::{open_store, StoreConfig};
+    use fixture::Fixture;
+
+    #[test]
+    fn put_get() {
+        let path = "/tmp/immutag_localstore_tests";
+        let mut fixture = Fixture::new().add_dirpath(path.to_string()).build();
+        let store = open_store(&StoreConfig::default(), path).unwrap();
+
+        let cid = store.put(b"foobar\n").unwrap();
+        let data = store.get(&cid).unwrap();
+        let has = store.has(&cid).unwrap();
+        let missing: Cid = "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH".parse().unwrap();
+        let has_missing = store.has(&missing).unwrap();
+        let get_missing = store.get(&missing);
+
+        fixture.teardown(true);
+
+        assert_eq!(cid.to_string(), "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL");
+        assert_eq!(data, b"foobar\n".to_vec());
+        assert!(has);
+        assert!(!has_missing);
+        assert!(get_missing.is_err());
+    }
+
+    #[test]
+    fn gc_keeps_pins() {
+        let path = "/tmp/immutag_localstore_gc_tests";
+        let mut fixture = Fixture::new().add_dirpath(path.to_string()).build();
+        let store = LocalStore::open(path).unwrap();
+
+        let kept = store.put(b"kept").unwrap();
+        let dropped = store.put(b"dropped").unwrap();
+        store.unpin(&dropped).unwrap();
+        let removed = store.gc().unwrap();
+        let has_kept = store.has(&kept).unwrap();
+        let has_dropped = store.has(&dropped).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(removed, vec![dropped]);
+        assert!(has_kept);
+        assert!(!has_dropped);
+    }
+
+    #[test]
+    fn corrupt_blob() {
+        let path = "/tmp/immutag_localstore_corrupt_tests";
+        let mut fixture = Fixture::new().add_dirpath(path.to_string()).build();
+        let store = LocalStore::open(path).unwrap();
+
+        let cid = store.put(b"original").unwrap();
+        write(store.blob_path(&cid), b"tampered").unwrap();
+        let result = store.get(&cid);
+
+        fixture.teardown(true);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unsupported_contentsys() {
+        let config = StoreConfig {
+            contentsys: Some("dropbox".to_string()),
+            ipfs_api: None,
+        };
+
+        assert!(open_store(&config, "/tmp/immutag_unused").is_err());
+    }
+}
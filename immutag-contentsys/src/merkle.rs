@@ -0,0 +1,131 @@
+/*
+A Merkle tree over sha256. Leaves and inner nodes are hashed with different
+prefixes, as in RFC 6962, so a leaf can't pass for a node. A node without a
+sibling moves up a level unchanged rather than being paired with itself.
+*/
+use ring::digest::{Context, SHA256};
+
+pub type Hash = [u8; 32];
+
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+
+/// A sibling on the way from a leaf to the root.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub hash: Hash,
+    /// Whether the sibling is hashed in on the left.
+    pub left: bool,
+}
+
+pub fn leaf(data: &[u8]) -> Hash {
+    hash(&[&[LEAF], data])
+}
+
+pub fn node(left: &Hash, right: &Hash) -> Hash {
+    hash(&[&[NODE], left, right])
+}
+
+/// The root of `leaves`, `None` if there aren't any.
+pub fn root(leaves: &[Hash]) -> Option<Hash> {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = up(&level);
+    }
+
+    level.pop()
+}
+
+/// The path from leaf `index` to the root.
+pub fn proof(leaves: &[Hash], mut index: usize) -> Option<Vec<Step>> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut steps = Vec::new();
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            steps.push(Step {
+                hash: level[sibling],
+                left: sibling < index,
+            });
+        }
+        level = up(&level);
+        index /= 2;
+    }
+
+    Some(steps)
+}
+
+/// The root `steps` lead to from `leaf`.
+pub fn fold(leaf: &Hash, steps: &[Step]) -> Hash {
+    steps.iter().fold(*leaf, |acc, step| match step.left {
+        true => node(&step.hash, &acc),
+        false => node(&acc, &step.hash),
+    })
+}
+
+fn up(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node(left, right),
+            [odd] => *odd,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+fn hash(parts: &[&[u8]]) -> Hash {
+    let mut context = Context::new(&SHA256);
+    for part in parts {
+        context.update(part);
+    }
+    let mut hash = [0; 32];
+    hash.copy_from_slice(context.finish().as_ref());
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_encoding::HEXLOWER;
+
+    fn leaves(n: usize) -> Vec<Hash> {
+        (0..n).map(|i| leaf(format!("leaf {}", i).as_bytes())).collect()
+    }
+
+    #[test]
+    fn roots() {
+        let three = leaves(3);
+
+        assert_eq!(root(&[]), None);
+        assert_eq!(root(&three[..1]), Some(three[0]));
+        assert_eq!(root(&three), Some(node(&node(&three[0], &three[1]), &three[2])));
+        // sha256 of 0x00 "foobar"
+        assert_eq!(
+            HEXLOWER.encode(&leaf(b"foobar")),
+            "fc2be4408ff8da1b9990e0b4fd839ffbf855205f6423225b773d8e7043af0677"
+        );
+    }
+
+    #[test]
+    fn proofs() {
+        for n in 1..12 {
+            let leaves = leaves(n);
+            let root = root(&leaves).unwrap();
+            for (i, leaf) in leaves.iter().enumerate() {
+                let steps = proof(&leaves, i).unwrap();
+
+                assert_eq!(fold(leaf, &steps), root);
+                if n > 1 {
+                    assert_ne!(fold(&leaves[(i + 1) % n], &steps), root);
+                }
+            }
+            assert_eq!(proof(&leaves, n), None);
+        }
+    }
+}
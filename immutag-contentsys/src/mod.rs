@@ -0,0 +1,146 @@
+/*
+This module manages the content-addressable side of immutag (the `contentsys`):
+computing ipfs addresses locally, without a daemon, and storing file versions.
+*/
+extern crate data_encoding;
+extern crate ring;
+extern crate rust_base58;
+
+pub mod cid;
+pub mod err;
+pub mod ipfs;
+pub mod local;
+pub mod merkle;
+pub mod store;
+pub mod unixfs;
+
+pub use cid::{Cid, CidVersion};
+pub use err::{ContentsysError, ErrorKind};
+pub use ipfs::IpfsStore;
+pub use local::LocalStore;
+pub use store::{open_store, ContentStore, StoreConfig};
+pub use unixfs::{Block, Dag, Layout};
+
+use data_encoding::HEXLOWER;
+use ring::digest::{digest, SHA256};
+use std::fs::File;
+use std::io::BufReader;
+
+/// The `file_hash` of a version, hex encoded sha256.
+pub fn file_hash(data: &[u8]) -> String {
+    HEXLOWER.encode(digest(&SHA256, data).as_ref())
+}
+
+/// The address `ipfs add` would give the data.
+pub fn content_addr(data: &[u8], version: CidVersion) -> Cid {
+    unixfs::build(data, &Layout::new(version))
+        .expect("reading from memory can't fail")
+        .root
+}
+
+/// The address `ipfs add` would give the file at `path`.
+pub fn content_addr_file<T: AsRef<str>>(path: T, version: CidVersion) -> Result<Cid, ContentsysError> {
+    let dag = dag_file(path, &Layout::new(version))?;
+
+    Ok(dag.root)
+}
+
+/// Every block `ipfs add` would store for the file at `path`.
+pub fn dag_file<T: AsRef<str>>(path: T, layout: &Layout) -> Result<Dag, ContentsysError> {
+    let file = File::open(path.as_ref())?;
+    let dag = unixfs::build(BufReader::new(file), layout)?;
+
+    Ok(dag)
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use fixture::Fixture;
+    use std::fs::write;
+
+    const LOREM: &[u8] = b"Lorem ipsum dolor sit amet, sit enim montes aliquam. Cras non lorem, \
+            rhoncus condimentum, irure et ante. Pulvinar suscipit odio ante, et tellus a enim, \
+            wisi ipsum, vel rhoncus eget faucibus varius, luctus turpis nibh vel odio nulla pede.";
+
+    fn setup_test<T: AsRef<str>>(path: T) -> Fixture {
+        let fixture = Fixture::new()
+            .add_dirpath(path.as_ref().to_string())
+            .build();
+
+        write(path.as_ref().to_string() + "/empty", b"").unwrap();
+        write(path.as_ref().to_string() + "/foobar", b"foobar\n").unwrap();
+        write(path.as_ref().to_string() + "/hello", b"hello world\n").unwrap();
+        write(path.as_ref().to_string() + "/lorem", LOREM).unwrap();
+
+        fixture
+    }
+
+    // Expected addresses come from go-ipfs.
+    #[test]
+    fn content_addr_v0() {
+        let path = "/tmp/immutag_contentsys_v0_tests";
+        let mut fixture = setup_test(path);
+        let empty = content_addr_file(format!("{}/empty", path), CidVersion::V0).unwrap();
+        let foobar = content_addr_file(format!("{}/foobar", path), CidVersion::V0).unwrap();
+        let hello = content_addr_file(format!("{}/hello", path), CidVersion::V0).unwrap();
+        fixture.teardown(true);
+
+        assert_eq!(empty.to_string(), "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH");
+        assert_eq!(foobar.to_string(), "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL");
+        assert_eq!(hello.to_string(), "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o");
+    }
+
+    #[test]
+    fn content_addr_v1() {
+        let path = "/tmp/immutag_contentsys_v1_tests";
+        let mut fixture = setup_test(path);
+        let empty = content_addr_file(format!("{}/empty", path), CidVersion::V1).unwrap();
+        let hello = content_addr_file(format!("{}/hello", path), CidVersion::V1).unwrap();
+        fixture.teardown(true);
+
+        assert_eq!(empty.to_string(), "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku");
+        assert_eq!(hello.to_string(), "bafkreifjjcie6lypi6ny7amxnfftagclbuxndqonfipmb64f2km2devei4");
+    }
+
+    #[test]
+    fn content_addr_multi_chunk() {
+        let path = "/tmp/immutag_contentsys_chunk_tests";
+        let mut fixture = setup_test(path);
+        let foobar = dag_file(format!("{}/foobar", path), &Layout::new(CidVersion::V0).chunk_size(2)).unwrap();
+        let lorem = dag_file(format!("{}/lorem", path), &Layout::new(CidVersion::V0).chunk_size(32)).unwrap();
+        let lorem_deep = dag_file(format!("{}/lorem", path), &Layout::new(CidVersion::V0).chunk_size(1)).unwrap();
+        fixture.teardown(true);
+
+        let foobar_blocks: Vec<String> = foobar.blocks.iter().map(|block| block.cid.to_string()).collect();
+        assert_eq!(
+            foobar_blocks,
+            vec![
+                "QmfVyMoStzTvdnUR7Uotzh82gmL427q9z3xW5Y8fUoszi4",
+                "QmdPyW4CWE3QBkgjWfjM5f7Tjb3HukxVuBXZtkqAGwsMnm",
+                "QmNhDQpphvMWhdCzP74taRzXDaEfPGq8vWfFRzD7mEgePM",
+                "Qmc5m94Gu7z62RC8waSKkZUrCCBJPyHbkpmGzEePxy2oXJ",
+                "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6",
+            ]
+        );
+        assert_eq!(lorem.root.to_string(), "QmYSLcVQqxKygiq7x9w1XGYxU29EShB8ZemiaQ8GAAw17h");
+        assert_eq!(lorem_deep.blocks.len(), 240);
+        assert_eq!(lorem_deep.root.to_string(), "QmRQ6NZNUs4JrCT2y7tmCC1wUhjqYuTssB8VXbbN3rMffg");
+    }
+
+    #[test]
+    fn file_hash_sha256() {
+        assert_eq!(
+            file_hash(b"foobar\n"),
+            "aec070645fe53ee3b3763059376134f058cc337247c978add178b6ccdfb0019f"
+        );
+    }
+
+    #[test]
+    fn content_addr_in_memory() {
+        assert_eq!(
+            content_addr(b"foobar\n", CidVersion::V0).to_string(),
+            "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use crate::cid::{Cid, CidVersion};
+use crate::err::{ContentsysError, Error, ErrorKind};
+use crate::ipfs::{IpfsStore, DEFAULT_IPFS_API};
+use crate::local::LocalStore;
+
+/// `contentsys` used when immutag.toml doesn't name one.
+pub const DEFAULT_CONTENTSYS: &str = "local";
+
+/// Where file version bytes live, keyed by content address. Swap in another
+/// content-addressable file system by implementing this.
+pub trait ContentStore {
+    /// Stores and pins the bytes, returning their content address.
+    fn put(&self, data: &[u8]) -> Result<Cid, ContentsysError>;
+    fn get(&self, cid: &Cid) -> Result<Vec<u8>, ContentsysError>;
+    fn has(&self, cid: &Cid) -> Result<bool, ContentsysError>;
+    /// Keeps the content around, even through garbage collection.
+    fn pin(&self, cid: &Cid) -> Result<(), ContentsysError>;
+    fn unpin(&self, cid: &Cid) -> Result<(), ContentsysError>;
+}
+
+/// The store settings of the Immutag file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StoreConfig {
+    /// `local` or `ipfs`.
+    pub contentsys: Option<String>,
+    /// Address of the ipfs node's RPC API.
+    pub ipfs_api: Option<String>,
+}
+
+/// Opens the store named by the config for the filesystem at `filesys_path`.
+pub fn open_store<T: AsRef<str>>(
+    config: &StoreConfig,
+    filesys_path: T,
+) -> Result<Box<dyn ContentStore>, ContentsysError> {
+    let contentsys = config.contentsys.as_deref().unwrap_or(DEFAULT_CONTENTSYS);
+
+    match contentsys {
+        "local" => Ok(Box::new(LocalStore::open(filesys_path)?)),
+        "ipfs" => {
+            let api = config.ipfs_api.as_deref().unwrap_or(DEFAULT_IPFS_API);
+            Ok(Box::new(IpfsStore::new(api, CidVersion::V0)))
+        }
+        _ => {
+            let msg = format!("unsupported contentsys '{}'", contentsys);
+            let err = Error::new(&msg, ErrorKind::UnsupportedContentsys);
+            Err(ContentsysError::from(err))
+        }
+    }
+}
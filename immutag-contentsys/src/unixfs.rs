@@ -0,0 +1,338 @@
+/*
+Builds the same merkledag `ipfs add` would: fixed size chunks, UnixFS `File`
+nodes and a balanced layout.
+*/
+use std::io::Read;
+
+use crate::cid::{multihash_sha256, Cid, CidVersion, DAG_PB, RAW};
+
+/// Default `ipfs add` chunker, `size-262144`.
+pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+/// Maximum links per node of the balanced layout.
+pub const DEFAULT_MAX_LINKS: usize = 174;
+
+/// UnixFS `Data.DataType.File`.
+const UNIXFS_FILE: u64 = 2;
+
+/// Mirrors the `ipfs add` options that change the resulting address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    pub cid_version: CidVersion,
+    pub chunk_size: usize,
+    pub max_links: usize,
+    /// `ipfs add --cid-version=1` implies `--raw-leaves`.
+    pub raw_leaves: bool,
+}
+
+impl Layout {
+    pub fn new(cid_version: CidVersion) -> Layout {
+        Layout {
+            cid_version,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_links: DEFAULT_MAX_LINKS,
+            raw_leaves: cid_version == CidVersion::V1,
+        }
+    }
+
+    pub fn chunk_size(mut self, chunk_size: usize) -> Layout {
+        self.chunk_size = chunk_size;
+
+        self
+    }
+
+    pub fn max_links(mut self, max_links: usize) -> Layout {
+        self.max_links = max_links;
+
+        self
+    }
+}
+
+/// A serialized node of the dag and its address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub cid: Cid,
+    pub data: Vec<u8>,
+}
+
+/// All the blocks of a file, the root block last.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dag {
+    pub root: Cid,
+    pub blocks: Vec<Block>,
+}
+
+struct Link {
+    cid: Cid,
+    /// Serialized size of the block plus everything it links to.
+    tsize: u64,
+    /// Bytes of the file under this link.
+    filesize: u64,
+}
+
+struct Builder<R: Read> {
+    reader: R,
+    layout: Layout,
+    peeked: Option<Vec<u8>>,
+    blocks: Vec<Block>,
+}
+
+/// Chunks and links everything the reader yields.
+pub fn build<R: Read>(reader: R, layout: &Layout) -> std::io::Result<Dag> {
+    let mut builder = Builder {
+        reader,
+        layout: layout.clone(),
+        peeked: None,
+        blocks: Vec::new(),
+    };
+
+    let root = builder.balanced()?;
+
+    Ok(Dag {
+        root: root.cid,
+        blocks: builder.blocks,
+    })
+}
+
+impl<R: Read> Builder<R> {
+    // Same algorithm as go-ipfs' balanced.Layout: the tree grows a level
+    // whenever the current root is full.
+    fn balanced(&mut self) -> std::io::Result<Link> {
+        let first = self.next_chunk()?.unwrap_or_default();
+        let mut root = self.leaf(first);
+        let mut depth = 1;
+
+        while !self.done()? {
+            let mut children = vec![root];
+            self.fill(&mut children, depth)?;
+            root = self.node(children);
+            depth += 1;
+        }
+
+        Ok(root)
+    }
+
+    fn fill(&mut self, children: &mut Vec<Link>, depth: usize) -> std::io::Result<()> {
+        while children.len() < self.layout.max_links && !self.done()? {
+            let child = if depth == 1 {
+                let chunk = self.next_chunk()?.unwrap_or_default();
+                self.leaf(chunk)
+            } else {
+                let mut grandchildren = Vec::new();
+                self.fill(&mut grandchildren, depth - 1)?;
+                self.node(grandchildren)
+            };
+            children.push(child);
+        }
+
+        Ok(())
+    }
+
+    fn leaf(&mut self, chunk: Vec<u8>) -> Link {
+        let filesize = chunk.len() as u64;
+
+        if self.layout.raw_leaves {
+            let cid = Cid::new_v1(RAW, multihash_sha256(&chunk));
+            self.push(cid, chunk, 0, filesize)
+        } else {
+            let data = unixfs_data(&chunk, filesize, &[]);
+            let node = pb_node(&[], &data);
+            let cid = self.dag_pb_cid(&node);
+            self.push(cid, node, 0, filesize)
+        }
+    }
+
+    fn node(&mut self, children: Vec<Link>) -> Link {
+        let filesize = children.iter().map(|link| link.filesize).sum();
+        let children_tsize = children.iter().map(|link| link.tsize).sum();
+        let blocksizes: Vec<u64> = children.iter().map(|link| link.filesize).collect();
+
+        let data = unixfs_data(&[], filesize, &blocksizes);
+        let node = pb_node(&children, &data);
+        let cid = self.dag_pb_cid(&node);
+
+        self.push(cid, node, children_tsize, filesize)
+    }
+
+    fn push(&mut self, cid: Cid, data: Vec<u8>, children_tsize: u64, filesize: u64) -> Link {
+        let tsize = data.len() as u64 + children_tsize;
+        self.blocks.push(Block {
+            cid: cid.clone(),
+            data,
+        });
+
+        Link {
+            cid,
+            tsize,
+            filesize,
+        }
+    }
+
+    fn dag_pb_cid(&self, node: &[u8]) -> Cid {
+        let multihash = multihash_sha256(node);
+        match self.layout.cid_version {
+            CidVersion::V0 => Cid::new_v0(multihash),
+            CidVersion::V1 => Cid::new_v1(DAG_PB, multihash),
+        }
+    }
+
+    fn done(&mut self) -> std::io::Result<bool> {
+        if self.peeked.is_none() {
+            self.peeked = self.read_chunk()?;
+        }
+
+        Ok(self.peeked.is_none())
+    }
+
+    fn next_chunk(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        if let Some(chunk) = self.peeked.take() {
+            Ok(Some(chunk))
+        } else {
+            self.read_chunk()
+        }
+    }
+
+    // Fills the chunk completely unless the reader runs out, like io.ReadFull.
+    fn read_chunk(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let mut chunk = vec![0; self.layout.chunk_size];
+        let mut filled = 0;
+        while filled < chunk.len() {
+            match self.reader.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if filled == 0 {
+            Ok(None)
+        } else {
+            chunk.truncate(filled);
+            Ok(Some(chunk))
+        }
+    }
+}
+
+// message Data { Type = 1; Data = 2; filesize = 3; repeated blocksizes = 4; }
+fn unixfs_data(data: &[u8], filesize: u64, blocksizes: &[u64]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_varint_field(&mut buf, 1, UNIXFS_FILE);
+    if !data.is_empty() {
+        write_bytes_field(&mut buf, 2, data);
+    }
+    write_varint_field(&mut buf, 3, filesize);
+    for blocksize in blocksizes {
+        write_varint_field(&mut buf, 4, *blocksize);
+    }
+
+    buf
+}
+
+// message PBNode { repeated PBLink Links = 2; Data = 1; }, links go first.
+// message PBLink { Hash = 1; Name = 2; Tsize = 3; }
+fn pb_node(links: &[Link], data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for link in links {
+        let mut pb_link = Vec::new();
+        write_bytes_field(&mut pb_link, 1, &link.cid.to_bytes());
+        write_bytes_field(&mut pb_link, 2, b"");
+        write_varint_field(&mut pb_link, 3, link.tsize);
+        write_bytes_field(&mut buf, 2, &pb_link);
+    }
+    write_bytes_field(&mut buf, 1, data);
+
+    buf
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(buf, field << 3);
+    write_varint(buf, value);
+}
+
+fn write_bytes_field(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    write_varint(buf, (field << 3) | 2);
+    write_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+/// Unsigned LEB128, used by both protobuf and the multiformats.
+pub fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+pub fn read_varint(buf: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *buf.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+        if shift > 63 {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint() {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0xac, 0x02]);
+
+        let mut pos = 0;
+        assert_eq!(read_varint(&buf, &mut pos), Some(300));
+        assert_eq!(pos, 2);
+    }
+
+    #[test]
+    fn empty_file_block() {
+        let dag = build(&b""[..], &Layout::new(CidVersion::V0)).unwrap();
+
+        assert_eq!(dag.blocks.len(), 1);
+        assert_eq!(dag.blocks[0].data, vec![0x0a, 0x04, 0x08, 0x02, 0x18, 0x00]);
+    }
+
+    #[test]
+    fn single_link_node_is_the_root() {
+        let layout = Layout::new(CidVersion::V0).chunk_size(1);
+        let dag = build(&[0u8; 174][..], &layout).unwrap();
+
+        // 174 leaves fit in a single node, no extra root on top.
+        assert_eq!(dag.blocks.len(), 175);
+        assert_eq!(dag.root.to_string(), "QmdgQac8c6Bo3MP5bHAg2yQ25KebFUsmkZFvyByYzf8UCB");
+    }
+
+    #[test]
+    fn full_node_and_a_byte() {
+        let layout = Layout::new(CidVersion::V0).chunk_size(2);
+        let dag = build(&[0u8; 2 * 174 + 1][..], &layout).unwrap();
+
+        // 175 leaves, a full node, a node wrapping the last leaf and the root.
+        assert_eq!(dag.blocks.len(), 175 + 3);
+        assert_eq!(dag.root.to_string(), "QmcHNWF1d56uCDSfJPA7t9fadZRV9we5HGSTGSmwuqmMP9");
+    }
+
+    #[test]
+    fn raw_leaves() {
+        let layout = Layout::new(CidVersion::V1).chunk_size(2);
+        let dag = build(&b"foobar\n"[..], &layout).unwrap();
+
+        assert_eq!(dag.blocks.len(), 5);
+        assert_eq!(dag.blocks[0].data, b"fo".to_vec());
+        assert_eq!(dag.blocks[0].cid.codec, RAW);
+        assert_eq!(dag.root.codec, DAG_PB);
+        assert_eq!(dag.root.version, CidVersion::V1);
+    }
+}
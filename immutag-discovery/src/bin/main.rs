@@ -0,0 +1,140 @@
+#[macro_use]
+extern crate clap;
+extern crate immutag_discovery;
+
+use std::fmt::Debug;
+use std::thread;
+use std::time::Duration;
+
+use clap::{App, Arg};
+use immutag_contentsys::{open_store, ContentStore, StoreConfig};
+use immutag_discovery::{ingest_ledger, ingest_metadata, Catalog, Service};
+use immutag_ledger::{open_ledger, LedgerConfig};
+
+fn main() {
+    let matches = App::new("immutag-discovery")
+        .version(crate_version!())
+        .about("Ingests immutags from the ledger and serves a JSON HTTP API to search them.")
+        .arg(
+            Arg::with_name("db")
+                .long("db")
+                .value_name("PATH")
+                .default_value("discovery.sqlite")
+                .help("The SQLite catalog."),
+        )
+        .arg(
+            Arg::with_name("follow")
+                .long("follow")
+                .value_name("FILESYS-ADDR")
+                .multiple(true)
+                .number_of_values(1)
+                .help("A filesystem root to ingest."),
+        )
+        .arg(
+            Arg::with_name("immutag")
+                .long("immutag")
+                .value_name("DIR")
+                .multiple(true)
+                .number_of_values(1)
+                .help("An immutag directory to follow and take aliases and tags from."),
+        )
+        .arg(Arg::with_name("ledger").long("ledger").value_name("LEDGER").help("bitcoinsv or bitcoin."))
+        .arg(
+            Arg::with_name("electrum")
+                .long("electrum")
+                .value_name("URL")
+                .required(true)
+                .help("The ElectrumX server, tcp://host:port or ssl://host:port."),
+        )
+        .arg(
+            Arg::with_name("contentsys")
+                .long("contentsys")
+                .value_name("CONTENTSYS")
+                .help("Where manifests are fetched from, local or ipfs."),
+        )
+        .arg(Arg::with_name("ipfs-api").long("ipfs-api").value_name("URL"))
+        .arg(
+            Arg::with_name("store")
+                .long("store")
+                .value_name("FILESYS-PATH")
+                .default_value(".")
+                .help("The filesystem whose store the local contentsys reads."),
+        )
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .value_name("ADDR")
+                .default_value("127.0.0.1:8080"),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .value_name("SECONDS")
+                .default_value("60")
+                .help("How long to wait between ingests."),
+        )
+        .get_matches();
+
+    let db = matches.value_of("db").unwrap().to_string();
+    let follow: Vec<String> = matches.values_of("follow").map(|v| v.map(String::from).collect()).unwrap_or_default();
+    let dirs: Vec<String> = matches.values_of("immutag").map(|v| v.map(String::from).collect()).unwrap_or_default();
+    let ledger_config = LedgerConfig {
+        ledger: matches.value_of("ledger").map(String::from),
+        electrum: matches.value_of("electrum").map(String::from),
+    };
+    let store_config = StoreConfig {
+        contentsys: matches.value_of("contentsys").map(String::from),
+        ipfs_api: matches.value_of("ipfs-api").map(String::from),
+    };
+    let store_path = matches.value_of("store").unwrap().to_string();
+    let interval = value_t!(matches, "interval", u64).unwrap_or_else(|e| e.exit());
+
+    let catalog = Catalog::open(db.as_str()).unwrap_or_else(|e| fail("failed to open the catalog", e));
+    for addr in follow.iter() {
+        if let Err(e) = catalog.follow(addr.as_str()) {
+            fail(format!("failed to follow {}", addr).as_str(), e);
+        }
+    }
+    let ledger = open_ledger(&ledger_config).unwrap_or_else(|e| fail("failed to open the ledger", e));
+    let store: Box<dyn ContentStore> =
+        open_store(&store_config, store_path).unwrap_or_else(|e| fail("failed to open the contentsys", e));
+    let service = Service::bind(db.as_str(), matches.value_of("listen").unwrap())
+        .unwrap_or_else(|e| fail("failed to listen", e));
+    if let Some(addr) = service.addr() {
+        println!("Listening on http://{}", addr);
+    }
+
+    // The ledger moves while the service answers, so answer on another
+    // thread. A failed ingest is reported and tried again.
+    thread::spawn(move || {
+        if let Err(e) = service.run() {
+            fail("failed to serve", e);
+        }
+    });
+    loop {
+        for dir in dirs.iter() {
+            if let Err(e) = ingest_metadata(&catalog, dir.as_str()) {
+                eprintln!("failed to ingest {}: {:?}", dir, e);
+            }
+        }
+        match ingest_ledger(&catalog, ledger.as_ref(), Some(store.as_ref())) {
+            Ok(ingested) => {
+                println!(
+                    "Ingested {} pegs, {} versions and {} forks",
+                    ingested.pegs, ingested.versions, ingested.forks
+                );
+                for addr in ingested.missing.iter() {
+                    println!("missing manifest: {}", addr);
+                }
+            }
+            Err(e) => eprintln!("failed to ingest the ledger: {:?}", e),
+        }
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
+
+/// Reports the error and exits, for what the service can't run without.
+fn fail<E: Debug>(what: &str, e: E) -> ! {
+    eprintln!("{}: {:?}", what, e);
+    std::process::exit(1);
+}
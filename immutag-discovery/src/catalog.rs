@@ -0,0 +1,497 @@
+/*
+The service's SQLite database: the filesystem roots it follows, the pegs and
+forks found on the ledger, the versions in the pegged manifests, and the
+aliases and tags of the immutag directories it pulls.
+*/
+use std::time::Duration;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+
+use immutag_filesys::manifest::Entry;
+
+use crate::err::DiscoveryError;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS root (addr TEXT PRIMARY KEY);
+CREATE TABLE IF NOT EXISTS peg (
+    txid TEXT PRIMARY KEY,
+    filesys_addr TEXT NOT NULL,
+    peg INTEGER NOT NULL,
+    manifest_addr TEXT NOT NULL,
+    height INTEGER,
+    fetched BOOLEAN NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS version (
+    file_addr TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    filesys_addr TEXT NOT NULL,
+    file_hash TEXT NOT NULL,
+    content_addr TEXT,
+    peg INTEGER NOT NULL,
+    PRIMARY KEY (file_addr, version)
+);
+CREATE INDEX IF NOT EXISTS idx_version_content_addr ON version(content_addr);
+CREATE TABLE IF NOT EXISTS fork (
+    txid TEXT NOT NULL,
+    file_addr TEXT NOT NULL,
+    branch_addr TEXT NOT NULL,
+    content_addr TEXT NOT NULL,
+    height INTEGER,
+    PRIMARY KEY (txid, branch_addr)
+);
+CREATE INDEX IF NOT EXISTS idx_fork_branch_addr ON fork(branch_addr);
+CREATE TABLE IF NOT EXISTS file (file_addr TEXT PRIMARY KEY, filesys_addr TEXT NOT NULL, alias TEXT);
+CREATE INDEX IF NOT EXISTS idx_file_alias ON file(alias);
+CREATE TABLE IF NOT EXISTS tag (file_addr TEXT NOT NULL, tag TEXT NOT NULL, PRIMARY KEY (file_addr, tag));
+CREATE INDEX IF NOT EXISTS idx_tag ON tag(tag);
+";
+
+/// What found files have to match. A ledger address is a file or branch
+/// address, and a content address can be any version's or fork's.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Search {
+    pub tags: Vec<String>,
+    pub alias: Option<String>,
+    pub content_addr: Option<String>,
+    pub addr: Option<String>,
+    /// The filesystem's address.
+    pub root: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PegEntry {
+    pub txid: String,
+    pub filesys_addr: String,
+    pub peg: u64,
+    pub manifest_addr: String,
+    /// `None` while it's unconfirmed.
+    pub height: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionEntry {
+    pub version: u64,
+    pub file_hash: String,
+    pub content_addr: Option<String>,
+    /// The first peg with the version.
+    pub peg: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForkEntry {
+    pub txid: String,
+    pub branch_addr: String,
+    pub content_addr: String,
+    pub height: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileEntry {
+    pub filesys_addr: String,
+    pub file_addr: String,
+    pub alias: Option<String>,
+    pub tags: Vec<String>,
+    pub versions: Vec<VersionEntry>,
+    pub forks: Vec<ForkEntry>,
+}
+
+impl PegEntry {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "txid": self.txid,
+            "filesys_addr": self.filesys_addr,
+            "peg": self.peg,
+            "manifest_addr": self.manifest_addr,
+            "height": self.height,
+        })
+    }
+}
+
+impl FileEntry {
+    pub fn to_json(&self) -> Value {
+        let versions: Vec<Value> = self
+            .versions
+            .iter()
+            .map(|v| {
+                json!({
+                    "version": v.version,
+                    "file_hash": v.file_hash,
+                    "content_addr": v.content_addr,
+                    "peg": v.peg,
+                })
+            })
+            .collect();
+        let forks: Vec<Value> = self
+            .forks
+            .iter()
+            .map(|f| {
+                json!({
+                    "txid": f.txid,
+                    "branch_addr": f.branch_addr,
+                    "content_addr": f.content_addr,
+                    "height": f.height,
+                })
+            })
+            .collect();
+
+        json!({
+            "filesys_addr": self.filesys_addr,
+            "file_addr": self.file_addr,
+            "alias": self.alias,
+            "tags": self.tags,
+            "versions": versions,
+            "forks": forks,
+        })
+    }
+}
+
+pub struct Catalog {
+    conn: Connection,
+}
+
+impl Catalog {
+    /// Opens the database, creating it if it doesn't exist. Each thread of
+    /// the service opens its own.
+    pub fn open<T: AsRef<str>>(db: T) -> Result<Catalog, DiscoveryError> {
+        let conn = Connection::open(db.as_ref())?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Catalog { conn })
+    }
+
+    /// Follows a filesystem on the ledger. Returns whether it's new.
+    pub fn follow<T: AsRef<str>>(&self, root: T) -> Result<bool, DiscoveryError> {
+        let added = self.conn.execute("INSERT OR IGNORE INTO root (addr) VALUES (?1)", params![root.as_ref()])?;
+
+        Ok(added > 0)
+    }
+
+    pub fn roots(&self) -> Result<Vec<String>, DiscoveryError> {
+        let mut stmt = self.conn.prepare("SELECT addr FROM root ORDER BY addr")?;
+        let roots = stmt.query_map(params![], |row| row.get(0))?;
+
+        Ok(roots.collect::<Result<Vec<String>, _>>()?)
+    }
+
+    /// Adds a peg, or updates its height. Returns whether it's new.
+    pub fn add_peg(&self, peg: &PegEntry) -> Result<bool, DiscoveryError> {
+        let height = peg.height.map(|h| h as i64);
+        let added = self.conn.execute(
+            "INSERT OR IGNORE INTO peg (txid, filesys_addr, peg, manifest_addr, height) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![peg.txid, peg.filesys_addr, peg.peg as i64, peg.manifest_addr, height],
+        )?;
+        self.conn.execute("UPDATE peg SET height = ?1 WHERE txid = ?2", params![height, peg.txid])?;
+
+        Ok(added > 0)
+    }
+
+    /// Pegs whose manifests haven't been fetched yet, oldest first.
+    pub fn unfetched(&self) -> Result<Vec<PegEntry>, DiscoveryError> {
+        self.pegs_where("fetched = 0", None)
+    }
+
+    /// Adds the versions of a peg's manifest, keeping the first peg each
+    /// version was in. Returns how many were new.
+    pub fn add_manifest(&self, peg: &PegEntry, entries: &[Entry]) -> Result<usize, DiscoveryError> {
+        let mut added = 0;
+        for entry in entries {
+            added += self.conn.execute(
+                "INSERT OR IGNORE INTO version (file_addr, version, filesys_addr, file_hash, content_addr, peg)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    entry.file_addr,
+                    entry.version as i64,
+                    peg.filesys_addr,
+                    entry.file_hash,
+                    entry.content_addr,
+                    peg.peg as i64
+                ],
+            )?;
+        }
+        self.conn.execute("UPDATE peg SET fetched = 1 WHERE txid = ?1", params![peg.txid])?;
+
+        Ok(added)
+    }
+
+    /// The pegs of a filesystem, or of every one, oldest first.
+    pub fn pegs(&self, root: Option<&str>) -> Result<Vec<PegEntry>, DiscoveryError> {
+        match root {
+            Some(root) => self.pegs_where("filesys_addr = ?1", Some(root)),
+            None => self.pegs_where("1 = 1", None),
+        }
+    }
+
+    /// The file addresses of a filesystem's pegged versions.
+    pub fn file_addrs<T: AsRef<str>>(&self, root: T) -> Result<Vec<String>, DiscoveryError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT file_addr FROM version WHERE filesys_addr = ?1 ORDER BY file_addr")?;
+        let addrs = stmt.query_map(params![root.as_ref()], |row| row.get(0))?;
+
+        Ok(addrs.collect::<Result<Vec<String>, _>>()?)
+    }
+
+    /// Adds a fork of `file_addr`, or updates its height. Returns whether
+    /// it's new.
+    pub fn add_fork<T: AsRef<str>>(&self, file_addr: T, fork: &ForkEntry) -> Result<bool, DiscoveryError> {
+        let height = fork.height.map(|h| h as i64);
+        let added = self.conn.execute(
+            "INSERT OR IGNORE INTO fork (txid, file_addr, branch_addr, content_addr, height) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![fork.txid, file_addr.as_ref(), fork.branch_addr, fork.content_addr, height],
+        )?;
+        self.conn.execute(
+            "UPDATE fork SET height = ?1 WHERE txid = ?2 AND branch_addr = ?3",
+            params![height, fork.txid, fork.branch_addr],
+        )?;
+
+        Ok(added > 0)
+    }
+
+    /// Replaces the alias and tags of a file.
+    pub fn set_metadata<T: AsRef<str>>(
+        &self,
+        filesys_addr: T,
+        file_addr: T,
+        alias: Option<&str>,
+        tags: &[String],
+    ) -> Result<(), DiscoveryError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO file (file_addr, filesys_addr, alias) VALUES (?1, ?2, ?3)",
+            params![file_addr.as_ref(), filesys_addr.as_ref(), alias],
+        )?;
+        self.conn.execute("DELETE FROM tag WHERE file_addr = ?1", params![file_addr.as_ref()])?;
+        for tag in tags {
+            self.conn.execute(
+                "INSERT OR IGNORE INTO tag (file_addr, tag) VALUES (?1, ?2)",
+                params![file_addr.as_ref(), tag],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Every file matching the search, by filesystem and file address.
+    pub fn search(&self, search: &Search) -> Result<Vec<FileEntry>, DiscoveryError> {
+        let mut sql = "WITH files AS (
+            SELECT file_addr, filesys_addr FROM version UNION SELECT file_addr, filesys_addr FROM file
+        ) SELECT file_addr, filesys_addr FROM files WHERE 1 = 1"
+            .to_string();
+        let mut values: Vec<String> = Vec::new();
+        for tag in search.tags.iter() {
+            sql.push_str(" AND file_addr IN (SELECT file_addr FROM tag WHERE tag = ?)");
+            values.push(tag.clone());
+        }
+        if let Some(alias) = search.alias.as_ref() {
+            sql.push_str(" AND file_addr IN (SELECT file_addr FROM file WHERE alias = ?)");
+            values.push(alias.clone());
+        }
+        if let Some(content_addr) = search.content_addr.as_ref() {
+            sql.push_str(
+                " AND file_addr IN (SELECT file_addr FROM version WHERE content_addr = ?
+                 UNION SELECT file_addr FROM fork WHERE content_addr = ?)",
+            );
+            values.extend(vec![content_addr.clone(); 2]);
+        }
+        if let Some(addr) = search.addr.as_ref() {
+            sql.push_str(" AND (file_addr = ? OR file_addr IN (SELECT file_addr FROM fork WHERE branch_addr = ?))");
+            values.extend(vec![addr.clone(); 2]);
+        }
+        if let Some(root) = search.root.as_ref() {
+            sql.push_str(" AND filesys_addr = ?");
+            values.push(root.clone());
+        }
+        sql.push_str(" ORDER BY filesys_addr, file_addr");
+
+        let mut found = Vec::new();
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let rows = stmt.query_map(&values, |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (file_addr, filesys_addr): (String, String) = row?;
+            found.push(self.file(file_addr, filesys_addr)?);
+        }
+
+        Ok(found)
+    }
+
+    fn file(&self, file_addr: String, filesys_addr: String) -> Result<FileEntry, DiscoveryError> {
+        let alias = self
+            .conn
+            .query_row("SELECT alias FROM file WHERE file_addr = ?1", params![file_addr], |row| row.get(0))
+            .optional()?
+            .unwrap_or(None);
+
+        let mut stmt = self.conn.prepare("SELECT tag FROM tag WHERE file_addr = ?1 ORDER BY tag")?;
+        let tags = stmt
+            .query_map(params![file_addr], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT version, file_hash, content_addr, peg FROM version WHERE file_addr = ?1 ORDER BY version",
+        )?;
+        let versions = stmt
+            .query_map(params![file_addr], |row| {
+                let (version, peg): (i64, i64) = (row.get(0)?, row.get(3)?);
+                Ok(VersionEntry {
+                    version: version as u64,
+                    file_hash: row.get(1)?,
+                    content_addr: row.get(2)?,
+                    peg: peg as u64,
+                })
+            })?
+            .collect::<Result<Vec<VersionEntry>, _>>()?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT txid, branch_addr, content_addr, height FROM fork WHERE file_addr = ?1 ORDER BY branch_addr",
+        )?;
+        let forks = stmt
+            .query_map(params![file_addr], |row| {
+                let height: Option<i64> = row.get(3)?;
+                Ok(ForkEntry {
+                    txid: row.get(0)?,
+                    branch_addr: row.get(1)?,
+                    content_addr: row.get(2)?,
+                    height: height.map(|h| h as u64),
+                })
+            })?
+            .collect::<Result<Vec<ForkEntry>, _>>()?;
+
+        Ok(FileEntry {
+            filesys_addr,
+            file_addr,
+            alias,
+            tags,
+            versions,
+            forks,
+        })
+    }
+
+    fn pegs_where(&self, condition: &str, value: Option<&str>) -> Result<Vec<PegEntry>, DiscoveryError> {
+        let sql = format!(
+            "SELECT txid, filesys_addr, peg, manifest_addr, height FROM peg WHERE {} ORDER BY filesys_addr, peg",
+            condition
+        );
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let values: Vec<&str> = value.into_iter().collect();
+        let pegs = stmt.query_map(&values, |row| {
+            let (peg, height): (i64, Option<i64>) = (row.get(2)?, row.get(4)?);
+            Ok(PegEntry {
+                txid: row.get(0)?,
+                filesys_addr: row.get(1)?,
+                peg: peg as u64,
+                manifest_addr: row.get(3)?,
+                height: height.map(|h| h as u64),
+            })
+        })?;
+
+        Ok(pegs.collect::<Result<Vec<PegEntry>, _>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: &str = "1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj";
+    const OTHER_ROOT: &str = "1BoatSLRHtKNngkdXEeobR76b53LETtpyT";
+    const MENU_ADDR: &str = "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9";
+    const PRICES_ADDR: &str = "13DfKEsGmgrdZ3zgvHzsmP58X9dNW1Vyyy";
+    const BRANCH_ADDR: &str = "1QKr8NEjbhe9uW3u1wGVWTDavr5n3HZTx9";
+
+    fn entry(file_addr: &str, version: usize) -> Entry {
+        Entry {
+            file_addr: file_addr.to_string(),
+            version,
+            file_hash: format!("{}{}", file_addr, version),
+            content_addr: Some(format!("Qm{}{}", file_addr, version)),
+        }
+    }
+
+    fn catalog() -> Catalog {
+        let catalog = Catalog::open(":memory:").unwrap();
+        let peg = PegEntry {
+            txid: "aa".to_string(),
+            filesys_addr: ROOT.to_string(),
+            peg: 0,
+            manifest_addr: "QmManifest".to_string(),
+            height: None,
+        };
+        catalog.follow(ROOT).unwrap();
+        catalog.add_peg(&peg).unwrap();
+        catalog.add_manifest(&peg, &[entry(MENU_ADDR, 0), entry(PRICES_ADDR, 0)]).unwrap();
+        let fork = ForkEntry {
+            txid: "bb".to_string(),
+            branch_addr: BRANCH_ADDR.to_string(),
+            content_addr: "QmBranch".to_string(),
+            height: Some(2),
+        };
+        catalog.add_fork(MENU_ADDR, &fork).unwrap();
+        catalog.set_metadata(ROOT, MENU_ADDR, Some("menu"), &["sales".to_string(), "summer".to_string()]).unwrap();
+        catalog.set_metadata(ROOT, PRICES_ADDR, None, &["sales".to_string()]).unwrap();
+        catalog.set_metadata(OTHER_ROOT, "1Ez69SnzzmePmZX3WpEzMKTrcBF2gpNQ55", None, &["sales".to_string()]).unwrap();
+
+        catalog
+    }
+
+    fn addrs(catalog: &Catalog, search: Search) -> Vec<String> {
+        catalog.search(&search).unwrap().into_iter().map(|f| f.file_addr).collect()
+    }
+
+    #[test]
+    fn search_by_tag() {
+        let catalog = catalog();
+        let tags = |tags: &[&str]| Search {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(addrs(&catalog, tags(&["summer"])), vec![MENU_ADDR]);
+        assert_eq!(addrs(&catalog, tags(&["sales", "summer"])), vec![MENU_ADDR]);
+        assert_eq!(addrs(&catalog, tags(&["sales"])).len(), 3);
+        assert!(addrs(&catalog, tags(&["winter"])).is_empty());
+
+        catalog.set_metadata(ROOT, MENU_ADDR, Some("menu"), &["winter".to_string()]).unwrap();
+        assert!(addrs(&catalog, tags(&["summer"])).is_empty());
+        assert_eq!(catalog.search(&tags(&["winter"])).unwrap()[0].tags, vec!["winter"]);
+    }
+
+    #[test]
+    fn search_by_addr() {
+        let catalog = catalog();
+        let addr = |addr: &str| Search {
+            addr: Some(addr.to_string()),
+            ..Default::default()
+        };
+
+        let found = catalog.search(&addr(MENU_ADDR)).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].alias.as_deref(), Some("menu"));
+        assert_eq!(found[0].versions[0].content_addr, entry(MENU_ADDR, 0).content_addr);
+        assert_eq!(found[0].forks[0].branch_addr, BRANCH_ADDR);
+        // A branch finds the file it forked from.
+        assert_eq!(addrs(&catalog, addr(BRANCH_ADDR)), vec![MENU_ADDR]);
+        assert!(addrs(&catalog, addr(OTHER_ROOT)).is_empty());
+    }
+
+    #[test]
+    fn search_by_root() {
+        let catalog = catalog();
+        let root = |root: &str| Search {
+            root: Some(root.to_string()),
+            ..Default::default()
+        };
+        let in_root = Search {
+            tags: vec!["sales".to_string()],
+            root: Some(OTHER_ROOT.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(addrs(&catalog, root(ROOT)), vec![PRICES_ADDR, MENU_ADDR]);
+        assert_eq!(addrs(&catalog, root(OTHER_ROOT)), vec!["1Ez69SnzzmePmZX3WpEzMKTrcBF2gpNQ55"]);
+        assert_eq!(addrs(&catalog, in_root), vec!["1Ez69SnzzmePmZX3WpEzMKTrcBF2gpNQ55"]);
+        assert!(addrs(&catalog, root(BRANCH_ADDR)).is_empty());
+        assert_eq!(catalog.pegs(Some(ROOT)).unwrap().len(), 1);
+        assert!(catalog.pegs(Some(OTHER_ROOT)).unwrap().is_empty());
+    }
+}
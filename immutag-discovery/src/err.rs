@@ -0,0 +1,98 @@
+pub use immutag::err::ImmutagError;
+pub use immutag_bitcoin::BitcoinError;
+pub use immutag_contentsys::ContentsysError;
+pub use immutag_filesys::FilesysError;
+pub use immutag_ledger::LedgerError;
+
+#[derive(Debug)]
+pub enum DiscoveryError {
+    IoError(std::io::Error),
+    SqliteError(rusqlite::Error),
+    ImmutagError(ImmutagError),
+    BitcoinError(BitcoinError),
+    ContentsysError(ContentsysError),
+    FilesysError(FilesysError),
+    LedgerError(LedgerError),
+    Error(Error),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    /// The service couldn't listen where it was told to.
+    Listen,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub details: String,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    pub fn new(msg: &str, kind: ErrorKind) -> Error {
+        Error {
+            details: msg.to_string(),
+            kind,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl From<std::io::Error> for DiscoveryError {
+    fn from(error: std::io::Error) -> Self {
+        DiscoveryError::IoError(error)
+    }
+}
+
+impl From<rusqlite::Error> for DiscoveryError {
+    fn from(error: rusqlite::Error) -> Self {
+        DiscoveryError::SqliteError(error)
+    }
+}
+
+impl From<ImmutagError> for DiscoveryError {
+    fn from(error: ImmutagError) -> Self {
+        DiscoveryError::ImmutagError(error)
+    }
+}
+
+impl From<BitcoinError> for DiscoveryError {
+    fn from(error: BitcoinError) -> Self {
+        DiscoveryError::BitcoinError(error)
+    }
+}
+
+impl From<ContentsysError> for DiscoveryError {
+    fn from(error: ContentsysError) -> Self {
+        DiscoveryError::ContentsysError(error)
+    }
+}
+
+impl From<FilesysError> for DiscoveryError {
+    fn from(error: FilesysError) -> Self {
+        DiscoveryError::FilesysError(error)
+    }
+}
+
+impl From<LedgerError> for DiscoveryError {
+    fn from(error: LedgerError) -> Self {
+        DiscoveryError::LedgerError(error)
+    }
+}
+
+impl From<Error> for DiscoveryError {
+    fn from(error: Error) -> Self {
+        DiscoveryError::Error(error)
+    }
+}
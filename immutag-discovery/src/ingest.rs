@@ -0,0 +1,256 @@
+/*
+Fills the catalog. Pegs are read from the history of each filesystem root
+the service follows, and their manifests fetched from a contentsys for the
+versions. Forks are read from the history of each file address in those
+manifests, and of each branch a fork makes. A record only counts if its
+parent address signed the transaction, so nobody can peg to a filesystem or
+fork a file that isn't theirs.
+
+Aliases and tags never go on the ledger, so they come from immutag
+directories the service has pulled, whose filesystems it then follows.
+*/
+use std::collections::BTreeSet;
+
+use immutag_bitcoin::opreturn::{Payload, Record};
+use immutag_contentsys::{Cid, ContentStore};
+use immutag_filesys::manifest;
+use immutag_ledger::{decode_tx, op_return_data, spends, Ledger};
+
+use crate::catalog::{Catalog, ForkEntry, PegEntry};
+use crate::err::{DiscoveryError, ImmutagError};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ingested {
+    /// New pegs and forks.
+    pub pegs: usize,
+    pub forks: usize,
+    /// New versions from the manifests fetched.
+    pub versions: usize,
+    /// Manifests the contentsys couldn't give, tried again next time.
+    pub missing: Vec<String>,
+}
+
+/// A record in a transaction its parent signed.
+struct Signed {
+    txid: String,
+    height: Option<u64>,
+    payload: Payload,
+}
+
+/// Reads the pegs and forks of the followed filesystems off the ledger.
+/// Without a store, manifests aren't fetched, and only the forks of files
+/// already known are found.
+pub fn ingest_ledger(
+    catalog: &Catalog,
+    ledger: &dyn Ledger,
+    store: Option<&dyn ContentStore>,
+) -> Result<Ingested, DiscoveryError> {
+    let mut ingested = Ingested::default();
+    for root in catalog.roots()? {
+        for signed in signed(ledger, root.as_str())? {
+            let version = match signed.payload.record {
+                Record::Peg { version } => version,
+                _ => continue,
+            };
+            let peg = PegEntry {
+                txid: signed.txid,
+                filesys_addr: root.clone(),
+                peg: version,
+                manifest_addr: Cid::from_bytes(&signed.payload.content_addr)?.to_string(),
+                height: signed.height,
+            };
+            if catalog.add_peg(&peg)? {
+                ingested.pegs += 1;
+            }
+        }
+    }
+
+    if let Some(store) = store {
+        for peg in catalog.unfetched()? {
+            match store.get(&peg.manifest_addr.parse::<Cid>()?) {
+                Ok(data) => {
+                    let entries = manifest::parse(String::from_utf8_lossy(&data))?;
+                    ingested.versions += catalog.add_manifest(&peg, &entries)?;
+                }
+                Err(_) => ingested.missing.push(peg.manifest_addr),
+            }
+        }
+    }
+
+    for root in catalog.roots()? {
+        let mut addrs: Vec<String> = catalog.file_addrs(root.as_str())?;
+        let mut seen: BTreeSet<String> = addrs.iter().cloned().collect();
+        while let Some(file_addr) = addrs.pop() {
+            for signed in signed(ledger, file_addr.as_str())? {
+                let branch_addr = match signed.payload.record {
+                    Record::Fork { branch_addr } => branch_addr,
+                    _ => continue,
+                };
+                let fork = ForkEntry {
+                    txid: signed.txid,
+                    branch_addr: branch_addr.clone(),
+                    content_addr: Cid::from_bytes(&signed.payload.content_addr)?.to_string(),
+                    height: signed.height,
+                };
+                if catalog.add_fork(file_addr.as_str(), &fork)? {
+                    ingested.forks += 1;
+                }
+                if seen.insert(branch_addr.clone()) {
+                    addrs.push(branch_addr);
+                }
+            }
+        }
+    }
+
+    Ok(ingested)
+}
+
+/// Copies the aliases and tags of the latest versions in the immutag
+/// directory at `path`, and follows its filesystems. Returns how many files
+/// it has.
+pub fn ingest_metadata<T: AsRef<str>>(catalog: &Catalog, path: T) -> Result<usize, DiscoveryError> {
+    for filesys_addr in immutag::files::get_filesystems(path.as_ref()).map_err(ImmutagError::from)? {
+        catalog.follow(filesys_addr)?;
+    }
+    let found = immutag::filesys::query(path.as_ref(), &Default::default())?;
+    for file in found.iter() {
+        catalog.set_metadata(file.filesys_addr.as_str(), file.file_addr.as_str(), file.alias.as_deref(), &file.tags)?;
+    }
+
+    Ok(found.len())
+}
+
+/// The immutag records of `addr`'s history whose parent is `addr`, in
+/// transactions it signed.
+fn signed(ledger: &dyn Ledger, addr: &str) -> Result<Vec<Signed>, DiscoveryError> {
+    let mut records = Vec::new();
+    for tx in ledger.history(addr)? {
+        let decoded = decode_tx(&tx.raw)?;
+        if !spends(&decoded, addr)? {
+            continue;
+        }
+        for data in op_return_data(&decoded) {
+            let payload = match Payload::decode(&data) {
+                Ok(payload) => payload,
+                Err(_) => continue,
+            };
+            if payload.parent.as_deref() == Some(addr) {
+                records.push(Signed {
+                    txid: tx.txid.clone(),
+                    height: tx.height,
+                    payload,
+                });
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use immutag::test_support::{FILESYS_ADDR, XPRIV};
+    use immutag_bitcoin::tx::{peg_tx, Utxo};
+    use immutag_contentsys::{content_addr, CidVersion};
+    use immutag_filesys::manifest::Entry;
+    use immutag_ledger::MockLedger;
+
+    const MENU_ADDR: &str = "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9";
+
+    fn payload(record: Record, parent: &str) -> Vec<u8> {
+        let payload = Payload {
+            record,
+            content_addr: content_addr(parent.as_bytes(), CidVersion::V0).to_bytes(),
+            parent: Some(parent.to_string()),
+        };
+
+        payload.encode().unwrap()
+    }
+
+    /// Broadcasts the record in a transaction the wallet's address at
+    /// `index` signs, and returns its txid.
+    fn signed_record(ledger: &MockLedger, index: u32, record: Record) -> String {
+        let addr = immutag_bitcoin::file_addr(XPRIV, index).unwrap();
+        let utxo = Utxo {
+            txid: ledger.fund(addr.as_str(), 10_000).unwrap(),
+            vout: 0,
+            satoshis: 10_000,
+            chain: 0,
+            index,
+        };
+        let data = payload(record, addr.as_str());
+        let raw = peg_tx(XPRIV, "bitcoinsv".parse().unwrap(), &[utxo], &data, index, 500).unwrap();
+
+        ledger.broadcast(&raw).unwrap()
+    }
+
+    fn catalog() -> Catalog {
+        let catalog = Catalog::open(":memory:").unwrap();
+        catalog.follow(FILESYS_ADDR).unwrap();
+
+        catalog
+    }
+
+    #[test]
+    fn ingest_signed_records() {
+        let catalog = catalog();
+        let ledger = MockLedger::new("bitcoinsv").unwrap();
+        let branch_addr = immutag_bitcoin::file_addr(XPRIV, 2).unwrap();
+
+        // Records sent to the parent's address by someone else.
+        ledger.send(FILESYS_ADDR, &payload(Record::Peg { version: 0 }, FILESYS_ADDR)).unwrap();
+        let fork = Record::Fork {
+            branch_addr: "1Ez69SnzzmePmZX3WpEzMKTrcBF2gpNQ55".to_string(),
+        };
+        ledger.send(MENU_ADDR, &payload(fork, MENU_ADDR)).unwrap();
+        let peg_txid = signed_record(&ledger, 0, Record::Peg { version: 0 });
+        let pegged = ingest_ledger(&catalog, &ledger, None).unwrap();
+        let pegs = catalog.pegs(None).unwrap();
+
+        let entry = Entry {
+            file_addr: MENU_ADDR.to_string(),
+            version: 0,
+            file_hash: "aa".to_string(),
+            content_addr: None,
+        };
+        catalog.add_manifest(&pegs[0], &[entry]).unwrap();
+        let fork_txid = signed_record(
+            &ledger,
+            1,
+            Record::Fork {
+                branch_addr: branch_addr.clone(),
+            },
+        );
+        let forked = ingest_ledger(&catalog, &ledger, None).unwrap();
+        let found = catalog.search(&Default::default()).unwrap();
+
+        assert_eq!((pegged.pegs, pegged.forks), (1, 0));
+        assert_eq!(pegs.len(), 1);
+        assert_eq!(pegs[0].txid, peg_txid);
+        assert_eq!((forked.pegs, forked.forks), (0, 1));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].forks.len(), 1);
+        assert_eq!(found[0].forks[0].txid, fork_txid);
+        assert_eq!(found[0].forks[0].branch_addr, branch_addr);
+    }
+
+    #[test]
+    fn reingest_block() {
+        let catalog = catalog();
+        let ledger = MockLedger::new("bitcoinsv").unwrap();
+
+        signed_record(&ledger, 0, Record::Peg { version: 0 });
+        let unconfirmed = ingest_ledger(&catalog, &ledger, None).unwrap();
+        let height = ledger.mine();
+        let confirmed = ingest_ledger(&catalog, &ledger, None).unwrap();
+        let again = ingest_ledger(&catalog, &ledger, None).unwrap();
+        let pegs = catalog.pegs(None).unwrap();
+
+        assert_eq!(unconfirmed.pegs, 1);
+        assert_eq!(confirmed, Ingested::default());
+        assert_eq!(again, Ingested::default());
+        assert_eq!(pegs.len(), 1);
+        assert_eq!(pegs[0].height, Some(height));
+    }
+}
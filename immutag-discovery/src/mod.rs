@@ -0,0 +1,137 @@
+/*
+A file discovery service: it keeps pulling the immutags of the filesystems
+it follows into a SQLite catalog, and answers queries of it over HTTP, so
+users don't have to go looking on the ledger themselves.
+*/
+extern crate immutag;
+extern crate immutag_bitcoin;
+extern crate immutag_contentsys;
+extern crate immutag_filesys;
+extern crate immutag_ledger;
+extern crate rusqlite;
+extern crate serde_json;
+extern crate tiny_http;
+
+pub mod catalog;
+pub mod err;
+pub mod ingest;
+pub mod server;
+
+pub use catalog::{Catalog, FileEntry, ForkEntry, PegEntry, Search, VersionEntry};
+pub use err::{DiscoveryError, ErrorKind};
+pub use ingest::{ingest_ledger, ingest_metadata, Ingested};
+pub use server::{respond, Service};
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use immutag::filesys::{branch, peg, Filesys};
+    use immutag::test_support::{broadcast, commit_version, setup, tag, FILESYS_ADDR};
+    use immutag_contentsys::LocalStore;
+    use immutag_filesys::{metadata, versionstore};
+    use immutag_ledger::MockLedger;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    const MENU_ADDR: &str = "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9";
+    const PRICES_ADDR: &str = "13DfKEsGmgrdZ3zgvHzsmP58X9dNW1Vyyy";
+
+    fn records(filesys: &Filesys, file_addr: &str) -> Vec<metadata::Record> {
+        let versionstore_path = immutag_filesys::path_versionstore(&filesys.path);
+        metadata::records(versionstore::path_metadata_file(versionstore_path, file_addr.to_string())).unwrap()
+    }
+
+    fn get(base: &str, url: &str) -> (u16, Value) {
+        let response = match ureq::get(format!("{}{}", base, url).as_str()).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("{:?}", e),
+        };
+        let status = response.status();
+
+        (status, serde_json::from_str(&response.into_string().unwrap()).unwrap())
+    }
+
+    fn addrs(body: &Value) -> Vec<&str> {
+        body["files"].as_array().unwrap().iter().map(|f| f["file_addr"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn discover_over_http() {
+        let path = "/tmp/immutag_discovery/";
+        let menu = "/tmp/immutag_discovery/menu";
+        let prices = "/tmp/immutag_discovery/prices";
+        let db = "/tmp/immutag_discovery/catalog.sqlite";
+        let mut fixture = setup(path);
+        let filesys = Filesys::open(path).unwrap();
+        let ledger = MockLedger::new("bitcoinsv").unwrap();
+
+        commit_version(path, menu, "lemonade", Some("menu"));
+        commit_version(path, prices, "$1", None);
+        tag(path, MENU_ADDR, "sales");
+        tag(path, MENU_ADDR, "summer");
+        tag(path, PRICES_ADDR, "sales");
+        peg(path).unwrap();
+        broadcast(&ledger, &filesys, 0);
+        ledger.mine();
+        let forked = branch(path, menu, 0).unwrap();
+        broadcast(&ledger, &filesys, 1);
+        let menu_content = records(&filesys, MENU_ADDR)[0].get(metadata::CONTENT_ADDR).unwrap().to_string();
+
+        let catalog = Catalog::open(db).unwrap();
+        let store = LocalStore::open(&filesys.path).unwrap();
+        let described = ingest_metadata(&catalog, path).unwrap();
+        let without_store = ingest_ledger(&catalog, &ledger, None).unwrap();
+        let ingested = ingest_ledger(&catalog, &ledger, Some(&store)).unwrap();
+        let again = ingest_ledger(&catalog, &ledger, Some(&store)).unwrap();
+
+        let service = Arc::new(Service::bind(db, "127.0.0.1:0").unwrap());
+        let base = format!("http://{}", service.addr().unwrap());
+        let running = service.clone();
+        let handle = std::thread::spawn(move || running.run().unwrap());
+        let sales = get(&base, "/files?tag=sales");
+        let both = get(&base, "/files?tag=sales&tag=summer");
+        let aliased = get(&base, "/files?alias=menu");
+        let content = get(&base, format!("/files?content_addr={}", menu_content).as_str());
+        let by_branch = get(&base, format!("/files?addr={}", forked.branch_addr).as_str());
+        let rooted = get(&base, format!("/files?root={}", FILESYS_ADDR).as_str());
+        let elsewhere = get(&base, "/files?root=1BoatSLRHtKNngkdXEeobR76b53LETtpyT");
+        let pegs = get(&base, "/pegs");
+        let roots = get(&base, "/roots");
+        let unknown = get(&base, "/files?color=red");
+        let missing = get(&base, "/nope");
+        service.stop();
+        handle.join().unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(described, 3);
+        assert_eq!((without_store.pegs, without_store.versions, without_store.forks), (1, 0, 0));
+        assert_eq!((ingested.pegs, ingested.versions, ingested.forks), (0, 2, 1));
+        assert!(ingested.missing.is_empty());
+        assert_eq!(again, Ingested::default());
+        // The branch is a file of its own, starting with the alias and tags
+        // of the version it forked from.
+        let branch_addr = forked.branch_addr.as_str();
+        assert_eq!(sales.0, 200);
+        assert_eq!(addrs(&sales.1), vec![PRICES_ADDR, branch_addr, MENU_ADDR]);
+        assert_eq!(addrs(&both.1), vec![branch_addr, MENU_ADDR]);
+        assert_eq!(addrs(&aliased.1), vec![branch_addr, MENU_ADDR]);
+        assert_eq!(addrs(&content.1), vec![MENU_ADDR]);
+        assert_eq!(addrs(&by_branch.1), vec![branch_addr, MENU_ADDR]);
+        assert_eq!(addrs(&rooted.1), vec![PRICES_ADDR, branch_addr, MENU_ADDR]);
+        assert!(addrs(&elsewhere.1).is_empty());
+        let menu_entry = &both.1["files"][1];
+        assert_eq!(menu_entry["alias"], "menu");
+        assert_eq!(menu_entry["tags"], serde_json::json!(["sales", "summer"]));
+        assert_eq!(menu_entry["versions"][0]["content_addr"], menu_content.as_str());
+        assert_eq!(menu_entry["versions"][0]["peg"], 0);
+        assert_eq!(menu_entry["forks"][0]["branch_addr"], branch_addr);
+        assert_eq!(menu_entry["forks"][0]["height"], Value::Null);
+        assert_eq!(pegs.1["pegs"][0]["filesys_addr"], FILESYS_ADDR);
+        assert_eq!(pegs.1["pegs"][0]["height"], 1);
+        assert_eq!(roots.1["roots"], serde_json::json!([FILESYS_ADDR]));
+        assert_eq!(unknown.0, 400);
+        assert_eq!(missing.0, 404);
+    }
+}
@@ -0,0 +1,116 @@
+/*
+The JSON HTTP API of the catalog. Everything is a GET:
+
+    /files?tag=&alias=&content_addr=&addr=&root=   matching files
+    /pegs?root=                                     pegs, oldest first
+    /roots                                          followed filesystems
+
+`tag` can be given more than once, and a file has to have them all.
+*/
+use std::net::SocketAddr;
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::catalog::{Catalog, Search};
+use crate::err::{DiscoveryError, Error, ErrorKind};
+
+pub struct Service {
+    db: String,
+    server: Server,
+}
+
+impl Service {
+    /// Listens on `addr`, like `127.0.0.1:8080`, for queries of the
+    /// catalog in `db`. Port 0 picks a free one.
+    pub fn bind<T: AsRef<str>>(db: T, addr: T) -> Result<Service, DiscoveryError> {
+        let server = Server::http(addr.as_ref()).map_err(|e| {
+            let msg = format!("can't listen on {}: {}", addr.as_ref(), e);
+            DiscoveryError::from(Error::new(&msg, ErrorKind::Listen))
+        })?;
+
+        Ok(Service {
+            db: db.as_ref().to_string(),
+            server,
+        })
+    }
+
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Answers queries until `stop` is called.
+    pub fn run(&self) -> Result<(), DiscoveryError> {
+        let catalog = Catalog::open(self.db.as_str())?;
+        let json = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("a valid header");
+        for request in self.server.incoming_requests() {
+            let (status, body) = match request.method() {
+                Method::Get => respond(&catalog, request.url()),
+                _ => (405, json!({ "error": "only GET is supported" })),
+            };
+            let response = Response::from_string(body.to_string())
+                .with_status_code(status)
+                .with_header(json.clone());
+            // A client that hung up isn't the service's problem.
+            let _ = request.respond(response);
+        }
+
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.server.unblock();
+    }
+}
+
+/// The status and body answering a request for `url`.
+pub fn respond(catalog: &Catalog, url: &str) -> (u16, Value) {
+    let (path, query) = match url.find('?') {
+        Some(pos) => (&url[..pos], &url[pos + 1..]),
+        None => (url, ""),
+    };
+    let params: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+
+    let answer = match path {
+        "/files" => search(&params).map(|search| {
+            catalog
+                .search(&search)
+                .map(|files| json!({ "files": files.iter().map(|f| f.to_json()).collect::<Vec<Value>>() }))
+        }),
+        "/pegs" => only(&params, &["root"]).map(|_| {
+            let root = params.iter().find(|(k, _)| k == "root").map(|(_, v)| v.as_str());
+            catalog
+                .pegs(root)
+                .map(|pegs| json!({ "pegs": pegs.iter().map(|p| p.to_json()).collect::<Vec<Value>>() }))
+        }),
+        "/roots" => only(&params, &[]).map(|_| catalog.roots().map(|roots| json!({ "roots": roots }))),
+        _ => return (404, json!({ "error": format!("there's nothing at {}", path) })),
+    };
+
+    match answer {
+        Ok(Ok(body)) => (200, body),
+        Ok(Err(e)) => (500, json!({ "error": format!("{:?}", e) })),
+        Err(msg) => (400, json!({ "error": msg })),
+    }
+}
+
+fn search(params: &[(String, String)]) -> Result<Search, String> {
+    only(params, &["tag", "alias", "content_addr", "addr", "root"])?;
+    let value = |key: &str| params.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+
+    Ok(Search {
+        tags: params.iter().filter(|(k, _)| k == "tag").map(|(_, v)| v.clone()).collect(),
+        alias: value("alias"),
+        content_addr: value("content_addr"),
+        addr: value("addr"),
+        root: value("root"),
+    })
+}
+
+/// Refuses parameters other than `allowed`.
+fn only(params: &[(String, String)], allowed: &[&str]) -> Result<(), String> {
+    match params.iter().find(|(k, _)| !allowed.contains(&k.as_str())) {
+        Some((k, _)) => Err(format!("unknown parameter {}", k)),
+        None => Ok(()),
+    }
+}
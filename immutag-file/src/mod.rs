@@ -96,6 +96,15 @@ pub fn immutag<T: AsRef<str>>(
     }
 }
 
+/// Bitcoin addresses of the filesystems in the Immutag file, in the order they were added.
+pub fn filesystems(doc: &Document) -> Vec<String> {
+    doc.as_table()
+        .iter()
+        .filter(|(key, item)| *key != "immutag" && item.is_table())
+        .map(|(key, _)| key.to_string())
+        .collect()
+}
+
 /// A crude way to find if an entry exits. Doesn't work for nested etnries.
 /// `path` paramaters is the path to the Immutag file.
 pub fn exists<T: AsRef<str>>(path: T, name: T) -> bool {
@@ -455,6 +464,20 @@ xpriv = "XPRIV"
 
         assert_eq!(new_toml.to_string(), expected);
     }
+
+    #[test]
+    fn filesystems_in_doc() {
+        let toml = r#"['immutag']
+version = "0.1.0"
+contentsys = "local"
+
+['1LrTstQYNZj8wCvBgipJqL9zghsofpsHEG']
+xpriv = "XPRIV"
+"#;
+        let doc = toml.parse::<Document>().expect("invalid doc");
+
+        assert_eq!(filesystems(&doc), vec!["1LrTstQYNZj8wCvBgipJqL9zghsofpsHEG"]);
+    }
 }
 
 #[cfg(test)]
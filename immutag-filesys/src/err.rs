@@ -0,0 +1,65 @@
+pub use git2::Error as GitError;
+
+#[derive(Debug)]
+pub enum FilesysError {
+    IoError(std::io::Error),
+    GitError(GitError),
+    Error(Error),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    InvalidVersionsFile,
+    InvalidRecfile,
+    InvalidManifest,
+    NotTracked,
+    NothingStaged,
+    NoRemote,
+    MergeConflict,
+    UncommittedChanges,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub details: String,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    pub fn new(msg: &str, kind: ErrorKind) -> Error {
+        Error {
+            details: msg.to_string(),
+            kind,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl From<std::io::Error> for FilesysError {
+    fn from(error: std::io::Error) -> Self {
+        FilesysError::IoError(error)
+    }
+}
+
+impl From<GitError> for FilesysError {
+    fn from(error: GitError) -> Self {
+        FilesysError::GitError(error)
+    }
+}
+
+impl From<Error> for FilesysError {
+    fn from(error: Error) -> Self {
+        FilesysError::Error(error)
+    }
+}
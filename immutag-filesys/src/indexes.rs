@@ -0,0 +1,118 @@
+/*
+The BIP-44 indexes files have taken, kept in the filesystem's metadata file.
+Each claim is a record of the index, the file address it derives and the
+path that took it. Collaborators each append their own, so after a merge the
+same index can be claimed for two different paths.
+*/
+use crate::err::FilesysError;
+use crate::metadata::{self, Descriptor, Record, FILE_ADDR, FILE_INDEX};
+
+/// The path of the file that took the index.
+pub const PATH: &str = "path";
+
+pub const DESCRIPTOR: Descriptor = Descriptor {
+    rec: "Claim",
+    mandatory: &[FILE_INDEX, FILE_ADDR, PATH],
+    name: "claims.rec",
+};
+
+/// An index claimed for more than one path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Collision {
+    pub file_index: u32,
+    pub file_addr: String,
+    pub paths: Vec<String>,
+}
+
+pub fn claims<T: AsRef<str>>(path: T) -> Result<Vec<Record>, FilesysError> {
+    metadata::records(path)
+}
+
+pub fn claim<T: AsRef<str>>(path: T, file_index: u32, file_addr: T, file_path: T) -> Result<(), FilesysError> {
+    let mut record = Record::new();
+    record.set(FILE_INDEX, file_index.to_string().as_str());
+    record.set(FILE_ADDR, file_addr.as_ref());
+    record.set(PATH, file_path.as_ref());
+
+    metadata::append_record_as(path, &DESCRIPTOR, record)
+}
+
+/// Drops the claims of `file_path` on the index.
+pub fn release<T: AsRef<str>>(path: T, file_index: u32, file_path: T) -> Result<(), FilesysError> {
+    let mut all = claims(path.as_ref())?;
+    all.retain(|r| !(index(r) == Some(file_index) && r.get(PATH) == Some(file_path.as_ref())));
+
+    metadata::write_records_as(path, &DESCRIPTOR, &all)
+}
+
+/// The index claimed for the file address, if it's been recorded.
+pub fn lookup_index<T: AsRef<str>>(path: T, file_addr: T) -> Result<Option<u32>, FilesysError> {
+    let found = claims(path)?
+        .iter()
+        .find(|r| r.get(FILE_ADDR) == Some(file_addr.as_ref()))
+        .and_then(index);
+
+    Ok(found)
+}
+
+pub fn index(claim: &Record) -> Option<u32> {
+    claim.get(FILE_INDEX).and_then(|i| i.parse().ok())
+}
+
+/// Indexes claimed for different paths, lowest first.
+pub fn collisions(claims: &[Record]) -> Vec<Collision> {
+    let mut collisions: Vec<Collision> = Vec::new();
+    for claim in claims {
+        let (file_index, file_path) = match (index(claim), claim.get(PATH)) {
+            (Some(file_index), Some(file_path)) => (file_index, file_path.to_string()),
+            _ => continue,
+        };
+        match collisions.iter_mut().find(|c| c.file_index == file_index) {
+            Some(collision) if !collision.paths.contains(&file_path) => collision.paths.push(file_path),
+            Some(_) => (),
+            None => collisions.push(Collision {
+                file_index,
+                file_addr: claim.get(FILE_ADDR).unwrap_or_default().to_string(),
+                paths: vec![file_path],
+            }),
+        }
+    }
+    collisions.retain(|c| c.paths.len() > 1);
+    collisions.sort_by_key(|c| c.file_index);
+
+    collisions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_collisions() {
+        let claims = metadata::parse(
+            "file_index: 1
+file_addr: 1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9
+path: /tmp/lemonade_stand.mp4
+
+file_index: 2
+file_addr: 13DfKEsGmgrdZ3zgvHzsmP58X9dNW1Vyyy
+path: /tmp/ledger.csv
+
+file_index: 1
+file_addr: 1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9
+path: /tmp/lemonade_stand.mp4
+
+file_index: 2
+file_addr: 13DfKEsGmgrdZ3zgvHzsmP58X9dNW1Vyyy
+path: /tmp/README.md
+",
+        )
+        .unwrap();
+
+        let collisions = collisions(&claims);
+
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].file_index, 2);
+        assert_eq!(collisions[0].paths, vec!["/tmp/ledger.csv".to_string(), "/tmp/README.md".to_string()]);
+    }
+}
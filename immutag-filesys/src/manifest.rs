@@ -0,0 +1,124 @@
+/*
+The version-store manifest: every version of every file, one line each, in a
+fixed order. It's what a peg commits to, each line a leaf of the Merkle tree.
+*/
+use crate::err::{Error, ErrorKind, FilesysError};
+use crate::metadata;
+use crate::versionstore;
+
+const HEADER: &str = "# file-addr version file-hash content-addr\n";
+/// Stands in for a version without a content address.
+const NONE: &str = "-";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub file_addr: String,
+    pub version: usize,
+    pub file_hash: String,
+    pub content_addr: Option<String>,
+}
+
+impl Entry {
+    /// The line of the manifest, and the data of its leaf.
+    pub fn line(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            self.file_addr,
+            self.version,
+            self.file_hash,
+            self.content_addr.as_deref().unwrap_or(NONE)
+        )
+    }
+}
+
+/// The manifest of the version-store, sorted by file address then version.
+pub fn entries<T: AsRef<str>>(versionstore_path: T) -> Result<Vec<Entry>, FilesysError> {
+    let mut entries = Vec::new();
+    for file_addr in versionstore::file_addrs(versionstore_path.as_ref())? {
+        let versions_file = versionstore::path_versions_file(versionstore_path.as_ref(), file_addr.as_str());
+        let metadata_file = versionstore::path_metadata_file(versionstore_path.as_ref(), file_addr.as_str());
+        let records = metadata::records(metadata_file)?;
+
+        for (version, v) in versionstore::versions(versions_file)?.into_iter().enumerate() {
+            let content_addr = records
+                .get(version)
+                .and_then(|r| r.get(metadata::CONTENT_ADDR))
+                .map(|a| a.to_string());
+            entries.push(Entry {
+                file_addr: file_addr.clone(),
+                version,
+                file_hash: v.file_hash,
+                content_addr,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+pub fn render(entries: &[Entry]) -> String {
+    let mut manifest = HEADER.to_string() + "\n";
+    for entry in entries {
+        manifest.push_str(&entry.line());
+        manifest.push('\n');
+    }
+
+    manifest
+}
+
+pub fn parse<T: AsRef<str>>(manifest: T) -> Result<Vec<Entry>, FilesysError> {
+    let mut entries = Vec::new();
+    for line in manifest.as_ref().lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let version = fields.get(1).and_then(|v| v.parse().ok());
+        match (fields.len(), version) {
+            (4, Some(version)) => entries.push(Entry {
+                file_addr: fields[0].to_string(),
+                version,
+                file_hash: fields[2].to_string(),
+                content_addr: Some(fields[3].to_string()).filter(|a| a != NONE),
+            }),
+            _ => {
+                let msg = format!("invalid manifest entry '{}'", line);
+                return Err(FilesysError::from(Error::new(&msg, ErrorKind::InvalidManifest)));
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let entries = vec![
+            Entry {
+                file_addr: "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9".to_string(),
+                version: 0,
+                file_hash: "aec070645fe53ee3b3763059376134f058cc337247c978add178b6ccdfb0019f".to_string(),
+                content_addr: Some("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL".to_string()),
+            },
+            Entry {
+                file_addr: "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9".to_string(),
+                version: 1,
+                file_hash: "a948904f2f0f479b8f8197694b30184b0d2ed1c1cd2a1ec0fb85d299a192a447".to_string(),
+                content_addr: None,
+            },
+        ];
+        let manifest = render(&entries);
+
+        assert!(manifest.ends_with(
+            "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9 1 a948904f2f0f479b8f8197694b30184b0d2ed1c1cd2a1ec0fb85d299a192a447 -\n"
+        ));
+        assert_eq!(parse(manifest).unwrap(), entries);
+        assert!(parse("1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9 first aec0 -").is_err());
+    }
+}
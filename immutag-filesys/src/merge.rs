@@ -0,0 +1,436 @@
+/*
+Three-way merges of the files collaborators both change, where git would
+only see two sides appending lines. A versions-file is an ordered set keyed
+by file hash: the versions both sides had, then ours, then theirs. A
+metadata-file follows its versions, and each record is merged field by
+field, with tags and branches merged as sets. A field both sides changed, like an
+alias, is a conflict: ours is kept and theirs is flagged as
+`conflict_<field>`, unless the resolution picks a side.
+*/
+use git2::Repository;
+use std::fs::write;
+use std::path::Path;
+
+use crate::err::FilesysError;
+use crate::indexes;
+use crate::metadata::{self, Record};
+use crate::pathcache;
+use crate::versionstore::{self, Version};
+
+/// Flags the other side's value of a conflicting field.
+pub const CONFLICT_PREFIX: &str = "conflict_";
+
+/// Fields about how a version was committed rather than what it is. When
+/// both sides commit the same bytes, ours are kept.
+const COMMITTED: [&str; 6] = [
+    metadata::VERSION_ADDR,
+    metadata::CONTENT_ADDR,
+    metadata::TIME,
+    metadata::AUTHOR,
+    metadata::SIGNATURE,
+    metadata::PUBLIC_KEY,
+];
+
+/// The fields of who signed a record.
+const SIGNED_BY: [&str; 2] = [metadata::SIGNATURE, metadata::PUBLIC_KEY];
+
+/// Routes the files immutag merges to its merge driver.
+pub const GITATTRIBUTES: &str = "/metadata merge=immutag
+/path-cache merge=immutag
+/version-store/*/versions-file merge=immutag
+/version-store/*/metadata-file merge=immutag
+";
+
+/// What to do with a field both sides changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+    Flag,
+    Ours,
+    Theirs,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Merged {
+    pub content: String,
+    /// Fields flagged for resolution, like `alias of version 2`.
+    pub conflicts: Vec<String>,
+}
+
+/// Where a merged entry comes from.
+struct Slot {
+    base: Option<usize>,
+    ours: Option<usize>,
+    theirs: Option<usize>,
+}
+
+/// Registers `immutag merge-driver` for plain git merges of the filesystem
+/// directory. Git keeps the driver in the local config, which a clone
+/// doesn't get, so each clone registers it again.
+pub fn register<T: AsRef<str>>(filesys_path: T) -> Result<(), FilesysError> {
+    let gitattributes = fixture::directorate(filesys_path.as_ref().to_string()) + ".gitattributes";
+    write(gitattributes, GITATTRIBUTES)?;
+
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let mut config = repo.config()?;
+    config.set_str("merge.immutag.name", "immutag versions and metadata")?;
+    config.set_str("merge.immutag.driver", "immutag merge-driver %O %A %B %P")?;
+
+    Ok(())
+}
+
+/// Merges one of the filesystem's files, by its path relative to the
+/// filesystem directory. `None` if it isn't one immutag knows how to merge.
+pub fn merge_file<T: AsRef<str>>(
+    file_path: T,
+    base: T,
+    ours: T,
+    theirs: T,
+    resolution: Resolution,
+) -> Result<Option<Merged>, FilesysError> {
+    let file_path = file_path.as_ref();
+    let (base, ours, theirs) = (base.as_ref(), ours.as_ref(), theirs.as_ref());
+
+    let merged = if file_path.ends_with("/versions-file") {
+        let merged = versions(
+            &versionstore::parse(base)?,
+            &versionstore::parse(ours)?,
+            &versionstore::parse(theirs)?,
+        );
+        Merged {
+            content: versionstore::render(&merged),
+            conflicts: Vec::new(),
+        }
+    } else if file_path.ends_with("/metadata-file") {
+        let (merged, conflicts) = records(
+            &metadata::parse(base)?,
+            &metadata::parse(ours)?,
+            &metadata::parse(theirs)?,
+            resolution,
+        );
+        Merged {
+            content: metadata::render(&merged),
+            conflicts,
+        }
+    } else if file_path == "metadata" {
+        let merged = set(&metadata::parse(base)?, &metadata::parse(ours)?, &metadata::parse(theirs)?);
+        Merged {
+            content: metadata::render_as(&indexes::DESCRIPTOR, &merged),
+            conflicts: Vec::new(),
+        }
+    } else if file_path == "path-cache" {
+        let merged = path_cache(&pathcache::parse(base), &pathcache::parse(ours), &pathcache::parse(theirs));
+        Merged {
+            content: pathcache::render(&merged),
+            conflicts: Vec::new(),
+        }
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(merged))
+}
+
+pub fn versions(base: &[Version], ours: &[Version], theirs: &[Version]) -> Vec<Version> {
+    align(base, ours, theirs, |v| v.file_hash.clone())
+        .iter()
+        .filter_map(|slot| match (slot.ours, slot.theirs) {
+            (Some(o), _) => Some(ours[o].clone()),
+            (None, Some(t)) => Some(theirs[t].clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The records of a metadata-file, lined up with the merged versions, and
+/// the conflicts flagged.
+pub fn records(base: &[Record], ours: &[Record], theirs: &[Record], resolution: Resolution) -> (Vec<Record>, Vec<String>) {
+    let key = |r: &Record| r.get(metadata::FILE_HASH).unwrap_or_default().to_string();
+    let mut merged = Vec::new();
+    let mut conflicts = Vec::new();
+    for slot in align(base, ours, theirs, key) {
+        let record = match (slot.ours, slot.theirs) {
+            (Some(o), Some(t)) => {
+                let (record, flagged) = fields(slot.base.map(|b| &base[b]), &ours[o], &theirs[t], resolution);
+                let version = merged.len();
+                conflicts.extend(flagged.iter().map(|k| format!("{} of version {}", k, version)));
+                record
+            }
+            (Some(o), None) => ours[o].clone(),
+            (None, Some(t)) => theirs[t].clone(),
+            (None, None) => continue,
+        };
+        merged.push(record);
+    }
+
+    (merged, conflicts)
+}
+
+/// Records as a set: what either side added, less what either removed.
+pub fn set(base: &[Record], ours: &[Record], theirs: &[Record]) -> Vec<Record> {
+    let mut merged: Vec<Record> = ours
+        .iter()
+        .filter(|r| theirs.contains(r) || !base.contains(r))
+        .cloned()
+        .collect();
+    for record in theirs {
+        if !merged.contains(record) && !base.contains(record) {
+            merged.push(record.clone());
+        }
+    }
+
+    merged
+}
+
+/// Path-cache entries keyed by address. Paths are local, so ours wins when
+/// both sides moved a file.
+pub fn path_cache(
+    base: &[(String, String)],
+    ours: &[(String, String)],
+    theirs: &[(String, String)],
+) -> Vec<(String, String)> {
+    let lookup = |entries: &[(String, String)], addr: &str| {
+        entries.iter().find(|(a, _)| a == addr).map(|(_, p)| p.clone())
+    };
+    let mut addrs: Vec<&String> = ours.iter().map(|(a, _)| a).collect();
+    for (addr, _) in theirs {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    addrs
+        .into_iter()
+        .filter_map(|addr| {
+            let (b, o, t) = (lookup(base, addr), lookup(ours, addr), lookup(theirs, addr));
+            let path = if o == b { t } else { o };
+            path.map(|p| (addr.clone(), p))
+        })
+        .collect()
+}
+
+/// The entries both sides had from the base, then ours, then theirs that
+/// ours doesn't have. Entries only get appended, so the base is a prefix.
+fn align<T, K: PartialEq>(base: &[T], ours: &[T], theirs: &[T], key: impl Fn(&T) -> K) -> Vec<Slot> {
+    let shared = base.len().min(ours.len()).min(theirs.len());
+    let mut slots: Vec<Slot> = (0..shared)
+        .map(|i| Slot {
+            base: Some(i),
+            ours: Some(i),
+            theirs: Some(i),
+        })
+        .collect();
+
+    let mut taken = vec![false; theirs.len()];
+    for (o, entry) in ours.iter().enumerate().skip(shared) {
+        let found = (shared..theirs.len()).find(|t| !taken[*t] && key(&theirs[*t]) == key(entry));
+        if let Some(t) = found {
+            taken[t] = true;
+        }
+        slots.push(Slot {
+            base: None,
+            ours: Some(o),
+            theirs: found,
+        });
+    }
+    for t in (shared..theirs.len()).filter(|t| !taken[*t]) {
+        slots.push(Slot {
+            base: None,
+            ours: None,
+            theirs: Some(t),
+        });
+    }
+
+    slots
+}
+
+/// Merges a record field by field, and returns the fields in conflict.
+fn fields(base: Option<&Record>, ours: &Record, theirs: &Record, resolution: Resolution) -> (Record, Vec<String>) {
+    let mut keys: Vec<&str> = ours.fields.iter().map(|(k, _)| k.as_str()).collect();
+    for (key, _) in theirs.fields.iter() {
+        if !keys.contains(&key.as_str()) {
+            keys.push(key);
+        }
+    }
+
+    let mut merged = Record::new();
+    let mut conflicts = Vec::new();
+    let mut contested = false;
+    for key in keys {
+        let b = base.and_then(|r| r.get(key));
+        let (o, t) = (ours.get(key), theirs.get(key));
+        let value = if key.starts_with(CONFLICT_PREFIX) {
+            o.or(t).map(|v| v.to_string())
+        } else if key.starts_with(metadata::TAG_PREFIX) {
+            // A tag either side added is kept, and one either removed is dropped.
+            if o == b { t } else { o }.map(|v| v.to_string())
+        } else if key == metadata::BRANCHES {
+            branches(b, o, t)
+        } else if o == t || t == b || (base.is_none() && COMMITTED.contains(&key)) {
+            o.map(|v| v.to_string())
+        } else if o == b {
+            t.map(|v| v.to_string())
+        } else if SIGNED_BY.contains(&key) {
+            // Follows the side the contested fields were taken from, below.
+            o.map(|v| v.to_string())
+        } else {
+            contested = true;
+            match resolution {
+                Resolution::Ours => o.map(|v| v.to_string()),
+                Resolution::Theirs => t.map(|v| v.to_string()),
+                Resolution::Flag => {
+                    let flag = format!("{}{}", CONFLICT_PREFIX, key);
+                    merged.set(flag.as_str(), t.unwrap_or_default());
+                    conflicts.push(key.to_string());
+                    o.map(|v| v.to_string())
+                }
+            }
+        };
+        if let Some(value) = value {
+            merged.set(key, value.as_str());
+        }
+    }
+    // A signature holds for one side's fields, so it's taken, or flagged,
+    // with them.
+    if contested {
+        let side = if resolution == Resolution::Theirs { theirs } else { ours };
+        for key in SIGNED_BY.iter() {
+            if let Some(value) = side.get(key) {
+                merged.set(*key, value);
+            }
+            match theirs.get(key) {
+                Some(t) if resolution == Resolution::Flag && merged.get(key) != Some(t) => {
+                    merged.set(format!("{}{}", CONFLICT_PREFIX, key).as_str(), t)
+                }
+                _ => (),
+            }
+        }
+    }
+    // Flags go after the fields they're about.
+    merged.fields.sort_by_key(|(k, _)| k.starts_with(CONFLICT_PREFIX));
+
+    (merged, conflicts)
+}
+
+/// Branch addresses as a set.
+fn branches(base: Option<&str>, ours: Option<&str>, theirs: Option<&str>) -> Option<String> {
+    let (base, ours, theirs) = (split(base), split(ours), split(theirs));
+    let mut merged: Vec<&str> = ours.iter().filter(|b| theirs.contains(b) || !base.contains(b)).cloned().collect();
+    for branch in theirs {
+        if !merged.contains(&branch) && !base.contains(&branch) {
+            merged.push(branch);
+        }
+    }
+
+    match merged.is_empty() {
+        true => None,
+        false => Some(merged.join(" ")),
+    }
+}
+
+fn split(branches: Option<&str>) -> Vec<&str> {
+    branches.map(|b| b.split_whitespace().collect()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(file_hash: &str) -> Version {
+        Version {
+            file_hash: file_hash.to_string(),
+            file_addr: "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9".to_string(),
+        }
+    }
+
+    fn record(fields: &[(&str, &str)]) -> Record {
+        let mut record = Record::new();
+        for (key, value) in fields {
+            record.set(*key, *value);
+        }
+
+        record
+    }
+
+    #[test]
+    fn merge_versions() {
+        let base = vec![version("aa")];
+        let ours = vec![version("aa"), version("bb"), version("cc")];
+        let theirs = vec![version("aa"), version("cc"), version("dd")];
+
+        let merged: Vec<String> = versions(&base, &ours, &theirs).into_iter().map(|v| v.file_hash).collect();
+
+        assert_eq!(merged, vec!["aa", "bb", "cc", "dd"]);
+    }
+
+    #[test]
+    fn merge_records() {
+        let base = vec![record(&[("file_hash", "aa"), ("alias", "stand"), ("tag_lemons", "1")])];
+        let ours = vec![
+            record(&[("file_hash", "aa"), ("alias", "stand"), ("tag_lemons", "1"), ("tag_sugar", "1")]),
+            record(&[("file_hash", "bb"), ("time", "1582934400 +0000")]),
+        ];
+        let theirs = vec![
+            record(&[("file_hash", "aa"), ("alias", "lemonade"), ("tag_lemons", "1"), ("tag_ice", "1")]),
+            record(&[("file_hash", "bb"), ("time", "1582934460 +0000")]),
+            record(&[("file_hash", "cc")]),
+        ];
+
+        let (merged, conflicts) = records(&base, &ours, &theirs, Resolution::Flag);
+
+        assert!(conflicts.is_empty());
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0].get("alias"), Some("lemonade"));
+        assert_eq!(merged[0].tags(), vec!["lemons", "sugar", "ice"]);
+        assert_eq!(merged[1].get("time"), Some("1582934400 +0000"));
+        assert_eq!(merged[2].get("file_hash"), Some("cc"));
+
+        let untagged = vec![record(&[("file_hash", "aa"), ("alias", "stand"), ("tag_ice", "1")])];
+        let (merged, _) = records(&base, &untagged, &theirs[..1], Resolution::Flag);
+        let (unchanged, _) = records(&base, &base, &untagged, Resolution::Flag);
+
+        assert_eq!(merged[0].tags(), vec!["ice"]);
+        assert_eq!(unchanged[0].tags(), vec!["ice"]);
+
+        let ours = vec![record(&[("file_hash", "aa"), ("alias", "stand, open")])];
+        let (flagged, conflicts) = records(&base, &ours, &theirs[..1], Resolution::Flag);
+        let (taken, _) = records(&base, &ours, &theirs[..1], Resolution::Theirs);
+
+        assert_eq!(conflicts, vec!["alias of version 0".to_string()]);
+        assert_eq!(flagged[0].get("alias"), Some("stand, open"));
+        assert_eq!(flagged[0].get("conflict_alias"), Some("lemonade"));
+        assert_eq!(taken[0].get("alias"), Some("lemonade"));
+        assert_eq!(taken[0].get("conflict_alias"), None);
+
+        let signed = |alias: &str, signature: &str| record(&[("file_hash", "aa"), ("alias", alias), ("signature", signature)]);
+        let base = vec![signed("stand", "H0")];
+        let (ours, theirs) = (vec![signed("stand, open", "H1")], vec![signed("lemonade", "H2")]);
+        let (flagged, conflicts) = records(&base, &ours, &theirs, Resolution::Flag);
+        let (taken, _) = records(&base, &ours, &theirs, Resolution::Theirs);
+        let (kept, _) = records(&base, &ours, &theirs, Resolution::Ours);
+
+        assert_eq!(conflicts, vec!["alias of version 0".to_string()]);
+        assert_eq!(flagged[0].get("signature"), Some("H1"));
+        assert_eq!(flagged[0].get("conflict_signature"), Some("H2"));
+        assert_eq!(taken[0], theirs[0]);
+        assert_eq!(kept[0], ours[0]);
+    }
+
+    #[test]
+    fn merge_sets() {
+        let claim = |i: &str| record(&[("file_index", i)]);
+        let base = vec![claim("1"), claim("2")];
+        let ours = vec![claim("1"), claim("3")];
+        let theirs = vec![claim("1"), claim("2"), claim("4")];
+
+        assert_eq!(set(&base, &ours, &theirs), vec![claim("1"), claim("3"), claim("4")]);
+        assert_eq!(branches(Some("a"), Some("a b"), Some("a c")), Some("a b c".to_string()));
+
+        let entry = |a: &str, p: &str| (a.to_string(), p.to_string());
+        let merged = path_cache(
+            &[entry("1a", "/a")],
+            &[entry("1a", "/a"), entry("1b", "/b")],
+            &[entry("1a", "/moved"), entry("1c", "/c")],
+        );
+        assert_eq!(merged, vec![entry("1a", "/moved"), entry("1b", "/b"), entry("1c", "/c")]);
+    }
+}
@@ -0,0 +1,308 @@
+/*
+Reads and writes the recfile metadata kept for each file version.
+*/
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+use crate::err::{Error, ErrorKind, FilesysError};
+
+pub const FILE_HASH: &str = "file_hash";
+pub const VERSION_ADDR: &str = "version_addr";
+pub const CONTENT_ADDR: &str = "content_addr";
+pub const FILE_TYPE: &str = "file_type";
+pub const FILE_ADDR: &str = "file_addr";
+pub const FILE_INDEX: &str = "file_index";
+pub const ALIAS: &str = "alias";
+pub const MESSAGE: &str = "metadata";
+pub const TAG_PREFIX: &str = "tag_";
+/// Branch number of a forked file, counting from 1.
+pub const BRANCH: &str = "branch";
+/// Space separated addresses of the branches forked from a version.
+pub const BRANCHES: &str = "branches";
+pub const PARENT_ADDR: &str = "parent_addr";
+pub const PARENT_VERSION: &str = "parent_version";
+/// When the version was committed, seconds since the epoch and a `+HHMM`
+/// offset, like git.
+pub const TIME: &str = "time";
+/// Who committed the version, `Name <email>`.
+pub const AUTHOR: &str = "author";
+/// DER signature of the record's canonical form, in hex.
+pub const SIGNATURE: &str = "signature";
+/// Compressed public key of the account that signed the record, in hex.
+pub const PUBLIC_KEY: &str = "public_key";
+
+/// Fields left out of the signed form. Addresses are derived from where the
+/// record sits, which merges and renumbering change, and branches are added
+/// as the version is forked.
+const UNSIGNED: [&str; 6] = [SIGNATURE, PUBLIC_KEY, VERSION_ADDR, FILE_ADDR, FILE_INDEX, BRANCHES];
+
+/// The record descriptor of a recfile, and the name its footer gives it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Descriptor {
+    pub rec: &'static str,
+    pub mandatory: &'static [&'static str],
+    pub name: &'static str,
+}
+
+/// Metadata-files, and the stage.
+pub const METADATA: Descriptor = Descriptor {
+    rec: "Metadata",
+    mandatory: &[FILE_HASH, VERSION_ADDR, CONTENT_ADDR, FILE_TYPE],
+    name: "metadata.rec",
+};
+
+/// A single record, fields kept in the order they were written.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    pub fields: Vec<(String, String)>,
+}
+
+impl Record {
+    pub fn new() -> Record {
+        Default::default()
+    }
+
+    pub fn get<T: AsRef<str>>(&self, key: T) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key.as_ref())
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Replaces the field if it exists, otherwise appends it.
+    pub fn set<T: AsRef<str>>(&mut self, key: T, value: T) {
+        if let Some(field) = self.fields.iter_mut().find(|(k, _)| k == key.as_ref()) {
+            field.1 = value.as_ref().to_string();
+        } else {
+            self.fields
+                .push((key.as_ref().to_string(), value.as_ref().to_string()));
+        }
+    }
+
+    pub fn remove<T: AsRef<str>>(&mut self, key: T) {
+        self.fields.retain(|(k, _)| k != key.as_ref());
+    }
+
+    /// Tags are stored as `tag_$name: 1`.
+    pub fn tags(&self) -> Vec<String> {
+        self.fields
+            .iter()
+            .filter(|(k, v)| k.starts_with(TAG_PREFIX) && v == "1")
+            .map(|(k, _)| k[TAG_PREFIX.len()..].to_string())
+            .collect()
+    }
+
+    pub fn add_tag<T: AsRef<str>>(&mut self, tag: T) {
+        self.set(format!("{}{}", TAG_PREFIX, tag.as_ref()), "1".to_string());
+    }
+
+    pub fn remove_tag<T: AsRef<str>>(&mut self, tag: T) {
+        self.remove(format!("{}{}", TAG_PREFIX, tag.as_ref()));
+    }
+}
+
+/// The form of a record that's signed: its signed fields sorted by name, one
+/// `name: value` line each. Fields a merge flagged aren't signed either.
+pub fn canonical(record: &Record) -> String {
+    let mut fields: Vec<&(String, String)> = record
+        .fields
+        .iter()
+        .filter(|(k, _)| !UNSIGNED.contains(&k.as_str()) && !k.starts_with(crate::merge::CONFLICT_PREFIX))
+        .collect();
+    fields.sort();
+
+    fields.iter().map(|(k, v)| format!("{}: {}\n", k, v)).collect()
+}
+
+/// Parses recfile records. Comments and record descriptors are skipped.
+pub fn parse<T: AsRef<str>>(recfile: T) -> Result<Vec<Record>, FilesysError> {
+    let mut records = Vec::new();
+    let mut record = Record::new();
+
+    for line in recfile.as_ref().lines() {
+        if line.trim().is_empty() {
+            if !record.fields.is_empty() {
+                records.push(record);
+                record = Record::new();
+            }
+        } else if line.starts_with('#') || line.starts_with('%') {
+            continue;
+        } else if let Some(continued) = line.strip_prefix('+') {
+            // Multi-line value.
+            if let Some(field) = record.fields.last_mut() {
+                field.1.push('\n');
+                field.1.push_str(continued.strip_prefix(' ').unwrap_or(continued));
+            } else {
+                let err = Error::new("continuation line without a field", ErrorKind::InvalidRecfile);
+                return Err(FilesysError::from(err));
+            }
+        } else if let Some(pos) = line.find(':') {
+            let key = line[..pos].trim().to_string();
+            let value = line[pos + 1..].trim().to_string();
+            record.fields.push((key, value));
+        } else {
+            let err = Error::new("invalid recfile field", ErrorKind::InvalidRecfile);
+            return Err(FilesysError::from(err));
+        }
+    }
+
+    if !record.fields.is_empty() {
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Renders the records as a metadata-file.
+pub fn render(records: &[Record]) -> String {
+    render_as(&METADATA, records)
+}
+
+/// Renders the records as a recfile of the descriptor.
+pub fn render_as(descriptor: &Descriptor, records: &[Record]) -> String {
+    let mut recfile = format!("# _*_ mode: rec _*_\n\n%rec: {}\n", descriptor.rec);
+    if !descriptor.mandatory.is_empty() {
+        recfile.push_str(&format!("%mandatory: {}\n", descriptor.mandatory.join(" ")));
+    }
+
+    for record in records {
+        recfile.push('\n');
+        for (key, value) in &record.fields {
+            let mut lines = value.split('\n');
+            recfile.push_str(&format!("{}: {}\n", key, lines.next().unwrap_or("")));
+            for line in lines {
+                recfile.push_str(&format!("+ {}\n", line));
+            }
+        }
+    }
+
+    recfile.push('\n');
+    recfile.push_str(&format!("# End of {}\n", descriptor.name));
+
+    recfile
+}
+
+/// All the records of a metadata-file, oldest first. A missing file has none.
+pub fn records<T: AsRef<str>>(path: T) -> Result<Vec<Record>, FilesysError> {
+    if !Path::new(path.as_ref()).exists() {
+        return Ok(Vec::new());
+    }
+
+    parse(read_to_string(path.as_ref())?)
+}
+
+pub fn write_records<T: AsRef<str>>(path: T, records: &[Record]) -> Result<(), FilesysError> {
+    write_records_as(path, &METADATA, records)
+}
+
+pub fn write_records_as<T: AsRef<str>>(path: T, descriptor: &Descriptor, records: &[Record]) -> Result<(), FilesysError> {
+    write(path.as_ref(), render_as(descriptor, records))?;
+
+    Ok(())
+}
+
+pub fn append_record<T: AsRef<str>>(path: T, record: Record) -> Result<(), FilesysError> {
+    append_record_as(path, &METADATA, record)
+}
+
+pub fn append_record_as<T: AsRef<str>>(path: T, descriptor: &Descriptor, record: Record) -> Result<(), FilesysError> {
+    let mut all = records(path.as_ref())?;
+    all.push(record);
+
+    write_records_as(path, descriptor, &all)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECFILE: &str = r#"# _*_ mode: rec _*_
+
+%rec: Metadata
+%mandatory: file_hash version_addr content_addr file_type
+
+file_hash: f909e48c4b5b8aeaf45cd6844994b37a0de5c52d43b36410c35d9dd8ae6f9afb
+version_addr: 37gsHDLSG5TJvApGfiUZDaDo9mSr6rjLv6
+content_addr: QmQPRexanRL6pnSPAzC696if49BviGaLNKvC3gp3ApPQmN
+alias: lemonade_stand
+file_type: mp4
+tag_video: 1
+tag_sales: 1
+
+file_hash: 1103def0e9d9036f59b7ef8524791710ed9a6e477b611abb94b0302edf887ee9
+version_addr: n2DoUfi8oUkTALKdd3AvVeTTyWg1AQmXCD
+content_addr: QmQPRexanRL6pnSPAzC696if49BviGaLNKvC3gp3ApPQmN
+file_type: mp4
+metadata: Our sales video
++ about the best lemonade, ever.
+tag_video: 1
+
+# End of metadata.rec
+"#;
+
+    #[test]
+    fn parse_recfile() {
+        let records = parse(RECFILE).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].get(ALIAS), Some("lemonade_stand"));
+        assert_eq!(records[0].tags(), vec!["video", "sales"]);
+        assert_eq!(records[1].get(MESSAGE), Some("Our sales video\nabout the best lemonade, ever."));
+        assert_eq!(records[1].get(ALIAS), None);
+    }
+
+    #[test]
+    fn render_round_trip() {
+        let records = parse(RECFILE).unwrap();
+        let rendered = render(&records);
+
+        assert_eq!(parse(&rendered).unwrap(), records);
+        assert!(rendered.starts_with("# _*_ mode: rec _*_"));
+        assert!(rendered.ends_with("# End of metadata.rec\n"));
+
+        let claims = Descriptor {
+            rec: "Claim",
+            mandatory: &[FILE_INDEX],
+            name: "claims.rec",
+        };
+        let rendered = render_as(&claims, &records);
+        assert_eq!(parse(&rendered).unwrap(), records);
+        assert!(rendered.contains("\n%rec: Claim\n%mandatory: file_index\n"));
+        assert!(!rendered.contains("Metadata"));
+        assert!(rendered.ends_with("# End of claims.rec\n"));
+    }
+
+    #[test]
+    fn edit_record() {
+        let mut record = Record::new();
+        record.set(ALIAS, "old");
+        record.set(ALIAS, "new");
+        record.add_tag("video");
+        record.add_tag("sales");
+        record.remove_tag("video");
+
+        assert_eq!(record.get(ALIAS), Some("new"));
+        assert_eq!(record.tags(), vec!["sales"]);
+    }
+
+    #[test]
+    fn canonical_form() {
+        let mut record = parse(RECFILE).unwrap().remove(0);
+        let signed = canonical(&record);
+        record.set(VERSION_ADDR, "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9");
+        record.set(SIGNATURE, "3044");
+        record.set("conflict_alias", "lemonade");
+
+        assert_eq!(
+            signed,
+            "alias: lemonade_stand
+content_addr: QmQPRexanRL6pnSPAzC696if49BviGaLNKvC3gp3ApPQmN
+file_hash: f909e48c4b5b8aeaf45cd6844994b37a0de5c52d43b36410c35d9dd8ae6f9afb
+file_type: mp4
+tag_sales: 1
+tag_video: 1
+"
+        );
+        assert_eq!(canonical(&record), signed);
+    }
+}
@@ -1,10 +1,30 @@
 use fixture::Fixture;
+use std::fs::write;
+
+pub mod err;
+pub mod indexes;
+pub mod manifest;
+pub mod merge;
+pub mod metadata;
+pub mod pathcache;
+pub mod pegs;
+pub mod pending;
+pub mod remote;
+pub mod stage;
+pub mod tardir;
+pub mod vcs;
+pub mod versionstore;
+
+pub use err::{ErrorKind, FilesysError};
 
 mod verify_immutag_file {
 
 }
 
-fn path_filesys<T: AsRef<str>>(immutag_file_path: T, bitcoin_addr: T) -> String {
+/// Content that stays local, like the blobs of the local content store.
+const GITIGNORE: &str = "objects/\n";
+
+pub fn path_filesys<T: AsRef<str>>(immutag_file_path: T, bitcoin_addr: T) -> String {
     let bitcoin_addr = fixture::directorate(bitcoin_addr.as_ref().to_string());
     let path = fixture::directorate(immutag_file_path.as_ref().to_string());
     let path = fixture::directorate(path + bitcoin_addr.as_ref());
@@ -12,20 +32,42 @@ fn path_filesys<T: AsRef<str>>(immutag_file_path: T, bitcoin_addr: T) -> String
     path
 }
 
-fn path_versionstore<T: AsRef<str>>(filesys_path: T) -> String {
+pub fn path_versionstore<T: AsRef<str>>(filesys_path: T) -> String {
     let path = fixture::directorate(filesys_path.as_ref().to_string());
     let path = fixture::directorate(filesys_path.as_ref().to_string() + "version-store");
 
     path
 }
 
-fn path_metadata<T: AsRef<str>>(filesys_path: T) -> String {
+pub fn path_metadata<T: AsRef<str>>(filesys_path: T) -> String {
     let path = fixture::filefy(filesys_path.as_ref().to_string());
     let path = fixture::filefy(filesys_path.as_ref().to_string() + "metadata");
 
     path
 }
 
+pub fn path_pathcache<T: AsRef<str>>(filesys_path: T) -> String {
+    fixture::filefy(fixture::directorate(filesys_path.as_ref().to_string()) + "path-cache")
+}
+
+pub fn path_stage<T: AsRef<str>>(filesys_path: T) -> String {
+    fixture::filefy(fixture::directorate(filesys_path.as_ref().to_string()) + "stage")
+}
+
+/// Records of ledger transactions that are waiting to be broadcast.
+pub fn path_pending<T: AsRef<str>>(filesys_path: T) -> String {
+    fixture::filefy(fixture::directorate(filesys_path.as_ref().to_string()) + "pending")
+}
+
+/// Records of the manifest pegs.
+pub fn path_pegs<T: AsRef<str>>(filesys_path: T) -> String {
+    fixture::filefy(fixture::directorate(filesys_path.as_ref().to_string()) + "pegs")
+}
+
+pub fn path_objects<T: AsRef<str>>(filesys_path: T) -> String {
+    fixture::directorate(fixture::directorate(filesys_path.as_ref().to_string()) + "objects")
+}
+
 fn mkdir_versionstore<T: AsRef<str>>(path: T) {
     let mut fixture = Fixture::new()
        .add_dirpath(path.as_ref().to_string())
@@ -43,8 +85,13 @@ fn mkdir_filesys<T: AsRef<str>>(filesys_path: T, versionstore_path: T, metadata_
        .add_dirpath(filesys_path.as_ref().to_string())
        .add_dirpath(versionstore_path.as_ref().to_string())
        .add_file(metadata_path.as_ref().to_string())
+       .add_file(path_pathcache(filesys_path.as_ref()))
        .add_git(filesys_path.as_ref().to_string())
        .build();
+
+    let gitignore = fixture::directorate(filesys_path.as_ref().to_string()) + ".gitignore";
+    write(gitignore, GITIGNORE).expect("failed to write .gitignore");
+    merge::register(filesys_path).expect("failed to register the merge driver");
 }
 
 
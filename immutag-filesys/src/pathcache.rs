@@ -0,0 +1,104 @@
+/*
+Maps addresses to the latest known local paths, one `addr: path` per line.
+Used for both a filesystem's path-cache and the global immutag-path-cache.
+*/
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+use crate::err::FilesysError;
+
+/// Entries in the order they were added.
+pub fn entries<T: AsRef<str>>(path: T) -> Result<Vec<(String, String)>, FilesysError> {
+    if !Path::new(path.as_ref()).exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(parse(read_to_string(path.as_ref())?))
+}
+
+pub fn parse<T: AsRef<str>>(cache: T) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    for line in cache.as_ref().lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(pos) = line.find(':') {
+            entries.push((line[..pos].trim().to_string(), line[pos + 1..].trim().to_string()));
+        }
+    }
+
+    entries
+}
+
+pub fn render(entries: &[(String, String)]) -> String {
+    let mut cache = String::new();
+    for (addr, file_path) in entries {
+        cache.push_str(&format!("{}: {}\n", addr, file_path));
+    }
+
+    cache
+}
+
+pub fn write_entries<T: AsRef<str>>(path: T, entries: &[(String, String)]) -> Result<(), FilesysError> {
+    write(path.as_ref(), render(entries))?;
+
+    Ok(())
+}
+
+pub fn lookup_addr<T: AsRef<str>>(path: T, file_path: T) -> Result<Option<String>, FilesysError> {
+    let found = entries(path)?
+        .into_iter()
+        .find(|(_, p)| p == file_path.as_ref())
+        .map(|(addr, _)| addr);
+
+    Ok(found)
+}
+
+pub fn lookup_path<T: AsRef<str>>(path: T, addr: T) -> Result<Option<String>, FilesysError> {
+    let found = entries(path)?
+        .into_iter()
+        .find(|(a, _)| a == addr.as_ref())
+        .map(|(_, file_path)| file_path);
+
+    Ok(found)
+}
+
+/// Points the address at a new path, adding the entry if needed.
+pub fn set<T: AsRef<str>>(path: T, addr: T, file_path: T) -> Result<(), FilesysError> {
+    let mut all = entries(path.as_ref())?;
+    if let Some(entry) = all.iter_mut().find(|(a, _)| a == addr.as_ref()) {
+        entry.1 = file_path.as_ref().to_string();
+    } else {
+        all.push((addr.as_ref().to_string(), file_path.as_ref().to_string()));
+    }
+
+    write_entries(path, &all)
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use fixture::Fixture;
+
+    #[test]
+    fn set_and_lookup() {
+        let dir = "/tmp/immutag_pathcache_test";
+        let cache = "/tmp/immutag_pathcache_test/path-cache";
+        let mut fixture = Fixture::new()
+            .add_dirpath(dir.to_string())
+            .build();
+
+        set(cache, "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9", "/old/path").unwrap();
+        set(cache, "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9", "/new/path").unwrap();
+        let addr = lookup_addr(cache, "/new/path").unwrap();
+        let old = lookup_addr(cache, "/old/path").unwrap();
+        let all = entries(cache).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(addr, Some("1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9".to_string()));
+        assert_eq!(old, None);
+        assert_eq!(all.len(), 1);
+    }
+}
@@ -0,0 +1,40 @@
+/*
+Pegs of the version-store manifest, oldest first. Like the pending ops, the
+file is a recfile of its own kind of record. A peg's number is its `peg`
+field, the one its transaction carries, so a recovered filesystem that's
+missing earlier pegs keeps the numbers of the later ones.
+*/
+use crate::err::FilesysError;
+use crate::metadata::{self, Descriptor, Record};
+
+pub const PEG: &str = "peg";
+/// Hex encoded Merkle root of the manifest.
+pub const ROOT: &str = "root";
+/// Content address of the manifest.
+pub const MANIFEST: &str = "manifest";
+/// The git commit the manifest was built from.
+pub const COMMIT: &str = "commit";
+/// Block height of the peg's transaction, `unconfirmed` until it's mined.
+pub const HEIGHT: &str = "height";
+pub const UNCONFIRMED: &str = "unconfirmed";
+/// The peg's transaction, once it's known.
+pub const TXID: &str = "txid";
+
+pub const DESCRIPTOR: Descriptor = Descriptor {
+    rec: "Peg",
+    mandatory: &[PEG, ROOT, MANIFEST],
+    name: "pegs.rec",
+};
+
+pub fn pegs<T: AsRef<str>>(path: T) -> Result<Vec<Record>, FilesysError> {
+    metadata::records(path)
+}
+
+/// The peg's number, or its position if the record has none.
+pub fn number(position: usize, record: &Record) -> usize {
+    record.get(PEG).and_then(|p| p.parse().ok()).unwrap_or(position)
+}
+
+pub fn add<T: AsRef<str>>(path: T, record: Record) -> Result<(), FilesysError> {
+    metadata::append_record_as(path, &DESCRIPTOR, record)
+}
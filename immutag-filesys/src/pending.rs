@@ -0,0 +1,30 @@
+/*
+Ledger records immutag would broadcast, kept until they are. The file is a
+recfile of its own kind of record.
+*/
+use crate::err::FilesysError;
+use crate::metadata::{self, Descriptor, Record};
+
+pub const OP: &str = "op";
+/// A file version was forked onto a branch.
+pub const OP_FORK: &str = "fork";
+/// The version-store manifest was pegged.
+pub const OP_PEG: &str = "peg";
+pub const VERSION: &str = "version";
+pub const BRANCH_ADDR: &str = "branch_addr";
+/// The hex encoded OP_RETURN data.
+pub const PAYLOAD: &str = "payload";
+
+pub const DESCRIPTOR: Descriptor = Descriptor {
+    rec: "Pending",
+    mandatory: &[OP, PAYLOAD],
+    name: "pending.rec",
+};
+
+pub fn pending<T: AsRef<str>>(path: T) -> Result<Vec<Record>, FilesysError> {
+    metadata::records(path)
+}
+
+pub fn add<T: AsRef<str>>(path: T, record: Record) -> Result<(), FilesysError> {
+    metadata::append_record_as(path, &DESCRIPTOR, record)
+}
@@ -0,0 +1,283 @@
+/*
+Git remotes of the filesystem directory, so collaborators can push and pull
+it. Anything libgit2 reaches works, like a bare repository on disk or a
+file:// URL.
+*/
+use git2::{build::CheckoutBuilder, IndexEntry, Repository, Status, StatusOptions};
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::err::{Error, ErrorKind, FilesysError};
+use crate::merge::{self, Resolution};
+use crate::vcs;
+
+/// The bits of an index entry's flags with its merge stage.
+const STAGE_MASK: u16 = 0x3000;
+
+/// What a pull did to the local branch.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Merge {
+    UpToDate,
+    FastForward,
+    /// A merge commit, with its id.
+    Merged(String),
+}
+
+/// Names and URLs of the remotes, sorted by name.
+pub fn remotes<T: AsRef<str>>(filesys_path: T) -> Result<Vec<(String, String)>, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let mut remotes = Vec::new();
+    for name in repo.remotes()?.iter().flatten() {
+        let url = repo.find_remote(name)?.url().unwrap_or_default().to_string();
+        remotes.push((name.to_string(), url));
+    }
+    remotes.sort();
+
+    Ok(remotes)
+}
+
+/// Adds a remote, and registers the merge driver for pulling from it.
+pub fn add<T: AsRef<str>>(filesys_path: T, name: T, url: T) -> Result<(), FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    repo.remote(name.as_ref(), url.as_ref())?;
+    merge::register(filesys_path)?;
+
+    Ok(())
+}
+
+pub fn remove<T: AsRef<str>>(filesys_path: T, name: T) -> Result<(), FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    find(&repo, name.as_ref())?;
+    repo.remote_delete(name.as_ref())?;
+
+    Ok(())
+}
+
+/// Pushes the current branch to the branch of the same name on the remote.
+pub fn push<T: AsRef<str>>(filesys_path: T, name: T) -> Result<(), FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let mut remote = find(&repo, name.as_ref())?;
+    let branch = branch(&repo)?;
+    let refspec = format!("{}:{}", branch, branch);
+    remote.push(&[refspec.as_str()], None)?;
+
+    Ok(())
+}
+
+/// Fetches the remote's branch of the same name as the current one, and
+/// returns the revision it's at, like `origin/master`. `None` if the remote
+/// doesn't have the branch.
+pub fn fetch<T: AsRef<str>>(filesys_path: T, name: T) -> Result<Option<String>, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let mut remote = find(&repo, name.as_ref())?;
+    let branch = branch(&repo)?;
+    let short = branch.trim_start_matches("refs/heads/");
+    let tracking = format!("refs/remotes/{}/{}", name.as_ref(), short);
+    let refspec = format!("+{}:{}", branch, tracking);
+    remote.fetch(&[refspec.as_str()], None, None)?;
+
+    let fetched = match repo.find_reference(&tracking) {
+        Ok(_) => Some(format!("{}/{}", name.as_ref(), short)),
+        Err(_) => None,
+    };
+
+    Ok(fetched)
+}
+
+/// Merges a revision into the current branch and checks it out. What git
+/// can't merge line by line is merged by `merge::merge_file`; conflicts in
+/// anything else are an error, and leave the branch as it was. The merge is
+/// of commits, so it's refused while anything is left uncommitted.
+pub fn merge<T: AsRef<str>>(filesys_path: T, rev: T, resolution: Resolution) -> Result<Merge, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let theirs = repo.revparse_single(rev.as_ref())?.peel_to_commit()?;
+    let annotated = repo.find_annotated_commit(theirs.id())?;
+    let (analysis, _) = repo.merge_analysis(&[&annotated])?;
+    if analysis.is_up_to_date() {
+        return Ok(Merge::UpToDate);
+    }
+
+    let changed = uncommitted(&repo)?;
+    if !changed.is_empty() {
+        let msg = format!("commit {} before merging {}", changed.join(" "), rev.as_ref());
+        return Err(FilesysError::from(Error::new(&msg, ErrorKind::UncommittedChanges)));
+    }
+
+    let branch = branch(&repo)?;
+    if analysis.is_unborn() || analysis.is_fast_forward() {
+        let message = format!("Fast-forward to {}", rev.as_ref());
+        repo.reference(&branch, theirs.id(), true, &message)?;
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+        return Ok(Merge::FastForward);
+    }
+
+    let ours = repo.head()?.peel_to_commit()?;
+    let mut index = repo.merge_commits(&ours, &theirs, None)?;
+    if index.has_conflicts() {
+        let mut conflicts = Vec::new();
+        for conflict in index.conflicts()? {
+            conflicts.push(conflict?);
+        }
+
+        let mut unmerged = Vec::new();
+        for conflict in conflicts {
+            let file_path = match conflict.our.as_ref().or(conflict.their.as_ref()) {
+                Some(entry) => String::from_utf8_lossy(&entry.path).to_string(),
+                None => continue,
+            };
+            let content = |entry: &Option<IndexEntry>| -> Result<String, FilesysError> {
+                match entry {
+                    Some(entry) => Ok(String::from_utf8_lossy(repo.find_blob(entry.id)?.content()).to_string()),
+                    None => Ok(String::new()),
+                }
+            };
+            let (base, ours, theirs) = (content(&conflict.ancestor)?, content(&conflict.our)?, content(&conflict.their)?);
+            match merge::merge_file(file_path.as_str(), base.as_str(), ours.as_str(), theirs.as_str(), resolution)? {
+                Some(merged) => {
+                    let mut entry = match conflict.our.or(conflict.their) {
+                        Some(entry) => entry,
+                        None => continue,
+                    };
+                    entry.id = repo.blob(merged.content.as_bytes())?;
+                    entry.file_size = merged.content.len() as u32;
+                    entry.flags &= !STAGE_MASK;
+                    index.remove_path(Path::new(&file_path))?;
+                    index.add(&entry)?;
+                }
+                None => unmerged.push(file_path),
+            }
+        }
+        if !unmerged.is_empty() {
+            let msg = format!("merging {} conflicts in {}", rev.as_ref(), unmerged.join(" "));
+            return Err(FilesysError::from(Error::new(&msg, ErrorKind::MergeConflict)));
+        }
+    }
+
+    let tree = repo.find_tree(index.write_tree_to(&repo)?)?;
+    let signature = vcs::signature(filesys_path.as_ref())?;
+    let message = format!("Merge {}", rev.as_ref());
+    let oid = repo.commit(Some("HEAD"), &signature, &signature, &message, &tree, &[&ours, &theirs])?;
+    repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+
+    Ok(Merge::Merged(oid.to_string()))
+}
+
+fn find<'r>(repo: &'r Repository, name: &str) -> Result<git2::Remote<'r>, FilesysError> {
+    match repo.find_remote(name) {
+        Ok(remote) => Ok(remote),
+        Err(_) => {
+            let msg = format!("there's no remote {}", name);
+            Err(FilesysError::from(Error::new(&msg, ErrorKind::NoRemote)))
+        }
+    }
+}
+
+/// Paths with changes a commit would pick up, untracked ones included. An
+/// untracked file as `add_filesys` writes it, before the first pull, isn't
+/// one: there's nothing in it to lose.
+fn uncommitted(repo: &Repository) -> Result<Vec<String>, FilesysError> {
+    let workdir = repo.workdir().unwrap_or_else(|| Path::new(""));
+    let skeleton = |path: &str| match read_to_string(workdir.join(path)) {
+        Ok(content) => match path {
+            ".gitignore" => content == crate::GITIGNORE,
+            ".gitattributes" => content == merge::GITATTRIBUTES,
+            _ => content.is_empty(),
+        },
+        Err(_) => false,
+    };
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true).include_ignored(false);
+    let paths = repo
+        .statuses(Some(&mut options))?
+        .iter()
+        .filter_map(|status| match status.path() {
+            Some(path) if !(status.status() == Status::WT_NEW && skeleton(path)) => Some(path.to_string()),
+            _ => None,
+        })
+        .collect();
+
+    Ok(paths)
+}
+
+/// The current branch, like `refs/heads/master`, even before its first commit.
+fn branch(repo: &Repository) -> Result<String, FilesysError> {
+    let head = repo.find_reference("HEAD")?;
+    let branch = head.symbolic_target().unwrap_or("refs/heads/master").to_string();
+
+    Ok(branch)
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use fixture::Fixture;
+    use std::fs::{remove_file, write};
+
+    #[test]
+    fn push_and_pull() {
+        let ours = "/tmp/immutag_remote_ours";
+        let theirs = "/tmp/immutag_remote_theirs";
+        let bare = "/tmp/immutag_remote_bare.git";
+        let mut fixture = Fixture::new()
+            .add_dirpath(ours.to_string())
+            .add_dirpath(theirs.to_string())
+            .add_dirpath(bare.to_string())
+            .add_git(ours.to_string())
+            .add_git(theirs.to_string())
+            .build();
+        Repository::init_bare(bare).unwrap();
+        let url = format!("file://{}", bare);
+
+        write(format!("{}/tracked", ours), "one").unwrap();
+        vcs::commit(ours, "first").unwrap();
+        add(ours, "origin", url.as_str()).unwrap();
+        push(ours, "origin").unwrap();
+
+        add(theirs, "origin", bare).unwrap();
+        let fetched = fetch(theirs, "origin").unwrap();
+        write(format!("{}/staged", theirs), "uncommitted").unwrap();
+        let dirty = merge(theirs, "origin/master", Resolution::Flag);
+        let kept = read_to_string(format!("{}/staged", theirs)).unwrap();
+        remove_file(format!("{}/staged", theirs)).unwrap();
+        let fast_forward = merge(theirs, "origin/master", Resolution::Flag).unwrap();
+        let again = merge(theirs, "origin/master", Resolution::Flag).unwrap();
+        let pulled = read_to_string(format!("{}/tracked", theirs)).unwrap();
+
+        write(format!("{}/other", theirs), "theirs").unwrap();
+        vcs::commit(theirs, "theirs").unwrap();
+        push(theirs, "origin").unwrap();
+        write(format!("{}/tracked", ours), "two").unwrap();
+        vcs::commit(ours, "second").unwrap();
+        fetch(ours, "origin").unwrap();
+        let merged = merge(ours, "origin/master", Resolution::Flag).unwrap();
+        let other = read_to_string(format!("{}/other", ours)).unwrap();
+
+        write(format!("{}/other", ours), "ours").unwrap();
+        vcs::commit(ours, "ours").unwrap();
+        push(ours, "origin").unwrap();
+        write(format!("{}/other", theirs), "conflicting").unwrap();
+        vcs::commit(theirs, "conflicting").unwrap();
+        fetch(theirs, "origin").unwrap();
+        let conflict = merge(theirs, "origin/master", Resolution::Flag);
+
+        let listed = remotes(ours).unwrap();
+        remove(ours, "origin").unwrap();
+        let removed = remotes(ours).unwrap();
+        let missing = push(ours, "origin");
+
+        fixture.teardown(true);
+
+        assert_eq!(fetched, Some("origin/master".to_string()));
+        assert!(matches!(dirty, Err(FilesysError::Error(Error { kind: ErrorKind::UncommittedChanges, .. }))));
+        assert_eq!(kept, "uncommitted");
+        assert_eq!(fast_forward, Merge::FastForward);
+        assert_eq!(again, Merge::UpToDate);
+        assert_eq!(pulled, "one");
+        assert!(matches!(merged, Merge::Merged(_)));
+        assert_eq!(other, "theirs");
+        assert!(matches!(conflict, Err(FilesysError::Error(Error { kind: ErrorKind::MergeConflict, .. }))));
+        assert_eq!(listed, vec![("origin".to_string(), url)]);
+        assert!(removed.is_empty());
+        assert!(matches!(missing, Err(FilesysError::Error(Error { kind: ErrorKind::NoRemote, .. }))));
+    }
+}
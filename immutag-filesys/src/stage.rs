@@ -0,0 +1,29 @@
+/*
+Staged file versions, waiting on a commit. Each is the metadata record the
+version will get, so the stage is itself a recfile.
+*/
+use crate::err::FilesysError;
+use crate::metadata::{self, Record, FILE_ADDR};
+
+pub fn staged<T: AsRef<str>>(path: T) -> Result<Vec<Record>, FilesysError> {
+    metadata::records(path)
+}
+
+/// Stages the record, replacing anything already staged for the same file.
+pub fn stage<T: AsRef<str>>(path: T, record: Record) -> Result<(), FilesysError> {
+    let mut all = staged(path.as_ref())?;
+    all.retain(|r| r.get(FILE_ADDR) != record.get(FILE_ADDR));
+    all.push(record);
+
+    metadata::write_records(path, &all)
+}
+
+/// Removes and returns whatever is staged for the file.
+pub fn unstage<T: AsRef<str>>(path: T, file_addr: T) -> Result<Option<Record>, FilesysError> {
+    let mut all = staged(path.as_ref())?;
+    let pos = all.iter().position(|r| r.get(FILE_ADDR) == Some(file_addr.as_ref()));
+    let record = pos.map(|pos| all.remove(pos));
+    metadata::write_records(path, &all)?;
+
+    Ok(record)
+}
@@ -0,0 +1,134 @@
+/*
+Directories, like git projects, are tracked as a single file: a tar of the
+tree that's the same for the same tree. Entries are sorted, and owners and
+times are left out, so only names, contents, symlinks and whether a file is
+executable make it into the tar.
+*/
+use std::fs::{create_dir_all, read, read_dir, read_link, symlink_metadata};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use tar::{Archive, Builder, EntryType, Header};
+
+use crate::err::FilesysError;
+
+/// The `file_type` of a packed directory.
+pub const TAR_DIR: &str = "tar-dir";
+
+/// Tars the tree under `dir`, with paths relative to it.
+pub fn pack<T: AsRef<Path>>(dir: T) -> Result<Vec<u8>, FilesysError> {
+    let dir = dir.as_ref();
+    let mut paths = Vec::new();
+    walk(dir, Path::new(""), &mut paths)?;
+    paths.sort();
+
+    let mut builder = Builder::new(Vec::new());
+    for path in paths {
+        let full = dir.join(&path);
+        let metadata = symlink_metadata(&full)?;
+        let mut header = Header::new_gnu();
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_size(0);
+
+        if metadata.file_type().is_symlink() {
+            header.set_entry_type(EntryType::Symlink);
+            header.set_mode(0o777);
+            builder.append_link(&mut header, &path, read_link(&full)?)?;
+        } else if metadata.is_dir() {
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(0o755);
+            builder.append_data(&mut header, &path, io::empty())?;
+        } else {
+            let data = read(&full)?;
+            let executable = metadata.permissions().mode() & 0o111 != 0;
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(if executable { 0o755 } else { 0o644 });
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, &path, &data[..])?;
+        }
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+/// Unpacks a tar made by `pack` into `dir`, which is created if it has to be.
+pub fn unpack<T: AsRef<Path>>(data: &[u8], dir: T) -> Result<(), FilesysError> {
+    create_dir_all(dir.as_ref())?;
+    let mut archive = Archive::new(data);
+    archive.set_preserve_mtime(false);
+    archive.unpack(dir.as_ref())?;
+
+    Ok(())
+}
+
+/// Every path under `dir`, relative to the root it was called with. Symlinks
+/// to directories aren't followed.
+fn walk(root: &Path, dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), FilesysError> {
+    for entry in read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+        paths.push(path.clone());
+        if is_dir {
+            walk(root, &path, paths)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixture::Fixture;
+    use std::fs::{read_to_string, set_permissions, write, Permissions};
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn pack_and_unpack() {
+        let one = "/tmp/immutag_tardir_one";
+        let two = "/tmp/immutag_tardir_two";
+        let unpacked = "/tmp/immutag_tardir_unpacked";
+        let mut fixture = Fixture::new()
+            .add_dirpath(one.to_string())
+            .add_dirpath(two.to_string())
+            .add_dirpath(unpacked.to_string())
+            .build();
+        create_dir_all(format!("{}/src/empty", one)).unwrap();
+        create_dir_all(format!("{}/src/empty", two)).unwrap();
+
+        // The same tree, made in another order at other times.
+        write(format!("{}/README.md", one), "# Lemonade stand").unwrap();
+        write(format!("{}/src/main.rs", one), "fn main() {}").unwrap();
+        write(format!("{}/run.sh", one), "cargo run").unwrap();
+        set_permissions(format!("{}/run.sh", one), Permissions::from_mode(0o700)).unwrap();
+        symlink("README.md", format!("{}/readme", one)).unwrap();
+        write(format!("{}/run.sh", two), "cargo run").unwrap();
+        set_permissions(format!("{}/run.sh", two), Permissions::from_mode(0o755)).unwrap();
+        symlink("README.md", format!("{}/readme", two)).unwrap();
+        write(format!("{}/src/main.rs", two), "fn main() {}").unwrap();
+        write(format!("{}/README.md", two), "# Lemonade stand").unwrap();
+
+        let packed = pack(one).unwrap();
+        let repacked = pack(two).unwrap();
+        write(format!("{}/src/main.rs", two), "fn main() { println!(); }").unwrap();
+        let changed = pack(two).unwrap();
+        unpack(&packed, unpacked).unwrap();
+        let readme = read_to_string(format!("{}/readme", unpacked)).unwrap();
+        let mode = symlink_metadata(format!("{}/run.sh", unpacked)).unwrap().permissions().mode();
+        let empty = Path::new(&format!("{}/src/empty", unpacked)).is_dir();
+        let unpacked_again = pack(unpacked).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(packed, repacked);
+        assert_ne!(packed, changed);
+        assert_eq!(readme, "# Lemonade stand");
+        assert_eq!(mode & 0o111, 0o111);
+        assert!(empty);
+        assert_eq!(unpacked_again, packed);
+    }
+}
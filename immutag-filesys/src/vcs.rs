@@ -0,0 +1,323 @@
+/*
+The filesystem directory is under automatic version control, with git.
+*/
+use git2::{IndexAddOption, Oid, Repository};
+pub use git2::{Signature, Time};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::err::FilesysError;
+
+/// Commits everything in the filesystem directory and returns the commit id.
+pub fn commit<T: AsRef<str>>(filesys_path: T, message: T) -> Result<String, FilesysError> {
+    let signature = signature(filesys_path.as_ref())?;
+
+    commit_by(filesys_path.as_ref(), message.as_ref(), &signature)
+}
+
+/// Like `commit`, but by `signature`, at its time.
+pub fn commit_by<T: AsRef<str>>(filesys_path: T, message: T, signature: &Signature) -> Result<String, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+
+    commit_as(&repo, signature, message.as_ref())
+}
+
+/// The user git is configured with, or immutag, as of now.
+pub fn signature<T: AsRef<str>>(filesys_path: T) -> Result<Signature<'static>, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let signature = match repo.signature() {
+        Ok(signature) => signature,
+        Err(_) => Signature::now("immutag", "immutag@localhost")?,
+    };
+
+    Ok(signature)
+}
+
+/// The signature of an author the metadata records as `Name <email>`, or
+/// immutag if it doesn't say.
+pub fn author_signature(author: Option<&str>, time: &Time) -> Result<Signature<'static>, FilesysError> {
+    let signature = match author.and_then(|a| a.split_once(" <")) {
+        Some((name, email)) => Signature::new(name, email.trim_end_matches('>'), time)?,
+        None => Signature::new("immutag", "immutag@localhost", time)?,
+    };
+
+    Ok(signature)
+}
+
+/// A time as the metadata records it, `1582934400 +0100`.
+pub fn format_time(time: &Time) -> String {
+    let offset = time.offset_minutes();
+    let sign = if offset < 0 { '-' } else { '+' };
+
+    format!("{} {}{:02}{:02}", time.seconds(), sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+pub fn parse_time<T: AsRef<str>>(time: T) -> Option<Time> {
+    let mut fields = time.as_ref().split_whitespace();
+    let seconds = fields.next()?.parse().ok()?;
+    let offset = match fields.next() {
+        Some(offset) if offset.len() == 5 => {
+            let minutes = offset[1..3].parse::<i32>().ok()? * 60 + offset[3..].parse::<i32>().ok()?;
+            match &offset[..1] {
+                "+" => minutes,
+                "-" => -minutes,
+                _ => return None,
+            }
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+
+    Some(Time::new(seconds, offset))
+}
+
+/// Starts a repository with no history in the filesystem directory.
+pub fn init<T: AsRef<str>>(filesys_path: T) -> Result<(), FilesysError> {
+    Repository::init(Path::new(filesys_path.as_ref()))?;
+
+    Ok(())
+}
+
+pub fn commit_as(repo: &Repository, signature: &Signature, message: &str) -> Result<String, FilesysError> {
+    let mut index = repo.index()?;
+    index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"].iter(), None)?;
+    index.write()?;
+    let tree_id = index.write_tree()?;
+    let tree = repo.find_tree(tree_id)?;
+
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(_) => None,
+    };
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+
+    let oid = repo.commit(Some("HEAD"), signature, signature, message, &tree, &parents)?;
+
+    Ok(oid.to_string())
+}
+
+/// Commits a tree of nothing but `files`, paths relative to the repository,
+/// without touching the working directory or the index.
+pub fn commit_files<T: AsRef<str>>(
+    filesys_path: T,
+    signature: &Signature,
+    message: &str,
+    files: &[(String, Vec<u8>)],
+) -> Result<String, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let entries: Vec<(&str, &[u8])> = files.iter().map(|(p, data)| (p.as_str(), data.as_slice())).collect();
+    let tree = repo.find_tree(write_tree(&repo, &entries)?)?;
+
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(_) => None,
+    };
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+
+    let oid = repo.commit(Some("HEAD"), signature, signature, message, &tree, &parents)?;
+
+    Ok(oid.to_string())
+}
+
+fn write_tree(repo: &Repository, files: &[(&str, &[u8])]) -> Result<Oid, FilesysError> {
+    let mut dirs: BTreeMap<&str, Vec<(&str, &[u8])>> = BTreeMap::new();
+    let mut builder = repo.treebuilder(None)?;
+    for (file_path, data) in files {
+        match file_path.find('/') {
+            Some(pos) => dirs.entry(&file_path[..pos]).or_default().push((&file_path[pos + 1..], data)),
+            None => {
+                builder.insert(file_path, repo.blob(data)?, 0o100_644)?;
+            }
+        }
+    }
+    for (dir, files) in dirs {
+        builder.insert(dir, write_tree(repo, &files)?, 0o040_000)?;
+    }
+
+    Ok(builder.write()?)
+}
+
+/// The commit id of HEAD, `None` before the first commit.
+pub fn head<T: AsRef<str>>(filesys_path: T) -> Result<Option<String>, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let head = match repo.head() {
+        Ok(head) => head.peel_to_commit()?.id().to_string(),
+        Err(_) => return Ok(None),
+    };
+
+    Ok(Some(head))
+}
+
+/// The commit id a git revision, like a branch or `HEAD~2`, points at.
+pub fn resolve<T: AsRef<str>>(filesys_path: T, rev: T) -> Result<String, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let commit = repo.revparse_single(rev.as_ref())?.peel_to_commit()?;
+
+    Ok(commit.id().to_string())
+}
+
+/// `file_path`, relative to the filesystem directory, as it was at `commit`.
+/// `None` if it didn't exist then.
+pub fn read_at<T: AsRef<str>>(filesys_path: T, commit: T, file_path: T) -> Result<Option<String>, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let commit = repo.find_commit(git2::Oid::from_str(commit.as_ref())?)?;
+    let content = match commit.tree()?.get_path(Path::new(file_path.as_ref())) {
+        Ok(entry) => {
+            let blob = repo.find_blob(entry.id())?;
+            Some(String::from_utf8_lossy(blob.content()).to_string())
+        }
+        Err(_) => None,
+    };
+
+    Ok(content)
+}
+
+/// A commit that changed a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Revision {
+    pub commit: String,
+    /// Seconds since the epoch.
+    pub time: i64,
+    /// Offset from UTC, in minutes.
+    pub offset: i32,
+    /// `None` if the commit deleted the file.
+    pub content: Option<String>,
+}
+
+/// Commits that changed `file_path`, relative to the filesystem directory,
+/// oldest first.
+pub fn history<T: AsRef<str>>(filesys_path: T, file_path: T) -> Result<Vec<Revision>, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    if repo.head().is_err() {
+        return Ok(Vec::new());
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE);
+
+    let mut revisions: Vec<Revision> = Vec::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let content = match commit.tree()?.get_path(Path::new(file_path.as_ref())) {
+            Ok(entry) => {
+                let blob = repo.find_blob(entry.id())?;
+                Some(String::from_utf8_lossy(blob.content()).to_string())
+            }
+            Err(_) => None,
+        };
+
+        let previous = revisions.last().and_then(|r| r.content.as_ref());
+        if content.as_ref() != previous && (content.is_some() || previous.is_some()) {
+            revisions.push(Revision {
+                commit: commit.id().to_string(),
+                time: commit.time().seconds(),
+                offset: commit.time().offset_minutes(),
+                content,
+            });
+        }
+    }
+
+    Ok(revisions)
+}
+
+/// Paths, relative to the filesystem directory, that differ between two
+/// commits. `None` if `from` isn't in the repository anymore.
+pub fn changed<T: AsRef<str>>(filesys_path: T, from: T, to: T) -> Result<Option<Vec<String>>, FilesysError> {
+    let repo = Repository::open(Path::new(filesys_path.as_ref()))?;
+    let from = match Oid::from_str(from.as_ref()).and_then(|oid| repo.find_commit(oid)) {
+        Ok(from) => from,
+        Err(_) => return Ok(None),
+    };
+    let to = repo.find_commit(Oid::from_str(to.as_ref())?)?;
+    let diff = repo.diff_tree_to_tree(Some(&from.tree()?), Some(&to.tree()?), None)?;
+
+    let mut paths = Vec::new();
+    for delta in diff.deltas() {
+        for file in [delta.old_file(), delta.new_file()].iter() {
+            if let Some(path) = file.path() {
+                paths.push(path.to_string_lossy().to_string());
+            }
+        }
+    }
+    paths.sort();
+    paths.dedup();
+
+    Ok(Some(paths))
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use fixture::Fixture;
+    use std::fs::write;
+
+    #[test]
+    fn file_history() {
+        let path = "/tmp/immutag_vcs_history_test";
+        let mut fixture = Fixture::new()
+            .add_dirpath(path.to_string())
+            .add_git(path.to_string())
+            .build();
+
+        write(format!("{}/tracked", path), "one").unwrap();
+        commit(path, "first").unwrap();
+        write(format!("{}/other", path), "unrelated").unwrap();
+        commit(path, "second").unwrap();
+        write(format!("{}/tracked", path), "two").unwrap();
+        let last = commit(path, "third").unwrap();
+        let revisions = history(path, "tracked").unwrap();
+        let first = read_at(path, revisions[0].commit.as_str(), "tracked").unwrap();
+        let missing = read_at(path, revisions[0].commit.as_str(), "other").unwrap();
+        let resolved = resolve(path, "HEAD~2").unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].content, Some("one".to_string()));
+        assert_eq!(revisions[1].content, Some("two".to_string()));
+        assert_eq!(revisions[1].commit, last);
+        assert_eq!(first, Some("one".to_string()));
+        assert_eq!(missing, None);
+        assert_eq!(resolved, revisions[0].commit);
+    }
+
+    #[test]
+    fn changed_paths() {
+        let path = "/tmp/immutag_vcs_changed_test";
+        let mut fixture = Fixture::new()
+            .add_dirpath(path.to_string())
+            .add_git(path.to_string())
+            .build();
+
+        write(format!("{}/tracked", path), "one").unwrap();
+        write(format!("{}/other", path), "unrelated").unwrap();
+        let first = commit(path, "first").unwrap();
+        write(format!("{}/tracked", path), "two").unwrap();
+        std::fs::remove_file(format!("{}/other", path)).unwrap();
+        let second = commit(path, "second").unwrap();
+        let paths = changed(path, first.as_str(), second.as_str()).unwrap();
+        let unchanged = changed(path, second.as_str(), second.as_str()).unwrap();
+        let missing = changed(path, "0000000000000000000000000000000000000001", second.as_str()).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(paths, Some(vec!["other".to_string(), "tracked".to_string()]));
+        assert_eq!(unchanged, Some(Vec::new()));
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn time_round_trip() {
+        let time = Time::new(1_582_934_400, -90);
+
+        let parsed = parse_time("1582934400 -0130").unwrap();
+        let utc = parse_time("1582934400").unwrap();
+
+        assert_eq!(format_time(&time), "1582934400 -0130");
+        assert_eq!((parsed.seconds(), parsed.offset_minutes()), (1_582_934_400, -90));
+        assert_eq!((utc.seconds(), utc.offset_minutes()), (1_582_934_400, 0));
+        assert!(parse_time("yesterday").is_none());
+    }
+}
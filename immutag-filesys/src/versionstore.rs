@@ -0,0 +1,137 @@
+/*
+Each file in the version-store gets its own directory, named after the file
+address, holding a versions-file and a metadata-file.
+*/
+use std::fs::{read_dir, read_to_string, write};
+use std::path::Path;
+
+use crate::err::{Error, ErrorKind, FilesysError};
+
+const HEADER: &str = "# file-hash: file-addr\n";
+
+/// A line of the versions-file. Its position is the version number.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Version {
+    pub file_hash: String,
+    pub file_addr: String,
+}
+
+pub fn path_file<T: AsRef<str>>(versionstore_path: T, file_addr: T) -> String {
+    let path = fixture::directorate(versionstore_path.as_ref().to_string());
+
+    fixture::directorate(path + file_addr.as_ref())
+}
+
+pub fn path_versions_file<T: AsRef<str>>(versionstore_path: T, file_addr: T) -> String {
+    path_file(versionstore_path, file_addr) + "versions-file"
+}
+
+pub fn path_metadata_file<T: AsRef<str>>(versionstore_path: T, file_addr: T) -> String {
+    path_file(versionstore_path, file_addr) + "metadata-file"
+}
+
+/// Addresses of every file in the version-store, sorted.
+pub fn file_addrs<T: AsRef<str>>(versionstore_path: T) -> Result<Vec<String>, FilesysError> {
+    let mut addrs = Vec::new();
+    if !Path::new(versionstore_path.as_ref()).exists() {
+        return Ok(addrs);
+    }
+
+    for entry in read_dir(versionstore_path.as_ref())? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            addrs.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    addrs.sort();
+
+    Ok(addrs)
+}
+
+pub fn parse<T: AsRef<str>>(versions_file: T) -> Result<Vec<Version>, FilesysError> {
+    let mut versions = Vec::new();
+
+    for line in versions_file.as_ref().lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(pos) = line.find(':') {
+            versions.push(Version {
+                file_hash: line[..pos].trim().to_string(),
+                file_addr: line[pos + 1..].trim().to_string(),
+            });
+        } else {
+            let err = Error::new("invalid versions-file entry", ErrorKind::InvalidVersionsFile);
+            return Err(FilesysError::from(err));
+        }
+    }
+
+    Ok(versions)
+}
+
+pub fn render(versions: &[Version]) -> String {
+    let mut versions_file = HEADER.to_string() + "\n";
+    for version in versions {
+        versions_file.push_str(&format!("{}: {}\n", version.file_hash, version.file_addr));
+    }
+
+    versions_file
+}
+
+/// Versions of a file, oldest first. A missing versions-file has none.
+pub fn versions<T: AsRef<str>>(path: T) -> Result<Vec<Version>, FilesysError> {
+    if !Path::new(path.as_ref()).exists() {
+        return Ok(Vec::new());
+    }
+
+    parse(read_to_string(path.as_ref())?)
+}
+
+pub fn write_versions<T: AsRef<str>>(path: T, versions: &[Version]) -> Result<(), FilesysError> {
+    write(path.as_ref(), render(versions))?;
+
+    Ok(())
+}
+
+/// Appends a version and returns its number.
+pub fn append_version<T: AsRef<str>>(path: T, version: Version) -> Result<usize, FilesysError> {
+    let mut all = versions(path.as_ref())?;
+    all.push(version);
+    write_versions(path, &all)?;
+
+    Ok(all.len() - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_versions_file() {
+        let versions_file = "# file-hash: file-addr
+
+1103def0e9d9036f59b7ef8524791710ed9a6e477b611abb94b0302edf887ee9: 1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9
+f909e48c4b5b8aeaf45cd6844994b37a0de5c52d43b36410c35d9dd8ae6f9afb: 1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9
+";
+        let versions = parse(versions_file).unwrap();
+
+        assert_eq!(versions.len(), 2);
+        assert_eq!(
+            versions[1].file_hash,
+            "f909e48c4b5b8aeaf45cd6844994b37a0de5c52d43b36410c35d9dd8ae6f9afb"
+        );
+        assert_eq!(render(&versions), versions_file);
+    }
+
+    #[test]
+    fn path_versions_file() {
+        let path = super::path_versions_file("/tmp/.immutag/1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj/version-store", "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9");
+
+        assert_eq!(
+            path,
+            "/tmp/.immutag/1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj/version-store/1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9/versions-file"
+        );
+    }
+}
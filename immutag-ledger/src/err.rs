@@ -0,0 +1,64 @@
+pub use sv::util::Error as SvError;
+
+#[derive(Debug)]
+pub enum LedgerError {
+    SvError(SvError),
+    IoError(std::io::Error),
+    Error(Error),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    InvalidTx,
+    AlreadyKnown,
+    DoubleSpend,
+    NotFound,
+    UnsupportedLedger,
+    Electrum,
+    NoServer,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub details: String,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    pub fn new(msg: &str, kind: ErrorKind) -> Error {
+        Error {
+            details: msg.to_string(),
+            kind,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl From<SvError> for LedgerError {
+    fn from(error: SvError) -> Self {
+        LedgerError::SvError(error)
+    }
+}
+
+impl From<std::io::Error> for LedgerError {
+    fn from(error: std::io::Error) -> Self {
+        LedgerError::IoError(error)
+    }
+}
+
+impl From<Error> for LedgerError {
+    fn from(error: Error) -> Self {
+        LedgerError::Error(error)
+    }
+}
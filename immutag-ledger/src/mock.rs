@@ -0,0 +1,335 @@
+/*
+An in-memory ledger, like a regtest node that only mines when it's told to.
+Transactions are checked like a node would: their inputs must exist, be
+unspent, and be signed the way the network expects. Everything is
+deterministic, so block hashes are the same from run to run.
+*/
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use immutag_bitcoin::tx::{Ledger as Network, DUST_LIMIT};
+use sv::messages::{OutPoint, Tx, TxIn, TxOut};
+use sv::script::op_codes::OP_CODESEPARATOR;
+use sv::script::{Script, TransactionChecker};
+use sv::transaction::p2pkh::create_pk_script;
+use sv::transaction::sighash::SigHashCache;
+use sv::util::{sha256d, Amount};
+
+use crate::err::{Error, ErrorKind, LedgerError};
+use crate::{addr_hash, decode_tx, encode_tx, involves, txid, Ledger, Tip, Transaction};
+
+pub struct MockLedger {
+    network: Network,
+    chain: Mutex<Chain>,
+}
+
+struct Chain {
+    /// Block hashes, the genesis block first.
+    blocks: Vec<String>,
+    /// Every transaction, in the order they were broadcast.
+    txs: Vec<(Tx, Transaction)>,
+    spent: HashSet<OutPoint>,
+    funded: u32,
+}
+
+impl MockLedger {
+    /// A chain of just the genesis block, signing like `ledger` does in the
+    /// Immutag file.
+    pub fn new<T: AsRef<str>>(ledger: T) -> Result<MockLedger, LedgerError> {
+        let network: Network = ledger.as_ref().parse().map_err(|_| {
+            let msg = format!("unsupported ledger '{}'", ledger.as_ref());
+            LedgerError::from(Error::new(&msg, ErrorKind::UnsupportedLedger))
+        })?;
+        let genesis = sha256d(format!("immutag mock {}", ledger.as_ref()).as_bytes()).encode();
+
+        Ok(MockLedger {
+            network,
+            chain: Mutex::new(Chain {
+                blocks: vec![genesis],
+                txs: Vec::new(),
+                spent: HashSet::new(),
+                funded: 0,
+            }),
+        })
+    }
+
+    /// Mines whatever is unconfirmed into a new block and returns its height.
+    pub fn mine(&self) -> u64 {
+        let mut chain = self.chain.lock().unwrap();
+        let height = chain.blocks.len() as u64;
+
+        let mut header = hex::decode(chain.blocks.last().unwrap()).unwrap();
+        for (_, tx) in chain.txs.iter_mut().filter(|(_, tx)| tx.height.is_none()) {
+            tx.height = Some(height);
+            header.extend_from_slice(tx.txid.as_bytes());
+        }
+        let hash = sha256d(&header).encode();
+        chain.blocks.push(hash);
+
+        height
+    }
+
+    /// Pays `satoshis` to `addr` out of thin air, like a coinbase would, and
+    /// returns the txid. The output is the first.
+    pub fn fund<T: AsRef<str>>(&self, addr: T, satoshis: u64) -> Result<String, LedgerError> {
+        let pk_script = create_pk_script(&addr_hash(addr.as_ref())?);
+
+        self.issue(vec![TxOut {
+            amount: Amount(satoshis as i64),
+            pk_script,
+        }])
+    }
+
+    /// Sends `payload` in an OP_RETURN to `addr`, with a dust output, out of
+    /// thin air, and returns the txid. Anyone can send anything to an
+    /// address, so it's what a forged record looks like: it doesn't spend
+    /// from `addr`.
+    pub fn send<T: AsRef<str>>(&self, addr: T, payload: &[u8]) -> Result<String, LedgerError> {
+        let pk_script = create_pk_script(&addr_hash(addr.as_ref())?);
+
+        self.issue(vec![
+            TxOut {
+                amount: Amount(0),
+                pk_script: self.network.data_script(payload),
+            },
+            TxOut {
+                amount: Amount(DUST_LIMIT as i64),
+                pk_script,
+            },
+        ])
+    }
+
+    fn issue(&self, outputs: Vec<TxOut>) -> Result<String, LedgerError> {
+        let mut chain = self.chain.lock().unwrap();
+        chain.funded += 1;
+
+        let tx = Tx {
+            version: 1,
+            inputs: vec![TxIn {
+                prev_output: OutPoint {
+                    hash: sha256d(format!("funding {}", chain.funded).as_bytes()),
+                    index: 0,
+                },
+                sig_script: Script::new(),
+                sequence: 0xffff_ffff,
+            }],
+            outputs,
+            lock_time: 0,
+        };
+        let txid = txid(&tx);
+        let raw = encode_tx(&tx);
+        chain.txs.push((
+            tx,
+            Transaction {
+                txid: txid.clone(),
+                raw,
+                height: None,
+            },
+        ));
+
+        Ok(txid)
+    }
+
+    fn output(chain: &Chain, outpoint: &OutPoint) -> Option<TxOut> {
+        chain
+            .txs
+            .iter()
+            .find(|(tx, _)| tx.hash() == outpoint.hash)
+            .and_then(|(tx, _)| tx.outputs.get(outpoint.index as usize).cloned())
+    }
+}
+
+impl Ledger for MockLedger {
+    fn broadcast(&self, raw_tx: &str) -> Result<String, LedgerError> {
+        let tx = decode_tx(raw_tx)?;
+        let txid = txid(&tx);
+        let mut chain = self.chain.lock().unwrap();
+        if chain.txs.iter().any(|(_, known)| known.txid == txid) {
+            let msg = format!("transaction {} is already known", txid);
+            return Err(LedgerError::from(Error::new(&msg, ErrorKind::AlreadyKnown)));
+        }
+        if tx.inputs.is_empty() || tx.outputs.is_empty() {
+            return Err(invalid_tx("transaction has no inputs or no outputs"));
+        }
+
+        let mut cache = SigHashCache::new();
+        let mut total_in = 0;
+        for (n, input) in tx.inputs.iter().enumerate() {
+            if chain.spent.contains(&input.prev_output) {
+                let msg = format!("input {} is already spent", n);
+                return Err(LedgerError::from(Error::new(&msg, ErrorKind::DoubleSpend)));
+            }
+            let spent = match MockLedger::output(&chain, &input.prev_output) {
+                Some(spent) => spent,
+                None => return Err(invalid_tx(&format!("input {} spends an unknown output", n))),
+            };
+            total_in += spent.amount.0;
+
+            let mut script = Script::new();
+            script.append_slice(&input.sig_script.0);
+            script.append(OP_CODESEPARATOR);
+            script.append_slice(&spent.pk_script.0);
+            let mut checker = TransactionChecker {
+                tx: &tx,
+                sig_hash_cache: &mut cache,
+                input: n,
+                amount: spent.amount,
+                require_sighash_forkid: self.network == Network::BitcoinSv,
+            };
+            if let Err(e) = script.eval(&mut checker) {
+                return Err(invalid_tx(&format!("input {} doesn't verify: {}", n, e)));
+            }
+        }
+        if tx.outputs.iter().map(|out| out.amount.0).sum::<i64>() > total_in {
+            return Err(invalid_tx("outputs are worth more than the inputs"));
+        }
+
+        for input in tx.inputs.iter() {
+            chain.spent.insert(input.prev_output.clone());
+        }
+        chain.txs.push((
+            tx,
+            Transaction {
+                txid: txid.clone(),
+                raw: raw_tx.to_string(),
+                height: None,
+            },
+        ));
+
+        Ok(txid)
+    }
+
+    fn transaction(&self, txid: &str) -> Result<Transaction, LedgerError> {
+        let chain = self.chain.lock().unwrap();
+        match chain.txs.iter().find(|(_, tx)| tx.txid == txid) {
+            Some((_, tx)) => Ok(tx.clone()),
+            None => {
+                let msg = format!("no transaction {}", txid);
+                Err(LedgerError::from(Error::new(&msg, ErrorKind::NotFound)))
+            }
+        }
+    }
+
+    fn history(&self, addr: &str) -> Result<Vec<Transaction>, LedgerError> {
+        let chain = self.chain.lock().unwrap();
+        let mut history = Vec::new();
+        for (tx, transaction) in chain.txs.iter() {
+            if involves(tx, addr)? {
+                history.push(transaction.clone());
+            }
+        }
+        // Confirmed first, like a node reports them.
+        history.sort_by_key(|tx| tx.height.unwrap_or(u64::MAX));
+
+        Ok(history)
+    }
+
+    fn tip(&self) -> Result<Tip, LedgerError> {
+        let chain = self.chain.lock().unwrap();
+
+        Ok(Tip {
+            height: chain.blocks.len() as u64 - 1,
+            hash: chain.blocks.last().unwrap().clone(),
+        })
+    }
+}
+
+fn invalid_tx(msg: &str) -> LedgerError {
+    LedgerError::from(Error::new(msg, ErrorKind::InvalidTx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use immutag_bitcoin::opreturn::{Payload, Record};
+    use immutag_bitcoin::tx::{change_addr, peg_tx, Utxo};
+
+    const XPRIV: &str = "xprv9s21ZrQH143K29TJGFSiEAAQM8SMBH2V6x5Aaf9bqvXftrs1v274STWWKfz8svukBLGEQgWqkgRhpt2CNFY89CFaqdsA3gicZeqexk2itxf";
+    const FILESYS_ADDR: &str = "1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj";
+
+    fn payload() -> Vec<u8> {
+        Payload {
+            record: Record::Peg { version: 0 },
+            content_addr: hex::decode("122031c3d57080d8463a3c63b2923df5a1d40ad7a73eae5a14af584213e5f504ac33").unwrap(),
+            parent: Some(FILESYS_ADDR.to_string()),
+        }
+        .encode()
+        .unwrap()
+    }
+
+    fn utxo(txid: String) -> Utxo {
+        Utxo {
+            txid,
+            vout: 0,
+            satoshis: 10_000,
+            chain: 0,
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn peg_round_trip() {
+        let ledger = MockLedger::new("bitcoinsv").unwrap();
+        let funding = ledger.fund(FILESYS_ADDR, 10_000).unwrap();
+        let funded_at = ledger.mine();
+        let raw = peg_tx(XPRIV, "bitcoinsv".parse().unwrap(), &[utxo(funding)], &payload(), 0, 500).unwrap();
+
+        let txid = ledger.broadcast(&raw).unwrap();
+        let unconfirmed = ledger.op_returns(FILESYS_ADDR).unwrap();
+        let mined_at = ledger.mine();
+        let confirmed = ledger.op_returns(FILESYS_ADDR).unwrap();
+        let history = ledger.history(FILESYS_ADDR).unwrap();
+        let change = ledger.history(&change_addr(XPRIV, 0).unwrap()).unwrap();
+        let again = ledger.broadcast(&raw);
+
+        assert_eq!((funded_at, mined_at), (1, 2));
+        assert_eq!(ledger.tip().unwrap().height, 2);
+        assert_eq!(unconfirmed[0].height, None);
+        assert_eq!(confirmed.len(), 1);
+        assert_eq!(confirmed[0].txid, txid);
+        assert_eq!(confirmed[0].height, Some(2));
+        assert_eq!(Payload::decode(&confirmed[0].data).unwrap().record, Record::Peg { version: 0 });
+        assert_eq!(history.len(), 2);
+        assert_eq!(change.len(), 1);
+        assert_eq!(ledger.transaction(&txid).unwrap().raw, raw);
+        assert!(matches!(again, Err(LedgerError::Error(ref e)) if e.kind == ErrorKind::AlreadyKnown));
+    }
+
+    #[test]
+    fn rejects() {
+        let ledger = MockLedger::new("bitcoinsv").unwrap();
+        let funding = ledger.fund(FILESYS_ADDR, 10_000).unwrap();
+        let legacy = peg_tx(XPRIV, "bitcoin".parse().unwrap(), &[utxo(funding.clone())], &payload(), 0, 500).unwrap();
+        let unknown = Utxo {
+            txid: "00".repeat(32),
+            ..utxo(funding.clone())
+        };
+        let unfunded = peg_tx(XPRIV, "bitcoinsv".parse().unwrap(), &[unknown], &payload(), 0, 500).unwrap();
+        let first = peg_tx(XPRIV, "bitcoinsv".parse().unwrap(), &[utxo(funding.clone())], &payload(), 0, 500).unwrap();
+        let second = peg_tx(XPRIV, "bitcoinsv".parse().unwrap(), &[utxo(funding)], &payload(), 1, 500).unwrap();
+
+        let kind = |result: Result<String, LedgerError>| match result {
+            Err(LedgerError::Error(e)) => Some(e.kind),
+            _ => None,
+        };
+        assert_eq!(kind(ledger.broadcast(&legacy)), Some(ErrorKind::InvalidTx));
+        assert_eq!(kind(ledger.broadcast(&unfunded)), Some(ErrorKind::InvalidTx));
+        assert_eq!(kind(ledger.broadcast("nope")), Some(ErrorKind::InvalidTx));
+        assert!(ledger.broadcast(&first).is_ok());
+        assert_eq!(kind(ledger.broadcast(&second)), Some(ErrorKind::DoubleSpend));
+        assert!(ledger.transaction(&"00".repeat(32)).is_err());
+        assert!(MockLedger::new("dogecoin").is_err());
+    }
+
+    #[test]
+    fn deterministic() {
+        let mine = || {
+            let ledger = MockLedger::new("bitcoin").unwrap();
+            ledger.fund(FILESYS_ADDR, 1_000).unwrap();
+            ledger.mine();
+            ledger.tip().unwrap().hash
+        };
+
+        assert_eq!(mine(), mine());
+    }
+}
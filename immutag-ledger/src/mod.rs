@@ -0,0 +1,244 @@
+/*
+This module is the `ledger` side of immutag: broadcasting peg and fork
+transactions and reading them back. A backend only has to broadcast, fetch
+transactions and report the chain tip; finding the OP_RETURN data in an
+address's history is the same for all of them.
+*/
+extern crate hex;
+extern crate sv;
+
+pub mod electrum;
+pub mod err;
+pub mod mock;
+
+pub use electrum::ElectrumLedger;
+pub use err::{ErrorKind, LedgerError};
+pub use mock::MockLedger;
+
+use sv::address::{addr_decode, AddressType};
+use sv::messages::Tx;
+use sv::network::Network;
+use sv::script::op_codes::{OP_FALSE, OP_PUSHDATA1, OP_PUSHDATA2, OP_PUSHDATA4, OP_RETURN};
+use sv::transaction::p2pkh;
+use sv::util::{hash160, Hash160, Serializable};
+
+use err::Error;
+
+/// `ledger` used when immutag.toml doesn't name one.
+pub const DEFAULT_LEDGER: &str = "bitcoinsv";
+
+/// The ledger settings of the Immutag file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LedgerConfig {
+    /// `bitcoinsv` or `bitcoin`.
+    pub ledger: Option<String>,
+    /// The ElectrumX server, `tcp://host:port` or `ssl://host:port`.
+    pub electrum: Option<String>,
+}
+
+/// Opens the electrum server named by the config.
+pub fn open_ledger(config: &LedgerConfig) -> Result<Box<dyn Ledger>, LedgerError> {
+    let ledger = config.ledger.as_deref().unwrap_or(DEFAULT_LEDGER);
+    if ledger.parse::<immutag_bitcoin::tx::Ledger>().is_err() {
+        let msg = format!("unsupported ledger '{}'", ledger);
+        return Err(LedgerError::from(Error::new(&msg, ErrorKind::UnsupportedLedger)));
+    }
+
+    match config.electrum.as_deref() {
+        Some(server) => Ok(Box::new(ElectrumLedger::new(server)?)),
+        None => {
+            let msg = format!("no electrum server is set for {}", ledger);
+            Err(LedgerError::from(Error::new(&msg, ErrorKind::NoServer)))
+        }
+    }
+}
+
+/// The chain's best block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tip {
+    pub height: u64,
+    pub hash: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transaction {
+    pub txid: String,
+    /// The raw transaction, hex encoded.
+    pub raw: String,
+    /// `None` while it's unconfirmed.
+    pub height: Option<u64>,
+}
+
+/// The data of an OP_RETURN output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpReturn {
+    pub txid: String,
+    pub height: Option<u64>,
+    pub data: Vec<u8>,
+}
+
+/// A bitcoin network immutag records pegs and forks on.
+pub trait Ledger {
+    /// Broadcasts a raw hex transaction and returns its txid.
+    fn broadcast(&self, raw_tx: &str) -> Result<String, LedgerError>;
+    fn transaction(&self, txid: &str) -> Result<Transaction, LedgerError>;
+    /// Transactions paying to or spending from `addr`, oldest first.
+    fn history(&self, addr: &str) -> Result<Vec<Transaction>, LedgerError>;
+    fn tip(&self) -> Result<Tip, LedgerError>;
+
+    /// OP_RETURN data of the transactions in `addr`'s history, oldest first.
+    fn op_returns(&self, addr: &str) -> Result<Vec<OpReturn>, LedgerError> {
+        let mut op_returns = Vec::new();
+        for tx in self.history(addr)? {
+            for data in op_return_data(&decode_tx(&tx.raw)?) {
+                op_returns.push(OpReturn {
+                    txid: tx.txid.clone(),
+                    height: tx.height,
+                    data,
+                });
+            }
+        }
+
+        Ok(op_returns)
+    }
+}
+
+pub fn decode_tx(raw_tx: &str) -> Result<Tx, LedgerError> {
+    let bytes = hex::decode(raw_tx).map_err(|_| invalid_tx("transaction isn't hex"))?;
+    let mut reader = &bytes[..];
+    let tx = Tx::read(&mut reader).map_err(|e| invalid_tx(&format!("can't decode transaction: {}", e)))?;
+    if !reader.is_empty() {
+        return Err(invalid_tx("trailing bytes after transaction"));
+    }
+
+    Ok(tx)
+}
+
+pub fn encode_tx(tx: &Tx) -> String {
+    let mut raw = Vec::new();
+    tx.write(&mut raw).expect("writing to memory can't fail");
+
+    hex::encode(raw)
+}
+
+/// The txid, in the usual (reversed) hex order.
+pub fn txid(tx: &Tx) -> String {
+    tx.hash().encode()
+}
+
+/// The data pushed by each `OP_RETURN` or `OP_FALSE OP_RETURN` output.
+pub fn op_return_data(tx: &Tx) -> Vec<Vec<u8>> {
+    tx.outputs
+        .iter()
+        .filter_map(|out| {
+            let script = &out.pk_script.0;
+            let start = match script.as_slice() {
+                [OP_FALSE, OP_RETURN, ..] => 2,
+                [OP_RETURN, ..] => 1,
+                _ => return None,
+            };
+            pushes(&script[start..])
+        })
+        .collect()
+}
+
+/// Whether the transaction pays to `addr` or spends from it.
+pub fn involves(tx: &Tx, addr: &str) -> Result<bool, LedgerError> {
+    let hash = addr_hash(addr)?;
+    let pays = tx.outputs.iter().any(|out| p2pkh::check_pk_script_addr(&hash, &out.pk_script.0));
+
+    Ok(pays || spends(tx, addr)?)
+}
+
+/// Whether the transaction spends from `addr`, so its key signed it.
+pub fn spends(tx: &Tx, addr: &str) -> Result<bool, LedgerError> {
+    let hash = addr_hash(addr)?;
+    let spends = tx.inputs.iter().any(|input| match p2pkh::extract_pubkey(&input.sig_script.0) {
+        Ok(public_key) => hash160(&public_key) == hash,
+        Err(_) => false,
+    });
+
+    Ok(spends)
+}
+
+pub fn addr_hash(addr: &str) -> Result<Hash160, LedgerError> {
+    match addr_decode(addr, Network::Mainnet)? {
+        (hash, AddressType::P2PKH) => Ok(hash),
+        _ => Err(LedgerError::from(Error::new("only P2PKH addresses are supported", ErrorKind::InvalidTx))),
+    }
+}
+
+/// The concatenated data of a script of pushes, `None` if it isn't one.
+fn pushes(script: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut pos = 0;
+    while pos < script.len() {
+        let op = script[pos];
+        let (len, header) = match op {
+            1..=75 => (op as usize, 1),
+            OP_PUSHDATA1 => (*script.get(pos + 1)? as usize, 2),
+            OP_PUSHDATA2 => (u16::from_le_bytes([*script.get(pos + 1)?, *script.get(pos + 2)?]) as usize, 3),
+            OP_PUSHDATA4 => {
+                let len = script.get(pos + 1..pos + 5)?;
+                (u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize, 5)
+            }
+            _ => return None,
+        };
+        data.extend_from_slice(script.get(pos + header..pos + header + len)?);
+        pos += header + len;
+    }
+
+    Some(data)
+}
+
+fn invalid_tx(msg: &str) -> LedgerError {
+    LedgerError::from(Error::new(msg, ErrorKind::InvalidTx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sv::script::Script;
+
+    #[test]
+    fn op_return_pushes() {
+        let mut safe = Script::new();
+        safe.append(OP_FALSE);
+        safe.append(OP_RETURN);
+        safe.append_data(b"IMT");
+        safe.append_data(&[7; 100]);
+        let mut legacy = Script::new();
+        legacy.append(OP_RETURN);
+        legacy.append_data(b"IMT");
+
+        assert_eq!(pushes(&safe.0[2..]), Some([&b"IMT"[..], &[7; 100]].concat()));
+        assert_eq!(pushes(&legacy.0[1..]), Some(b"IMT".to_vec()));
+        // Truncated, then not a push.
+        assert_eq!(pushes(&[OP_PUSHDATA1, 5, 1]), None);
+        assert_eq!(pushes(&[OP_RETURN]), None);
+    }
+
+    #[test]
+    fn bad_transactions() {
+        assert!(decode_tx("zz").is_err());
+        assert!(decode_tx("0100").is_err());
+        assert!(addr_hash("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy").is_err());
+    }
+
+    #[test]
+    fn configs() {
+        let config = |ledger: Option<&str>, electrum: Option<&str>| LedgerConfig {
+            ledger: ledger.map(|l| l.to_string()),
+            electrum: electrum.map(|e| e.to_string()),
+        };
+        let kind = |config: LedgerConfig| match open_ledger(&config) {
+            Err(LedgerError::Error(e)) => Some(e.kind),
+            _ => None,
+        };
+
+        assert!(open_ledger(&config(None, Some("ssl://electrumx.example.com:50002"))).is_ok());
+        assert!(open_ledger(&config(Some("bitcoin"), Some("tcp://127.0.0.1:50001"))).is_ok());
+        assert_eq!(kind(config(None, None)), Some(ErrorKind::NoServer));
+        assert_eq!(kind(config(Some("dogecoin"), Some("tcp://127.0.0.1:50001"))), Some(ErrorKind::UnsupportedLedger));
+    }
+}
@@ -2,8 +2,12 @@
 extern crate clap;
 extern crate immutag;
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgGroup, SubCommand};
 use immutag::{bitcoin, files, filesys};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
 
 
 fn main() {
@@ -35,6 +39,107 @@ fn main() {
                                .index(3)
                        ),
                 )
+                .subcommand(
+                   SubCommand::with_name("peg")
+                       .about("Pegs the Merkle root of the version-store manifest.")
+                )
+                .subcommand(
+                   SubCommand::with_name("recover")
+                       .about("Rebuilds a filesystem from its mnemonic, through the ledger.")
+                       .arg(
+                           Arg::with_name("MNEMONIC")
+                               .required(true)
+                               .help("The filesystem's mnemonic, quoted.")
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("PATH")
+                               .index(2)
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("remote")
+                       .about("Manages the git remotes collaborators push to and pull from.")
+                       .subcommand(
+                          SubCommand::with_name("add")
+                              .arg(
+                                  Arg::with_name("NAME")
+                                      .required(true)
+                                      .index(1)
+                              )
+                              .arg(
+                                  Arg::with_name("URL")
+                                      .required(true)
+                                      .help("A bare repository's path or URL, like file:///srv/lemonade.git")
+                                      .index(2)
+                              ),
+                       )
+                       .subcommand(
+                          SubCommand::with_name("remove")
+                              .arg(
+                                  Arg::with_name("NAME")
+                                      .required(true)
+                                      .index(1)
+                              ),
+                       )
+                       .subcommand(
+                          SubCommand::with_name("list")
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("push")
+                       .about("Pushes the filesystem's history to a remote.")
+                       .arg(
+                           Arg::with_name("REMOTE")
+                               .index(1)
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("pull")
+                       .about("Merges a remote's history and checks the filesystem.")
+                       .arg(
+                           Arg::with_name("REMOTE")
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("ours")
+                               .help("Keep our value of metadata fields both sides changed.")
+                               .long("ours")
+                       )
+                       .arg(
+                           Arg::with_name("theirs")
+                               .help("Take their value of metadata fields both sides changed.")
+                               .long("theirs")
+                       )
+                       .group(ArgGroup::with_name("resolution").args(&["ours", "theirs"])),
+                )
+                .subcommand(
+                   SubCommand::with_name("rebuild-git")
+                       .about("Rebuilds the filesystem's git history from the version-store.")
+                       .arg(
+                           Arg::with_name("force")
+                               .help("Replace the git history there is.")
+                               .long("force")
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("account")
+                       .about("Shows or sets the account whose key signs your metadata.")
+                       .arg(
+                           Arg::with_name("ACCOUNT")
+                               .help("Account k of the key m/44'/0'/k'.")
+                               .index(1)
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("collisions")
+                       .about("Lists file indexes claimed for more than one file.")
+                       .arg(
+                           Arg::with_name("REV")
+                               .help("A git revision to merge, like a fetched branch.")
+                               .index(1)
+                       ),
+                )
         )
         .subcommand(
             SubCommand::with_name("file")
@@ -51,6 +156,11 @@ fn main() {
                                       .takes_value(true)
                                       .help("Set an alias for the file.")
                                       .long("alias")
+                              )
+                              .arg(
+                                  Arg::with_name("scan-ledger")
+                                      .help("Skip the indexes with history on the ledger for a new file.")
+                                      .long("scan-ledger")
                               ),
                        )
                        .subcommand(
@@ -93,6 +203,140 @@ fn main() {
                               )
                        )
                 )
+                .subcommand(
+                   SubCommand::with_name("commit")
+                       .arg(
+                           Arg::with_name("FILE")
+                               .required(true)
+                               .index(1)
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("checkout")
+                       .arg(
+                           Arg::with_name("FILE")
+                               .required(true)
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("version")
+                               .takes_value(true)
+                               .help("Version number, counting from 0.")
+                               .long("version")
+                       )
+                       .arg(
+                           Arg::with_name("hash")
+                               .takes_value(true)
+                               .help("File hash of the version.")
+                               .long("hash")
+                       )
+                       .arg(
+                           Arg::with_name("version-addr")
+                               .takes_value(true)
+                               .help("Bitcoin address of the version.")
+                               .long("version-addr")
+                       )
+                       .group(
+                           ArgGroup::with_name("selector")
+                               .args(&["version", "hash", "version-addr"])
+                               .required(true)
+                       )
+                       .arg(
+                           Arg::with_name("output")
+                               .takes_value(true)
+                               .help("Write the version here instead of the tracked path.")
+                               .long("output")
+                       )
+                       .arg(
+                           Arg::with_name("force")
+                               .help("Overwrite uncommitted changes.")
+                               .long("force")
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("log")
+                       .arg(
+                           Arg::with_name("FILE")
+                               .required(true)
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("json")
+                               .help("Print the history as JSON.")
+                               .long("json")
+                       )
+                       .arg(
+                           Arg::with_name("limit")
+                               .takes_value(true)
+                               .help("Show only the latest versions.")
+                               .long("limit")
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("branch")
+                       .arg(
+                           Arg::with_name("FILE")
+                               .required(true)
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("from-version")
+                               .takes_value(true)
+                               .required(true)
+                               .help("Version to fork onto the new branch.")
+                               .long("from-version")
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("proof")
+                       .arg(
+                           Arg::with_name("FILE")
+                               .required(true)
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("version")
+                               .takes_value(true)
+                               .required(true)
+                               .help("Version to prove is in a peg.")
+                               .long("version")
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("renumber")
+                       .about("Moves a file to the next free file index.")
+                       .arg(
+                           Arg::with_name("FILE")
+                               .required(true)
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("with")
+                               .takes_value(true)
+                               .help("A git revision whose file indexes to keep clear of.")
+                               .long("with")
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("resolve")
+                       .about("Resolves the metadata fields a merge flagged.")
+                       .arg(
+                           Arg::with_name("FILE")
+                               .required(true)
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("ours")
+                               .help("Keep our values.")
+                               .long("ours")
+                       )
+                       .arg(
+                           Arg::with_name("theirs")
+                               .help("Take their values.")
+                               .long("theirs")
+                       )
+                       .group(ArgGroup::with_name("resolution").args(&["ours", "theirs"]).required(true)),
+                )
                 .subcommand(
                    SubCommand::with_name("update")
                        .arg(
@@ -108,6 +352,157 @@ fn main() {
                        ),
                 )
         )
+        .subcommand(
+            SubCommand::with_name("merge-driver")
+                .about("Merges a file of the filesystem for git, which registers it as the immutag merge driver.")
+                .arg(Arg::with_name("BASE").required(true).index(1))
+                .arg(Arg::with_name("OURS").required(true).index(2))
+                .arg(Arg::with_name("THEIRS").required(true).index(3))
+                .arg(Arg::with_name("PATH").required(true).index(4)),
+        )
+        .subcommand(
+            SubCommand::with_name("view")
+                .about("A tree of symlinks to the tracked files, by tag.")
+                .subcommand(
+                   SubCommand::with_name("build")
+                       .about("Builds the view, and keeps it up to date as the metadata changes.")
+                       .arg(
+                           Arg::with_name("DIR")
+                               .help("Where to build it, the last one by default.")
+                               .index(1)
+                       ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Imports tags from another tagging tool.")
+                .subcommand(
+                   SubCommand::with_name("tmsu")
+                       .about("Adds the tags of a TMSU database to the files they match, by path or fingerprint.")
+                       .arg(
+                           Arg::with_name("PATH-TO-DB")
+                               .required(true)
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("dry-run")
+                               .help("Only report what would be imported.")
+                               .long("dry-run")
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("squaretag")
+                       .about("Adds the tags in squaretag's file names, like menu[lemonade sales].txt, to the files they match.")
+                       .arg(
+                           Arg::with_name("DIR")
+                               .help("Where to look, the current directory by default.")
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("dry-run")
+                               .help("Only report what would be imported.")
+                               .long("dry-run")
+                       ),
+                )
+                .subcommand(
+                   SubCommand::with_name("tagspaces")
+                       .about("Adds the tags in TagSpaces' .ts sidecar files to the files they match.")
+                       .arg(
+                           Arg::with_name("DIR")
+                               .help("Where to look, the current directory by default.")
+                               .index(1)
+                       )
+                       .arg(
+                           Arg::with_name("dry-run")
+                               .help("Only report what would be imported.")
+                               .long("dry-run")
+                       ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Exports tags to another tagging tool.")
+                .subcommand(
+                   SubCommand::with_name("tmsu")
+                       .about("Adds the tags of the tracked files to a TMSU database, creating it if needed.")
+                       .arg(
+                           Arg::with_name("PATH-TO-DB")
+                               .help("The database, .tmsu/db by default.")
+                               .index(1)
+                       ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("index")
+                .about("A SQLite index of the files of every filesystem, which queries use when it's there.")
+                .subcommand(
+                   SubCommand::with_name("update")
+                       .about("Creates the index, or indexes what's been committed since it was updated.")
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("query")
+                .about("Finds the files with every tag given, one JSON object each.")
+                .arg(
+                    Arg::with_name("TAG")
+                    .multiple(true)
+                    .index(1)
+                )
+                .arg(
+                    Arg::with_name("alias")
+                    .takes_value(true)
+                    .help("Only files with this alias.")
+                    .long("alias")
+                )
+                .arg(
+                    Arg::with_name("addr")
+                    .takes_value(true)
+                    .help("Only the file with this file, version or content address, or file hash.")
+                    .long("addr")
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Stages changes to the tracked files as they happen, until SIGTERM.")
+                .arg(
+                    Arg::with_name("debounce")
+                    .takes_value(true)
+                    .default_value("1000")
+                    .help("Milliseconds a file has to be left alone before it's staged.")
+                    .long("debounce")
+                )
+                .arg(
+                    Arg::with_name("commit-after")
+                    .takes_value(true)
+                    .help("Commit what's staged after this many seconds without changes.")
+                    .long("commit-after")
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify-metadata")
+                .about("Checks the signature of every metadata record in a file's history.")
+                .arg(
+                    Arg::with_name("FILE")
+                    .required(true)
+                    .index(1)
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Checks a file version against the pegs and the contentsys.")
+                .arg(
+                    Arg::with_name("FILE")
+                    .required(true)
+                    .index(1)
+                )
+                .arg(
+                    Arg::with_name("version")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Version to verify.")
+                    .long("version")
+                ),
+        )
         .get_matches();
 
     if let Some(matches) = matches.subcommand_matches("init") {
@@ -147,6 +542,138 @@ fn main() {
 
             }
          }
+        if matches.subcommand_matches("peg").is_some() {
+            match filesys::peg("") {
+                Ok(peg) => {
+                    println!("Peg {}", peg.peg);
+                    println!("root: {}", peg.root);
+                    println!("manifest: {}", peg.manifest_addr);
+                    println!("commit: {}", peg.commit);
+                    println!("payload: {}", peg.payload);
+                }
+                Err(e) => {
+                    eprintln!("failed to peg: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("recover") {
+            let mnemonic = matches.value_of("MNEMONIC").unwrap();
+            let path = matches.value_of("PATH").unwrap_or("");
+            match filesys::recover(path, mnemonic) {
+                Ok(recovery) => {
+                    println!("Recovered filesys {}", recovery.filesys_addr);
+                    println!("pegs: {}", recovery.pegs);
+                    println!("forks: {}", recovery.forks);
+                    println!("versions: {}", recovery.versions);
+                    for file in recovery.files.iter() {
+                        println!("wrote {}", file);
+                    }
+                    for content_addr in recovery.missing.iter() {
+                        eprintln!("missing content {}", content_addr);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("failed to recover: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("remote") {
+            if let Some(matches) = matches.subcommand_matches("add") {
+                let name = matches.value_of("NAME").unwrap();
+                if let Err(e) = filesys::remote_add("", name, matches.value_of("URL").unwrap()) {
+                    eprintln!("failed to add remote {}: {:?}", name, e);
+                    std::process::exit(1);
+                }
+            }
+            if let Some(matches) = matches.subcommand_matches("remove") {
+                let name = matches.value_of("NAME").unwrap();
+                if let Err(e) = filesys::remote_remove("", name) {
+                    eprintln!("failed to remove remote {}: {:?}", name, e);
+                    std::process::exit(1);
+                }
+            }
+            if matches.subcommand_matches("list").is_some() {
+                match filesys::remotes("") {
+                    Ok(remotes) => {
+                        for (name, url) in remotes.iter() {
+                            println!("{} {}", name, url);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("failed to list remotes: {:?}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("push") {
+            let name = matches.value_of("REMOTE").unwrap_or("origin");
+            if let Err(e) = filesys::push("", name) {
+                eprintln!("failed to push to {}: {:?}", name, e);
+                std::process::exit(1);
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("pull") {
+            let name = matches.value_of("REMOTE").unwrap_or("origin");
+            match filesys::pull("", name, resolution(matches)) {
+                Ok(pull) => {
+                    match &pull.merge {
+                        filesys::remote::Merge::UpToDate => println!("Already up to date"),
+                        filesys::remote::Merge::FastForward => println!("Fast-forwarded to {}", pull.rev.as_deref().unwrap_or_default()),
+                        filesys::remote::Merge::Merged(commit) => println!("Merged {} in {}", pull.rev.as_deref().unwrap_or_default(), commit),
+                    }
+                    for check in pull.checks.iter() {
+                        let status = if check.ok { "ok" } else { "FAILED" };
+                        println!("{}: {} {}", check.check, status, check.detail);
+                    }
+                    if !pull.ok() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("failed to pull from {}: {:?}", name, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("rebuild-git") {
+            match filesys::rebuild_git("", matches.is_present("force")) {
+                Ok(rebuild) => {
+                    println!("Rebuilt {} commits", rebuild.commits);
+                    println!("versions: {}", rebuild.versions);
+                    println!("pegs: {}", rebuild.pegs);
+                    println!("head: {}", rebuild.head);
+                }
+                Err(e) => {
+                    eprintln!("failed to rebuild the git history: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("account") {
+            if let Err(e) = account(matches.value_of("ACCOUNT")) {
+                eprintln!("failed to set the account: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("collisions") {
+            match filesys::collisions("", matches.value_of("REV")) {
+                Ok(collisions) => {
+                    for collision in collisions.iter() {
+                        println!("{} {} {}", collision.file_index, collision.file_addr, collision.paths.join(" "));
+                    }
+                    if !collisions.is_empty() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("failed to check file indexes: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
 
     }
 
@@ -161,6 +688,14 @@ fn main() {
                     } else {
                         println!("file: {}", f);
                     }
+                    let added = match matches.is_present("scan-ledger") {
+                        true => filesys::add_content_scanned("", f, alias),
+                        false => filesys::add_content("", f, alias),
+                    };
+                    if let Err(e) = added {
+                        eprintln!("failed to stage {}: {:?}", f, e);
+                        std::process::exit(1);
+                    }
                 } else {
                     println!("file command fail")
 
@@ -176,8 +711,340 @@ fn main() {
                 }
             }
          }
+        if let Some(matches) = matches.subcommand_matches("checkout") {
+            let file = matches.value_of("FILE").unwrap();
+            let selector = if let Some(v) = matches.value_of("version") {
+                match v.parse() {
+                    Ok(version) => filesys::Selector::Version(version),
+                    Err(_) => {
+                        eprintln!("invalid version {}", v);
+                        std::process::exit(1);
+                    }
+                }
+            } else if let Some(h) = matches.value_of("hash") {
+                filesys::Selector::Hash(h.to_string())
+            } else {
+                filesys::Selector::VersionAddr(matches.value_of("version-addr").unwrap().to_string())
+            };
+            let output = matches.value_of("output");
+            let force = matches.is_present("force");
+
+            match filesys::checkout("", file, &selector, output, force) {
+                Ok((version, destination)) => println!("Checked out version {} of {} to {}", version, file, destination),
+                Err(e) => {
+                    eprintln!("failed to checkout {}: {:?}", file, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("log") {
+            let file = matches.value_of("FILE").unwrap();
+            let limit = match matches.value_of("limit").map(|l| l.parse()) {
+                Some(Ok(limit)) => Some(limit),
+                Some(Err(_)) => {
+                    eprintln!("invalid limit {}", matches.value_of("limit").unwrap());
+                    std::process::exit(1);
+                }
+                None => None,
+            };
+
+            match filesys::log("", file, limit) {
+                Ok(entries) => {
+                    if matches.is_present("json") {
+                        println!("{}", filesys::log::to_json(&entries));
+                    } else {
+                        let entries: Vec<String> = entries.iter().map(|e| e.to_string()).collect();
+                        print!("{}", entries.join("\n"));
+                    }
+                }
+                Err(e) => {
+                    eprintln!("failed to read the log of {}: {:?}", file, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("branch") {
+            let file = matches.value_of("FILE").unwrap();
+            let from_version = match matches.value_of("from-version").unwrap().parse() {
+                Ok(version) => version,
+                Err(_) => {
+                    eprintln!("invalid version {}", matches.value_of("from-version").unwrap());
+                    std::process::exit(1);
+                }
+            };
+
+            match filesys::branch("", file, from_version) {
+                Ok(branch) => println!(
+                    "Branched version {} of {} onto {}",
+                    branch.parent_version, file, branch.branch_addr
+                ),
+                Err(e) => {
+                    eprintln!("failed to branch {}: {:?}", file, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("proof") {
+            let file = matches.value_of("FILE").unwrap();
+            let version = match matches.value_of("version").unwrap().parse() {
+                Ok(version) => version,
+                Err(_) => {
+                    eprintln!("invalid version {}", matches.value_of("version").unwrap());
+                    std::process::exit(1);
+                }
+            };
+
+            match filesys::proof("", file, version) {
+                Ok(proof) => println!("{}", proof.to_json()),
+                Err(e) => {
+                    eprintln!("failed to prove version {} of {}: {:?}", version, file, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("renumber") {
+            let file = matches.value_of("FILE").unwrap();
+            match filesys::renumber("", file, matches.value_of("with")) {
+                Ok(renumbered) => println!(
+                    "Renumbered {} from index {} to {}, {}",
+                    file, renumbered.from_index, renumbered.to_index, renumbered.to_addr
+                ),
+                Err(e) => {
+                    eprintln!("failed to renumber {}: {:?}", file, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("resolve") {
+            let file = matches.value_of("FILE").unwrap();
+            match filesys::resolve("", file, resolution(matches)) {
+                Ok(resolved) if resolved.is_empty() => println!("Nothing to resolve in {}", file),
+                Ok(resolved) => {
+                    for field in resolved.iter() {
+                        println!("Resolved {}", field);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("failed to resolve {}: {:?}", file, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(matches) = matches.subcommand_matches("commit") {
+            if let Some(f) = matches.value_of("FILE") {
+                match filesys::commit("", f) {
+                    Ok(version) => println!("Committed version {} of {}", version, f),
+                    Err(e) => {
+                        eprintln!("failed to commit {}: {:?}", f, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
 
     }
+
+    if let Some(matches) = matches.subcommand_matches("merge-driver") {
+        // Git runs the driver in the filesystem directory, two below the
+        // immutag directory.
+        let file_path = matches.value_of("PATH").unwrap();
+        let merged = filesys::merge_driver(
+            "../..",
+            matches.value_of("BASE").unwrap(),
+            matches.value_of("OURS").unwrap(),
+            matches.value_of("THEIRS").unwrap(),
+            file_path,
+            filesys::Resolution::Flag,
+        );
+        match merged {
+            Ok(Some(merged)) => {
+                for conflict in merged.conflicts.iter() {
+                    eprintln!("flagged {} in {}", conflict, file_path);
+                }
+            }
+            Ok(None) => {
+                eprintln!("immutag doesn't merge {}", file_path);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("failed to merge {}: {:?}", file_path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(matches) = matches.subcommand_matches("view") {
+        if let Some(matches) = matches.subcommand_matches("build") {
+            match filesys::build_view("", matches.value_of("DIR")) {
+                Ok(view) => println!(
+                    "Built the view in {}: {} links, {} added, {} removed",
+                    view.dir, view.links, view.added, view.removed
+                ),
+                Err(e) => {
+                    eprintln!("failed to build the view: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    if let Some(matches) = matches.subcommand_matches("import") {
+        let (from, imported) = if let Some(matches) = matches.subcommand_matches("tmsu") {
+            let db = matches.value_of("PATH-TO-DB").unwrap();
+            (db, filesys::import_tmsu("", db, matches.is_present("dry-run")))
+        } else if let Some(matches) = matches.subcommand_matches("squaretag") {
+            let dir = matches.value_of("DIR").unwrap_or(".");
+            (dir, filesys::import_squaretag("", dir, matches.is_present("dry-run")))
+        } else if let Some(matches) = matches.subcommand_matches("tagspaces") {
+            let dir = matches.value_of("DIR").unwrap_or(".");
+            (dir, filesys::import_tagspaces("", dir, matches.is_present("dry-run")))
+        } else {
+            eprintln!("import from tmsu, squaretag or tagspaces");
+            std::process::exit(1);
+        };
+        match imported {
+            Ok(imported) => {
+                let verb = if imported.dry_run { "Would import" } else { "Imported" };
+                println!("{} {} tags onto {} files from {}", verb, imported.tags, imported.files, from);
+                for file_path in imported.unmatched.iter() {
+                    println!("not tracked: {}", file_path);
+                }
+                for tag in imported.skipped.iter() {
+                    println!("skipped: {}", tag);
+                }
+            }
+            Err(e) => {
+                eprintln!("failed to import from {}: {:?}", from, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(matches) = matches.subcommand_matches("export") {
+        if let Some(matches) = matches.subcommand_matches("tmsu") {
+            let db = matches.value_of("PATH-TO-DB").unwrap_or(filesys::tmsu::TMSU_DB);
+            match filesys::export_tmsu("", db) {
+                Ok(exported) => println!("Exported {} tags of {} files to {}", exported.tags, exported.files, db),
+                Err(e) => {
+                    eprintln!("failed to export to {}: {:?}", db, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    if let Some(matches) = matches.subcommand_matches("index") {
+        if matches.subcommand_matches("update").is_some() {
+            match filesys::update_index("") {
+                Ok(update) => println!(
+                    "Indexed {} files of {} filesystems in {}",
+                    update.files, update.filesystems, update.db
+                ),
+                Err(e) => {
+                    eprintln!("failed to update the index: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    if let Some(matches) = matches.subcommand_matches("query") {
+        let query = filesys::Query {
+            tags: matches.values_of("TAG").map(|t| t.map(String::from).collect()).unwrap_or_default(),
+            alias: matches.value_of("alias").map(String::from),
+            addr: matches.value_of("addr").map(String::from),
+        };
+        match filesys::query("", &query) {
+            Ok(found) => {
+                for found in found.iter() {
+                    println!("{}", found.to_json());
+                }
+            }
+            Err(e) => {
+                eprintln!("failed to query: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(matches) = matches.subcommand_matches("watch") {
+        let debounce = value_t!(matches, "debounce", u64).unwrap_or_else(|e| e.exit());
+        let commit_after = matches
+            .value_of("commit-after")
+            .map(|_| value_t!(matches, "commit-after", u64).unwrap_or_else(|e| e.exit()));
+        let options = filesys::WatchOptions {
+            debounce: Duration::from_millis(debounce),
+            commit_after: commit_after.map(Duration::from_secs),
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        for signal in [SIGTERM, SIGINT].iter() {
+            signal_hook::flag::register(*signal, stop.clone()).expect("failed to handle signals");
+        }
+        if let Err(e) = filesys::watch("", options, &stop) {
+            eprintln!("failed to watch: {:?}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(matches) = matches.subcommand_matches("verify-metadata") {
+        let file = matches.value_of("FILE").unwrap();
+        match filesys::verify_metadata("", file) {
+            Ok(verification) => {
+                println!("{}", verification.to_json());
+                if !verification.ok() {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("failed to verify the metadata of {}: {:?}", file, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(matches) = matches.subcommand_matches("verify") {
+        let file = matches.value_of("FILE").unwrap();
+        let version = match matches.value_of("version").unwrap().parse() {
+            Ok(version) => version,
+            Err(_) => {
+                eprintln!("invalid version {}", matches.value_of("version").unwrap());
+                std::process::exit(1);
+            }
+        };
+
+        match filesys::verify("", file, version) {
+            Ok(verification) => {
+                println!("{}", verification.to_json());
+                if !verification.ok() {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("failed to verify version {} of {}: {:?}", version, file, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Sets the account if one is given, then prints it and its public key.
+fn account(account: Option<&str>) -> Result<(), String> {
+    let filesys = filesys::Filesys::open("").map_err(|e| format!("{:?}", e))?;
+    let account = match account {
+        Some(account) => {
+            let account: u32 = account.parse().map_err(|_| format!("invalid account {}", account))?;
+            files::set_account("", filesys.addr.as_str(), account).map_err(|e| format!("{:?}", e))?;
+            account
+        }
+        None => filesys.account,
+    };
+    let public_key = immutag_bitcoin::message::account_public_key(&filesys.xpriv, account).map_err(|e| format!("{:?}", e))?;
+    println!("account: {}\npublic_key: {}", account, public_key);
+
+    Ok(())
+}
+
+/// How to resolve conflicting metadata fields, from `--ours` or `--theirs`.
+fn resolution(matches: &clap::ArgMatches) -> filesys::Resolution {
+    if matches.is_present("ours") {
+        filesys::Resolution::Ours
+    } else if matches.is_present("theirs") {
+        filesys::Resolution::Theirs
+    } else {
+        filesys::Resolution::Flag
+    }
 }
 
 #[cfg(test)]
@@ -403,4 +1270,243 @@ mod tests {
             "file: FILE\ntag: TAG\n"
         );
     }
+
+    #[test]
+    fn cli_commitfile() {
+        let test_path = std::path::Path::new("/tmp/immutag_commit_test");
+        let mut fixture = Fixture::new()
+           .add_dirpath(test_path.to_str().unwrap().to_string())
+           .build();
+        std::fs::write("/tmp/immutag_commit_test/README.md", "The README.").unwrap();
+
+        let mut path_cache = command_assistors::PathCache::new(test_path);
+
+        // Changing directories.
+        path_cache.switch();
+
+        Command::new("/immutag/target/debug/immutag")
+            .arg("init")
+            .output()
+            .expect("failed to execute immutag init process");
+
+        Command::new("/immutag/target/debug/immutag")
+            .arg("filesys")
+            .arg("import")
+            .arg("1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj")
+            .arg("xprv9s21ZrQH143K29TJGFSiEAAQM8SMBH2V6x5Aaf9bqvXftrs1v274STWWKfz8svukBLGEQgWqkgRhpt2CNFY89CFaqdsA3gicZeqexk2itxf")
+            .output()
+            .expect("failed to execute immutag addfilesys process");
+
+        let output_add = Command::new("/immutag/target/debug/immutag")
+            .arg("file")
+            .arg("add")
+            .arg("content")
+            .arg("README.md")
+            .output()
+            .expect("failed to execute immutag add content process");
+
+        let output_commit = Command::new("/immutag/target/debug/immutag")
+            .arg("file")
+            .arg("commit")
+            .arg("README.md")
+            .output()
+            .expect("failed to execute immutag commit process");
+
+        std::fs::write("/tmp/immutag_commit_test/README.md", "Changed.").unwrap();
+
+        let output_refused = Command::new("/immutag/target/debug/immutag")
+            .arg("file")
+            .arg("checkout")
+            .arg("README.md")
+            .arg("--version")
+            .arg("0")
+            .output()
+            .expect("failed to execute immutag checkout process");
+
+        let output_checkout = Command::new("/immutag/target/debug/immutag")
+            .arg("file")
+            .arg("checkout")
+            .arg("README.md")
+            .arg("--version")
+            .arg("0")
+            .arg("--force")
+            .output()
+            .expect("failed to execute immutag checkout process");
+
+        let output_log = Command::new("/immutag/target/debug/immutag")
+            .arg("file")
+            .arg("log")
+            .arg("README.md")
+            .arg("--json")
+            .arg("--limit")
+            .arg("1")
+            .output()
+            .expect("failed to execute immutag log process");
+
+        let output_nothing_staged = Command::new("/immutag/target/debug/immutag")
+            .arg("file")
+            .arg("commit")
+            .arg("README.md")
+            .output()
+            .expect("failed to execute immutag commit process");
+
+        let output_add_missing = Command::new("/immutag/target/debug/immutag")
+            .arg("file")
+            .arg("add")
+            .arg("content")
+            .arg("MISSING.md")
+            .output()
+            .expect("failed to execute immutag add content process");
+
+        path_cache.switch_back();
+
+        let readme = read_to_string("/tmp/immutag_commit_test/README.md").unwrap();
+        let log = String::from_utf8_lossy(&output_log.stdout).to_string();
+
+        let objects = std::fs::read_dir("/tmp/immutag_commit_test/.immutag/1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj/objects")
+            .unwrap()
+            .count();
+
+        fixture.teardown(true);
+
+        assert_eq!(String::from_utf8_lossy(&output_add.stderr), "");
+        assert_eq!(
+            String::from_utf8_lossy(&output_commit.stdout),
+            "Committed version 0 of README.md\n"
+        );
+        // The blob and the pins.
+        assert_eq!(objects, 2);
+        assert!(!output_refused.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output_checkout.stdout),
+            "Checked out version 0 of README.md to /tmp/immutag_commit_test/README.md\n"
+        );
+        assert_eq!(readme, "The README.");
+        assert!(log.starts_with("[{"));
+        assert!(log.contains("\"version_addr\":\"1QKr8NEjbhe9uW3u1wGVWTDavr5n3HZTx9\""));
+        assert!(!output_nothing_staged.status.success());
+        assert!(!output_add_missing.status.success());
+    }
+
+    #[test]
+    fn cli_peg() {
+        let test_path = std::path::Path::new("/tmp/immutag_peg_test");
+        let mut fixture = Fixture::new()
+           .add_dirpath(test_path.to_str().unwrap().to_string())
+           .build();
+        std::fs::write("/tmp/immutag_peg_test/README.md", "The README.").unwrap();
+
+        let mut path_cache = command_assistors::PathCache::new(test_path);
+
+        // Changing directories.
+        path_cache.switch();
+
+        Command::new("/immutag/target/debug/immutag")
+            .arg("init")
+            .output()
+            .expect("failed to execute immutag init process");
+
+        Command::new("/immutag/target/debug/immutag")
+            .arg("filesys")
+            .arg("import")
+            .arg("1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj")
+            .arg("xprv9s21ZrQH143K29TJGFSiEAAQM8SMBH2V6x5Aaf9bqvXftrs1v274STWWKfz8svukBLGEQgWqkgRhpt2CNFY89CFaqdsA3gicZeqexk2itxf")
+            .output()
+            .expect("failed to execute immutag addfilesys process");
+
+        Command::new("/immutag/target/debug/immutag")
+            .arg("file")
+            .arg("add")
+            .arg("content")
+            .arg("README.md")
+            .output()
+            .expect("failed to execute immutag add content process");
+
+        Command::new("/immutag/target/debug/immutag")
+            .arg("file")
+            .arg("commit")
+            .arg("README.md")
+            .output()
+            .expect("failed to execute immutag commit process");
+
+        let output_peg = Command::new("/immutag/target/debug/immutag")
+            .arg("filesys")
+            .arg("peg")
+            .output()
+            .expect("failed to execute immutag peg process");
+
+        let output_proof = Command::new("/immutag/target/debug/immutag")
+            .arg("file")
+            .arg("proof")
+            .arg("README.md")
+            .arg("--version")
+            .arg("0")
+            .output()
+            .expect("failed to execute immutag proof process");
+
+        let output_verify = Command::new("/immutag/target/debug/immutag")
+            .arg("verify")
+            .arg("README.md")
+            .arg("--version")
+            .arg("0")
+            .output()
+            .expect("failed to execute immutag verify process");
+
+        path_cache.switch_back();
+
+        let pegs = read_to_string("/tmp/immutag_peg_test/.immutag/1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj/pegs").unwrap();
+        let peg = String::from_utf8_lossy(&output_peg.stdout).to_string();
+        let proof = String::from_utf8_lossy(&output_proof.stdout).to_string();
+        let verify: serde_json::Value = serde_json::from_slice(&output_verify.stdout).unwrap();
+
+        fixture.teardown(true);
+
+        assert!(peg.starts_with("Peg 0\nroot: "));
+        assert!(peg.contains("\npayload: 494d540181"));
+        assert!(pegs.contains("height: unconfirmed"));
+        // A single version is its own root.
+        assert!(proof.contains("\"path\":[]"));
+        assert!(proof.contains("\"leaf\":\"1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9 0 "));
+        assert!(output_verify.status.success());
+        assert_eq!(verify["ok"], true);
+        assert_eq!(verify["checks"].as_array().map(|c| c.len()), Some(5));
+    }
+
+    #[test]
+    fn cli_recover() {
+        let test_path = std::path::Path::new("/tmp/immutag_recover_test");
+        let mut fixture = Fixture::new()
+           .add_dirpath(test_path.to_str().unwrap().to_string())
+           .build();
+
+        let mut path_cache = command_assistors::PathCache::new(test_path);
+
+        // Changing directories.
+        path_cache.switch();
+
+        Command::new("/immutag/target/debug/immutag")
+            .arg("init")
+            .output()
+            .expect("failed to execute immutag init process");
+
+        let output_recover = Command::new("/immutag/target/debug/immutag")
+            .arg("filesys")
+            .arg("recover")
+            .arg("certain dust pave crane renew multiply stone stuff proud flee fancy knee")
+            .output()
+            .expect("failed to execute immutag recover process");
+
+        path_cache.switch_back();
+
+        let immutag_file_content = read_to_string("/tmp/immutag_recover_test/.immutag/Immutag").unwrap();
+        let stderr = String::from_utf8_lossy(&output_recover.stderr).to_string();
+
+        fixture.teardown(true);
+
+        // Without an electrum server there's no ledger to recover from, and
+        // nothing is imported.
+        assert!(!output_recover.status.success());
+        assert!(stderr.contains("NoServer"));
+        assert!(!immutag_file_content.contains("1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj"));
+    }
 }
@@ -0,0 +1,10 @@
+use immutag_contentsys;
+
+pub use immutag_contentsys::{Cid, CidVersion, ContentsysError};
+
+/// The ipfs address of a file, computed locally.
+pub fn content_addr<T: AsRef<str>>(path: T, version: CidVersion) -> Result<String, ContentsysError> {
+    let cid = immutag_contentsys::content_addr_file(path, version)?;
+
+    Ok(cid.to_string())
+}
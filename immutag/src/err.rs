@@ -0,0 +1,110 @@
+pub use immutag_bitcoin::BitcoinError;
+pub use immutag_contentsys::ContentsysError;
+pub use immutag_file::ImmutagFileError;
+pub use immutag_filesys::FilesysError;
+pub use immutag_ledger::LedgerError;
+
+#[derive(Debug)]
+pub enum ImmutagError {
+    IoError(std::io::Error),
+    ImmutagFileError(ImmutagFileError),
+    ContentsysError(ContentsysError),
+    FilesysError(FilesysError),
+    BitcoinError(BitcoinError),
+    LedgerError(LedgerError),
+    SqliteError(rusqlite::Error),
+    Error(Error),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    NoFilesys,
+    NotTracked,
+    NothingStaged,
+    NoSuchVersion,
+    HashMismatch,
+    UncommittedChanges,
+    NothingToPeg,
+    NotPegged,
+    FilesysExists,
+    NotRecoverable,
+    NotRenumberable,
+    HistoryExists,
+    IndexCollision,
+    NoView,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub details: String,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    pub fn new(msg: &str, kind: ErrorKind) -> Error {
+        Error {
+            details: msg.to_string(),
+            kind,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl From<std::io::Error> for ImmutagError {
+    fn from(error: std::io::Error) -> Self {
+        ImmutagError::IoError(error)
+    }
+}
+
+impl From<ImmutagFileError> for ImmutagError {
+    fn from(error: ImmutagFileError) -> Self {
+        ImmutagError::ImmutagFileError(error)
+    }
+}
+
+impl From<ContentsysError> for ImmutagError {
+    fn from(error: ContentsysError) -> Self {
+        ImmutagError::ContentsysError(error)
+    }
+}
+
+impl From<FilesysError> for ImmutagError {
+    fn from(error: FilesysError) -> Self {
+        ImmutagError::FilesysError(error)
+    }
+}
+
+impl From<BitcoinError> for ImmutagError {
+    fn from(error: BitcoinError) -> Self {
+        ImmutagError::BitcoinError(error)
+    }
+}
+
+impl From<LedgerError> for ImmutagError {
+    fn from(error: LedgerError) -> Self {
+        ImmutagError::LedgerError(error)
+    }
+}
+
+impl From<rusqlite::Error> for ImmutagError {
+    fn from(error: rusqlite::Error) -> Self {
+        ImmutagError::SqliteError(error)
+    }
+}
+
+impl From<Error> for ImmutagError {
+    fn from(error: Error) -> Self {
+        ImmutagError::Error(error)
+    }
+}
@@ -57,6 +57,103 @@ pub fn get_mnemonic<T: AsRef<str>>(
 
     immutag_file::immutag(&doc, Some(bitcoin_addr.as_ref()), "mnemonic")
 }
+/// Account `k` of the collaborator's metadata key, `m / 44' / 0' / k'`, the
+/// `account` field of the filesystem's entry. `None` if it isn't set.
+pub fn get_account<T: AsRef<str>>(
+    path: T,
+    bitcoin_addr: T,
+) -> Result<Option<u32>, ImmutagFileError> {
+    let (_, filepath) = paths(path);
+    let doc = open(filepath)?;
+
+    Ok(immutag(&doc, Some(bitcoin_addr.as_ref()), "account").ok().and_then(|a| a.parse().ok()))
+}
+
+pub fn set_account<T: AsRef<str>>(
+    path: T,
+    bitcoin_addr: T,
+    account: u32,
+) -> Result<(), ImmutagFileError> {
+    let (_, filepath) = paths(path);
+    let doc = open(filepath.clone())?;
+    let doc = insert_entry(
+        &doc,
+        Some(bitcoin_addr.as_ref()),
+        "account",
+        account.to_string().as_str(),
+    );
+
+    write(doc?, filepath)
+}
+
+/// Directory of the filesystem's tag view, the `view` field of the
+/// filesystem's entry. `None` if one hasn't been built.
+pub fn get_view<T: AsRef<str>>(
+    path: T,
+    bitcoin_addr: T,
+) -> Result<Option<String>, ImmutagFileError> {
+    let (_, filepath) = paths(path);
+    let doc = open(filepath)?;
+
+    Ok(immutag(&doc, Some(bitcoin_addr.as_ref()), "view").ok())
+}
+
+pub fn set_view<T: AsRef<str>>(
+    path: T,
+    bitcoin_addr: T,
+    view: T,
+) -> Result<(), ImmutagFileError> {
+    let (_, filepath) = paths(path);
+    let doc = open(filepath.clone())?;
+    let doc = insert_entry(&doc, Some(bitcoin_addr.as_ref()), "view", view.as_ref());
+
+    write(doc?, filepath)
+}
+
+/// Name of the content store the filesystems use, the `contentsys` field of
+/// the `immutag` entry. `None` if it isn't set.
+pub fn get_contentsys<T: AsRef<str>>(path: T) -> Result<Option<String>, ImmutagFileError> {
+    let (_, filepath) = paths(path);
+    let doc = open(filepath)?;
+
+    Ok(immutag(&doc, Some("immutag"), "contentsys").ok())
+}
+
+/// Address of the ipfs node's RPC API, the `ipfs_api` field of the `immutag`
+/// entry. `None` if it isn't set.
+pub fn get_ipfs_api<T: AsRef<str>>(path: T) -> Result<Option<String>, ImmutagFileError> {
+    let (_, filepath) = paths(path);
+    let doc = open(filepath)?;
+
+    Ok(immutag(&doc, Some("immutag"), "ipfs_api").ok())
+}
+
+/// Network pegs and forks go on, the `ledger` field of the `immutag` entry.
+/// `None` if it isn't set.
+pub fn get_ledger<T: AsRef<str>>(path: T) -> Result<Option<String>, ImmutagFileError> {
+    let (_, filepath) = paths(path);
+    let doc = open(filepath)?;
+
+    Ok(immutag(&doc, Some("immutag"), "ledger").ok())
+}
+
+/// ElectrumX server the ledger is read through, the `electrum` field of the
+/// `immutag` entry. `None` if it isn't set.
+pub fn get_electrum<T: AsRef<str>>(path: T) -> Result<Option<String>, ImmutagFileError> {
+    let (_, filepath) = paths(path);
+    let doc = open(filepath)?;
+
+    Ok(immutag(&doc, Some("immutag"), "electrum").ok())
+}
+
+/// Bitcoin addresses of the imported filesystems.
+pub fn get_filesystems<T: AsRef<str>>(path: T) -> Result<Vec<String>, ImmutagFileError> {
+    let (_, filepath) = paths(path);
+    let doc = open(filepath)?;
+
+    Ok(immutag_file::filesystems(&doc))
+}
+
 ///! Retrieve field data from a Immutag file. For example, if the file name is provided, it will attempt to retrieve the field `immutag` nested in the `README.md` entry.
 ///!  ```ignore
 ///!  [README.md]
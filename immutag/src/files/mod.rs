@@ -29,3 +29,53 @@ pub fn get_mnemonic<T: AsRef<str>>(
 ) -> Result<String, ImmutagFileError> {
     file::get_mnemonic(path, bitcoin_addr)
 }
+
+pub fn get_account<T: AsRef<str>>(
+    path: T,
+    bitcoin_addr: T
+) -> Result<Option<u32>, ImmutagFileError> {
+    file::get_account(path, bitcoin_addr)
+}
+
+pub fn set_account<T: AsRef<str>>(
+    path: T,
+    bitcoin_addr: T,
+    account: u32
+) -> Result<(), ImmutagFileError> {
+    file::set_account(path, bitcoin_addr, account)
+}
+
+pub fn get_view<T: AsRef<str>>(
+    path: T,
+    bitcoin_addr: T
+) -> Result<Option<String>, ImmutagFileError> {
+    file::get_view(path, bitcoin_addr)
+}
+
+pub fn set_view<T: AsRef<str>>(
+    path: T,
+    bitcoin_addr: T,
+    view: T
+) -> Result<(), ImmutagFileError> {
+    file::set_view(path, bitcoin_addr, view)
+}
+
+pub fn get_contentsys<T: AsRef<str>>(path: T) -> Result<Option<String>, ImmutagFileError> {
+    file::get_contentsys(path)
+}
+
+pub fn get_ipfs_api<T: AsRef<str>>(path: T) -> Result<Option<String>, ImmutagFileError> {
+    file::get_ipfs_api(path)
+}
+
+pub fn get_ledger<T: AsRef<str>>(path: T) -> Result<Option<String>, ImmutagFileError> {
+    file::get_ledger(path)
+}
+
+pub fn get_electrum<T: AsRef<str>>(path: T) -> Result<Option<String>, ImmutagFileError> {
+    file::get_electrum(path)
+}
+
+pub fn get_filesystems<T: AsRef<str>>(path: T) -> Result<Vec<String>, ImmutagFileError> {
+    file::get_filesystems(path)
+}
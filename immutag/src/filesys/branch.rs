@@ -0,0 +1,190 @@
+/*
+Forks a file version onto a new branch, a hardened child of the file address
+with a version-store entry of its own.
+*/
+use std::fs::create_dir_all;
+
+use immutag_bitcoin;
+use immutag_bitcoin::opreturn::{self, Payload};
+use immutag_contentsys::Cid;
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::{pending, vcs, versionstore};
+
+use crate::err::{Error, ErrorKind, ImmutagError};
+use crate::filesys::signing;
+use crate::filesys::version::{canonical, stamp, tracked, Filesys};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Branch {
+    /// Counts from 1, for each file.
+    pub branch: u32,
+    pub branch_addr: String,
+    pub parent_addr: String,
+    pub parent_version: usize,
+    /// What the fork's OP_RETURN will record, once broadcast.
+    pub pending: Record,
+}
+
+/// Forks `from_version` of `file` onto the file's next branch.
+pub fn branch<T: AsRef<str>>(path: T, file: T, from_version: usize) -> Result<Branch, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let file_path = canonical(file)?;
+    let (file_addr, file_index) = tracked(&filesys, &file_path)?;
+
+    let versionstore_path = filesys.versionstore();
+    let metadata_file = versionstore::path_metadata_file(versionstore_path.clone(), file_addr.clone());
+    let mut records = metadata::records(metadata_file.clone())?;
+    let mut record = match records.get(from_version) {
+        Some(record) => record.clone(),
+        None => {
+            let msg = format!("{} has no version {}", file_path, from_version);
+            return Err(ImmutagError::from(Error::new(&msg, ErrorKind::NoSuchVersion)));
+        }
+    };
+
+    let branch = branches(&versionstore_path, &file_addr)? + 1;
+    let branch_addr = immutag_bitcoin::branch_addr(&filesys.xpriv, file_index, branch)?;
+    let version_addr = immutag_bitcoin::branch_version_addr(&filesys.xpriv, file_index, branch, 0)?;
+
+    // The branch starts out with the forked version as its version 0.
+    create_dir_all(versionstore::path_file(versionstore_path.clone(), branch_addr.clone()))?;
+    let file_hash = record.get(metadata::FILE_HASH).unwrap_or_default().to_string();
+    versionstore::write_versions(
+        versionstore::path_versions_file(versionstore_path.clone(), branch_addr.clone()),
+        &[versionstore::Version {
+            file_hash,
+            file_addr: branch_addr.clone(),
+        }],
+    )?;
+    record.remove(metadata::BRANCHES);
+    record.set(metadata::FILE_ADDR, branch_addr.as_str());
+    record.set(metadata::VERSION_ADDR, version_addr.as_str());
+    record.set(metadata::BRANCH, branch.to_string().as_str());
+    record.set(metadata::PARENT_ADDR, file_addr.as_str());
+    record.set(metadata::PARENT_VERSION, from_version.to_string().as_str());
+    let signature = vcs::signature(filesys.path.as_str())?;
+    stamp(&mut record, &signature);
+    signing::sign(&filesys, &mut record)?;
+    metadata::write_records(
+        versionstore::path_metadata_file(versionstore_path, branch_addr.clone()),
+        &[record.clone()],
+    )?;
+
+    let parent = &mut records[from_version];
+    let children = match parent.get(metadata::BRANCHES) {
+        Some(children) => format!("{} {}", children, branch_addr),
+        None => branch_addr.clone(),
+    };
+    parent.set(metadata::BRANCHES, children.as_str());
+    metadata::write_records(metadata_file, &records)?;
+
+    let content_addr = record.get(metadata::CONTENT_ADDR).unwrap_or_default();
+    let payload = Payload {
+        record: opreturn::Record::Fork {
+            branch_addr: branch_addr.clone(),
+        },
+        content_addr: content_addr.parse::<Cid>()?.to_bytes(),
+        parent: Some(file_addr.clone()),
+    };
+
+    let mut op = Record::new();
+    op.set(pending::OP, pending::OP_FORK);
+    op.set(metadata::FILE_ADDR, file_addr.as_str());
+    op.set(pending::VERSION, from_version.to_string().as_str());
+    op.set(metadata::CONTENT_ADDR, content_addr);
+    op.set(pending::BRANCH_ADDR, branch_addr.as_str());
+    op.set(pending::PAYLOAD, hex::encode(payload.encode()?).as_str());
+    pending::add(immutag_filesys::path_pending(&filesys.path), op.clone())?;
+
+    let message = format!("Branch version {} of {} onto {}", from_version, file_addr, branch_addr);
+    vcs::commit_by(filesys.path.as_str(), message.as_str(), &signature)?;
+
+    Ok(Branch {
+        branch,
+        branch_addr,
+        parent_addr: file_addr,
+        parent_version: from_version,
+        pending: op,
+    })
+}
+
+/// How many branches have been forked from the file.
+fn branches(versionstore_path: &str, file_addr: &str) -> Result<u32, ImmutagError> {
+    let mut count = 0;
+    for addr in versionstore::file_addrs(versionstore_path)? {
+        let metadata_file = versionstore::path_metadata_file(versionstore_path, addr.as_str());
+        let records = metadata::records(metadata_file)?;
+        if records.first().and_then(|r| r.get(metadata::PARENT_ADDR)) == Some(file_addr) {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::{add_content, commit};
+    use crate::test_support::setup;
+    use std::fs::write;
+
+    #[test]
+    fn branch_versions() {
+        let path = "/tmp/immutag_branch_tests";
+        let file = "/tmp/immutag_branch_tests/lemonade_stand.mp4";
+        let mut fixture = setup(path);
+
+        write(file, b"foobar\n").unwrap();
+        add_content(path, file, Some("lemonade_stand")).unwrap();
+        commit(path, file).unwrap();
+        write(file, b"hello world\n").unwrap();
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+
+        let first = branch(path, file, 0).unwrap();
+        let second = branch(path, file, 0).unwrap();
+        let missing = branch(path, file, 2);
+
+        let filesys = Filesys::open(path).unwrap();
+        let versionstore_path = filesys.versionstore();
+        let parent = metadata::records(versionstore::path_metadata_file(
+            versionstore_path.as_str(),
+            "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9",
+        )).unwrap();
+        let child = metadata::records(versionstore::path_metadata_file(
+            versionstore_path.as_str(),
+            first.branch_addr.as_str(),
+        )).unwrap();
+        let child_versions = versionstore::versions(versionstore::path_versions_file(
+            versionstore_path.as_str(),
+            first.branch_addr.as_str(),
+        )).unwrap();
+        let pending = pending::pending(immutag_filesys::path_pending(&filesys.path)).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!((first.branch, second.branch), (1, 2));
+        assert_eq!(first.branch_addr, "176MrXcWHpGxMuXg8v31bChp2huL8XuzjP");
+        assert!(missing.is_err());
+        assert_eq!(
+            parent[0].get(metadata::BRANCHES),
+            Some(format!("{} {}", first.branch_addr, second.branch_addr).as_str())
+        );
+        assert_eq!(parent[1].get(metadata::BRANCHES), None);
+        assert_eq!(child.len(), 1);
+        assert_eq!(child[0].get(metadata::PARENT_ADDR), Some("1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9"));
+        assert_eq!(child[0].get(metadata::PARENT_VERSION), Some("0"));
+        assert_eq!(child[0].get(metadata::VERSION_ADDR), Some("18N3aRLGFxfhE7tFVz5tbyQeopgmgZXJ7o"));
+        assert_eq!(child[0].get(metadata::ALIAS), Some("lemonade_stand"));
+        assert_eq!(child_versions[0].file_hash, immutag_contentsys::file_hash(b"foobar\n"));
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].get(pending::OP), Some(pending::OP_FORK));
+        assert_eq!(pending[1].get(pending::BRANCH_ADDR), Some(second.branch_addr.as_str()));
+        assert_eq!(pending[0].get(metadata::CONTENT_ADDR), Some("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"));
+        let payload = hex::decode(pending[0].get(pending::PAYLOAD).unwrap()).unwrap();
+        let payload = Payload::decode(&payload).unwrap();
+        assert_eq!(payload.record, opreturn::Record::Fork { branch_addr: first.branch_addr.clone() });
+        assert_eq!(payload.parent, Some("1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9".to_string()));
+    }
+}
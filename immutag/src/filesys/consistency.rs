@@ -0,0 +1,92 @@
+/*
+Checks a filesystem holds together, as it should after a pull brings in a
+collaborator's commits: no index is claimed twice, every version has its
+metadata, the path-cache doesn't give an address two paths, every peg's
+commit is in the history, and no merge conflict is left flagged.
+*/
+use std::collections::HashMap;
+
+use immutag_filesys::indexes;
+use immutag_filesys::merge;
+use immutag_filesys::metadata;
+use immutag_filesys::{pathcache, pegs, vcs, versionstore};
+
+use crate::err::ImmutagError;
+use crate::filesys::verify::{failed, passed, Check};
+use crate::filesys::version::Filesys;
+
+pub const INDEX_CLAIMS: &str = "index_claims";
+pub const VERSION_STORE: &str = "version_store";
+pub const PATH_CACHE: &str = "path_cache";
+pub const PEG_COMMITS: &str = "peg_commits";
+pub const MERGE_CONFLICTS: &str = "merge_conflicts";
+
+/// Runs every check on the filesystem. A failed check is reported, not
+/// returned as an error.
+pub fn consistency<T: AsRef<str>>(path: T) -> Result<Vec<Check>, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let mut checks = Vec::new();
+
+    let claims = indexes::claims(filesys.metadata())?;
+    let collisions = indexes::collisions(&claims);
+    checks.push(match collisions.first() {
+        None => passed(INDEX_CLAIMS, format!("{} claims, none for the same index", claims.len())),
+        Some(c) => failed(INDEX_CLAIMS, format!("index {} is claimed for {}", c.file_index, c.paths.join(" "))),
+    });
+
+    let versionstore_path = filesys.versionstore();
+    let file_addrs = versionstore::file_addrs(versionstore_path.as_str())?;
+    let mut broken = Vec::new();
+    let mut flagged = Vec::new();
+    for file_addr in file_addrs.iter() {
+        let versions_file = versionstore::path_versions_file(versionstore_path.as_str(), file_addr.as_str());
+        let metadata_file = versionstore::path_metadata_file(versionstore_path.as_str(), file_addr.as_str());
+        let versions = versionstore::versions(versions_file)?;
+        let records = metadata::records(metadata_file)?;
+        let addressed = versions.iter().all(|v| &v.file_addr == file_addr)
+            && records.iter().all(|r| r.get(metadata::FILE_ADDR).is_none_or(|a| a == file_addr));
+        if versions.len() != records.len() || !addressed {
+            broken.push(file_addr.clone());
+        }
+        let conflicts = records.iter().any(|r| r.fields.iter().any(|(k, _)| k.starts_with(merge::CONFLICT_PREFIX)));
+        if conflicts {
+            flagged.push(file_addr.clone());
+        }
+    }
+    checks.push(match broken.is_empty() {
+        true => passed(VERSION_STORE, format!("{} files, each version with its metadata", file_addrs.len())),
+        false => failed(VERSION_STORE, format!("the versions and metadata of {} don't match", broken.join(" "))),
+    });
+
+    let entries = pathcache::entries(filesys.pathcache())?;
+    let mut paths: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (file_addr, file_path) in entries.iter() {
+        paths.entry(file_addr.as_str()).or_default().push(file_path.as_str());
+    }
+    let mut doubled: Vec<&str> = paths.iter().filter(|(_, p)| p.len() > 1).map(|(a, _)| *a).collect();
+    doubled.sort();
+    checks.push(match doubled.is_empty() {
+        true => passed(PATH_CACHE, format!("{} paths, one for each address", entries.len())),
+        false => failed(PATH_CACHE, format!("{} have more than one path", doubled.join(" "))),
+    });
+
+    let pegged = pegs::pegs(immutag_filesys::path_pegs(&filesys.path))?;
+    let mut missing = Vec::new();
+    for (pos, record) in pegged.iter().enumerate() {
+        let commit = record.get(pegs::COMMIT).unwrap_or_default();
+        if commit.is_empty() || vcs::resolve(filesys.path.as_str(), commit).is_err() {
+            missing.push(pegs::number(pos, record).to_string());
+        }
+    }
+    checks.push(match missing.is_empty() {
+        true => passed(PEG_COMMITS, format!("{} pegs, each commit in the history", pegged.len())),
+        false => failed(PEG_COMMITS, format!("the commits of pegs {} aren't in the history", missing.join(" "))),
+    });
+
+    checks.push(match flagged.is_empty() {
+        true => passed(MERGE_CONFLICTS, "no fields flagged by a merge".to_string()),
+        false => failed(MERGE_CONFLICTS, format!("{} have fields to resolve", flagged.join(" "))),
+    });
+
+    Ok(checks)
+}
@@ -0,0 +1,325 @@
+/*
+Imports tags kept by other tagging tools. Each importer finds the tagged
+paths its way, and they're matched to tracked files by path, or else by
+fingerprint, the file hash of the content. The tags are added to the latest
+version of each file. A dry run only reports what would be added.
+*/
+use std::collections::HashMap;
+use std::fs::{read, read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::{pathcache, vcs, versionstore};
+
+use crate::err::ImmutagError;
+use crate::filesys::version::{canonical, Filesys};
+use crate::filesys::{signing, view};
+
+/// Where TagSpaces keeps the sidecar files of a directory's files.
+pub const TAGSPACES_DIR: &str = ".ts";
+/// The sidecar of a TagSpaces directory itself, rather than a file.
+const TAGSPACES_FOLDER: &str = "tsm.json";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagImport {
+    /// Files that were, or with a dry run would be, given tags they didn't
+    /// have.
+    pub files: usize,
+    pub tags: usize,
+    /// Tagged paths that aren't tracked.
+    pub unmatched: Vec<String>,
+    /// Tags that can't be field names, like ones with a `:`.
+    pub skipped: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Tags another tool gave a path.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Tagged {
+    pub file_path: PathBuf,
+    /// What the tool last saw of the content, if it keeps it. Otherwise the
+    /// file hash of the path is used.
+    pub fingerprint: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Imports the tags squaretag keeps in the names of the files under `dir`,
+/// like `menu[lemonade sales].txt`. Tags are split on spaces or commas.
+pub fn import_squaretag<T: AsRef<str>>(path: T, dir: T, dry_run: bool) -> Result<TagImport, ImmutagError> {
+    let mut file_paths = Vec::new();
+    walk(Path::new(dir.as_ref()), &mut file_paths)?;
+
+    let mut tagged = Vec::new();
+    for file_path in file_paths {
+        let name = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let tags = filename_tags(name.as_str());
+        if !tags.is_empty() {
+            tagged.push(Tagged {
+                file_path,
+                fingerprint: None,
+                tags,
+            });
+        }
+    }
+
+    apply(path.as_ref(), "squaretag", tagged, dry_run)
+}
+
+/// Imports the tags of the TagSpaces sidecar files under `dir`: the tags of
+/// `notes.txt` are in `.ts/notes.txt.json` beside it.
+pub fn import_tagspaces<T: AsRef<str>>(path: T, dir: T, dry_run: bool) -> Result<TagImport, ImmutagError> {
+    let mut file_paths = Vec::new();
+    walk(Path::new(dir.as_ref()), &mut file_paths)?;
+
+    let mut tagged = Vec::new();
+    for sidecar in file_paths {
+        let sidecar_dir = match sidecar.parent() {
+            Some(sidecar_dir) if sidecar_dir.file_name() == Some(TAGSPACES_DIR.as_ref()) => sidecar_dir,
+            _ => continue,
+        };
+        let name = sidecar.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let name = match name.strip_suffix(".json") {
+            Some(name) if name != TAGSPACES_FOLDER.trim_end_matches(".json") => name.to_string(),
+            _ => continue,
+        };
+        let tags = sidecar_tags(read_to_string(&sidecar)?.as_str());
+        if !tags.is_empty() {
+            tagged.push(Tagged {
+                file_path: sidecar_dir.parent().unwrap_or(sidecar_dir).join(name),
+                fingerprint: None,
+                tags,
+            });
+        }
+    }
+
+    apply(path.as_ref(), "TagSpaces", tagged, dry_run)
+}
+
+/// Matches the tagged paths to tracked files and adds their tags, committing
+/// the metadata as imported from `source`.
+pub(crate) fn apply(path: &str, source: &str, tagged: Vec<Tagged>, dry_run: bool) -> Result<TagImport, ImmutagError> {
+    let filesys = Filesys::open(path)?;
+    let by_path: HashMap<String, String> = pathcache::entries(filesys.pathcache())?
+        .into_iter()
+        .map(|(addr, file_path)| (file_path, addr))
+        .collect();
+    let mut by_hash = HashMap::new();
+    for file_addr in by_path.values() {
+        if let Some(record) = records(&filesys, file_addr)?.pop() {
+            if let Some(file_hash) = record.get(metadata::FILE_HASH) {
+                by_hash.insert(file_hash.to_string(), file_addr.clone());
+            }
+        }
+    }
+
+    let mut imported = TagImport {
+        files: 0,
+        tags: 0,
+        unmatched: Vec::new(),
+        skipped: Vec::new(),
+        dry_run,
+    };
+    let mut committed = false;
+    for tagged in tagged {
+        let file_path = match tagged.file_path.exists() {
+            true => canonical(tagged.file_path.to_string_lossy())?,
+            false => tagged.file_path.to_string_lossy().to_string(),
+        };
+        let fingerprint = match tagged.fingerprint {
+            Some(fingerprint) => Some(fingerprint),
+            None if tagged.file_path.is_file() => Some(immutag_contentsys::file_hash(&read(&tagged.file_path)?)),
+            None => None,
+        };
+        let file_addr = match by_path.get(&file_path).or_else(|| fingerprint.and_then(|f| by_hash.get(&f))) {
+            Some(file_addr) => file_addr,
+            None => {
+                imported.unmatched.push(file_path);
+                continue;
+            }
+        };
+
+        let mut records = records(&filesys, file_addr)?;
+        let record = match records.last_mut() {
+            Some(record) => record,
+            None => continue,
+        };
+        let mut added = 0;
+        for tag in tagged.tags {
+            if tag.contains(':') || tag.contains('\n') {
+                imported.skipped.push(format!("{} of {}", tag, file_path));
+            } else if !record.tags().contains(&tag) {
+                record.add_tag(tag);
+                added += 1;
+            }
+        }
+        if added > 0 {
+            imported.files += 1;
+            imported.tags += added;
+            if !dry_run {
+                signing::sign(&filesys, record)?;
+                let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr.clone());
+                metadata::write_records(metadata_file, &records)?;
+                committed = true;
+            }
+        }
+    }
+
+    if committed {
+        let message = format!("Import tags from {}", source);
+        vcs::commit(filesys.path.as_str(), message.as_str())?;
+        view::refresh(path)?;
+    }
+
+    Ok(imported)
+}
+
+/// The records of a file, whose last is its latest version.
+pub(crate) fn records(filesys: &Filesys, file_addr: &str) -> Result<Vec<Record>, ImmutagError> {
+    let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr.to_string());
+
+    Ok(metadata::records(metadata_file)?)
+}
+
+/// The tags in the brackets of a name, before its extension.
+fn filename_tags(name: &str) -> Vec<String> {
+    let (open, close) = match (name.rfind('['), name.rfind(']')) {
+        (Some(open), Some(close)) if open < close => (open, close),
+        _ => return Vec::new(),
+    };
+    let rest = &name[close + 1..];
+    if !rest.is_empty() && !rest.starts_with('.') {
+        return Vec::new();
+    }
+
+    name[open + 1..close]
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.to_string())
+        .collect()
+}
+
+/// The titles of the tags in a sidecar file.
+fn sidecar_tags(sidecar: &str) -> Vec<String> {
+    let sidecar: serde_json::Value = match serde_json::from_str(sidecar) {
+        Ok(sidecar) => sidecar,
+        Err(_) => return Vec::new(),
+    };
+    let tags = match sidecar["tags"].as_array() {
+        Some(tags) => tags,
+        None => return Vec::new(),
+    };
+
+    tags.iter()
+        .filter_map(|tag| tag["title"].as_str())
+        .filter(|title| !title.is_empty())
+        .map(|title| title.to_string())
+        .collect()
+}
+
+/// Every file under `dir`, sorted, leaving out immutag's and git's own.
+fn walk(dir: &Path, file_paths: &mut Vec<PathBuf>) -> Result<(), ImmutagError> {
+    let mut entries = Vec::new();
+    for entry in read_dir(dir)? {
+        entries.push(entry?.path());
+    }
+    entries.sort();
+
+    for entry in entries {
+        let name = entry.file_name().unwrap_or_default();
+        if name == ".immutag" || name == ".git" {
+            continue;
+        }
+        if entry.is_dir() && !entry.symlink_metadata()?.file_type().is_symlink() {
+            walk(&entry, file_paths)?;
+        } else {
+            file_paths.push(entry);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_filename_tags() {
+        assert_eq!(filename_tags("menu[lemonade sales].txt"), vec!["lemonade", "sales"]);
+        assert_eq!(filename_tags("menu[lemonade,year=2020]"), vec!["lemonade", "year=2020"]);
+        assert_eq!(filename_tags("menu.txt"), Vec::<String>::new());
+        assert_eq!(filename_tags("[draft] menu.txt"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn parse_sidecar_tags() {
+        let sidecar = r##"{"appName":"TagSpaces","tags":[{"title":"sales","color":"#008000","type":"sidecar"},{"title":"summer"}]}"##;
+        assert_eq!(sidecar_tags(sidecar), vec!["sales", "summer"]);
+        assert_eq!(sidecar_tags("{\"description\":\"\"}"), Vec::<String>::new());
+    }
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::{add_content, commit};
+    use crate::test_support::setup;
+    use std::fs::{create_dir_all, write};
+
+    const MENU_ADDR: &str = "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9";
+    const PRICES_ADDR: &str = "13DfKEsGmgrdZ3zgvHzsmP58X9dNW1Vyyy";
+
+    fn tags(path: &str, file_addr: &str) -> Vec<String> {
+        let filesys = Filesys::open(path).unwrap();
+        let mut tags = records(&filesys, file_addr).unwrap().pop().unwrap().tags();
+        tags.sort();
+        tags
+    }
+
+    #[test]
+    fn import_squaretag_and_tagspaces() {
+        let path = "/tmp/immutag_import/";
+        let menu = "/tmp/immutag_import/menu[lemonade sales].txt";
+        let prices = "/tmp/immutag_import/stand/prices";
+        let mut fixture = setup(path);
+        create_dir_all("/tmp/immutag_import/stand/.ts").unwrap();
+        for (file, content) in [(menu, "lemonade"), (prices, "$1")].iter() {
+            write(file, content).unwrap();
+            add_content(path, *file, None).unwrap();
+            commit(path, *file).unwrap();
+        }
+        // A renamed copy is matched by its content, an untracked file isn't.
+        write("/tmp/immutag_import/stand/prices[summer].bak", "$1").unwrap();
+        write("/tmp/immutag_import/stand/flyer[sales].txt", "Come by").unwrap();
+        write(
+            "/tmp/immutag_import/stand/.ts/prices.json",
+            r#"{"appName":"TagSpaces","tags":[{"title":"sales","type":"sidecar"},{"title":"time: noon"}]}"#,
+        )
+        .unwrap();
+        write("/tmp/immutag_import/stand/.ts/tsm.json", r#"{"tags":[{"title":"stand"}]}"#).unwrap();
+
+        let dry_run = import_squaretag(path, path, true).unwrap();
+        let untouched = tags(path, MENU_ADDR);
+        let squaretag = import_squaretag(path, path, false).unwrap();
+        let tagspaces = import_tagspaces(path, path, false).unwrap();
+        let menu_tags = tags(path, MENU_ADDR);
+        let price_tags = tags(path, PRICES_ADDR);
+        let verification = crate::filesys::verify_metadata(path, prices).unwrap();
+
+        fixture.teardown(true);
+
+        assert!(dry_run.dry_run);
+        assert_eq!(dry_run.files, 2);
+        assert_eq!(dry_run.tags, 3);
+        assert_eq!(dry_run.unmatched, vec!["/tmp/immutag_import/stand/flyer[sales].txt".to_string()]);
+        assert!(untouched.is_empty());
+        assert_eq!(squaretag, TagImport { dry_run: false, ..dry_run });
+        assert_eq!(tagspaces.files, 1);
+        assert_eq!(tagspaces.tags, 1);
+        assert!(tagspaces.unmatched.is_empty());
+        assert_eq!(tagspaces.skipped, vec!["time: noon of /tmp/immutag_import/stand/prices".to_string()]);
+        assert_eq!(menu_tags, vec!["lemonade", "sales"]);
+        assert_eq!(price_tags, vec!["sales", "summer"]);
+        assert!(verification.ok(), "{:?}", verification.records);
+    }
+}
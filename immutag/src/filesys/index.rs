@@ -0,0 +1,314 @@
+/*
+Picks the BIP-44 index a new file derives its address from. Collaborators on
+a filesystem each add files, so an index is taken if it's claimed in the
+filesystem's metadata or recorded in the version-store. Renumbering, or an
+add that asks for it, also takes those that have history on the ledger within
+the gap limit. Two collaborators can still take the same index before they
+merge; the file that isn't on the ledger yet can be renumbered to the next
+free one.
+*/
+use std::collections::HashSet;
+use std::fs::{remove_dir_all, rename};
+
+use immutag_filesys::indexes::{self, Collision};
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::{pathcache, pegs, pending, stage, vcs, versionstore};
+use immutag_ledger::Ledger;
+
+use crate::err::{Error, ErrorKind, ImmutagError};
+use crate::filesys::version::{canonical, tracked, Filesys};
+
+/// Unused addresses in a row before a scan gives up, as in BIP-44.
+pub const GAP_LIMIT: u32 = 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Renumbered {
+    pub file_path: String,
+    pub from_index: u32,
+    pub to_index: u32,
+    pub from_addr: String,
+    pub to_addr: String,
+}
+
+/// The index after the last one taken. `also_used` are indexes taken
+/// somewhere the filesystem can't see, like a collaborator's commits. With
+/// `ledger`, so are the indexes that have history on it, unless it can't be
+/// read, when the filesystem's own claims are all there is to go on.
+pub fn next_index(filesys: &Filesys, ledger: Option<&dyn Ledger>, also_used: &HashSet<u32>) -> Result<u32, ImmutagError> {
+    let mut used = used(filesys)?;
+    used.extend(also_used);
+    let mut last = used.iter().max().cloned().unwrap_or(0);
+
+    if let Some(Ok(on_ledger)) = ledger.map(|ledger| last_on_ledger(filesys, ledger, &used)) {
+        last = last.max(on_ledger);
+    }
+
+    Ok(last + 1)
+}
+
+/// Indexes claimed for more than one file, here or, with `rev`, in the
+/// filesystem as it is at that git revision, like a fetched remote branch.
+pub fn collisions<T: AsRef<str>>(path: T, rev: Option<T>) -> Result<Vec<Collision>, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let mut claims = indexes::claims(filesys.metadata())?;
+    if let Some(rev) = rev {
+        claims.extend(claims_at(&filesys, rev.as_ref())?);
+    }
+
+    Ok(indexes::collisions(&claims))
+}
+
+/// Moves `file` to the next free index, one none of the claims at `rev`
+/// take either. A file that's been pegged, forked or has something pending
+/// for the ledger keeps its index.
+pub fn renumber<T: AsRef<str>>(path: T, file: T, rev: Option<T>) -> Result<Renumbered, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let file_path = canonical(file)?;
+    let (from_addr, from_index) = tracked(&filesys, &file_path)?;
+    on_ledger(&filesys, &file_path, &from_addr)?;
+
+    let mut also_used = HashSet::new();
+    if let Some(rev) = rev {
+        also_used.extend(claims_at(&filesys, rev.as_ref())?.iter().filter_map(indexes::index));
+    }
+    let ledger = filesys.ledger(path.as_ref())?;
+    let to_index = next_index(&filesys, ledger.as_deref(), &also_used)?;
+    let to_addr = immutag_bitcoin::file_addr(&filesys.xpriv, to_index)?;
+
+    let versionstore_path = filesys.versionstore();
+    let from_dir = versionstore::path_file(versionstore_path.as_str(), from_addr.as_str());
+    let to_dir = versionstore::path_file(versionstore_path.as_str(), to_addr.as_str());
+    if std::path::Path::new(&from_dir).exists() {
+        let versions_file = versionstore::path_versions_file(versionstore_path.as_str(), from_addr.as_str());
+        let versions: Vec<versionstore::Version> = versionstore::versions(versions_file.as_str())?
+            .into_iter()
+            .map(|v| versionstore::Version {
+                file_hash: v.file_hash,
+                file_addr: to_addr.clone(),
+            })
+            .collect();
+        versionstore::write_versions(versions_file, &versions)?;
+
+        let metadata_file = versionstore::path_metadata_file(versionstore_path.as_str(), from_addr.as_str());
+        let mut records = metadata::records(metadata_file.as_str())?;
+        for (version, record) in records.iter_mut().enumerate() {
+            renumber_record(record, &to_addr, to_index);
+            let version_addr = immutag_bitcoin::version_addr(&filesys.xpriv, to_index, version as u32)?;
+            record.set(metadata::VERSION_ADDR, version_addr.as_str());
+        }
+        metadata::write_records(metadata_file, &records)?;
+
+        if std::path::Path::new(&to_dir).exists() {
+            remove_dir_all(&to_dir)?;
+        }
+        rename(&from_dir, &to_dir)?;
+    }
+
+    if let Some(mut record) = stage::unstage(filesys.stage(), from_addr.clone())? {
+        renumber_record(&mut record, &to_addr, to_index);
+        stage::stage(filesys.stage(), record)?;
+    }
+
+    let entries: Vec<(String, String)> = pathcache::entries(filesys.pathcache())?
+        .into_iter()
+        .map(|(addr, p)| if addr == from_addr { (to_addr.clone(), p) } else { (addr, p) })
+        .collect();
+    pathcache::write_entries(filesys.pathcache(), &entries)?;
+    indexes::release(filesys.metadata(), from_index, file_path.clone())?;
+    indexes::claim(filesys.metadata(), to_index, to_addr.clone(), file_path.clone())?;
+
+    let message = format!("Renumber {} from index {} to {}", file_path, from_index, to_index);
+    vcs::commit(filesys.path.as_str(), message.as_str())?;
+
+    Ok(Renumbered {
+        file_path,
+        from_index,
+        to_index,
+        from_addr,
+        to_addr,
+    })
+}
+
+/// Indexes the filesystem has taken. A file tracked before indexes were
+/// claimed took its place in the path-cache.
+fn used(filesys: &Filesys) -> Result<HashSet<u32>, ImmutagError> {
+    let claims = indexes::claims(filesys.metadata())?;
+    let mut used: HashSet<u32> = claims.iter().filter_map(indexes::index).collect();
+
+    let versionstore_path = filesys.versionstore();
+    for file_addr in versionstore::file_addrs(versionstore_path.as_str())? {
+        let metadata_file = versionstore::path_metadata_file(versionstore_path.as_str(), file_addr.as_str());
+        let file_index: Option<u32> = metadata::records(metadata_file)?
+            .first()
+            .and_then(|r| r.get(metadata::FILE_INDEX))
+            .and_then(|i| i.parse().ok());
+        used.extend(file_index);
+    }
+
+    let claimed: HashSet<&str> = claims.iter().filter_map(|r| r.get(metadata::FILE_ADDR)).collect();
+    for (pos, (file_addr, _)) in pathcache::entries(filesys.pathcache())?.iter().enumerate() {
+        if !claimed.contains(file_addr.as_str()) {
+            used.insert(pos as u32 + 1);
+        }
+    }
+
+    Ok(used)
+}
+
+/// The last index that's used or has history on the ledger, scanning until
+/// a gap of unused ones.
+fn last_on_ledger(filesys: &Filesys, ledger: &dyn Ledger, used: &HashSet<u32>) -> Result<u32, ImmutagError> {
+    let (mut last, mut gap, mut index) = (0, 0, 1);
+    while gap < GAP_LIMIT {
+        let file_addr = immutag_bitcoin::file_addr(&filesys.xpriv, index)?;
+        if used.contains(&index) || !ledger.history(&file_addr)?.is_empty() {
+            last = index;
+            gap = 0;
+        } else {
+            gap += 1;
+        }
+        index += 1;
+    }
+
+    Ok(last)
+}
+
+/// The claims in the filesystem's metadata at a git revision.
+fn claims_at(filesys: &Filesys, rev: &str) -> Result<Vec<Record>, ImmutagError> {
+    let commit = vcs::resolve(filesys.path.as_str(), rev)?;
+    let claims = match vcs::read_at(filesys.path.as_str(), commit.as_str(), "metadata")? {
+        Some(recfile) => metadata::parse(recfile)?,
+        None => Vec::new(),
+    };
+
+    Ok(claims)
+}
+
+/// Errors if the file address has gone, or is about to go, on the ledger.
+fn on_ledger(filesys: &Filesys, file_path: &str, file_addr: &str) -> Result<(), ImmutagError> {
+    let versionstore_path = filesys.versionstore();
+    let metadata_file = versionstore::path_metadata_file(versionstore_path.as_str(), file_addr);
+    let forked = metadata::records(metadata_file)?.iter().any(|r| r.get(metadata::BRANCHES).is_some());
+    let queued = pending::pending(immutag_filesys::path_pending(&filesys.path))?
+        .iter()
+        .any(|r| r.get(metadata::FILE_ADDR) == Some(file_addr));
+
+    let versions_file = format!("version-store/{}/versions-file", file_addr);
+    let mut pegged = false;
+    for peg in pegs::pegs(immutag_filesys::path_pegs(&filesys.path))? {
+        let commit = match peg.get(pegs::COMMIT) {
+            Some(commit) if !commit.is_empty() => commit,
+            _ => continue,
+        };
+        if vcs::read_at(filesys.path.as_str(), commit, versions_file.as_str())?.is_some() {
+            pegged = true;
+            break;
+        }
+    }
+
+    if forked || queued || pegged {
+        let msg = format!("{} is on the ledger as {}, it can't be renumbered", file_path, file_addr);
+        return Err(ImmutagError::from(Error::new(&msg, ErrorKind::NotRenumberable)));
+    }
+
+    Ok(())
+}
+
+fn renumber_record(record: &mut Record, file_addr: &str, file_index: u32) {
+    record.set(metadata::FILE_ADDR, file_addr);
+    record.set(metadata::FILE_INDEX, file_index.to_string().as_str());
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::add_content;
+    use crate::test_support::{commit_version, setup, XPRIV};
+    use immutag_ledger::{ElectrumLedger, MockLedger};
+    use std::fs::write;
+
+    fn add_file(path: &str, name: &str, content: &str) -> String {
+        let file = format!("{}{}", path, name);
+        commit_version(path, file.as_str(), content, None);
+
+        file
+    }
+
+    #[test]
+    fn allocate_indexes() {
+        let path = "/tmp/immutag_index_allocate/";
+        let mut fixture = setup(path);
+
+        add_file(path, "README.md", "# Lemonade stand");
+        add_file(path, "ledger.csv", "cups,10");
+        let filesys = Filesys::open(path).unwrap();
+        let claims = indexes::claims(filesys.metadata()).unwrap();
+
+        assert_eq!(claims.iter().filter_map(indexes::index).collect::<Vec<u32>>(), vec![1, 2]);
+        assert_eq!(next_index(&filesys, None, &HashSet::new()).unwrap(), 3);
+        assert_eq!(next_index(&filesys, None, &[7].iter().cloned().collect()).unwrap(), 8);
+
+        // Index 25 is past a gap of more than the gap limit.
+        let ledger = MockLedger::new("bitcoinsv").unwrap();
+        ledger.fund(immutag_bitcoin::file_addr(XPRIV, 3).unwrap(), 1000).unwrap();
+        ledger.fund(immutag_bitcoin::file_addr(XPRIV, 25).unwrap(), 1000).unwrap();
+        ledger.mine();
+
+        assert_eq!(next_index(&filesys, Some(&ledger), &HashSet::new()).unwrap(), 4);
+        // Nothing listens there, so only the claims count.
+        let unreachable = ElectrumLedger::new("tcp://127.0.0.1:1").unwrap();
+        assert_eq!(next_index(&filesys, Some(&unreachable), &HashSet::new()).unwrap(), 3);
+
+        fixture.teardown(true);
+    }
+
+    #[test]
+    fn renumber_collision() {
+        let path = "/tmp/immutag_index_renumber/";
+        let mut fixture = setup(path);
+
+        add_file(path, "README.md", "# Lemonade stand");
+        let filesys = Filesys::open(path).unwrap();
+        let base = vcs::head(filesys.path.as_str()).unwrap().unwrap();
+
+        // A collaborator's branch, which took index 2 for another file.
+        add_file(path, "menu.txt", "lemonade");
+        let theirs = vcs::head(filesys.path.as_str()).unwrap().unwrap();
+        let repo = git2::Repository::open(filesys.path.as_str()).unwrap();
+        repo.branch("theirs", &repo.find_commit(git2::Oid::from_str(&theirs).unwrap()).unwrap(), false)
+            .unwrap();
+        let base_commit = repo.find_object(git2::Oid::from_str(&base).unwrap(), None).unwrap();
+        repo.reset(&base_commit, git2::ResetType::Hard, None).unwrap();
+
+        let ledger_csv = add_file(path, "ledger.csv", "cups,10");
+        let found = collisions(path, Some("theirs")).unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].file_index, 2);
+        assert_eq!(found[0].file_addr, "13DfKEsGmgrdZ3zgvHzsmP58X9dNW1Vyyy");
+
+        let renumbered = renumber(path, ledger_csv.as_str(), Some("theirs")).unwrap();
+
+        assert_eq!(renumbered.from_index, 2);
+        assert_eq!(renumbered.to_index, 3);
+        assert_eq!(renumbered.to_addr, "15EWDuVZn8aN9q431Ng1y2jgjLbo9AZqAN");
+        assert!(collisions(path, Some("theirs")).unwrap().is_empty());
+
+        let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), renumbered.to_addr.clone());
+        let records = metadata::records(metadata_file).unwrap();
+        assert_eq!(records[0].get(metadata::FILE_INDEX), Some("3"));
+        assert_eq!(
+            records[0].get(metadata::VERSION_ADDR),
+            Some(immutag_bitcoin::version_addr(XPRIV, 3, 0).unwrap().as_str())
+        );
+        let versions_file = versionstore::path_versions_file(filesys.versionstore(), renumbered.to_addr.clone());
+        assert_eq!(versionstore::versions(versions_file).unwrap()[0].file_addr, renumbered.to_addr);
+
+        // The next version goes on at the new index.
+        write(&ledger_csv, "cups,12").unwrap();
+        let record = add_content(path, ledger_csv.as_str(), None).unwrap();
+        assert_eq!(record.get(metadata::FILE_INDEX), Some("3"));
+
+        fixture.teardown(true);
+    }
+}
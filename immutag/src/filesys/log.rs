@@ -0,0 +1,244 @@
+/*
+The version history of a file, from its version-store entry and the git
+history of the filesystem.
+*/
+use serde_json::{json, Value};
+
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::{vcs, versionstore};
+
+use crate::err::ImmutagError;
+use crate::filesys::util::datetime;
+use crate::filesys::version::{canonical, tracked, Filesys};
+
+/// Fields that change with every version, so aren't worth reporting.
+const PER_VERSION: [&str; 7] = [
+    metadata::FILE_HASH,
+    metadata::VERSION_ADDR,
+    metadata::CONTENT_ADDR,
+    metadata::TIME,
+    metadata::AUTHOR,
+    metadata::SIGNATURE,
+    metadata::PUBLIC_KEY,
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    pub version: usize,
+    pub file_hash: String,
+    pub version_addr: Option<String>,
+    pub content_addr: Option<String>,
+    /// When the version was committed, seconds since the epoch and the
+    /// committer's offset from UTC in minutes.
+    pub time: Option<(i64, i32)>,
+    /// Metadata fields, other than tags, that were added, changed or removed
+    /// since the previous version.
+    pub changed: Vec<String>,
+    pub tags_added: Vec<String>,
+    pub tags_removed: Vec<String>,
+}
+
+impl LogEntry {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "version": self.version,
+            "file_hash": self.file_hash,
+            "version_addr": self.version_addr,
+            "content_addr": self.content_addr,
+            "time": self.time.map(|(seconds, _)| seconds),
+            "changed": self.changed,
+            "tags_added": self.tags_added,
+            "tags_removed": self.tags_removed,
+        })
+    }
+}
+
+impl std::fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "version {}", self.version)?;
+        writeln!(f, "file_hash: {}", self.file_hash)?;
+        if let Some(version_addr) = &self.version_addr {
+            writeln!(f, "version_addr: {}", version_addr)?;
+        }
+        if let Some(content_addr) = &self.content_addr {
+            writeln!(f, "content_addr: {}", content_addr)?;
+        }
+        if let Some((seconds, offset)) = self.time {
+            writeln!(f, "date: {}", format_time(seconds, offset))?;
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "changed: {}", self.changed.join(" "))?;
+        }
+        if !self.tags_added.is_empty() || !self.tags_removed.is_empty() {
+            let added = self.tags_added.iter().map(|t| format!("+{}", t));
+            let removed = self.tags_removed.iter().map(|t| format!("-{}", t));
+            writeln!(f, "tags: {}", added.chain(removed).collect::<Vec<_>>().join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The versions of `file`, newest first, at most `limit` of them.
+pub fn log<T: AsRef<str>>(path: T, file: T, limit: Option<usize>) -> Result<Vec<LogEntry>, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let file_path = canonical(file)?;
+    let (file_addr, _) = tracked(&filesys, &file_path)?;
+
+    let versions_file = versionstore::path_versions_file(filesys.versionstore(), file_addr.clone());
+    let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr.clone());
+    let versions = versionstore::versions(versions_file)?;
+    let records = metadata::records(metadata_file)?;
+
+    // A version was committed with the first revision of the versions-file
+    // that lists it.
+    let relative = format!("version-store/{}/versions-file", file_addr);
+    let revisions = vcs::history(filesys.path.as_str(), relative.as_str())?;
+    let committed: Vec<usize> = revisions
+        .iter()
+        .map(|r| match &r.content {
+            Some(content) => versionstore::parse(content).map(|v| v.len()).unwrap_or(0),
+            None => 0,
+        })
+        .collect();
+
+    let empty = Record::new();
+    let mut entries = Vec::new();
+    for (version, v) in versions.iter().enumerate() {
+        let record = records.get(version).unwrap_or(&empty);
+        let previous = match version {
+            0 => &empty,
+            _ => records.get(version - 1).unwrap_or(&empty),
+        };
+        let time = committed
+            .iter()
+            .position(|count| *count > version)
+            .map(|pos| (revisions[pos].time, revisions[pos].offset));
+        let (changed, tags_added, tags_removed) = diff(previous, record);
+
+        entries.push(LogEntry {
+            version,
+            file_hash: v.file_hash.clone(),
+            version_addr: record.get(metadata::VERSION_ADDR).map(|a| a.to_string()),
+            content_addr: record.get(metadata::CONTENT_ADDR).map(|a| a.to_string()),
+            time,
+            changed,
+            tags_added,
+            tags_removed,
+        });
+    }
+    entries.reverse();
+    if let Some(limit) = limit {
+        entries.truncate(limit);
+    }
+
+    Ok(entries)
+}
+
+pub fn to_json(entries: &[LogEntry]) -> String {
+    let entries: Vec<Value> = entries.iter().map(|e| e.to_json()).collect();
+
+    Value::Array(entries).to_string()
+}
+
+/// Changed fields, added tags and removed tags.
+fn diff(previous: &Record, record: &Record) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let is_tag = |key: &str| key.starts_with(metadata::TAG_PREFIX);
+    let mut changed: Vec<String> = record
+        .fields
+        .iter()
+        .filter(|(k, v)| !is_tag(k) && previous.get(k) != Some(v.as_str()))
+        .map(|(k, _)| k.clone())
+        .collect();
+    changed.extend(
+        previous
+            .fields
+            .iter()
+            .filter(|(k, _)| !is_tag(k) && record.get(k).is_none())
+            .map(|(k, _)| k.clone()),
+    );
+    changed.retain(|k| !PER_VERSION.contains(&k.as_str()));
+
+    let (tags, previous_tags) = (record.tags(), previous.tags());
+    let added = tags.iter().filter(|t| !previous_tags.contains(t)).cloned().collect();
+    let removed = previous_tags.iter().filter(|t| !tags.contains(t)).cloned().collect();
+
+    (changed, added, removed)
+}
+
+/// `YYYY-MM-DD HH:MM:SS +HHMM`, in the committer's time zone.
+fn format_time(seconds: i64, offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+
+    format!("{} {}{:02}{:02}", datetime(seconds, offset), sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_times() {
+        assert_eq!(format_time(0, 0), "1970-01-01 00:00:00 +0000");
+        assert_eq!(format_time(1_582_934_400, 0), "2020-02-29 00:00:00 +0000");
+        assert_eq!(format_time(1_582_934_400, -90), "2020-02-28 22:30:00 -0130");
+    }
+
+    #[test]
+    fn diff_records() {
+        let mut previous = Record::new();
+        previous.set(metadata::FILE_HASH, "a");
+        previous.set(metadata::MESSAGE, "first");
+        previous.add_tag("video");
+        previous.add_tag("sales");
+        let mut record = Record::new();
+        record.set(metadata::FILE_HASH, "b");
+        record.set(metadata::ALIAS, "lemonade_stand");
+        record.add_tag("video");
+        record.add_tag("draft");
+
+        let (changed, added, removed) = diff(&previous, &record);
+
+        assert_eq!(changed, vec![metadata::ALIAS, metadata::MESSAGE]);
+        assert_eq!(added, vec!["draft"]);
+        assert_eq!(removed, vec!["sales"]);
+    }
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::{add_content, commit};
+    use crate::test_support::setup;
+    use std::fs::write;
+
+    #[test]
+    fn log_versions() {
+        let path = "/tmp/immutag_log_tests";
+        let file = "/tmp/immutag_log_tests/lemonade_stand.mp4";
+        let mut fixture = setup(path);
+
+        write(file, b"foobar\n").unwrap();
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+        write(file, b"hello world\n").unwrap();
+        add_content(path, file, Some("lemonade_stand")).unwrap();
+        commit(path, file).unwrap();
+
+        let entries = log(path, file, None).unwrap();
+        let limited = log(path, file, Some(1)).unwrap();
+        let json: Value = serde_json::from_str(&to_json(&entries)).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].version, 1);
+        assert_eq!(entries[0].changed, vec![metadata::ALIAS]);
+        assert_eq!(entries[1].version_addr, Some("1QKr8NEjbhe9uW3u1wGVWTDavr5n3HZTx9".to_string()));
+        assert_eq!(entries[1].content_addr, Some("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL".to_string()));
+        assert!(entries.iter().all(|e| e.time.is_some()));
+        assert_eq!(limited, entries[..1].to_vec());
+        assert_eq!(json[1]["version"], 0);
+        assert_eq!(json[0]["changed"][0], "alias");
+    }
+}
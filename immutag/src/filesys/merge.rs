@@ -0,0 +1,339 @@
+/*
+Merges of the files collaborators both change, whether by a pull or by plain
+git through the `immutag merge-driver` it's registered to run. A version's
+address is derived from its version number, so the versions a merge moves
+are readdressed for their new positions. A record the merge leaves unlike
+either side's, such as one with both sides' tags, is signed again.
+*/
+use std::collections::HashSet;
+use std::fs::{read_to_string, write};
+
+pub use immutag_filesys::merge::{Merged, Resolution, CONFLICT_PREFIX};
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::{merge, vcs, versionstore};
+
+use crate::err::ImmutagError;
+use crate::filesys::{signing, view};
+use crate::filesys::version::{canonical, tracked, Filesys};
+
+/// Merges a file of the filesystem the way git's merge drivers do: `base`,
+/// `ours` and `theirs` are files holding each side, and the result is
+/// written over `ours`. `file_path` is where the file is in the filesystem
+/// directory. `None` if it isn't one immutag knows how to merge.
+pub fn merge_driver<T: AsRef<str>>(
+    path: T,
+    base: T,
+    ours: T,
+    theirs: T,
+    file_path: T,
+    resolution: Resolution,
+) -> Result<Option<Merged>, ImmutagError> {
+    let (ours_content, theirs_content) = (read_to_string(ours.as_ref())?, read_to_string(theirs.as_ref())?);
+    let merged = merge::merge_file(
+        file_path.as_ref(),
+        read_to_string(base.as_ref())?.as_str(),
+        ours_content.as_str(),
+        theirs_content.as_str(),
+        resolution,
+    )?;
+    let mut merged = match merged {
+        Some(merged) => merged,
+        None => return Ok(None),
+    };
+
+    if file_path.as_ref().ends_with("/metadata-file") {
+        let filesys = Filesys::open(path.as_ref())?;
+        let mut records = metadata::parse(merged.content.as_str())?;
+        let sides = vec![metadata::parse(ours_content)?, metadata::parse(theirs_content)?];
+        let readdressed = readdress(&filesys.xpriv, &mut records)?;
+        if resign(&filesys, &mut records, &sides)? || readdressed {
+            merged.content = metadata::render(&records);
+        }
+    }
+    write(ours.as_ref(), merged.content.as_str())?;
+
+    Ok(Some(merged))
+}
+
+/// Readdresses the versions of every file in the version-store, and signs
+/// the records unlike those of each side, the commits merged. Returns the
+/// addresses of the files that changed.
+pub fn readdress_versionstore<T: AsRef<str>>(path: T, sides: &[String]) -> Result<Vec<String>, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let versionstore_path = filesys.versionstore();
+    let mut readdressed = Vec::new();
+    for file_addr in versionstore::file_addrs(versionstore_path.as_str())? {
+        let metadata_file = versionstore::path_metadata_file(versionstore_path.clone(), file_addr.clone());
+        let mut records = metadata::records(metadata_file.clone())?;
+        let mut sides_records = Vec::new();
+        for side in sides {
+            let file_path = format!("version-store/{}/metadata-file", file_addr);
+            let content = vcs::read_at(filesys.path.as_str(), side.as_str(), file_path.as_str())?;
+            sides_records.push(metadata::parse(content.unwrap_or_default())?);
+        }
+        let moved = readdress(&filesys.xpriv, &mut records)?;
+        if resign(&filesys, &mut records, &sides_records)? || moved {
+            metadata::write_records(metadata_file, &records)?;
+            readdressed.push(file_addr);
+        }
+    }
+
+    Ok(readdressed)
+}
+
+/// Resolves the conflicts a merge flagged in the metadata of `file`, by
+/// taking their values with `Resolution::Theirs`, or keeping ours otherwise.
+/// Returns the fields resolved, like `alias of version 2`.
+pub fn resolve<T: AsRef<str>>(path: T, file: T, resolution: Resolution) -> Result<Vec<String>, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let file_path = canonical(file)?;
+    let (file_addr, _) = tracked(&filesys, &file_path)?;
+
+    let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr.clone());
+    let mut records = metadata::records(metadata_file.clone())?;
+    let mut resolved = Vec::new();
+    for (version, record) in records.iter_mut().enumerate() {
+        let flags: Vec<(String, String)> = record
+            .fields
+            .iter()
+            .filter(|(k, _)| k.starts_with(CONFLICT_PREFIX))
+            .cloned()
+            .collect();
+        let signed = metadata::canonical(record);
+        for (flag, theirs) in flags.iter() {
+            let key = flag.trim_start_matches(CONFLICT_PREFIX);
+            if resolution == Resolution::Theirs {
+                record.set(key, theirs.as_str());
+            }
+            record.remove(flag.as_str());
+            // Their signature is flagged with their fields, not as a conflict.
+            if key != metadata::SIGNATURE && key != metadata::PUBLIC_KEY {
+                resolved.push(format!("{} of version {}", key, version));
+            }
+        }
+        // A side taken as it was keeps its signer's signature.
+        if metadata::canonical(record) != signed && !signing::holds(record) {
+            signing::sign(&filesys, record)?;
+        }
+    }
+
+    if !resolved.is_empty() {
+        metadata::write_records(metadata_file, &records)?;
+        let message = format!("Resolve the merge conflicts of {}", file_addr);
+        vcs::commit(filesys.path.as_str(), message.as_str())?;
+        view::refresh(path.as_ref())?;
+    }
+
+    Ok(resolved)
+}
+
+/// Signs the records that are like no record of either side, and returns
+/// whether any were. Flagged conflicts aren't signed, so a record merged with
+/// its own side's fields keeps that side's signature.
+fn resign(filesys: &Filesys, records: &mut [Record], sides: &[Vec<Record>]) -> Result<bool, ImmutagError> {
+    let signed: HashSet<String> = sides.iter().flatten().map(metadata::canonical).collect();
+    let mut changed = false;
+    for record in records.iter_mut() {
+        if !signed.contains(&metadata::canonical(record)) {
+            signing::sign(filesys, record)?;
+            changed = true;
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Gives each record the version address of its position, and returns
+/// whether any changed.
+fn readdress(xpriv: &str, records: &mut [Record]) -> Result<bool, ImmutagError> {
+    let mut changed = false;
+    for (version, record) in records.iter_mut().enumerate() {
+        let file_index = match record.get(metadata::FILE_INDEX).and_then(|i| i.parse().ok()) {
+            Some(file_index) => file_index,
+            None => continue,
+        };
+        let branch = record.get(metadata::BRANCH).and_then(|b| b.parse().ok()).unwrap_or(0);
+        let version_addr = immutag_bitcoin::branch_version_addr(xpriv, file_index, branch, version as u32)?;
+        if record.get(metadata::VERSION_ADDR) != Some(version_addr.as_str()) {
+            record.set(metadata::VERSION_ADDR, version_addr.as_str());
+            changed = true;
+        }
+    }
+
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::consistency;
+    use crate::filesys::import::import_squaretag;
+    use crate::filesys::remote::{pull, push, remote_add, Merge};
+    use crate::files;
+    use crate::test_support::{commit_version, init, setup, FILESYS_ADDR, XPRIV};
+    use fixture::Fixture;
+
+    const FILE_ADDR: &str = "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9";
+
+    fn records(path: &str) -> Vec<Record> {
+        let filesys = Filesys::open(path).unwrap();
+        metadata::records(versionstore::path_metadata_file(filesys.versionstore(), FILE_ADDR.to_string())).unwrap()
+    }
+
+    #[test]
+    fn merge_concurrent_versions() {
+        let ours = "/tmp/immutag_merge_ours/";
+        let theirs = "/tmp/immutag_merge_theirs/";
+        let shared = "/tmp/immutag_merge_shared/";
+        let bare = "/tmp/immutag_merge_bare.git";
+        let readme = "/tmp/immutag_merge_shared/README.md";
+        let mut fixture = Fixture::new()
+            .add_dirpath(ours.to_string())
+            .add_dirpath(theirs.to_string())
+            .add_dirpath(shared.to_string())
+            .build();
+        git2::Repository::init_bare(bare).unwrap();
+        let url = format!("file://{}", bare);
+        init(ours);
+        init(theirs);
+        let driver = git2::Repository::open(Filesys::open(ours).unwrap().path)
+            .and_then(|r| r.config())
+            .and_then(|c| c.get_string("merge.immutag.driver"))
+            .unwrap();
+
+        commit_version(ours, readme, "# Lemonade stand", None);
+        remote_add(ours, "origin", url.as_str()).unwrap();
+        push(ours, "origin").unwrap();
+        remote_add(theirs, "origin", url.as_str()).unwrap();
+        pull(theirs, "origin", Resolution::Flag).unwrap();
+
+        commit_version(ours, readme, "# Lemonade stand, open", None);
+        commit_version(theirs, readme, "# Lemonade stand, closed", None);
+        push(theirs, "origin").unwrap();
+        let merged = pull(ours, "origin", Resolution::Flag).unwrap();
+        let readdressed = records(ours);
+        push(ours, "origin").unwrap();
+        let fast_forward = pull(theirs, "origin", Resolution::Flag).unwrap();
+
+        commit_version(ours, readme, "# Lemonade stand, sold out", Some("stand"));
+        commit_version(theirs, readme, "# Lemonade stand, sold out", Some("lemonade"));
+        push(theirs, "origin").unwrap();
+        let flagged = pull(ours, "origin", Resolution::Flag).unwrap();
+        let conflicted = records(ours);
+        let resolved = resolve(ours, readme, Resolution::Theirs).unwrap();
+        let taken = records(ours);
+        let checks = consistency(ours).unwrap();
+
+        fixture.teardown(true);
+        std::fs::remove_dir_all(bare).unwrap();
+
+        assert_eq!(driver, "immutag merge-driver %O %A %B %P");
+        assert!(matches!(merged.merge, Merge::Merged(_)));
+        assert!(merged.ok(), "{:?}", merged.checks);
+        assert_eq!(readdressed.len(), 3);
+        assert_eq!(readdressed[1].get(metadata::FILE_HASH), Some(immutag_contentsys::file_hash(b"# Lemonade stand, open").as_str()));
+        for (version, record) in readdressed.iter().enumerate() {
+            let version_addr = immutag_bitcoin::version_addr(XPRIV, 1, version as u32).unwrap();
+            assert_eq!(record.get(metadata::VERSION_ADDR), Some(version_addr.as_str()));
+        }
+        assert_eq!(fast_forward.merge, Merge::FastForward);
+        assert!(!flagged.ok());
+        assert_eq!(conflicted[3].get(metadata::ALIAS), Some("stand"));
+        assert_eq!(conflicted[3].get("conflict_alias"), Some("lemonade"));
+        assert_eq!(resolved, vec!["alias of version 3".to_string()]);
+        assert_eq!(taken[3].get(metadata::ALIAS), Some("lemonade"));
+        assert_eq!(taken[3].get("conflict_alias"), None);
+        assert!(checks.iter().all(|c| c.ok), "{:?}", checks);
+    }
+
+    #[test]
+    fn sign_concurrent_tags() {
+        let ours = "/tmp/immutag_merge_tags_ours/";
+        let theirs = "/tmp/immutag_merge_tags_theirs/";
+        let shared = "/tmp/immutag_merge_tags_shared/";
+        let bare = "/tmp/immutag_merge_tags_bare.git";
+        let readme = "/tmp/immutag_merge_tags_shared/README.md";
+        let mut fixture = Fixture::new()
+            .add_dirpath(ours.to_string())
+            .add_dirpath(theirs.to_string())
+            .add_dirpath(format!("{}ours", shared))
+            .add_dirpath(format!("{}theirs", shared))
+            .build();
+        git2::Repository::init_bare(bare).unwrap();
+        let url = format!("file://{}", bare);
+        init(ours);
+        init(theirs);
+
+        commit_version(ours, readme, "# Lemonade stand", None);
+        remote_add(ours, "origin", url.as_str()).unwrap();
+        push(ours, "origin").unwrap();
+        remote_add(theirs, "origin", url.as_str()).unwrap();
+        pull(theirs, "origin", Resolution::Flag).unwrap();
+        let base = records(theirs);
+
+        write(format!("{}ours/README[stand].md", shared), "# Lemonade stand").unwrap();
+        write(format!("{}theirs/README[lemonade].md", shared), "# Lemonade stand").unwrap();
+        import_squaretag(ours, format!("{}ours", shared).as_str(), false).unwrap();
+        import_squaretag(theirs, format!("{}theirs", shared).as_str(), false).unwrap();
+        let (ours_records, theirs_records) = (records(ours), records(theirs));
+
+        // The merge driver, as plain git runs it.
+        let sides = [("base", &base), ("ours", &ours_records), ("theirs", &theirs_records)];
+        for (side, side_records) in sides.iter() {
+            write(format!("{}{}.rec", shared, side), metadata::render(side_records)).unwrap();
+        }
+        let side = |name: &str| format!("{}{}.rec", shared, name);
+        let file_path = format!("version-store/{}/metadata-file", FILE_ADDR);
+        merge_driver(ours, &side("base"), &side("ours"), &side("theirs"), &file_path, Resolution::Flag).unwrap();
+        let driven = metadata::records(side("ours")).unwrap();
+
+        push(theirs, "origin").unwrap();
+        let merged = pull(ours, "origin", Resolution::Flag).unwrap();
+        let pulled = records(ours);
+        let signed = signing::verify_metadata(ours, readme).unwrap();
+
+        fixture.teardown(true);
+        std::fs::remove_dir_all(bare).unwrap();
+
+        assert!(signing::holds(&driven[0]));
+        assert_eq!(driven[0].tags(), vec!["stand".to_string(), "lemonade".to_string()]);
+        assert!(matches!(merged.merge, Merge::Merged(_)));
+        assert_eq!(pulled[0].tags(), driven[0].tags());
+        assert!(signed.ok(), "{:?}", signed.records);
+    }
+
+    #[test]
+    fn resolve_keeps_signatures() {
+        let path = "/tmp/immutag_merge_resolve/";
+        let readme = "/tmp/immutag_merge_resolve/README.md";
+        let mut fixture = setup(path);
+        commit_version(path, readme, "# Lemonade stand", Some("stand"));
+
+        let filesys = Filesys::open(path).unwrap();
+        let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), FILE_ADDR.to_string());
+        let base = records(path);
+        let mut ours = base.clone();
+        ours[0].set(metadata::ALIAS, "stand, open");
+        signing::sign(&filesys, &mut ours[0]).unwrap();
+        files::set_account(path, FILESYS_ADDR, 2).unwrap();
+        let mut theirs = base.clone();
+        theirs[0].set(metadata::ALIAS, "lemonade");
+        signing::sign(&Filesys::open(path).unwrap(), &mut theirs[0]).unwrap();
+        let (conflicted, _) = merge::records(&base, &ours, &theirs, Resolution::Flag);
+
+        metadata::write_records(metadata_file.clone(), &conflicted).unwrap();
+        let resolved = resolve(path, readme, Resolution::Theirs).unwrap();
+        let taken = records(path);
+        metadata::write_records(metadata_file, &conflicted).unwrap();
+        resolve(path, readme, Resolution::Ours).unwrap();
+        let kept = records(path);
+        let signed = signing::verify_metadata(path, readme).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(resolved, vec!["alias of version 0".to_string()]);
+        assert_eq!(taken, theirs);
+        assert_eq!(kept, ours);
+        assert_eq!(signed.records[0].account, Some(0));
+    }
+}
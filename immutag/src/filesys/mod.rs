@@ -1,6 +1,43 @@
+pub mod branch;
+pub mod consistency;
+pub mod import;
+pub mod index;
+pub mod log;
+pub mod merge;
+pub mod peg;
+pub mod query;
+pub mod rebuild;
+pub mod recover;
+pub mod remote;
+pub mod signing;
+pub mod tmsu;
+mod util;
+pub mod verify;
+pub mod version;
+pub mod view;
+pub mod watch;
+
 use immutag_filesys;
 use fixture;
 
+pub use branch::{branch, Branch};
+pub use consistency::consistency;
+pub use import::{import_squaretag, import_tagspaces, TagImport};
+pub use index::{collisions, next_index, renumber, Renumbered, GAP_LIMIT};
+pub use log::{log, LogEntry};
+pub use merge::{merge_driver, resolve, Resolution};
+pub use peg::{peg, proof, Peg, Proof};
+pub use query::{query, update_index, Found, IndexUpdate, Query};
+pub use rebuild::{rebuild_git, Rebuild};
+pub use recover::{recover, recover_from, Recovery};
+pub use remote::{pull, push, remote_add, remote_remove, remotes, Pull};
+pub use signing::{verify_metadata, MetadataVerification, SignedRecord};
+pub use tmsu::{export_tmsu, import_tmsu, TmsuExport};
+pub use verify::{verify, Check, Verification};
+pub use version::{add_content, add_content_scanned, checkout, commit, Filesys, Selector};
+pub use view::{build_view, View};
+pub use watch::{watch, WatchOptions, Watcher};
+
 pub fn add_filesys<T: AsRef<str>>(path: T, bitcoin_addr: T) {
     let mut path = fixture::directorate(path.as_ref().to_string());
     path = fixture::directorate(path.to_string() + ".immutag");
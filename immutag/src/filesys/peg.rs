@@ -0,0 +1,254 @@
+/*
+Pegs the version-store manifest: the Merkle root of every file version, with
+the manifest itself in the contentsys so the root can be rebuilt from the
+ledger. Inclusion proofs show a version was part of a peg.
+*/
+use serde_json::{json, Value};
+
+use immutag_bitcoin::opreturn::{self, Payload};
+use immutag_contentsys::merkle::{self, Hash, Step};
+use immutag_contentsys::Cid;
+use immutag_filesys::manifest::{self, Entry};
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::{pegs, pending, vcs};
+
+use crate::err::{Error, ErrorKind, ImmutagError};
+use crate::filesys::version::{canonical, tracked, Filesys};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Peg {
+    /// Counts from 0, for each filesystem.
+    pub peg: usize,
+    /// Hex encoded Merkle root of the manifest.
+    pub root: String,
+    pub manifest_addr: String,
+    pub commit: String,
+    /// Hex encoded OP_RETURN data.
+    pub payload: String,
+}
+
+/// A version's path to the root of a peg.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Proof {
+    pub peg: usize,
+    pub root: String,
+    pub manifest_addr: String,
+    pub entry: Entry,
+    pub steps: Vec<Step>,
+}
+
+impl Proof {
+    /// Whether the steps lead from the entry to the root.
+    pub fn check(&self) -> bool {
+        hex::encode(merkle::fold(&merkle::leaf(self.entry.line().as_bytes()), &self.steps)) == self.root
+    }
+
+    /// Whether `manifest` is the one the peg's OP_RETURN commits to, has the
+    /// entry, and hashes to the root. The ledger only has the manifest's
+    /// content address, so the root is only as good as this check.
+    pub fn check_manifest(&self, manifest: &[u8]) -> bool {
+        let cid = match self.manifest_addr.parse::<Cid>() {
+            Ok(cid) => cid,
+            Err(_) => return false,
+        };
+        if immutag_contentsys::content_addr(manifest, cid.version) != cid {
+            return false;
+        }
+        let entries = match manifest::parse(String::from_utf8_lossy(manifest)) {
+            Ok(entries) => entries,
+            Err(_) => return false,
+        };
+
+        entries.contains(&self.entry) && merkle::root(&leaves(&entries)).map(hex::encode).as_deref() == Some(self.root.as_str())
+    }
+
+    pub fn to_json(&self) -> Value {
+        let steps: Vec<Value> = self
+            .steps
+            .iter()
+            .map(|s| {
+                json!({
+                    "hash": hex::encode(s.hash),
+                    "side": if s.left { "left" } else { "right" },
+                })
+            })
+            .collect();
+
+        json!({
+            "peg": self.peg,
+            "root": self.root,
+            "manifest": self.manifest_addr,
+            "leaf": self.entry.line(),
+            "path": steps,
+        })
+    }
+}
+
+/// Pegs the manifest of everything committed so far.
+pub fn peg<T: AsRef<str>>(path: T) -> Result<Peg, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let entries = manifest::entries(filesys.versionstore())?;
+    let root = match merkle::root(&leaves(&entries)) {
+        Some(root) => hex::encode(root),
+        None => {
+            let err = Error::new("no versions have been committed", ErrorKind::NothingToPeg);
+            return Err(ImmutagError::from(err));
+        }
+    };
+
+    let pegs_file = immutag_filesys::path_pegs(&filesys.path);
+    let previous = pegs::pegs(&pegs_file)?;
+    let last = previous.last().map(|p| pegs::number(previous.len() - 1, p));
+    if previous.last().and_then(|p| p.get(pegs::ROOT)) == Some(root.as_str()) {
+        let msg = format!("nothing was committed since peg {}", last.unwrap_or_default());
+        return Err(ImmutagError::from(Error::new(&msg, ErrorKind::NothingToPeg)));
+    }
+
+    let manifest_addr = filesys.store(path.as_ref())?.put(manifest::render(&entries).as_bytes())?;
+    let commit = vcs::head(&filesys.path)?.unwrap_or_default();
+    let peg = last.map_or(0, |last| last + 1);
+    let payload = Payload {
+        record: opreturn::Record::Peg { version: peg as u64 },
+        content_addr: manifest_addr.to_bytes(),
+        parent: Some(filesys.addr.clone()),
+    };
+    let payload = hex::encode(payload.encode()?);
+
+    let mut record = Record::new();
+    record.set(pegs::PEG, peg.to_string().as_str());
+    record.set(pegs::ROOT, root.as_str());
+    record.set(pegs::MANIFEST, manifest_addr.to_string().as_str());
+    record.set(pegs::COMMIT, commit.as_str());
+    record.set(pegs::HEIGHT, pegs::UNCONFIRMED);
+    pegs::add(&pegs_file, record)?;
+
+    let mut op = Record::new();
+    op.set(pending::OP, pending::OP_PEG);
+    op.set(pegs::PEG, peg.to_string().as_str());
+    op.set(metadata::CONTENT_ADDR, manifest_addr.to_string().as_str());
+    op.set(pending::PAYLOAD, payload.as_str());
+    pending::add(immutag_filesys::path_pending(&filesys.path), op)?;
+
+    let message = format!("Peg {} of the manifest, root {}", peg, root);
+    vcs::commit(filesys.path, message)?;
+
+    Ok(Peg {
+        peg,
+        root,
+        manifest_addr: manifest_addr.to_string(),
+        commit,
+        payload,
+    })
+}
+
+/// Proves `version` of `file` is in the first peg that has it.
+pub fn proof<T: AsRef<str>>(path: T, file: T, version: usize) -> Result<Proof, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let file_path = canonical(file)?;
+    let (file_addr, _) = tracked(&filesys, &file_path)?;
+    let store = filesys.store(path.as_ref())?;
+
+    for (pos, record) in pegs::pegs(immutag_filesys::path_pegs(&filesys.path))?.iter().enumerate() {
+        let peg = pegs::number(pos, record);
+        let manifest_addr = record.get(pegs::MANIFEST).unwrap_or_default();
+        let root = record.get(pegs::ROOT).unwrap_or_default();
+        let data = store.get(&manifest_addr.parse::<Cid>()?)?;
+        let entries = manifest::parse(String::from_utf8_lossy(&data))?;
+        let leaves = leaves(&entries);
+        if merkle::root(&leaves).map(hex::encode).as_deref() != Some(root) {
+            let msg = format!("the manifest of peg {} doesn't hash to its root", peg);
+            return Err(ImmutagError::from(Error::new(&msg, ErrorKind::HashMismatch)));
+        }
+
+        let pos = entries.iter().position(|e| e.file_addr == file_addr && e.version == version);
+        if let Some(pos) = pos {
+            return Ok(Proof {
+                peg,
+                root: root.to_string(),
+                manifest_addr: manifest_addr.to_string(),
+                entry: entries[pos].clone(),
+                steps: merkle::proof(&leaves, pos).unwrap_or_default(),
+            });
+        }
+    }
+
+    let msg = format!("version {} of {} hasn't been pegged", version, file_path);
+    Err(ImmutagError::from(Error::new(&msg, ErrorKind::NotPegged)))
+}
+
+pub(crate) fn leaves(entries: &[Entry]) -> Vec<Hash> {
+    entries.iter().map(|e| merkle::leaf(e.line().as_bytes())).collect()
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::{add_content, commit};
+    use crate::test_support::{setup, FILESYS_ADDR};
+    use std::fs::write;
+
+    #[test]
+    fn peg_and_prove() {
+        let path = "/tmp/immutag_peg_tests";
+        let file = "/tmp/immutag_peg_tests/lemonade_stand.mp4";
+        let other = "/tmp/immutag_peg_tests/ledger.csv";
+        let mut fixture = setup(path);
+
+        let nothing = peg(path);
+        write(file, b"foobar\n").unwrap();
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+        write(other, b"hello world\n").unwrap();
+        add_content(path, other, None).unwrap();
+        commit(path, other).unwrap();
+        let first = peg(path).unwrap();
+        let again = peg(path);
+        write(file, b"hello world\n").unwrap();
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+        let unpegged = proof(path, file, 1);
+        let second = peg(path).unwrap();
+
+        let proof_0 = proof(path, file, 0).unwrap();
+        let proof_1 = proof(path, file, 1).unwrap();
+        let filesys = Filesys::open(path).unwrap();
+        let recorded = pegs::pegs(immutag_filesys::path_pegs(&filesys.path)).unwrap();
+        let ops = pending::pending(immutag_filesys::path_pending(&filesys.path)).unwrap();
+        let store = filesys.store(path).unwrap();
+        let manifest_0 = store.get(&first.manifest_addr.parse::<Cid>().unwrap()).unwrap();
+        let manifest_1 = store.get(&second.manifest_addr.parse::<Cid>().unwrap()).unwrap();
+
+        fixture.teardown(true);
+
+        let kind = |result: Result<Peg, ImmutagError>| match result {
+            Err(ImmutagError::Error(err)) => Some(err.kind),
+            _ => None,
+        };
+        assert_eq!(kind(nothing), Some(ErrorKind::NothingToPeg));
+        assert_eq!(kind(again), Some(ErrorKind::NothingToPeg));
+        assert!(matches!(unpegged, Err(ImmutagError::Error(ref err)) if err.kind == ErrorKind::NotPegged));
+        assert_eq!((first.peg, second.peg), (0, 1));
+        assert!(first.payload.starts_with(&hex::encode(b"IMT\x01\x81")));
+        assert_eq!(
+            Payload::decode(&hex::decode(&second.payload).unwrap()).unwrap().parent,
+            Some(FILESYS_ADDR.to_string())
+        );
+        assert_eq!(proof_0.peg, 0);
+        assert_eq!(proof_0.root, first.root);
+        assert_eq!(proof_1.peg, 1);
+        assert_eq!(proof_1.entry.content_addr, Some("QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o".to_string()));
+        assert!(proof_0.check() && proof_1.check());
+        assert!(proof_0.check_manifest(&manifest_0) && proof_1.check_manifest(&manifest_1));
+        // Another peg's manifest, or one that isn't what the peg committed to.
+        assert!(!proof_0.check_manifest(&manifest_1));
+        let mut forged = proof_1.clone();
+        forged.manifest_addr = first.manifest_addr.clone();
+        assert!(!forged.check_manifest(&manifest_1));
+        assert_eq!(proof_0.steps.len(), 1);
+        assert_eq!(proof_0.to_json()["path"][0]["hash"].as_str().map(|h| h.len()), Some(64));
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[1].get(pegs::COMMIT), Some(second.commit.as_str()));
+        assert_eq!(recorded[1].get(pegs::HEIGHT), Some(pegs::UNCONFIRMED));
+        assert_eq!(ops[0].get(pending::OP), Some(pending::OP_PEG));
+    }
+}
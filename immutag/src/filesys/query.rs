@@ -0,0 +1,393 @@
+/*
+Finds tracked files by tag, alias or address, across every filesystem of the
+immutag directory. The `immutag index` is a SQLite cache of the files, their
+versions and addresses, and the tags and alias of their latest versions. It
+remembers the commit of each filesystem it's up to date with, and reindexes
+only the files whose metadata changed since. Queries use it when it's there,
+and scan the recfiles otherwise.
+*/
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde_json::{json, Value};
+
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::{pathcache, vcs, versionstore};
+
+use crate::err::ImmutagError;
+use crate::files;
+
+/// The index, in the `.immutag` directory.
+pub const INDEX_DB: &str = "index.sqlite";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS filesystem (addr TEXT PRIMARY KEY, head TEXT);
+CREATE TABLE IF NOT EXISTS file (
+    file_addr TEXT PRIMARY KEY,
+    filesys_addr TEXT NOT NULL,
+    path TEXT,
+    alias TEXT,
+    version INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_file_alias ON file(alias);
+CREATE TABLE IF NOT EXISTS version (
+    file_addr TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    version_addr TEXT,
+    content_addr TEXT,
+    file_hash TEXT,
+    PRIMARY KEY (file_addr, version)
+);
+CREATE INDEX IF NOT EXISTS idx_version_addr ON version(version_addr);
+CREATE INDEX IF NOT EXISTS idx_content_addr ON version(content_addr);
+CREATE INDEX IF NOT EXISTS idx_file_hash ON version(file_hash);
+CREATE TABLE IF NOT EXISTS tag (file_addr TEXT NOT NULL, tag TEXT NOT NULL, PRIMARY KEY (file_addr, tag));
+CREATE INDEX IF NOT EXISTS idx_tag ON tag(tag);
+";
+
+/// What a file has to match. Tags and the alias are those of its latest
+/// version; the address is its file address, or a version, content or file
+/// hash address of any of its versions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    pub tags: Vec<String>,
+    pub alias: Option<String>,
+    pub addr: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Found {
+    pub filesys_addr: String,
+    pub file_addr: String,
+    /// Where the path-cache last saw it.
+    pub path: Option<String>,
+    pub alias: Option<String>,
+    pub tags: Vec<String>,
+    /// Its latest version.
+    pub version: usize,
+}
+
+impl Found {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "filesys_addr": self.filesys_addr,
+            "file_addr": self.file_addr,
+            "path": self.path,
+            "alias": self.alias,
+            "tags": self.tags,
+            "version": self.version,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexUpdate {
+    pub db: String,
+    pub filesystems: usize,
+    /// Files whose rows were rewritten.
+    pub files: usize,
+}
+
+/// Creates the index, or brings it up to date with the filesystems' commits.
+pub fn update_index<T: AsRef<str>>(path: T) -> Result<IndexUpdate, ImmutagError> {
+    let db = path_index(path.as_ref());
+    let mut conn = Connection::open(db.as_str())?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+
+    let mut update = IndexUpdate {
+        db,
+        filesystems: 0,
+        files: 0,
+    };
+    for (filesys_addr, filesys_path) in filesystems(path.as_ref())? {
+        update.filesystems += 1;
+        let head = vcs::head(filesys_path.as_str())?;
+        let indexed: Option<Option<String>> = tx
+            .query_row("SELECT head FROM filesystem WHERE addr = ?1", params![filesys_addr], |row| row.get(0))
+            .optional()?;
+        let indexed = indexed.unwrap_or(None);
+        if indexed == head {
+            continue;
+        }
+
+        let changed = match (indexed.as_ref(), head.as_ref()) {
+            (Some(indexed), Some(head)) => vcs::changed(filesys_path.as_str(), indexed.as_str(), head.as_str())?,
+            _ => None,
+        };
+        let versionstore_path = immutag_filesys::path_versionstore(&filesys_path);
+        let file_addrs = match changed.as_ref() {
+            Some(paths) => paths
+                .iter()
+                .filter_map(|p| p.strip_prefix("version-store/"))
+                .filter_map(|p| p.strip_suffix("/metadata-file"))
+                .map(|a| a.to_string())
+                .collect(),
+            None => {
+                for table in ["tag", "version"].iter() {
+                    let sql = format!(
+                        "DELETE FROM {} WHERE file_addr IN (SELECT file_addr FROM file WHERE filesys_addr = ?1)",
+                        table
+                    );
+                    tx.execute(sql.as_str(), params![filesys_addr])?;
+                }
+                tx.execute("DELETE FROM file WHERE filesys_addr = ?1", params![filesys_addr])?;
+                versionstore::file_addrs(versionstore_path.as_str())?
+            }
+        };
+
+        let pathcache_path = immutag_filesys::path_pathcache(&filesys_path);
+        for file_addr in file_addrs.iter() {
+            let metadata_file = versionstore::path_metadata_file(versionstore_path.clone(), file_addr.clone());
+            let records = metadata::records(metadata_file)?;
+            let file_path = pathcache::lookup_path(pathcache_path.as_str(), file_addr.as_str())?;
+            reindex(&tx, filesys_addr.as_str(), file_addr.as_str(), file_path, &records)?;
+            update.files += 1;
+        }
+        if changed.map(|paths| paths.iter().any(|p| p == "path-cache")).unwrap_or(false) {
+            for (file_addr, file_path) in pathcache::entries(pathcache_path)? {
+                tx.execute("UPDATE file SET path = ?1 WHERE file_addr = ?2", params![file_path, file_addr])?;
+            }
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO filesystem (addr, head) VALUES (?1, ?2)",
+            params![filesys_addr, head],
+        )?;
+    }
+    tx.commit()?;
+
+    Ok(update)
+}
+
+/// The files matching the query, by filesystem and file address. Uses the
+/// index, bringing it up to date first, if it's been created.
+pub fn query<T: AsRef<str>>(path: T, query: &Query) -> Result<Vec<Found>, ImmutagError> {
+    if Path::new(&path_index(path.as_ref())).exists() {
+        update_index(path.as_ref())?;
+        query_index(path.as_ref(), query)
+    } else {
+        query_recfiles(path.as_ref(), query)
+    }
+}
+
+fn query_index(path: &str, query: &Query) -> Result<Vec<Found>, ImmutagError> {
+    let conn = Connection::open(path_index(path))?;
+    let mut sql = "SELECT filesys_addr, file_addr, path, alias, version FROM file WHERE 1 = 1".to_string();
+    let mut values: Vec<String> = Vec::new();
+    for tag in query.tags.iter() {
+        sql.push_str(" AND file_addr IN (SELECT file_addr FROM tag WHERE tag = ?)");
+        values.push(tag.clone());
+    }
+    if let Some(alias) = query.alias.as_ref() {
+        sql.push_str(" AND alias = ?");
+        values.push(alias.clone());
+    }
+    if let Some(addr) = query.addr.as_ref() {
+        sql.push_str(
+            " AND (file_addr = ? OR file_addr IN (SELECT file_addr FROM version
+             WHERE version_addr = ? OR content_addr = ? OR file_hash = ?))",
+        );
+        values.extend(vec![addr.clone(); 4]);
+    }
+    sql.push_str(" ORDER BY filesys_addr, file_addr");
+
+    let mut found = Vec::new();
+    let mut stmt = conn.prepare(sql.as_str())?;
+    let mut rows = stmt.query(&values)?;
+    while let Some(row) = rows.next()? {
+        let version: i64 = row.get(4)?;
+        found.push(Found {
+            filesys_addr: row.get(0)?,
+            file_addr: row.get(1)?,
+            path: row.get(2)?,
+            alias: row.get(3)?,
+            tags: Vec::new(),
+            version: version as usize,
+        });
+    }
+    let mut stmt = conn.prepare("SELECT tag FROM tag WHERE file_addr = ?1 ORDER BY tag")?;
+    for found in found.iter_mut() {
+        let tags = stmt.query_map(params![found.file_addr], |row| row.get(0))?;
+        found.tags = tags.collect::<Result<Vec<String>, _>>()?;
+    }
+
+    Ok(found)
+}
+
+/// The query without the index, reading every metadata-file.
+fn query_recfiles(path: &str, query: &Query) -> Result<Vec<Found>, ImmutagError> {
+    let mut found = Vec::new();
+    for (filesys_addr, filesys_path) in filesystems(path)? {
+        let versionstore_path = immutag_filesys::path_versionstore(&filesys_path);
+        let pathcache_path = immutag_filesys::path_pathcache(&filesys_path);
+        for file_addr in versionstore::file_addrs(versionstore_path.as_str())? {
+            let metadata_file = versionstore::path_metadata_file(versionstore_path.clone(), file_addr.clone());
+            let records = metadata::records(metadata_file)?;
+            let latest = match records.last() {
+                Some(latest) => latest,
+                None => continue,
+            };
+            let tags = tags(latest);
+            let alias = latest.get(metadata::ALIAS).map(|a| a.to_string());
+            if !query.tags.iter().all(|t| tags.contains(t)) {
+                continue;
+            }
+            if query.alias.is_some() && query.alias != alias {
+                continue;
+            }
+            if let Some(addr) = query.addr.as_ref() {
+                let addressed = |r: &Record| {
+                    [metadata::VERSION_ADDR, metadata::CONTENT_ADDR, metadata::FILE_HASH]
+                        .iter()
+                        .any(|k| r.get(k) == Some(addr.as_str()))
+                };
+                if &file_addr != addr && !records.iter().any(addressed) {
+                    continue;
+                }
+            }
+
+            found.push(Found {
+                filesys_addr: filesys_addr.clone(),
+                path: pathcache::lookup_path(pathcache_path.as_str(), file_addr.as_str())?,
+                file_addr,
+                alias,
+                tags: tags.into_iter().collect(),
+                version: records.len() - 1,
+            });
+        }
+    }
+
+    Ok(found)
+}
+
+/// Rewrites the rows of a file.
+fn reindex(
+    tx: &Transaction,
+    filesys_addr: &str,
+    file_addr: &str,
+    file_path: Option<String>,
+    records: &[Record],
+) -> Result<(), ImmutagError> {
+    for table in ["file", "version", "tag"].iter() {
+        let sql = format!("DELETE FROM {} WHERE file_addr = ?1", table);
+        tx.execute(sql.as_str(), params![file_addr])?;
+    }
+    let latest = match records.last() {
+        Some(latest) => latest,
+        None => return Ok(()),
+    };
+
+    tx.execute(
+        "INSERT INTO file (file_addr, filesys_addr, path, alias, version) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![file_addr, filesys_addr, file_path, latest.get(metadata::ALIAS), (records.len() - 1) as i64],
+    )?;
+    for (version, record) in records.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO version (file_addr, version, version_addr, content_addr, file_hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                file_addr,
+                version as i64,
+                record.get(metadata::VERSION_ADDR),
+                record.get(metadata::CONTENT_ADDR),
+                record.get(metadata::FILE_HASH)
+            ],
+        )?;
+    }
+    for tag in tags(latest) {
+        tx.execute("INSERT INTO tag (file_addr, tag) VALUES (?1, ?2)", params![file_addr, tag])?;
+    }
+
+    Ok(())
+}
+
+fn tags(record: &Record) -> BTreeSet<String> {
+    record.tags().into_iter().collect()
+}
+
+/// The address and directory of each filesystem of the immutag directory.
+fn filesystems(path: &str) -> Result<Vec<(String, String)>, ImmutagError> {
+    let dotimmutag = fixture::directorate(path.to_string()) + ".immutag";
+    let mut filesystems = Vec::new();
+    for addr in files::get_filesystems(path)? {
+        let filesys_path = immutag_filesys::path_filesys(dotimmutag.clone(), addr.clone());
+        filesystems.push((addr, filesys_path));
+    }
+    filesystems.sort();
+
+    Ok(filesystems)
+}
+
+fn path_index(path: &str) -> String {
+    fixture::directorate(path.to_string()) + ".immutag/" + INDEX_DB
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::test_support::{commit_version, setup, tag};
+
+    const MENU_ADDR: &str = "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9";
+    const PRICES_ADDR: &str = "13DfKEsGmgrdZ3zgvHzsmP58X9dNW1Vyyy";
+
+    fn addrs(found: &[Found]) -> Vec<&str> {
+        found.iter().map(|f| f.file_addr.as_str()).collect()
+    }
+
+    #[test]
+    fn query_with_and_without_index() {
+        let path = "/tmp/immutag_query/";
+        let menu = "/tmp/immutag_query/menu";
+        let prices = "/tmp/immutag_query/prices";
+        let mut fixture = setup(path);
+        commit_version(path, menu, "lemonade", Some("menu"));
+        commit_version(path, prices, "$1", None);
+        tag(path, MENU_ADDR, "sales");
+        tag(path, MENU_ADDR, "summer");
+        tag(path, PRICES_ADDR, "sales");
+
+        let sales = Query {
+            tags: vec!["sales".to_string()],
+            ..Default::default()
+        };
+        let both = Query {
+            tags: vec!["sales".to_string(), "summer".to_string()],
+            ..Default::default()
+        };
+        let aliased = Query {
+            alias: Some("menu".to_string()),
+            ..Default::default()
+        };
+        let content = Query {
+            addr: Some(immutag_contentsys::file_hash(b"$1")),
+            ..Default::default()
+        };
+        let scanned: Vec<Vec<Found>> = [&sales, &both, &aliased, &content].iter().map(|q| query(path, q).unwrap()).collect();
+        let created = update_index(path).unwrap();
+        let again = update_index(path).unwrap();
+        let indexed: Vec<Vec<Found>> = [&sales, &both, &aliased, &content].iter().map(|q| query(path, q).unwrap()).collect();
+
+        // The query brings the index up to date first.
+        tag(path, PRICES_ADDR, "summer");
+        let retagged = query(path, &both).unwrap();
+        let connection = Connection::open(path_index(path)).unwrap();
+        let heads: i64 = connection.query_row("SELECT COUNT(*) FROM filesystem", params![], |row| row.get(0)).unwrap();
+        let after = update_index(path).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(addrs(&scanned[0]), vec![PRICES_ADDR, MENU_ADDR]);
+        assert_eq!(addrs(&scanned[1]), vec![MENU_ADDR]);
+        assert_eq!(addrs(&scanned[2]), vec![MENU_ADDR]);
+        assert_eq!(addrs(&scanned[3]), vec![PRICES_ADDR]);
+        assert_eq!(scanned[1][0].path, Some(menu.to_string()));
+        assert_eq!(scanned[1][0].tags, vec!["sales", "summer"]);
+        assert_eq!(created.filesystems, 1);
+        assert_eq!(created.files, 2);
+        assert_eq!(again.files, 0);
+        assert_eq!(indexed, scanned);
+        assert_eq!(addrs(&retagged), vec![PRICES_ADDR, MENU_ADDR]);
+        assert_eq!(heads, 1);
+        assert_eq!(after.files, 0);
+    }
+}
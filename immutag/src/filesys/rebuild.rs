@@ -0,0 +1,331 @@
+/*
+Rebuilds the git history of a filesystem from its version-store. Every
+version, of every file and branch, becomes a commit of the version-store as
+it was then, by the author and at the time its metadata recorded. A peg
+follows the version after which the manifest hashes to its root, so the pegs
+point at the rebuilt commits. Git times only go down to the second, so the
+pegs' manifests, when the contentsys has them, decide which versions went
+first. The same version-store always rebuilds to the same commits.
+*/
+use std::collections::BTreeMap;
+use std::fs::remove_dir_all;
+use std::path::Path;
+
+use immutag_contentsys::{merkle, Cid};
+use immutag_filesys::manifest::{self, Entry};
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::vcs::{self, Time};
+use immutag_filesys::versionstore::{self, Version};
+use immutag_filesys::pegs;
+
+use crate::err::{Error, ErrorKind, ImmutagError};
+use crate::filesys::peg::leaves;
+use crate::filesys::version::Filesys;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rebuild {
+    pub commits: usize,
+    pub versions: usize,
+    /// Pegs matched to a rebuilt commit.
+    pub pegs: usize,
+    pub head: String,
+}
+
+/// A version of a file, or of a branch.
+struct Event {
+    file_addr: String,
+    version: usize,
+    file_index: u32,
+    branch: u32,
+    seconds: i64,
+    offset: i32,
+    author: Option<String>,
+}
+
+/// Writes a fresh git history for the filesystem. An existing one is only
+/// replaced with `force`.
+pub fn rebuild_git<T: AsRef<str>>(path: T, force: bool) -> Result<Rebuild, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let git = fixture::directorate(filesys.path.clone()) + ".git";
+    if Path::new(&git).exists() {
+        if !force {
+            let msg = format!("{} already has a git history", filesys.path);
+            return Err(ImmutagError::from(Error::new(&msg, ErrorKind::HistoryExists)));
+        }
+        remove_dir_all(&git)?;
+    }
+    vcs::init(filesys.path.as_str())?;
+
+    let versionstore_path = filesys.versionstore();
+    let mut versions: BTreeMap<String, (Vec<Version>, Vec<Record>)> = BTreeMap::new();
+    for file_addr in versionstore::file_addrs(versionstore_path.as_str())? {
+        let versions_file = versionstore::path_versions_file(versionstore_path.as_str(), file_addr.as_str());
+        let metadata_file = versionstore::path_metadata_file(versionstore_path.as_str(), file_addr.as_str());
+        versions.insert(
+            file_addr,
+            (versionstore::versions(versions_file)?, metadata::records(metadata_file)?),
+        );
+    }
+    let pegs_file = immutag_filesys::path_pegs(&filesys.path);
+    let mut pegged = pegs::pegs(pegs_file.as_str())?;
+    let store = filesys.store(path.as_ref())?;
+    let manifests: Vec<Option<Vec<Entry>>> = pegged
+        .iter()
+        .map(|peg| {
+            let cid = peg.get(pegs::MANIFEST)?.parse::<Cid>().ok()?;
+            let data = store.get(&cid).ok()?;
+            manifest::parse(String::from_utf8_lossy(&data)).ok()
+        })
+        .collect();
+    let events = peg_order(events(&versions), &manifests);
+    let mut rebuild = Rebuild {
+        commits: 0,
+        versions: events.len(),
+        pegs: 0,
+        head: String::new(),
+    };
+    let mut replayed: BTreeMap<String, usize> = BTreeMap::new();
+    let mut signature = vcs::author_signature(None, &Time::new(0, 0))?;
+    for event in events.iter() {
+        replayed.insert(event.file_addr.clone(), event.version + 1);
+        let time = Time::new(event.seconds, event.offset);
+        signature = vcs::author_signature(event.author.as_deref(), &time)?;
+        // A version without its metadata is still committed, as a version.
+        let first = versions[&event.file_addr].1.first();
+        let message = match (event.version, first.and_then(|r| r.get(metadata::PARENT_ADDR))) {
+            (0, Some(parent_addr)) => format!(
+                "Branch version {} of {} onto {}",
+                first.and_then(|r| r.get(metadata::PARENT_VERSION)).unwrap_or_default(),
+                parent_addr,
+                event.file_addr
+            ),
+            _ => format!("Commit version {} of {}", event.version, event.file_addr),
+        };
+        let mut files = versionstore_files(&versions, &replayed);
+        files.extend(pegs_files(&pegged[..rebuild.pegs]));
+        rebuild.head = vcs::commit_files(filesys.path.as_str(), &signature, message.as_str(), &files)?;
+        rebuild.commits += 1;
+
+        // Pegs of the version-store as it is now, one after the other.
+        let root = merkle::root(&leaves(&manifest(&versions, &replayed))).map(hex::encode);
+        while rebuild.pegs < pegged.len() && pegged[rebuild.pegs].get(pegs::ROOT) == root.as_deref() {
+            let peg = rebuild.pegs;
+            pegged[peg].set(pegs::COMMIT, rebuild.head.as_str());
+            rebuild.pegs += 1;
+
+            let message = format!("Peg {} of the manifest, root {}", peg, root.as_deref().unwrap_or_default());
+            let mut files = versionstore_files(&versions, &replayed);
+            files.extend(pegs_files(&pegged[..rebuild.pegs]));
+            rebuild.head = vcs::commit_files(filesys.path.as_str(), &signature, message.as_str(), &files)?;
+            rebuild.commits += 1;
+        }
+    }
+
+    // Everything else, like the path-cache and what's pending, as it is now.
+    metadata::write_records_as(pegs_file, &pegs::DESCRIPTOR, &pegged)?;
+    rebuild.head = vcs::commit_by(filesys.path.as_str(), "Rebuild the history from the version-store", &signature)?;
+    rebuild.commits += 1;
+
+    Ok(rebuild)
+}
+
+/// Every version, in the order they were committed. Versions of a file
+/// never go back in time, and a branch starts after the version it forked.
+fn events(versions: &BTreeMap<String, (Vec<Version>, Vec<Record>)>) -> Vec<Event> {
+    let empty = Record::new();
+    let mut events = Vec::new();
+    for (file_addr, (file_versions, records)) in versions.iter() {
+        let mut last: Option<(i64, i32)> = None;
+        for version in 0..file_versions.len() {
+            let record = records.get(version).unwrap_or(&empty);
+            let time = record
+                .get(metadata::TIME)
+                .and_then(vcs::parse_time)
+                .map(|t| (t.seconds(), t.offset_minutes()));
+            let (seconds, offset) = match (time, last) {
+                (Some(time), Some(last)) if time.0 < last.0 => last,
+                (Some(time), _) => time,
+                (None, Some(last)) => last,
+                (None, None) => (0, 0),
+            };
+            last = Some((seconds, offset));
+
+            events.push(Event {
+                file_addr: file_addr.clone(),
+                version,
+                file_index: parse(record.get(metadata::FILE_INDEX)),
+                branch: parse(record.get(metadata::BRANCH)),
+                seconds,
+                offset,
+                author: record.get(metadata::AUTHOR).map(|a| a.to_string()),
+            });
+        }
+    }
+
+    // A branch's first version can't come before the one it was forked from.
+    let forked: Vec<(usize, i64)> = events
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.version == 0 && e.branch > 0)
+        .filter_map(|(pos, e)| {
+            let record = &versions[&e.file_addr].1[0];
+            let parent_addr = record.get(metadata::PARENT_ADDR)?;
+            let parent_version: usize = record.get(metadata::PARENT_VERSION)?.parse().ok()?;
+            let parent = events
+                .iter()
+                .find(|p| p.file_addr == parent_addr && p.version == parent_version)?;
+            Some((pos, parent.seconds))
+        })
+        .collect();
+    for (pos, seconds) in forked {
+        let file_addr = events[pos].file_addr.clone();
+        for event in events.iter_mut().filter(|e| e.file_addr == file_addr && e.seconds < seconds) {
+            event.seconds = seconds;
+        }
+    }
+
+    events.sort_by(|a, b| {
+        (a.seconds, a.file_index, a.branch, a.version, &a.file_addr)
+            .cmp(&(b.seconds, b.file_index, b.branch, b.version, &b.file_addr))
+    });
+
+    events
+}
+
+/// Moves the versions of each peg ahead of the ones after it, as far as the
+/// contentsys has the pegs' manifests.
+fn peg_order(mut events: Vec<Event>, manifests: &[Option<Vec<Entry>>]) -> Vec<Event> {
+    let mut ordered = Vec::new();
+    for entries in manifests.iter() {
+        let entries = match entries {
+            Some(entries) => entries,
+            None => break,
+        };
+        let (pegged, rest): (Vec<Event>, Vec<Event>) = events
+            .into_iter()
+            .partition(|e| entries.iter().any(|p| p.file_addr == e.file_addr && p.version == e.version));
+        ordered.extend(pegged);
+        events = rest;
+    }
+    ordered.extend(events);
+
+    ordered
+}
+
+fn parse(value: Option<&str>) -> u32 {
+    value.and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
+/// The versions-files and metadata-files with just the versions replayed.
+fn versionstore_files(
+    versions: &BTreeMap<String, (Vec<Version>, Vec<Record>)>,
+    replayed: &BTreeMap<String, usize>,
+) -> Vec<(String, Vec<u8>)> {
+    let mut files = Vec::new();
+    for (file_addr, count) in replayed.iter() {
+        let (file_versions, records) = &versions[file_addr];
+        let mut records: Vec<Record> = records.iter().take(*count).cloned().collect();
+        // Branches that haven't been forked yet.
+        for record in records.iter_mut() {
+            let branches: Vec<&str> = match record.get(metadata::BRANCHES) {
+                Some(branches) => branches.split_whitespace().filter(|b| replayed.contains_key(*b)).collect(),
+                None => continue,
+            };
+            match branches.is_empty() {
+                true => record.remove(metadata::BRANCHES),
+                false => {
+                    let branches = branches.join(" ");
+                    record.set(metadata::BRANCHES, branches.as_str());
+                }
+            }
+        }
+
+        let dir = format!("version-store/{}/", file_addr);
+        files.push((
+            dir.clone() + "versions-file",
+            versionstore::render(&file_versions[..*count]).into_bytes(),
+        ));
+        files.push((dir + "metadata-file", metadata::render(&records).into_bytes()));
+    }
+
+    files
+}
+
+fn pegs_files(pegged: &[Record]) -> Vec<(String, Vec<u8>)> {
+    match pegged.is_empty() {
+        true => Vec::new(),
+        false => vec![("pegs".to_string(), metadata::render_as(&pegs::DESCRIPTOR, pegged).into_bytes())],
+    }
+}
+
+/// The manifest of the versions replayed, like `manifest::entries`.
+fn manifest(
+    versions: &BTreeMap<String, (Vec<Version>, Vec<Record>)>,
+    replayed: &BTreeMap<String, usize>,
+) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (file_addr, count) in replayed.iter() {
+        let (file_versions, records) = &versions[file_addr];
+        for (version, v) in file_versions.iter().take(*count).enumerate() {
+            entries.push(Entry {
+                file_addr: file_addr.clone(),
+                version,
+                file_hash: v.file_hash.clone(),
+                content_addr: records
+                    .get(version)
+                    .and_then(|r| r.get(metadata::CONTENT_ADDR))
+                    .map(|a| a.to_string()),
+            });
+        }
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::{branch, peg, verify};
+    use crate::test_support::{commit_version, setup, XPRIV};
+
+    #[test]
+    fn rebuild_history() {
+        let path = "/tmp/immutag_rebuild_git/";
+        let file = "/tmp/immutag_rebuild_git/lemonade_stand.mp4";
+        let other = "/tmp/immutag_rebuild_git/ledger.csv";
+        let mut fixture = setup(path);
+
+        commit_version(path, file, "foobar\n", None);
+        commit_version(path, other, "cups,10\n", None);
+        peg(path).unwrap();
+        commit_version(path, file, "hello world\n", None);
+        branch(path, file, 0).unwrap();
+
+        let filesys = Filesys::open(path).unwrap();
+        let existing = rebuild_git(path, false);
+        let first = rebuild_git(path, true).unwrap();
+        let second = rebuild_git(path, true).unwrap();
+        let verification = verify(path, file, 0).unwrap();
+        let history = vcs::history(filesys.path.as_str(), "pegs").unwrap();
+        let file_addr = immutag_bitcoin::file_addr(XPRIV, 1).unwrap();
+        let versions = format!("version-store/{}/versions-file", file_addr);
+        let version_commits = vcs::history(filesys.path.as_str(), versions.as_str()).unwrap();
+        let other_addr = immutag_bitcoin::file_addr(XPRIV, 2).unwrap();
+        std::fs::write(versionstore::path_metadata_file(filesys.versionstore(), other_addr), "").unwrap();
+        let without_metadata = rebuild_git(path, true).unwrap();
+
+        fixture.teardown(true);
+
+        assert!(existing.is_err());
+        assert_eq!(first, second);
+        assert_eq!(first.versions, 4);
+        assert_eq!(first.pegs, 1);
+        // A commit for each version, the peg, and what's left.
+        assert_eq!(first.commits, 6);
+        assert!(verification.ok());
+        assert_eq!(history.len(), 1);
+        assert_eq!(version_commits.len(), 2);
+        assert!(version_commits[0].time <= version_commits[1].time);
+        assert_eq!(without_metadata.versions, 4);
+    }
+}
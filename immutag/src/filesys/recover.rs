@@ -0,0 +1,707 @@
+/*
+Rebuilds a filesystem from nothing but its mnemonic. The wallet's addresses
+are scanned on the ledger for pegs and forks, until a gap limit of unused
+ones, and each peg's manifest is fetched from the contentsys. The manifests
+are replayed in order, a peg's versions then the peg itself, so the git
+history ends up with the same pegs the original had.
+
+Only what was pegged comes back: aliases, tags, messages and file paths
+never leave the filesystem, so recovered files are named after their address.
+The rebuilt metadata records are signed by the account recovering them.
+*/
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+
+use immutag_bitcoin::opreturn::{self, Payload};
+use immutag_bitcoin::tx::change_addr;
+use immutag_contentsys::{merkle, open_store, Cid, ContentStore, StoreConfig};
+use immutag_filesys::manifest::{self, Entry};
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::{indexes, pathcache, pegs, vcs, versionstore};
+use immutag_ledger::{decode_tx, op_return_data, spends, Ledger, LedgerConfig};
+
+use crate::err::{Error, ErrorKind, ImmutagError};
+use crate::files;
+use crate::filesys::index::GAP_LIMIT;
+use crate::filesys::peg::leaves;
+use crate::filesys::signing;
+use crate::filesys::version::{canonical, Filesys};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recovery {
+    pub filesys_addr: String,
+    pub pegs: usize,
+    pub forks: usize,
+    pub versions: usize,
+    /// Where the latest version of each file was written.
+    pub files: Vec<String>,
+    /// Content addresses the contentsys couldn't give back.
+    pub missing: Vec<String>,
+}
+
+/// An immutag record in one of the wallet's transactions.
+struct Found {
+    payload: Payload,
+    txid: String,
+    height: Option<u64>,
+}
+
+/// A peg and the manifest fetched for it.
+struct Pegged<'a> {
+    version: u64,
+    cid: Cid,
+    data: Vec<u8>,
+    entries: Vec<Entry>,
+    found: &'a Found,
+}
+
+/// Where an address is in the wallet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Position {
+    File(u32),
+    /// A file index and branch.
+    Branch(u32, u32),
+}
+
+/// Recovers the filesystem of `mnemonic` into the immutag directory at
+/// `path`, through the ledger and contentsys its Immutag file names.
+pub fn recover<T: AsRef<str>>(path: T, mnemonic: T) -> Result<Recovery, ImmutagError> {
+    let config = LedgerConfig {
+        ledger: files::get_ledger(path.as_ref())?,
+        electrum: files::get_electrum(path.as_ref())?,
+    };
+    let ledger = immutag_ledger::open_ledger(&config)?;
+
+    recover_from(path.as_ref(), mnemonic.as_ref(), ledger.as_ref(), None)
+}
+
+/// Like `recover`, but reads `ledger`, and fetches content from `source`
+/// rather than the filesystem's own store when there's one. What's fetched
+/// from `source` is copied into the filesystem's store.
+pub fn recover_from<T: AsRef<str>>(
+    path: T,
+    mnemonic: T,
+    ledger: &dyn Ledger,
+    source: Option<&dyn ContentStore>,
+) -> Result<Recovery, ImmutagError> {
+    let xpriv = immutag_bitcoin::mnemonic_xpriv(mnemonic.as_ref())?;
+    let filesys_addr = immutag_bitcoin::filesys_addr(&xpriv)?;
+    if !files::get_filesystems(path.as_ref())?.is_empty() {
+        let msg = "recover into an immutag directory without filesystems";
+        return Err(ImmutagError::from(Error::new(msg, ErrorKind::FilesysExists)));
+    }
+
+    let (found, mut used) = scan(ledger, &xpriv)?;
+    let mut pegged: Vec<(u64, &Found)> = found
+        .iter()
+        .filter(|f| f.payload.parent.as_deref() == Some(filesys_addr.as_str()))
+        .filter_map(|f| match f.payload.record {
+            opreturn::Record::Peg { version } => Some((version, f)),
+            _ => None,
+        })
+        .collect();
+    pegged.sort_by_key(|(version, f)| (*version, f.height.unwrap_or(u64::MAX)));
+    // A peg broadcast twice is still the one peg, the first to be mined.
+    pegged.dedup_by_key(|(version, _)| *version);
+    // Branch address to the fork's record.
+    let forks: HashMap<String, &Found> = found
+        .iter()
+        .filter_map(|f| match &f.payload.record {
+            opreturn::Record::Fork { branch_addr } => Some((branch_addr.clone(), f)),
+            _ => None,
+        })
+        .collect();
+    if pegged.is_empty() {
+        let msg = format!("no pegs of {} were found on the ledger", filesys_addr);
+        return Err(ImmutagError::from(Error::new(&msg, ErrorKind::NotPegged)));
+    }
+
+    // Nothing is written until every manifest has been fetched and replayed,
+    // so a failed recovery can be tried again.
+    let dotimmutag = fixture::directorate(path.as_ref().to_string()) + ".immutag";
+    let config = StoreConfig {
+        contentsys: files::get_contentsys(path.as_ref())?,
+        ipfs_api: files::get_ipfs_api(path.as_ref())?,
+    };
+    let own = open_store(&config, immutag_filesys::path_filesys(dotimmutag, filesys_addr.clone()))?;
+    let store = source.unwrap_or_else(|| own.as_ref());
+
+    let mut manifests = Vec::new();
+    for (version, peg) in pegged {
+        let cid = Cid::from_bytes(&peg.payload.content_addr)?;
+        let data = match store.get(&cid) {
+            Ok(data) => data,
+            Err(e) => {
+                let msg = format!("can't fetch the manifest of peg {}, {}: {:?}", peg.txid, cid, e);
+                return Err(ImmutagError::from(Error::new(&msg, ErrorKind::NotRecoverable)));
+            }
+        };
+        let entries = manifest::parse(String::from_utf8_lossy(&data))?;
+        used.extend(entries.iter().map(|e| e.file_addr.clone()));
+        manifests.push(Pegged {
+            version,
+            cid,
+            data,
+            entries,
+            found: peg,
+        });
+    }
+    used.extend(forks.keys().cloned());
+    let positions = positions(&xpriv, &used)?;
+    let replayed = replay(&xpriv, &manifests, &positions, &forks)?;
+
+    files::add_filesystem(path.as_ref(), filesys_addr.as_str(), xpriv.as_str())?;
+    crate::filesys::add_filesys(path.as_ref(), filesys_addr.as_str());
+    let filesys = Filesys::open(path.as_ref())?;
+
+    // Every index up to the last one gets an entry and a claim, so the
+    // path-cache lines up with the indexes of older filesystems too.
+    let dir = fixture::directorate(path.as_ref().to_string());
+    let last = positions
+        .values()
+        .filter_map(|p| match p {
+            Position::File(index) => Some(*index),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let mut file_paths = Vec::new();
+    for index in 1..=last {
+        let file_addr = immutag_bitcoin::file_addr(&xpriv, index)?;
+        let file_path = canonical(dir.clone() + &file_addr)?;
+        pathcache::set(filesys.pathcache(), file_addr.clone(), file_path.clone())?;
+        indexes::claim(filesys.metadata(), index, file_addr.clone(), file_path.clone())?;
+        file_paths.push((file_addr, file_path));
+    }
+
+    let mut recovery = Recovery {
+        filesys_addr: filesys_addr.clone(),
+        pegs: manifests.len(),
+        forks: forks
+            .values()
+            .filter(|f| f.payload.parent.as_ref().is_some_and(|p| positions.contains_key(p)))
+            .count(),
+        versions: 0,
+        files: Vec::new(),
+        missing: Vec::new(),
+    };
+    let versionstore_path = filesys.versionstore();
+    for (pegged, new) in manifests.iter().zip(replayed) {
+        let peg = pegged.version;
+        // Verifying a recovered version reads the manifest from the store.
+        if source.is_some() {
+            own.put(&pegged.data)?;
+        }
+
+        let mut recovered = 0;
+        for (entry, position) in new {
+            let versions_file = versionstore::path_versions_file(versionstore_path.as_str(), entry.file_addr.as_str());
+            let mut record = version_record(&xpriv, &versionstore_path, entry, position, &forks)?;
+            signing::sign(&filesys, &mut record)?;
+            create_dir_all(versionstore::path_file(versionstore_path.as_str(), entry.file_addr.as_str()))?;
+            versionstore::append_version(
+                versions_file,
+                versionstore::Version {
+                    file_hash: entry.file_hash.clone(),
+                    file_addr: entry.file_addr.clone(),
+                },
+            )?;
+            let metadata_file = versionstore::path_metadata_file(versionstore_path.as_str(), entry.file_addr.as_str());
+            metadata::append_record(metadata_file, record)?;
+
+            if let Some(content_addr) = entry.content_addr.as_ref() {
+                if fetch(store, own.as_ref(), source.is_some(), content_addr, &entry.file_hash)?.is_none() {
+                    recovery.missing.push(content_addr.clone());
+                }
+            }
+            recovered += 1;
+        }
+        recovery.versions += recovered;
+
+        if recovered > 0 {
+            let message = format!("Recover the versions of peg {}", peg);
+            vcs::commit(filesys.path.as_str(), message.as_str())?;
+        }
+        let root = merkle::root(&leaves(&pegged.entries)).map(hex::encode).unwrap_or_default();
+        let height = pegged.found.height.map(|h| h.to_string());
+        let mut record = Record::new();
+        record.set(pegs::PEG, peg.to_string().as_str());
+        record.set(pegs::ROOT, root.as_str());
+        record.set(pegs::MANIFEST, pegged.cid.to_string().as_str());
+        record.set(pegs::COMMIT, vcs::head(&filesys.path)?.unwrap_or_default().as_str());
+        record.set(pegs::HEIGHT, height.as_deref().unwrap_or(pegs::UNCONFIRMED));
+        record.set(pegs::TXID, pegged.found.txid.as_str());
+        pegs::add(immutag_filesys::path_pegs(&filesys.path), record)?;
+
+        let message = format!("Peg {} of the manifest, root {}", peg, root);
+        vcs::commit(filesys.path.as_str(), message.as_str())?;
+    }
+
+    // The latest version of each file goes where the path-cache points.
+    for (file_addr, file_path) in file_paths {
+        let metadata_file = versionstore::path_metadata_file(versionstore_path.as_str(), file_addr.as_str());
+        let latest = match metadata::records(metadata_file)?.pop() {
+            Some(latest) => latest,
+            None => continue,
+        };
+        let content_addr = latest.get(metadata::CONTENT_ADDR).unwrap_or_default();
+        let file_hash = latest.get(metadata::FILE_HASH).unwrap_or_default();
+        if Path::new(&file_path).exists() {
+            continue;
+        }
+        if let Some(data) = fetch(store, own.as_ref(), false, content_addr, file_hash)? {
+            write(&file_path, data)?;
+            recovery.files.push(file_path);
+        }
+    }
+    recovery.missing.sort();
+    recovery.missing.dedup();
+
+    Ok(recovery)
+}
+
+/// The wallet's immutag records, from the histories of its external and
+/// change addresses, and the external addresses that have been used.
+fn scan(ledger: &dyn Ledger, xpriv: &str) -> Result<(Vec<Found>, HashSet<String>), ImmutagError> {
+    let mut found = Vec::new();
+    let mut used = HashSet::new();
+    let mut txids = HashSet::new();
+
+    for chain in 0..2 {
+        let (mut index, mut unused) = (0, 0);
+        while unused < GAP_LIMIT {
+            let addr = match chain {
+                0 => immutag_bitcoin::file_addr(xpriv, index)?,
+                _ => change_addr(xpriv, index)?,
+            };
+            let history = ledger.history(&addr)?;
+            index += 1;
+            if history.is_empty() {
+                unused += 1;
+                continue;
+            }
+            unused = 0;
+            if chain == 0 {
+                used.insert(addr);
+            }
+
+            for tx in history {
+                if !txids.insert(tx.txid.clone()) {
+                    continue;
+                }
+                let decoded = decode_tx(&tx.raw)?;
+                for data in op_return_data(&decoded) {
+                    // Not everything in an OP_RETURN is immutag's.
+                    let payload = match Payload::decode(&data) {
+                        Ok(payload) => payload,
+                        Err(_) => continue,
+                    };
+                    // Anyone can send a record to an address, so only the
+                    // ones its parent's key signed count.
+                    let signed = match payload.parent.as_deref() {
+                        Some(parent) => spends(&decoded, parent)?,
+                        None => false,
+                    };
+                    if signed {
+                        found.push(Found {
+                            payload,
+                            txid: tx.txid.clone(),
+                            height: tx.height,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok((found, used))
+}
+
+/// Replays the manifests without writing anything, and returns the versions
+/// each one adds, files before branches so a branch's parent version is
+/// there. Fails on anything that can't be recovered: an address that isn't
+/// the wallet's, a version that differs from or skips the ones pegged
+/// before it, or a branch forked from no version of its parent.
+fn replay<'a>(
+    xpriv: &str,
+    manifests: &'a [Pegged],
+    positions: &HashMap<String, Position>,
+    forks: &HashMap<String, &Found>,
+) -> Result<Vec<Vec<(&'a Entry, Position)>>, ImmutagError> {
+    let not_recoverable = |msg: String| ImmutagError::from(Error::new(&msg, ErrorKind::NotRecoverable));
+    // File address to its versions so far.
+    let mut versions: HashMap<&str, Vec<&Entry>> = HashMap::new();
+    let mut replayed = Vec::new();
+
+    for pegged in manifests.iter() {
+        let peg = pegged.version;
+        let mut ordered: Vec<(&Entry, Position)> = Vec::new();
+        for entry in pegged.entries.iter() {
+            match positions.get(&entry.file_addr) {
+                Some(position) => ordered.push((entry, *position)),
+                None => {
+                    return Err(not_recoverable(format!(
+                        "{} in peg {} isn't one of the wallet's addresses within the gap limit",
+                        entry.file_addr, peg
+                    )))
+                }
+            }
+        }
+        ordered.sort_by_key(|(_, position)| matches!(position, Position::Branch(..)));
+
+        let mut new = Vec::new();
+        for (entry, position) in ordered {
+            let known = versions.get(entry.file_addr.as_str()).map(|v| v.len()).unwrap_or(0);
+            if entry.version < known {
+                if versions[entry.file_addr.as_str()][entry.version].file_hash != entry.file_hash {
+                    return Err(not_recoverable(format!(
+                        "peg {} has another version {} of {} than the pegs before it",
+                        peg, entry.version, entry.file_addr
+                    )));
+                }
+                continue;
+            }
+            if entry.version > known {
+                return Err(not_recoverable(format!("peg {} skips versions of {}", peg, entry.file_addr)));
+            }
+            if let (Position::Branch(index, _), 0) = (position, entry.version) {
+                let parent_addr = immutag_bitcoin::file_addr(xpriv, index)?;
+                let parents = versions.get(parent_addr.as_str()).cloned().unwrap_or_default();
+                let forked = forks.get(&entry.file_addr).map(|f| Cid::from_bytes(&f.payload.content_addr));
+                let parent_version = match forked {
+                    Some(Ok(cid)) => {
+                        let content_addr = cid.to_string();
+                        parents.iter().position(|p| p.content_addr.as_ref() == Some(&content_addr))
+                    }
+                    _ => parents.iter().position(|p| p.file_hash == entry.file_hash),
+                };
+                if parent_version.is_none() {
+                    return Err(not_recoverable(format!(
+                        "no version of {} was forked onto {}",
+                        parent_addr, entry.file_addr
+                    )));
+                }
+            }
+
+            versions.entry(entry.file_addr.as_str()).or_default().push(entry);
+            new.push((entry, position));
+        }
+        replayed.push(new);
+    }
+
+    Ok(replayed)
+}
+
+/// Finds the used addresses in the wallet: files from index 1, and the
+/// branches of each file from 1, until a gap of unused ones.
+fn positions(xpriv: &str, used: &HashSet<String>) -> Result<HashMap<String, Position>, ImmutagError> {
+    let mut positions = HashMap::new();
+    let (mut index, mut unused) = (1, 0);
+    while unused < GAP_LIMIT {
+        let file_addr = immutag_bitcoin::file_addr(xpriv, index)?;
+        if used.contains(&file_addr) {
+            positions.insert(file_addr, Position::File(index));
+            unused = 0;
+        } else {
+            unused += 1;
+        }
+        index += 1;
+    }
+
+    let files: Vec<u32> = positions
+        .values()
+        .filter_map(|p| match p {
+            Position::File(index) => Some(*index),
+            _ => None,
+        })
+        .collect();
+    for index in files {
+        let (mut branch, mut unused) = (1, 0);
+        while unused < GAP_LIMIT {
+            let branch_addr = immutag_bitcoin::branch_addr(xpriv, index, branch)?;
+            if used.contains(&branch_addr) {
+                positions.insert(branch_addr, Position::Branch(index, branch));
+                unused = 0;
+            } else {
+                unused += 1;
+            }
+            branch += 1;
+        }
+    }
+
+    Ok(positions)
+}
+
+/// The metadata record of a version, as committing or branching would have
+/// written it, without what only the original filesystem knew.
+fn version_record(
+    xpriv: &str,
+    versionstore_path: &str,
+    entry: &Entry,
+    position: Position,
+    forks: &HashMap<String, &Found>,
+) -> Result<Record, ImmutagError> {
+    let metadata_file = versionstore::path_metadata_file(versionstore_path, entry.file_addr.as_str());
+    let version = entry.version as u32;
+    let mut record = match (position, entry.version) {
+        (Position::File(index), 0) => {
+            let mut record = Record::new();
+            record.set(metadata::FILE_ADDR, entry.file_addr.as_str());
+            record.set(metadata::FILE_INDEX, index.to_string().as_str());
+            record
+        }
+        (Position::Branch(index, branch), 0) => {
+            let parent_addr = immutag_bitcoin::file_addr(xpriv, index)?;
+            let parent_file = versionstore::path_metadata_file(versionstore_path, parent_addr.as_str());
+            let mut parents = metadata::records(parent_file.as_str())?;
+            // The forked version is the one the fork recorded, or else the
+            // first with the same bytes.
+            let forked = forks.get(&entry.file_addr).map(|f| Cid::from_bytes(&f.payload.content_addr));
+            let parent_version = match forked {
+                Some(Ok(cid)) => {
+                    let content_addr = cid.to_string();
+                    parents.iter().position(|r| r.get(metadata::CONTENT_ADDR) == Some(content_addr.as_str()))
+                }
+                _ => parents.iter().position(|r| r.get(metadata::FILE_HASH) == Some(entry.file_hash.as_str())),
+            };
+            let parent_version = match parent_version {
+                Some(parent_version) => parent_version,
+                None => {
+                    let msg = format!("no version of {} was forked onto {}", parent_addr, entry.file_addr);
+                    return Err(ImmutagError::from(Error::new(&msg, ErrorKind::NotRecoverable)));
+                }
+            };
+
+            let mut record = parents[parent_version].clone();
+            record.remove(metadata::BRANCHES);
+            record.set(metadata::FILE_ADDR, entry.file_addr.as_str());
+            record.set(metadata::BRANCH, branch.to_string().as_str());
+            record.set(metadata::PARENT_ADDR, parent_addr.as_str());
+            record.set(metadata::PARENT_VERSION, parent_version.to_string().as_str());
+
+            let parent = &mut parents[parent_version];
+            let children = match parent.get(metadata::BRANCHES) {
+                Some(children) => format!("{} {}", children, entry.file_addr),
+                None => entry.file_addr.clone(),
+            };
+            parent.set(metadata::BRANCHES, children.as_str());
+            metadata::write_records(parent_file, &parents)?;
+
+            record
+        }
+        // Later versions carry over from the one before, like adding does.
+        _ => metadata::records(metadata_file)?.pop().unwrap_or_default(),
+    };
+
+    let version_addr = match position {
+        Position::File(index) => immutag_bitcoin::version_addr(xpriv, index, version)?,
+        Position::Branch(index, branch) => immutag_bitcoin::branch_version_addr(xpriv, index, branch, version)?,
+    };
+    record.remove(metadata::MESSAGE);
+    record.remove(metadata::BRANCHES);
+    record.set(metadata::FILE_HASH, entry.file_hash.as_str());
+    match entry.content_addr.as_ref() {
+        Some(content_addr) => record.set(metadata::CONTENT_ADDR, content_addr.as_str()),
+        None => record.remove(metadata::CONTENT_ADDR),
+    }
+    record.set(metadata::VERSION_ADDR, version_addr.as_str());
+
+    Ok(record)
+}
+
+/// The bytes of a version, if the store has them and they hash right. With
+/// `copy` they're put in the filesystem's own store too.
+fn fetch(
+    store: &dyn ContentStore,
+    own: &dyn ContentStore,
+    copy: bool,
+    content_addr: &str,
+    file_hash: &str,
+) -> Result<Option<Vec<u8>>, ImmutagError> {
+    let data = content_addr
+        .parse::<Cid>()
+        .ok()
+        .and_then(|cid| store.get(&cid).ok())
+        .filter(|data| immutag_contentsys::file_hash(data) == file_hash);
+    if let (Some(data), true) = (data.as_ref(), copy) {
+        own.put(data)?;
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::{add_content, branch, commit, peg, verify};
+    use crate::test_support::{broadcast, init, FILESYS_ADDR};
+    use fixture::Fixture;
+    use immutag_contentsys::{CidVersion, LocalStore};
+    use immutag_ledger::MockLedger;
+    use std::fs::read;
+
+    const MNEMONIC: &str = "certain dust pave crane renew multiply stone stuff proud flee fancy knee";
+    const FILE_ADDR: &str = "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9";
+
+    fn read_file(path: String) -> String {
+        String::from_utf8(read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn recover_filesys() {
+        let original = "/tmp/immutag_recover_tests/original";
+        let recovered = "/tmp/immutag_recover_tests/recovered";
+        let file = "/tmp/immutag_recover_tests/original/lemonade_stand.mp4";
+        let other = "/tmp/immutag_recover_tests/original/ledger.csv";
+        let mut fixture = Fixture::new()
+            .add_dirpath(original.to_string())
+            .add_dirpath(recovered.to_string())
+            .build();
+        init(original);
+        files::immutag_file_init(recovered, "0.1.0").unwrap();
+        let ledger = MockLedger::new("bitcoinsv").unwrap();
+        let filesys = Filesys::open(original).unwrap();
+
+        write(file, b"foobar\n").unwrap();
+        add_content(original, file, None).unwrap();
+        commit(original, file).unwrap();
+        write(other, b"hello world\n").unwrap();
+        add_content(original, other, None).unwrap();
+        commit(original, other).unwrap();
+        peg(original).unwrap();
+        // Someone else's peg 0, sent to the filesystem's address ahead of
+        // the real one.
+        let forged = Payload {
+            record: opreturn::Record::Peg { version: 0 },
+            content_addr: immutag_contentsys::content_addr(b"forged", CidVersion::V0).to_bytes(),
+            parent: Some(FILESYS_ADDR.to_string()),
+        };
+        ledger.send(FILESYS_ADDR, &forged.encode().unwrap()).unwrap();
+        broadcast(&ledger, &filesys, 0);
+        ledger.mine();
+        write(file, b"hello world\n").unwrap();
+        add_content(original, file, None).unwrap();
+        commit(original, file).unwrap();
+        let forked = branch(original, file, 0).unwrap();
+        broadcast(&ledger, &filesys, 1);
+        peg(original).unwrap();
+        broadcast(&ledger, &filesys, 0);
+
+        let nobody = recover_from(recovered, "legal winner thank year wave sausage worth useful legal winner thank yellow", &ledger, None);
+        let source = LocalStore::open(&filesys.path).unwrap();
+        let elsewhere = LocalStore::new(format!("{}/elsewhere", original), CidVersion::V0).unwrap();
+        let unfetched = recover_from(recovered, MNEMONIC, &ledger, Some(&elsewhere));
+        let recovery = recover_from(recovered, MNEMONIC, &ledger, Some(&source)).unwrap();
+        let again = recover_from(recovered, MNEMONIC, &ledger, Some(&source));
+
+        let restored = Filesys::open(recovered).unwrap();
+        let versionstore = |filesys: &Filesys, addr: &str, name: &str| {
+            read_file(versionstore::path_file(filesys.versionstore(), addr.to_string()) + name)
+        };
+        let restored_file = format!("{}/{}", recovered, FILE_ADDR);
+        let verification = verify(recovered, restored_file.as_str(), 0).unwrap();
+        let signed = signing::verify_metadata(recovered, restored_file.as_str()).unwrap();
+        let restored_pegs = pegs::pegs(immutag_filesys::path_pegs(&restored.path)).unwrap();
+        let original_pegs = pegs::pegs(immutag_filesys::path_pegs(&filesys.path)).unwrap();
+        let branch_records = metadata::records(versionstore::path_metadata_file(
+            restored.versionstore(),
+            forked.branch_addr.clone(),
+        )).unwrap();
+        let original_branch = metadata::records(versionstore::path_metadata_file(
+            filesys.versionstore(),
+            forked.branch_addr.clone(),
+        )).unwrap();
+        let versions_file = format!("version-store/{}/versions-file", FILE_ADDR);
+        let log = vcs::history(restored.path.as_str(), versions_file.as_str()).unwrap();
+        let (original_manifest, restored_manifest) = (
+            manifest::entries(filesys.versionstore()).unwrap(),
+            manifest::entries(restored.versionstore()).unwrap(),
+        );
+        let (original_versions, restored_versions) = (
+            versionstore(&filesys, FILE_ADDR, "versions-file"),
+            versionstore(&restored, FILE_ADDR, "versions-file"),
+        );
+        let content = read_file(restored_file.clone());
+
+        fixture.teardown(true);
+
+        let kind = |result: Result<Recovery, ImmutagError>| match result {
+            Err(ImmutagError::Error(err)) => Some(err.kind),
+            _ => None,
+        };
+        assert_eq!(kind(nobody), Some(ErrorKind::NotPegged));
+        // Nothing was written, so the recovery after it went through.
+        assert_eq!(kind(unfetched), Some(ErrorKind::NotRecoverable));
+        assert_eq!(kind(again), Some(ErrorKind::FilesysExists));
+        assert_eq!(recovery.filesys_addr, FILESYS_ADDR);
+        assert_eq!((recovery.pegs, recovery.forks, recovery.versions), (2, 1, 4));
+        assert!(recovery.missing.is_empty());
+        assert_eq!(recovery.files.len(), 2);
+        assert_eq!(content, "hello world\n");
+        assert_eq!(restored_manifest, original_manifest);
+        assert_eq!(restored_versions, original_versions);
+        assert_eq!(restored_pegs.len(), 2);
+        for (restored, original) in restored_pegs.iter().zip(original_pegs.iter()) {
+            assert_eq!(restored.get(pegs::PEG), original.get(pegs::PEG));
+            assert_eq!(restored.get(pegs::ROOT), original.get(pegs::ROOT));
+            assert_eq!(restored.get(pegs::MANIFEST), original.get(pegs::MANIFEST));
+        }
+        assert_eq!(restored_pegs[0].get(pegs::HEIGHT), Some("1"));
+        assert_eq!(restored_pegs[1].get(pegs::HEIGHT), Some(pegs::UNCONFIRMED));
+        assert_eq!(branch_records[0].get(metadata::PARENT_VERSION), Some("0"));
+        assert_eq!(branch_records[0].get(metadata::BRANCH), Some("1"));
+        assert_eq!(branch_records[0].get(metadata::VERSION_ADDR), original_branch[0].get(metadata::VERSION_ADDR));
+        assert_eq!(branch_records[0].get(metadata::PARENT_ADDR), Some(FILE_ADDR));
+        assert_eq!(log.len(), 2);
+        assert!(verification.ok());
+        assert!(signed.ok(), "{:?}", signed.records);
+        assert_eq!(signed.records.len(), 3);
+    }
+
+    #[test]
+    fn recover_later_peg() {
+        let original = "/tmp/immutag_recover_later_tests/original";
+        let recovered = "/tmp/immutag_recover_later_tests/recovered";
+        let file = "/tmp/immutag_recover_later_tests/original/lemonade_stand.mp4";
+        let mut fixture = Fixture::new()
+            .add_dirpath(original.to_string())
+            .add_dirpath(recovered.to_string())
+            .build();
+        init(original);
+        files::immutag_file_init(recovered, "0.1.0").unwrap();
+        let ledger = MockLedger::new("bitcoinsv").unwrap();
+        let filesys = Filesys::open(original).unwrap();
+
+        // Peg 0 never makes it onto the ledger, peg 1 does.
+        write(file, b"foobar\n").unwrap();
+        add_content(original, file, None).unwrap();
+        commit(original, file).unwrap();
+        peg(original).unwrap();
+        write(file, b"hello world\n").unwrap();
+        add_content(original, file, None).unwrap();
+        commit(original, file).unwrap();
+        peg(original).unwrap();
+        broadcast(&ledger, &filesys, 0);
+
+        let source = LocalStore::open(&filesys.path).unwrap();
+        let recovery = recover_from(recovered, MNEMONIC, &ledger, Some(&source)).unwrap();
+        let restored = Filesys::open(recovered).unwrap();
+        let restored_pegs = pegs::pegs(immutag_filesys::path_pegs(&restored.path)).unwrap();
+        let restored_file = format!("{}/{}", recovered, FILE_ADDR);
+        let verification = verify(recovered, restored_file.as_str(), 1).unwrap();
+        write(&restored_file, b"lemons\n").unwrap();
+        add_content(recovered, restored_file.as_str(), None).unwrap();
+        commit(recovered, restored_file.as_str()).unwrap();
+        let next = peg(recovered).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!((recovery.pegs, recovery.versions), (1, 2));
+        assert_eq!(restored_pegs.len(), 1);
+        assert_eq!(restored_pegs[0].get(pegs::PEG), Some("1"));
+        assert_eq!(verification.peg, Some(1));
+        assert!(verification.ok());
+        assert_eq!(next.peg, 2);
+    }
+}
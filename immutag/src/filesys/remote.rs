@@ -0,0 +1,162 @@
+/*
+Pushes and pulls the filesystem directory to and from collaborators' git
+remotes. A pull that would bring in a file under an index already taken here
+is refused, since the two files would share an address; one of them has to
+be renumbered first. Versions the merge moves are readdressed, and every pull
+ends with a consistency check.
+*/
+pub use immutag_filesys::remote::Merge;
+use immutag_filesys::{remote, vcs};
+
+use crate::err::{Error, ErrorKind, ImmutagError};
+use crate::filesys::consistency::consistency;
+use crate::filesys::index::collisions;
+use crate::filesys::merge::{readdress_versionstore, Resolution};
+use crate::filesys::verify::Check;
+use crate::filesys::version::Filesys;
+use crate::filesys::view;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pull {
+    /// The remote branch pulled, like `origin/master`, if the remote has it.
+    pub rev: Option<String>,
+    pub merge: Merge,
+    pub checks: Vec<Check>,
+}
+
+impl Pull {
+    pub fn ok(&self) -> bool {
+        self.checks.iter().all(|c| c.ok)
+    }
+}
+
+pub fn remote_add<T: AsRef<str>>(path: T, name: T, url: T) -> Result<(), ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+
+    Ok(remote::add(filesys.path.as_str(), name.as_ref(), url.as_ref())?)
+}
+
+pub fn remote_remove<T: AsRef<str>>(path: T, name: T) -> Result<(), ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+
+    Ok(remote::remove(filesys.path.as_str(), name.as_ref())?)
+}
+
+/// Names and URLs of the remotes.
+pub fn remotes<T: AsRef<str>>(path: T) -> Result<Vec<(String, String)>, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+
+    Ok(remote::remotes(filesys.path.as_str())?)
+}
+
+pub fn push<T: AsRef<str>>(path: T, name: T) -> Result<(), ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+
+    Ok(remote::push(filesys.path.as_str(), name.as_ref())?)
+}
+
+/// Fetches and merges the remote's branch, then checks the filesystem.
+/// Metadata fields both sides changed go by `resolution`.
+pub fn pull<T: AsRef<str>>(path: T, name: T, resolution: Resolution) -> Result<Pull, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let rev = remote::fetch(filesys.path.as_str(), name.as_ref())?;
+    let mut sides = Vec::new();
+
+    let merge = match rev.as_ref() {
+        Some(rev) => {
+            let collisions = collisions(path.as_ref(), Some(rev.as_str()))?;
+            if let Some(collision) = collisions.first() {
+                let msg = format!(
+                    "{} claims index {} for another file than here, {}; renumber one of them with --with {}",
+                    rev,
+                    collision.file_index,
+                    collision.paths.join(" "),
+                    rev
+                );
+                return Err(ImmutagError::from(Error::new(&msg, ErrorKind::IndexCollision)));
+            }
+            let ours = vcs::head(filesys.path.as_str())?.unwrap_or_default();
+            let theirs = vcs::resolve(filesys.path.as_str(), rev.as_str())?;
+            sides = vec![ours, theirs];
+            remote::merge(filesys.path.as_str(), rev.as_str(), resolution)?
+        }
+        None => Merge::UpToDate,
+    };
+    if let Merge::Merged(_) = merge {
+        if !readdress_versionstore(path.as_ref(), &sides)?.is_empty() {
+            vcs::commit(filesys.path.as_str(), "Readdress and sign the records the merge changed")?;
+        }
+    }
+    if merge != Merge::UpToDate {
+        view::refresh(path.as_ref())?;
+    }
+
+    Ok(Pull {
+        rev,
+        merge,
+        checks: consistency(path.as_ref())?,
+    })
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::test_support::{commit_version, init, XPRIV};
+    use fixture::Fixture;
+
+    fn commit_file(path: &str, name: &str, content: &str) {
+        commit_version(path, format!("{}{}", path, name).as_str(), content, None);
+    }
+
+    #[test]
+    fn collaborate() {
+        let ours = "/tmp/immutag_pull_ours/";
+        let theirs = "/tmp/immutag_pull_theirs/";
+        let bare = "/tmp/immutag_pull_bare.git";
+        let mut fixture = Fixture::new()
+            .add_dirpath(ours.to_string())
+            .add_dirpath(theirs.to_string())
+            .build();
+        git2::Repository::init_bare(bare).unwrap();
+        let url = format!("file://{}", bare);
+        init(ours);
+        init(theirs);
+
+        commit_file(ours, "README.md", "# Lemonade stand");
+        remote_add(ours, "origin", url.as_str()).unwrap();
+        push(ours, "origin").unwrap();
+
+        remote_add(theirs, "origin", bare).unwrap();
+        let cloned = pull(theirs, "origin", Resolution::Flag).unwrap();
+        commit_file(theirs, "ledger.csv", "cups,10");
+        push(theirs, "origin").unwrap();
+
+        commit_file(ours, "README.md", "# Lemonade stand, open");
+        let merged = pull(ours, "origin", Resolution::Flag).unwrap();
+        commit_file(ours, "menu.txt", "lemonade");
+        let menu_index = Filesys::open(ours)
+            .map(|f| immutag_filesys::indexes::lookup_index(f.metadata(), immutag_bitcoin::file_addr(XPRIV, 3).unwrap()))
+            .unwrap()
+            .unwrap();
+        push(ours, "origin").unwrap();
+
+        commit_file(theirs, "recipe.txt", "lemons, sugar, water");
+        let collision = pull(theirs, "origin", Resolution::Flag);
+        let listed = remotes(theirs).unwrap();
+
+        fixture.teardown(true);
+        std::fs::remove_dir_all(bare).unwrap();
+
+        assert_eq!(cloned.rev, Some("origin/master".to_string()));
+        assert_eq!(cloned.merge, Merge::FastForward);
+        assert!(cloned.ok());
+        assert!(matches!(merged.merge, Merge::Merged(_)));
+        assert!(merged.ok(), "{:?}", merged.checks);
+        assert_eq!(menu_index, Some(3));
+        match collision {
+            Err(ImmutagError::Error(e)) => assert_eq!(e.kind, ErrorKind::IndexCollision),
+            other => panic!("expected an index collision, got {:?}", other),
+        }
+        assert_eq!(listed, vec![("origin".to_string(), bare.to_string())]);
+    }
+}
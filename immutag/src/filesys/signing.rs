@@ -0,0 +1,193 @@
+/*
+Signs each metadata record with the committing collaborator's account key,
+`m / 44' / 0' / k'`, so a record shows who made it. Every collaborator holds
+the filesystem's wallet, so the signature tells which account signed, not
+that nobody else could have.
+*/
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use immutag_bitcoin::message;
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::versionstore;
+
+use crate::err::ImmutagError;
+use crate::filesys::index::GAP_LIMIT;
+use crate::filesys::version::{canonical, tracked, Filesys};
+
+/// A record of a file's history and whether its signature holds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedRecord {
+    /// The file, or the branch of it, the record is in.
+    pub file_addr: String,
+    pub version: usize,
+    /// The account that signed the record, if it's one of the filesystem's.
+    pub account: Option<u32>,
+    pub ok: bool,
+    pub detail: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetadataVerification {
+    pub file_addr: String,
+    pub records: Vec<SignedRecord>,
+}
+
+impl MetadataVerification {
+    pub fn ok(&self) -> bool {
+        self.records.iter().all(|r| r.ok)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let records: Vec<Value> = self
+            .records
+            .iter()
+            .map(|r| {
+                json!({
+                    "file_addr": r.file_addr,
+                    "version": r.version,
+                    "account": r.account,
+                    "ok": r.ok,
+                    "detail": r.detail,
+                })
+            })
+            .collect();
+
+        json!({
+            "file_addr": self.file_addr,
+            "ok": self.ok(),
+            "records": records,
+        })
+    }
+}
+
+/// Signs the canonical form of the record with the filesystem's account, and
+/// stores the signature and public key in it.
+pub(crate) fn sign(filesys: &Filesys, record: &mut Record) -> Result<(), ImmutagError> {
+    let canonical = metadata::canonical(record);
+    let (signature, public_key) = message::sign_message(&filesys.xpriv, filesys.account, canonical.as_bytes())?;
+    record.set(metadata::PUBLIC_KEY, public_key.as_str());
+    record.set(metadata::SIGNATURE, signature.as_str());
+
+    Ok(())
+}
+
+/// Whether the record's signature holds for its fields, whoever signed it.
+pub(crate) fn holds(record: &Record) -> bool {
+    match (record.get(metadata::SIGNATURE), record.get(metadata::PUBLIC_KEY)) {
+        (Some(signature), Some(public_key)) => {
+            message::verify_message(public_key, metadata::canonical(record).as_bytes(), signature)
+        }
+        _ => false,
+    }
+}
+
+/// Checks the signature of every record of `file`, and of its branches. A
+/// record that doesn't verify is reported, not returned as an error.
+pub fn verify_metadata<T: AsRef<str>>(path: T, file: T) -> Result<MetadataVerification, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let file_path = canonical(file)?;
+    let (file_addr, _) = tracked(&filesys, &file_path)?;
+
+    // Accounts are looked for like file indexes, up to the gap limit.
+    let mut accounts = HashMap::new();
+    for account in 0..GAP_LIMIT {
+        accounts.insert(message::account_public_key(&filesys.xpriv, account)?, account);
+    }
+
+    let mut records = Vec::new();
+    let mut addrs = vec![file_addr.clone()];
+    while let Some(addr) = addrs.pop() {
+        let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), addr.clone());
+        for (version, record) in metadata::records(metadata_file)?.iter().enumerate() {
+            let branches = record.get(metadata::BRANCHES).unwrap_or_default();
+            addrs.extend(branches.split_whitespace().rev().map(|b| b.to_string()));
+            records.push(check(&accounts, addr.as_str(), version, record));
+        }
+    }
+
+    Ok(MetadataVerification { file_addr, records })
+}
+
+fn check(accounts: &HashMap<String, u32>, file_addr: &str, version: usize, record: &Record) -> SignedRecord {
+    let mut signed = SignedRecord {
+        file_addr: file_addr.to_string(),
+        version,
+        account: None,
+        ok: false,
+        detail: String::new(),
+    };
+    let public_key = match (record.get(metadata::SIGNATURE), record.get(metadata::PUBLIC_KEY)) {
+        (Some(_), Some(public_key)) => public_key,
+        _ => {
+            signed.detail = "unsigned".to_string();
+            return signed;
+        }
+    };
+
+    if !holds(record) {
+        signed.detail = format!("the signature of {} doesn't match the record", public_key);
+        return signed;
+    }
+    signed.account = accounts.get(public_key).cloned();
+    match signed.account {
+        Some(account) => {
+            signed.ok = true;
+            let author = record.get(metadata::AUTHOR).unwrap_or("nobody named");
+            signed.detail = format!("signed by account {}, {}", account, author);
+        }
+        None => signed.detail = format!("signed by {}, which isn't one of the filesystem's accounts", public_key),
+    }
+
+    signed
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::files;
+    use crate::filesys::{add_content, branch, commit};
+    use crate::test_support::{setup, FILESYS_ADDR};
+    use std::fs::write;
+
+    const FILE_ADDR: &str = "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9";
+
+    #[test]
+    fn signed_metadata() {
+        let path = "/tmp/immutag_signing/";
+        let file = "/tmp/immutag_signing/README.md";
+        let mut fixture = setup(path);
+
+        write(file, "# Lemonade stand").unwrap();
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+        files::set_account(path, FILESYS_ADDR, 2).unwrap();
+        write(file, "# Lemonade stand, open").unwrap();
+        add_content(path, file, Some("stand")).unwrap();
+        commit(path, file).unwrap();
+        let forked = branch(path, file, 0).unwrap();
+        let account = files::get_account(path, FILESYS_ADDR).unwrap();
+        let signed = verify_metadata(path, file).unwrap();
+
+        let filesys = Filesys::open(path).unwrap();
+        let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), FILE_ADDR.to_string());
+        let mut records = metadata::records(metadata_file.clone()).unwrap();
+        records[1].set(metadata::ALIAS, "lemonade");
+        records[0].remove(metadata::SIGNATURE);
+        metadata::write_records(metadata_file, &records).unwrap();
+        let tampered = verify_metadata(path, file).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(account, Some(2));
+        assert!(signed.ok(), "{:?}", signed.records);
+        let accounts: Vec<Option<u32>> = signed.records.iter().map(|r| r.account).collect();
+        assert_eq!(accounts, vec![Some(0), Some(2), Some(2)]);
+        assert_eq!(signed.records[2].file_addr, forked.branch_addr);
+        assert!(!tampered.ok());
+        assert_eq!(tampered.records[0].detail, "unsigned");
+        assert!(!tampered.records[1].ok);
+        assert!(tampered.records[2].ok);
+    }
+}
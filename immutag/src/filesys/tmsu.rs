@@ -0,0 +1,334 @@
+/*
+Moves tags between the metadata and a TMSU database, so a team can migrate
+at its own pace. A TMSU tag with a value, like `year=2020`, is the immutag
+tag `year=2020`. Files are matched by their path, then by their fingerprint,
+which for TMSU's default `dynamic:SHA256` is the file hash of any file
+under 5 MB.
+*/
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, symlink_metadata};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::pathcache;
+
+use crate::err::ImmutagError;
+use crate::filesys::import::{apply, records, TagImport, Tagged};
+use crate::filesys::util::datetime;
+use crate::filesys::version::Filesys;
+
+/// Where TMSU keeps a database, under the directory it tags.
+pub const TMSU_DB: &str = ".tmsu/db";
+
+/// The tables TMSU 0.7 creates.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS tag (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+CREATE INDEX IF NOT EXISTS idx_tag_name ON tag(name);
+CREATE TABLE IF NOT EXISTS file (
+    id INTEGER PRIMARY KEY,
+    directory TEXT NOT NULL,
+    name TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    mod_time DATETIME NOT NULL,
+    size INTEGER NOT NULL,
+    is_dir BOOLEAN NOT NULL,
+    CONSTRAINT con_file_path UNIQUE (directory, name)
+);
+CREATE INDEX IF NOT EXISTS idx_file_fingerprint ON file(fingerprint);
+CREATE TABLE IF NOT EXISTS value (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    CONSTRAINT con_value_name UNIQUE (name)
+);
+CREATE TABLE IF NOT EXISTS file_tag (
+    file_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    value_id INTEGER NOT NULL,
+    PRIMARY KEY (file_id, tag_id, value_id),
+    FOREIGN KEY (file_id) REFERENCES file(id),
+    FOREIGN KEY (tag_id) REFERENCES tag(id),
+    FOREIGN KEY (value_id) REFERENCES value(id)
+);
+CREATE TABLE IF NOT EXISTS implication (
+    tag_id INTEGER NOT NULL,
+    value_id INTEGER NOT NULL,
+    implied_tag_id INTEGER NOT NULL,
+    implied_value_id INTEGER NOT NULL,
+    PRIMARY KEY (tag_id, value_id, implied_tag_id, implied_value_id)
+);
+CREATE TABLE IF NOT EXISTS query (text TEXT PRIMARY KEY);
+CREATE TABLE IF NOT EXISTS setting (name TEXT PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS version (
+    major NUMBER NOT NULL,
+    minor NUMBER NOT NULL,
+    patch NUMBER NOT NULL,
+    revision TEXT NOT NULL,
+    PRIMARY KEY (major, minor, patch)
+);
+";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TmsuExport {
+    pub files: usize,
+    pub tags: usize,
+}
+
+/// Adds the tags of a TMSU database to the latest version of each file
+/// they match, and commits the metadata.
+pub fn import_tmsu<T: AsRef<str>>(path: T, db: T, dry_run: bool) -> Result<TagImport, ImmutagError> {
+    let conn = Connection::open_with_flags(db.as_ref(), OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let root = root(db.as_ref());
+
+    let mut tagged: BTreeMap<(String, String), Tagged> = BTreeMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT file.directory, file.name, file.fingerprint, tag.name, value.name
+         FROM file_tag
+         JOIN file ON file.id = file_tag.file_id
+         JOIN tag ON tag.id = file_tag.tag_id
+         LEFT JOIN value ON value.id = file_tag.value_id",
+    )?;
+    let mut rows = stmt.query(params![])?;
+    while let Some(row) = rows.next()? {
+        let (directory, name, fingerprint): (String, String, String) = (row.get(0)?, row.get(1)?, row.get(2)?);
+        let (tag, value): (String, Option<String>) = (row.get(3)?, row.get(4)?);
+        let tag = match value {
+            Some(value) => format!("{}={}", tag, value),
+            None => tag,
+        };
+        let file_path = root.join(&directory).join(&name);
+        let entry = tagged.entry((directory, name)).or_insert(Tagged {
+            file_path,
+            fingerprint: Some(fingerprint),
+            tags: Vec::new(),
+        });
+        entry.tags.push(tag);
+    }
+    let source = format!("TMSU database {}", db.as_ref());
+
+    apply(path.as_ref(), source.as_str(), tagged.into_values().collect(), dry_run)
+}
+
+/// Adds the tags of each file's latest version to a TMSU database, creating
+/// it if it doesn't exist. Tags already in the database are kept.
+pub fn export_tmsu<T: AsRef<str>>(path: T, db: T) -> Result<TmsuExport, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    if let Some(dir) = Path::new(db.as_ref()).parent() {
+        if !dir.as_os_str().is_empty() {
+            create_dir_all(dir)?;
+        }
+    }
+    let mut conn = Connection::open(db.as_ref())?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    let versioned: i64 = tx.query_row("SELECT COUNT(*) FROM version", params![], |row| row.get(0))?;
+    if versioned == 0 {
+        tx.execute("INSERT INTO version (major, minor, patch, revision) VALUES (0, 7, 0, '')", params![])?;
+    }
+
+    let mut exported = TmsuExport { files: 0, tags: 0 };
+    for (file_addr, file_path) in pathcache::entries(filesys.pathcache())? {
+        let record = match records(&filesys, &file_addr)?.pop() {
+            Some(record) => record,
+            None => continue,
+        };
+        let mut tags = record.tags();
+        tags.sort();
+        tags.dedup();
+        if tags.is_empty() {
+            continue;
+        }
+
+        let file_id = file_row(&tx, &file_path, &record)?;
+        for tag in tags.iter() {
+            let (tag, value) = match tag.find('=') {
+                Some(pos) => (&tag[..pos], Some(&tag[pos + 1..])),
+                None => (tag.as_str(), None),
+            };
+            let tag_id = match tx
+                .query_row("SELECT id FROM tag WHERE name = ?1", params![tag], |row| row.get(0))
+                .optional()?
+            {
+                Some(tag_id) => tag_id,
+                None => {
+                    tx.execute("INSERT INTO tag (name) VALUES (?1)", params![tag])?;
+                    tx.last_insert_rowid()
+                }
+            };
+            let value_id: i64 = match value {
+                Some(value) => {
+                    tx.execute("INSERT OR IGNORE INTO value (name) VALUES (?1)", params![value])?;
+                    tx.query_row("SELECT id FROM value WHERE name = ?1", params![value], |row| row.get(0))?
+                }
+                None => 0,
+            };
+            exported.tags += tx.execute(
+                "INSERT OR IGNORE INTO file_tag (file_id, tag_id, value_id) VALUES (?1, ?2, ?3)",
+                params![file_id, tag_id, value_id],
+            )?;
+        }
+        exported.files += 1;
+    }
+    tx.commit()?;
+
+    Ok(exported)
+}
+
+/// The directory a database's relative paths are from: the one holding its
+/// `.tmsu` directory.
+fn root(db: &str) -> PathBuf {
+    let dir = Path::new(db).parent().unwrap_or_else(|| Path::new("."));
+    match dir.file_name() {
+        Some(name) if name == ".tmsu" => dir.parent().unwrap_or(dir).to_path_buf(),
+        _ => dir.to_path_buf(),
+    }
+}
+
+/// The id of the file's row, which is added or brought up to date.
+fn file_row(tx: &Connection, file_path: &str, record: &Record) -> Result<i64, ImmutagError> {
+    let file_path = Path::new(file_path);
+    let directory = file_path.parent().unwrap_or_else(|| Path::new("/")).to_string_lossy().to_string();
+    let name = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let is_dir = record.get(metadata::FILE_TYPE) == Some(immutag_filesys::tardir::TAR_DIR);
+    // TMSU fingerprints a directory as nothing.
+    let fingerprint = match is_dir {
+        true => "",
+        false => record.get(metadata::FILE_HASH).unwrap_or_default(),
+    };
+    let (mod_time, size) = match symlink_metadata(file_path) {
+        Ok(md) => {
+            let secs = md.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+            (datetime(secs, 0), if is_dir { 0 } else { md.len() as i64 })
+        }
+        Err(_) => (datetime(0, 0), 0),
+    };
+
+    tx.execute(
+        "INSERT OR IGNORE INTO file (directory, name, fingerprint, mod_time, size, is_dir) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![directory, name, fingerprint, mod_time, size, is_dir],
+    )?;
+    tx.execute(
+        "UPDATE file SET fingerprint = ?3, mod_time = ?4, size = ?5, is_dir = ?6 WHERE directory = ?1 AND name = ?2",
+        params![directory, name, fingerprint, mod_time, size, is_dir],
+    )?;
+    let file_id = tx.query_row(
+        "SELECT id FROM file WHERE directory = ?1 AND name = ?2",
+        params![directory, name],
+        |row| row.get(0),
+    )?;
+
+    Ok(file_id)
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::{add_content, build_view, commit};
+    use crate::test_support::setup;
+    use std::fs::write;
+
+    const MENU_ADDR: &str = "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9";
+    const PRICES_ADDR: &str = "13DfKEsGmgrdZ3zgvHzsmP58X9dNW1Vyyy";
+
+    fn tags(path: &str, file_addr: &str) -> Vec<String> {
+        let filesys = Filesys::open(path).unwrap();
+        let mut tags = records(&filesys, file_addr).unwrap().pop().unwrap().tags();
+        tags.sort();
+        tags
+    }
+
+    fn file_tags(db: &str) -> Vec<(String, String, Option<String>)> {
+        let conn = Connection::open(db).unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT file.name, tag.name, value.name FROM file_tag
+                 JOIN file ON file.id = file_tag.file_id
+                 JOIN tag ON tag.id = file_tag.tag_id
+                 LEFT JOIN value ON value.id = file_tag.value_id
+                 ORDER BY file.name, tag.name",
+            )
+            .unwrap();
+        let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap();
+        rows.map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn tmsu_import_and_export() {
+        let path = "/tmp/immutag_tmsu/";
+        let view_dir = "/tmp/immutag_tmsu/view";
+        let menu = "/tmp/immutag_tmsu/menu";
+        let prices = "/tmp/immutag_tmsu/prices";
+        let db = "/tmp/immutag_tmsu/.tmsu/db";
+        let exported_db = "/tmp/immutag_tmsu/exported.db";
+        let mut fixture = setup(path);
+        for (file, content) in [(menu, "lemonade"), (prices, "$1")].iter() {
+            write(file, content).unwrap();
+            add_content(path, *file, None).unwrap();
+            commit(path, *file).unwrap();
+        }
+        build_view(path, Some(view_dir)).unwrap();
+
+        // The menu is matched by its relative path, the prices by their
+        // fingerprint after TMSU last saw them somewhere else.
+        create_dir_all("/tmp/immutag_tmsu/.tmsu").unwrap();
+        let conn = Connection::open(db).unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        let price_hash = immutag_contentsys::file_hash(b"$1");
+        conn.execute_batch(&format!(
+            "INSERT INTO file VALUES (1, '.', 'menu', 'stale', '2020-01-01 00:00:00', 8, 0);
+             INSERT INTO file VALUES (2, '/elsewhere', 'prices', '{}', '2020-01-01 00:00:00', 2, 0);
+             INSERT INTO file VALUES (3, '/elsewhere', 'gone', 'none', '2020-01-01 00:00:00', 1, 0);
+             INSERT INTO tag VALUES (1, 'sales');
+             INSERT INTO tag VALUES (2, 'year');
+             INSERT INTO tag VALUES (3, 'time: noon');
+             INSERT INTO value VALUES (1, '2020');
+             INSERT INTO file_tag VALUES (1, 1, 0);
+             INSERT INTO file_tag VALUES (1, 2, 1);
+             INSERT INTO file_tag VALUES (2, 1, 0);
+             INSERT INTO file_tag VALUES (2, 3, 0);
+             INSERT INTO file_tag VALUES (3, 1, 0);",
+            price_hash
+        ))
+        .unwrap();
+        drop(conn);
+
+        let imported = import_tmsu(path, db, false).unwrap();
+        let menu_tags = tags(path, MENU_ADDR);
+        let price_tags = tags(path, PRICES_ADDR);
+        let again = import_tmsu(path, db, false).unwrap();
+        let linked = Path::new(view_dir).join("tags/sales/prices").exists();
+        let verification = crate::filesys::verify_metadata(path, menu).unwrap();
+        let exported = export_tmsu(path, exported_db).unwrap();
+        let reexported = export_tmsu(path, exported_db).unwrap();
+        let exported_tags = file_tags(exported_db);
+        let fingerprint: String = Connection::open(exported_db)
+            .unwrap()
+            .query_row("SELECT fingerprint FROM file WHERE name = 'prices'", params![], |row| row.get(0))
+            .unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(imported.files, 2);
+        assert_eq!(imported.tags, 3);
+        assert_eq!(imported.unmatched, vec!["/elsewhere/gone".to_string()]);
+        assert_eq!(imported.skipped, vec!["time: noon of /elsewhere/prices".to_string()]);
+        assert_eq!(menu_tags, vec!["sales", "year=2020"]);
+        assert_eq!(price_tags, vec!["sales"]);
+        assert_eq!(again.files, 0);
+        assert!(linked);
+        assert!(verification.ok(), "{:?}", verification.records);
+        assert_eq!(exported, TmsuExport { files: 2, tags: 3 });
+        assert_eq!(reexported, TmsuExport { files: 2, tags: 0 });
+        assert_eq!(
+            exported_tags,
+            vec![
+                ("menu".to_string(), "sales".to_string(), None),
+                ("menu".to_string(), "year".to_string(), Some("2020".to_string())),
+                ("prices".to_string(), "sales".to_string(), None),
+            ]
+        );
+        assert_eq!(fingerprint, price_hash);
+    }
+}
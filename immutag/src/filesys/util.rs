@@ -0,0 +1,44 @@
+/*
+Helpers more than one of the filesystem's modules use.
+*/
+
+/// Seconds since the epoch as `YYYY-MM-DD HH:MM:SS`, `offset` minutes from
+/// UTC. In UTC it's a form TMSU reads and the watch log is stamped with.
+pub(crate) fn datetime(secs: i64, offset: i32) -> String {
+    let local = secs + i64::from(offset) * 60;
+    let (days, rem) = (local.div_euclid(86_400), local.rem_euclid(86_400));
+    // Civil date from days since the epoch, in 400 year eras, from Howard
+    // Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utc_datetime() {
+        assert_eq!(datetime(0, 0), "1970-01-01 00:00:00");
+        assert_eq!(datetime(951_782_400, 0), "2000-02-29 00:00:00");
+        assert_eq!(datetime(1_700_000_000, 0), "2023-11-14 22:13:20");
+        assert_eq!(datetime(0, -90), "1969-12-31 22:30:00");
+    }
+}
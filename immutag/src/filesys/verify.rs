@@ -0,0 +1,329 @@
+/*
+Checks that a file version was committed before a peg: the manifest is
+rebuilt from the version-store as it was at the peg's commit, the manifest
+the peg's OP_RETURN commits to is fetched by its content address and hashed
+to the peg's root, the version is proved to be in that manifest, and the
+version's bytes are read back from the contentsys.
+*/
+use serde_json::{json, Value};
+
+use immutag_contentsys::merkle;
+use immutag_contentsys::Cid;
+use immutag_filesys::manifest::{self, Entry};
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::{pegs, vcs, versionstore};
+
+use crate::err::ImmutagError;
+use crate::filesys::peg::leaves;
+use crate::filesys::version::{canonical, tracked, Filesys};
+
+pub const PEGGED: &str = "pegged";
+pub const MERKLE_ROOT: &str = "merkle_root";
+pub const INCLUSION: &str = "inclusion";
+pub const MANIFEST: &str = "manifest";
+pub const CONTENT_HASH: &str = "content_hash";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Check {
+    pub check: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Verification {
+    pub file_addr: String,
+    pub version: usize,
+    /// The first peg with the version, if there's one.
+    pub peg: Option<usize>,
+    pub checks: Vec<Check>,
+}
+
+impl Verification {
+    pub fn ok(&self) -> bool {
+        self.checks.iter().all(|c| c.ok)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let checks: Vec<Value> = self
+            .checks
+            .iter()
+            .map(|c| json!({ "check": c.check, "ok": c.ok, "detail": c.detail }))
+            .collect();
+
+        json!({
+            "file_addr": self.file_addr,
+            "version": self.version,
+            "peg": self.peg,
+            "ok": self.ok(),
+            "checks": checks,
+        })
+    }
+}
+
+/// Runs every check on `version` of `file`. A failed check is reported, not
+/// returned as an error.
+pub fn verify<T: AsRef<str>>(path: T, file: T, version: usize) -> Result<Verification, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let file_path = canonical(file)?;
+    let (file_addr, _) = tracked(&filesys, &file_path)?;
+    let versionstore_path = filesys.versionstore();
+
+    let versions_file = versionstore::path_versions_file(versionstore_path.clone(), file_addr.clone());
+    let metadata_file = versionstore::path_metadata_file(versionstore_path.clone(), file_addr.clone());
+    let versions = versionstore::versions(versions_file)?;
+    let records = metadata::records(metadata_file)?;
+    let entry = versions.get(version).map(|v| Entry {
+        file_addr: file_addr.clone(),
+        version,
+        file_hash: v.file_hash.clone(),
+        content_addr: records
+            .get(version)
+            .and_then(|r| r.get(metadata::CONTENT_ADDR))
+            .map(|a| a.to_string()),
+    });
+
+    let mut verification = Verification {
+        file_addr: file_addr.clone(),
+        version,
+        peg: None,
+        checks: Vec::new(),
+    };
+    let entry = match entry {
+        Some(entry) => entry,
+        None => {
+            let detail = format!("{} has no version {}", file_path, version);
+            verification.checks.push(failed(PEGGED, detail));
+            return Ok(verification);
+        }
+    };
+
+    // The first peg whose manifest has the version.
+    let mut pegged = None;
+    for (pos, record) in pegs::pegs(immutag_filesys::path_pegs(&filesys.path))?.iter().enumerate() {
+        let entries = manifest_at(&filesys, record)?;
+        if entries.iter().any(|e| e.file_addr == file_addr && e.version == version) {
+            pegged = Some((pegs::number(pos, record), record.clone(), entries));
+            break;
+        }
+    }
+
+    match pegged {
+        Some((peg, record, entries)) => {
+            verification.peg = Some(peg);
+            let commit = record.get(pegs::COMMIT).unwrap_or_default();
+            verification.checks.push(passed(PEGGED, format!("in peg {}, at commit {}", peg, commit)));
+
+            let root = record.get(pegs::ROOT).unwrap_or_default();
+            let leaves = leaves(&entries);
+            let rebuilt = merkle::root(&leaves).map(hex::encode).unwrap_or_default();
+            verification.checks.push(match rebuilt == root {
+                true => passed(MERKLE_ROOT, format!("the version-store hashes to {}", root)),
+                false => failed(MERKLE_ROOT, format!("the version-store hashes to {}, peg {} has {}", rebuilt, peg, root)),
+            });
+
+            let (check, pegged_entries) = pegged_manifest(&filesys, path.as_ref(), &record);
+            verification.checks.push(check);
+            verification.checks.push(inclusion(pegged_entries.as_deref(), &entry, root));
+        }
+        None => {
+            let detail = format!("no peg has version {} of {}", version, file_addr);
+            verification.checks.push(failed(PEGGED, detail));
+        }
+    }
+
+    verification.checks.push(content_hash(&filesys, path.as_ref(), &entry));
+
+    Ok(verification)
+}
+
+/// The manifest of a peg, from the version-store at the peg's commit. Versions
+/// are append-only, so everything but which of them existed then comes from
+/// the version-store as it is now.
+fn manifest_at(filesys: &Filesys, peg: &Record) -> Result<Vec<Entry>, ImmutagError> {
+    let commit = peg.get(pegs::COMMIT).unwrap_or_default();
+    let versionstore_path = filesys.versionstore();
+
+    let mut entries = Vec::new();
+    for file_addr in versionstore::file_addrs(versionstore_path.as_str())? {
+        let relative = format!("version-store/{}/versions-file", file_addr);
+        let pegged = match vcs::read_at(filesys.path.as_str(), commit, relative.as_str())? {
+            Some(versions_file) => versionstore::parse(versions_file)?.len(),
+            None => continue,
+        };
+
+        let versions_file = versionstore::path_versions_file(versionstore_path.as_str(), file_addr.as_str());
+        let metadata_file = versionstore::path_metadata_file(versionstore_path.as_str(), file_addr.as_str());
+        let records = metadata::records(metadata_file)?;
+        for (version, v) in versionstore::versions(versions_file)?.into_iter().take(pegged).enumerate() {
+            entries.push(Entry {
+                file_addr: file_addr.clone(),
+                version,
+                file_hash: v.file_hash,
+                content_addr: records
+                    .get(version)
+                    .and_then(|r| r.get(metadata::CONTENT_ADDR))
+                    .map(|a| a.to_string()),
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Fetches the manifest the peg's OP_RETURN commits to, by its content
+/// address, and checks it hashes to the peg's root.
+fn pegged_manifest(filesys: &Filesys, path: &str, peg: &Record) -> (Check, Option<Vec<Entry>>) {
+    let manifest_addr = peg.get(pegs::MANIFEST).unwrap_or_default();
+    let root = peg.get(pegs::ROOT).unwrap_or_default();
+    let cid = match manifest_addr.parse::<Cid>() {
+        Ok(cid) => cid,
+        Err(_) => return (failed(MANIFEST, format!("invalid manifest address {}", manifest_addr)), None),
+    };
+    let data = match filesys.store(path).map(|store| store.get(&cid)) {
+        Ok(Ok(data)) => data,
+        Ok(Err(e)) => return (failed(MANIFEST, format!("can't read {}: {:?}", cid, e)), None),
+        Err(e) => return (failed(MANIFEST, format!("can't open the contentsys: {:?}", e)), None),
+    };
+    if immutag_contentsys::content_addr(&data, cid.version) != cid {
+        return (failed(MANIFEST, format!("what was read for {} has another address", cid)), None);
+    }
+    let entries = match manifest::parse(String::from_utf8_lossy(&data)) {
+        Ok(entries) => entries,
+        Err(e) => return (failed(MANIFEST, format!("can't parse {}: {:?}", cid, e)), None),
+    };
+
+    let rebuilt = merkle::root(&leaves(&entries)).map(hex::encode).unwrap_or_default();
+    match rebuilt == root {
+        true => (passed(MANIFEST, format!("{} hashes to {}", cid, root)), Some(entries)),
+        false => (failed(MANIFEST, format!("{} hashes to {}, not {}", cid, rebuilt, root)), None),
+    }
+}
+
+/// Folds the version, as the version-store has it, up the path the pegged
+/// manifest gives its position, and compares with the root.
+fn inclusion(pegged: Option<&[Entry]>, entry: &Entry, root: &str) -> Check {
+    let entries = match pegged {
+        Some(entries) => entries,
+        None => return failed(INCLUSION, "the pegged manifest isn't there to prove against".to_string()),
+    };
+    let pos = match entries.iter().position(|e| e.file_addr == entry.file_addr && e.version == entry.version) {
+        Some(pos) => pos,
+        None => return failed(INCLUSION, "the pegged manifest doesn't have the version".to_string()),
+    };
+
+    let steps = merkle::proof(&leaves(entries), pos).unwrap_or_default();
+    let folded = hex::encode(merkle::fold(&merkle::leaf(entry.line().as_bytes()), &steps));
+    match folded == root {
+        true => passed(INCLUSION, format!("{} steps to the root", steps.len())),
+        false => failed(INCLUSION, format!("the path leads to {}, not {}", folded, root)),
+    }
+}
+
+fn content_hash(filesys: &Filesys, path: &str, entry: &Entry) -> Check {
+    let cid = match entry.content_addr.as_ref().map(|a| a.parse::<Cid>()) {
+        Some(Ok(cid)) => cid,
+        Some(Err(_)) => return failed(CONTENT_HASH, "invalid content address".to_string()),
+        None => return failed(CONTENT_HASH, "the version has no content address".to_string()),
+    };
+    let data = match filesys.store(path).map(|store| store.get(&cid)) {
+        Ok(Ok(data)) => data,
+        Ok(Err(e)) => return failed(CONTENT_HASH, format!("can't read {}: {:?}", cid, e)),
+        Err(e) => return failed(CONTENT_HASH, format!("can't open the contentsys: {:?}", e)),
+    };
+
+    let file_hash = immutag_contentsys::file_hash(&data);
+    match file_hash == entry.file_hash {
+        true => passed(CONTENT_HASH, format!("{} hashes to {}", cid, file_hash)),
+        false => failed(CONTENT_HASH, format!("{} hashes to {}, not {}", cid, file_hash, entry.file_hash)),
+    }
+}
+
+pub(crate) fn passed(check: &'static str, detail: String) -> Check {
+    Check { check, ok: true, detail }
+}
+
+pub(crate) fn failed(check: &'static str, detail: String) -> Check {
+    Check { check, ok: false, detail }
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::{add_content, commit, peg};
+    use crate::test_support::setup;
+    use std::fs::{read_to_string, remove_file, write};
+
+    fn checks(verification: &Verification) -> Vec<(&'static str, bool)> {
+        verification.checks.iter().map(|c| (c.check, c.ok)).collect()
+    }
+
+    #[test]
+    fn verify_versions() {
+        let path = "/tmp/immutag_verify_tests";
+        let file = "/tmp/immutag_verify_tests/lemonade_stand.mp4";
+        let other = "/tmp/immutag_verify_tests/recipes.txt";
+        let mut fixture = setup(path);
+
+        write(file, b"foobar\n").unwrap();
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+        write(other, b"lemons, sugar\n").unwrap();
+        add_content(path, other, None).unwrap();
+        commit(path, other).unwrap();
+        peg(path).unwrap();
+        write(file, b"hello world\n").unwrap();
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+
+        let pegged = verify(path, file, 0).unwrap();
+        let unpegged = verify(path, file, 1).unwrap();
+        let missing = verify(path, file, 2).unwrap();
+
+        // Rewrite another file's pegged hash, then this version's, then lose
+        // its content.
+        let filesys = Filesys::open(path).unwrap();
+        let other_file = versionstore::path_versions_file(filesys.versionstore(), "13DfKEsGmgrdZ3zgvHzsmP58X9dNW1Vyyy".to_string());
+        let pegged_other = read_to_string(&other_file).unwrap();
+        let other_hash = immutag_contentsys::file_hash(b"lemons, sugar\n");
+        write(&other_file, pegged_other.replacen(&other_hash[..4], "0000", 1)).unwrap();
+        let elsewhere = verify(path, file, 0).unwrap();
+        write(&other_file, pegged_other).unwrap();
+        let versions_file = versionstore::path_versions_file(filesys.versionstore(), "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9".to_string());
+        let rewritten = read_to_string(&versions_file).unwrap().replacen("aec0", "0000", 1);
+        write(&versions_file, rewritten).unwrap();
+        let tampered = verify(path, file, 0).unwrap();
+        let objects = immutag_filesys::path_objects(&filesys.path);
+        let blob = std::fs::read_dir(&objects)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.file_name().unwrap() != "pins")
+            .unwrap();
+        remove_file(blob).unwrap();
+        let lost = verify(path, file, 0).unwrap();
+
+        fixture.teardown(true);
+
+        assert!(pegged.ok());
+        assert_eq!(pegged.peg, Some(0));
+        assert_eq!(
+            checks(&pegged),
+            vec![(PEGGED, true), (MERKLE_ROOT, true), (MANIFEST, true), (INCLUSION, true), (CONTENT_HASH, true)]
+        );
+        assert_eq!(pegged.to_json()["checks"][4]["ok"], true);
+        assert!(!unpegged.ok());
+        assert_eq!(checks(&unpegged), vec![(PEGGED, false), (CONTENT_HASH, true)]);
+        assert_eq!(checks(&missing), vec![(PEGGED, false)]);
+        // The version is still in the pegged manifest when only another's
+        // hash changed, and the manifest itself holds either way.
+        assert_eq!(
+            checks(&elsewhere),
+            vec![(PEGGED, true), (MERKLE_ROOT, false), (MANIFEST, true), (INCLUSION, true), (CONTENT_HASH, true)]
+        );
+        assert_eq!(
+            checks(&tampered),
+            vec![(PEGGED, true), (MERKLE_ROOT, false), (MANIFEST, true), (INCLUSION, false), (CONTENT_HASH, false)]
+        );
+        assert_eq!(checks(&lost)[4], (CONTENT_HASH, false));
+    }
+}
@@ -0,0 +1,568 @@
+/*
+Staging and committing file versions. The bytes go to the contentsys, and the
+version-store, metadata and git history of the filesystem record them.
+*/
+use std::collections::HashSet;
+use std::fs::{canonicalize, create_dir_all, read, remove_dir_all, rename, write};
+use std::path::Path;
+
+use immutag_bitcoin;
+use immutag_contentsys::{self, Cid, ContentStore, StoreConfig};
+use immutag_filesys::merge::CONFLICT_PREFIX;
+use immutag_filesys::metadata::{self, Record};
+use immutag_filesys::{indexes, pathcache, stage, tardir, vcs, versionstore};
+use immutag_ledger::{Ledger, LedgerConfig};
+
+use crate::err::{Error, ErrorKind, ImmutagError};
+use crate::files;
+use crate::filesys::{index, signing, view};
+
+/// A filesystem of an immutag directory, with what's needed to sign for it.
+pub struct Filesys {
+    pub addr: String,
+    pub xpriv: String,
+    pub path: String,
+    /// The collaborator's account, whose key signs the metadata.
+    pub account: u32,
+}
+
+impl Filesys {
+    /// The first filesystem imported into the immutag directory at `path`.
+    pub fn open<T: AsRef<str>>(path: T) -> Result<Filesys, ImmutagError> {
+        let addr = match files::get_filesystems(path.as_ref())?.into_iter().next() {
+            Some(addr) => addr,
+            None => {
+                let err = Error::new("no filesystem has been imported", ErrorKind::NoFilesys);
+                return Err(ImmutagError::from(err));
+            }
+        };
+        let xpriv = files::get_xpriv(path.as_ref(), addr.as_ref())?;
+        let account = files::get_account(path.as_ref(), addr.as_ref())?.unwrap_or(0);
+        let dotimmutag = fixture::directorate(path.as_ref().to_string()) + ".immutag";
+
+        Ok(Filesys {
+            path: immutag_filesys::path_filesys(dotimmutag, addr.clone()),
+            addr,
+            xpriv,
+            account,
+        })
+    }
+
+    /// The content store picked by `contentsys` in the Immutag file.
+    pub fn store<T: AsRef<str>>(&self, path: T) -> Result<Box<dyn ContentStore>, ImmutagError> {
+        let config = StoreConfig {
+            contentsys: files::get_contentsys(path.as_ref())?,
+            ipfs_api: files::get_ipfs_api(path.as_ref())?,
+        };
+
+        Ok(immutag_contentsys::open_store(&config, self.path.as_str())?)
+    }
+
+    /// The ledger of the Immutag file, `None` without an `electrum` server
+    /// to read it through.
+    pub fn ledger<T: AsRef<str>>(&self, path: T) -> Result<Option<Box<dyn Ledger>>, ImmutagError> {
+        let config = LedgerConfig {
+            ledger: files::get_ledger(path.as_ref())?,
+            electrum: files::get_electrum(path.as_ref())?,
+        };
+        if config.electrum.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(immutag_ledger::open_ledger(&config)?))
+    }
+
+    pub(crate) fn versionstore(&self) -> String {
+        immutag_filesys::path_versionstore(&self.path)
+    }
+
+    /// The filesystem's own metadata, where file indexes are claimed.
+    pub(crate) fn metadata(&self) -> String {
+        immutag_filesys::path_metadata(&self.path)
+    }
+
+    pub(crate) fn pathcache(&self) -> String {
+        immutag_filesys::path_pathcache(&self.path)
+    }
+
+    pub(crate) fn stage(&self) -> String {
+        immutag_filesys::path_stage(&self.path)
+    }
+}
+
+/// Stages the current bytes of `file` as its next version. A file the
+/// filesystem hasn't seen gets the next file index it hasn't claimed. A
+/// directory is staged as a tar of its tree, a `tar-dir`.
+pub fn add_content<T: AsRef<str>>(path: T, file: T, alias: Option<T>) -> Result<Record, ImmutagError> {
+    add(path, file, alias, false)
+}
+
+/// Like `add_content`, but a new file's index also skips the ones with
+/// history on the ledger.
+pub fn add_content_scanned<T: AsRef<str>>(path: T, file: T, alias: Option<T>) -> Result<Record, ImmutagError> {
+    add(path, file, alias, true)
+}
+
+fn add<T: AsRef<str>>(path: T, file: T, alias: Option<T>, scan: bool) -> Result<Record, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let file_path = canonical(file)?;
+    let data = content(&file_path)?;
+
+    let (file_addr, file_index) = match file_addr(&filesys, &file_path)? {
+        Some(tracked) => tracked,
+        None => {
+            let ledger = match scan {
+                true => filesys.ledger(path.as_ref())?,
+                false => None,
+            };
+            let file_index = index::next_index(&filesys, ledger.as_deref(), &HashSet::new())?;
+            let file_addr = immutag_bitcoin::file_addr(&filesys.xpriv, file_index)?;
+            pathcache::set(filesys.pathcache(), file_addr.clone(), file_path.clone())?;
+            indexes::claim(filesys.metadata(), file_index, file_addr.clone(), file_path.clone())?;
+
+            (file_addr, file_index)
+        }
+    };
+
+    let cid = filesys.store(path.as_ref())?.put(&data)?;
+
+    // Alias and tags carry over from the latest version, but not its forks
+    // or the conflicts a merge left on it.
+    let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr.clone());
+    let mut record = metadata::records(metadata_file)?.pop().unwrap_or_default();
+    record.remove(metadata::MESSAGE);
+    record.remove(metadata::TIME);
+    record.remove(metadata::AUTHOR);
+    record.remove(metadata::BRANCHES);
+    record.fields.retain(|(k, _)| !k.starts_with(CONFLICT_PREFIX));
+    record.set(metadata::FILE_HASH, immutag_contentsys::file_hash(&data).as_str());
+    record.set(metadata::CONTENT_ADDR, cid.to_string().as_str());
+    record.set(metadata::FILE_TYPE, file_type(&file_path).as_str());
+    record.set(metadata::FILE_ADDR, file_addr.as_str());
+    record.set(metadata::FILE_INDEX, file_index.to_string().as_str());
+    if let Some(alias) = alias {
+        record.set(metadata::ALIAS, alias.as_ref());
+    }
+    stage::stage(filesys.stage(), record.clone())?;
+
+    Ok(record)
+}
+
+/// Commits what's staged for `file` and returns the version number.
+pub fn commit<T: AsRef<str>>(path: T, file: T) -> Result<usize, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let file_path = canonical(file)?;
+
+    let (file_addr, file_index) = tracked(&filesys, &file_path)?;
+    let mut record = match stage::unstage(filesys.stage(), file_addr.clone())? {
+        Some(record) => record,
+        None => {
+            let msg = format!("nothing staged for {}", file_path);
+            return Err(ImmutagError::from(Error::new(&msg, ErrorKind::NothingStaged)));
+        }
+    };
+
+    create_dir_all(versionstore::path_file(filesys.versionstore(), file_addr.clone()))?;
+    let versions_file = versionstore::path_versions_file(filesys.versionstore(), file_addr.clone());
+    let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr.clone());
+
+    let version = versionstore::append_version(
+        versions_file,
+        versionstore::Version {
+            file_hash: record.get(metadata::FILE_HASH).unwrap_or_default().to_string(),
+            file_addr: file_addr.clone(),
+        },
+    )?;
+    let version_addr = immutag_bitcoin::version_addr(&filesys.xpriv, file_index, version as u32)?;
+    record.set(metadata::VERSION_ADDR, version_addr.as_str());
+    let signature = vcs::signature(filesys.path.as_str())?;
+    stamp(&mut record, &signature);
+    signing::sign(&filesys, &mut record)?;
+    metadata::append_record(metadata_file, record)?;
+
+    let message = format!("Commit version {} of {}", version, file_addr);
+    vcs::commit_by(filesys.path.as_str(), message.as_str(), &signature)?;
+    view::refresh(path.as_ref())?;
+
+    Ok(version)
+}
+
+/// Picks out one of a file's versions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selector {
+    Version(usize),
+    /// The latest version with the file hash.
+    Hash(String),
+    VersionAddr(String),
+}
+
+/// Writes a committed version of `file` back to its tracked path, or to
+/// `output`. Returns the version number and where it was written.
+///
+/// Refuses to overwrite anything that isn't one of the file's committed
+/// versions, unless `force` is set. A `tar-dir` is unpacked in place of the
+/// directory.
+pub fn checkout<T: AsRef<str>>(
+    path: T,
+    file: T,
+    selector: &Selector,
+    output: Option<T>,
+    force: bool,
+) -> Result<(usize, String), ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let file_path = canonical(file)?;
+
+    let (file_addr, _) = tracked(&filesys, &file_path)?;
+    let versions_file = versionstore::path_versions_file(filesys.versionstore(), file_addr.clone());
+    let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr);
+    let versions = versionstore::versions(versions_file)?;
+    let records = metadata::records(metadata_file)?;
+
+    let version = match selector {
+        Selector::Version(version) => Some(*version).filter(|v| *v < versions.len()),
+        Selector::Hash(hash) => versions.iter().rposition(|v| &v.file_hash == hash),
+        Selector::VersionAddr(addr) => records
+            .iter()
+            .position(|r| r.get(metadata::VERSION_ADDR) == Some(addr.as_str())),
+    };
+    let (version, record) = match version.and_then(|v| records.get(v).map(|r| (v, r))) {
+        Some(found) => found,
+        None => {
+            let msg = format!("no such version of {}: {:?}", file_path, selector);
+            return Err(ImmutagError::from(Error::new(&msg, ErrorKind::NoSuchVersion)));
+        }
+    };
+
+    let destination = match output {
+        Some(output) => output.as_ref().to_string(),
+        None => file_path.clone(),
+    };
+    if !force && Path::new(&destination).exists() {
+        let current = immutag_contentsys::file_hash(&content(&destination)?);
+        if !versions.iter().any(|v| v.file_hash == current) {
+            let msg = format!("{} has uncommitted changes, use force to overwrite", destination);
+            return Err(ImmutagError::from(Error::new(&msg, ErrorKind::UncommittedChanges)));
+        }
+    }
+
+    let cid: Cid = match record.get(metadata::CONTENT_ADDR).unwrap_or_default().parse() {
+        Ok(cid) => cid,
+        Err(_) => {
+            let msg = format!("version {} of {} has no valid content address", version, file_path);
+            return Err(ImmutagError::from(Error::new(&msg, ErrorKind::NoSuchVersion)));
+        }
+    };
+    let data = filesys.store(path.as_ref())?.get(&cid)?;
+    if Some(immutag_contentsys::file_hash(&data).as_str()) != record.get(metadata::FILE_HASH) {
+        let msg = format!("version {} of {} doesn't match its file hash", version, file_path);
+        return Err(ImmutagError::from(Error::new(&msg, ErrorKind::HashMismatch)));
+    }
+    if record.get(metadata::FILE_TYPE) == Some(tardir::TAR_DIR) {
+        unpack_over(&data, &destination)?;
+    } else {
+        write(&destination, data)?;
+    }
+
+    Ok((version, destination))
+}
+
+/// Unpacks a tar-dir beside `dir` and swaps it in, so the directory is left
+/// as it was if unpacking fails.
+fn unpack_over(data: &[u8], dir: &str) -> Result<(), ImmutagError> {
+    let dir = Path::new(dir);
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    let sibling = |suffix: &str| dir.with_file_name(format!(".{}.{}", name, suffix));
+    let (unpacked, replaced) = (sibling("checkout"), sibling("replaced"));
+    for stale in [&unpacked, &replaced] {
+        if stale.is_dir() {
+            remove_dir_all(stale)?;
+        }
+    }
+
+    if let Err(err) = tardir::unpack(data, &unpacked) {
+        remove_dir_all(&unpacked).ok();
+        return Err(ImmutagError::from(err));
+    }
+    if dir.is_dir() {
+        rename(dir, &replaced)?;
+        rename(&unpacked, dir)?;
+        remove_dir_all(&replaced)?;
+    } else {
+        rename(&unpacked, dir)?;
+    }
+
+    Ok(())
+}
+
+/// Address and index of a tracked file. Files that never claimed their
+/// index took their place in the path-cache.
+fn file_addr(filesys: &Filesys, file_path: &str) -> Result<Option<(String, u32)>, ImmutagError> {
+    let entries = pathcache::entries(filesys.pathcache())?;
+    let pos = match entries.iter().position(|(_, p)| p == file_path) {
+        Some(pos) => pos,
+        None => return Ok(None),
+    };
+    let file_addr = entries[pos].0.clone();
+    let file_index = indexes::lookup_index(filesys.metadata(), file_addr.clone())?.unwrap_or(pos as u32 + 1);
+
+    Ok(Some((file_addr, file_index)))
+}
+
+/// Records who committed a version, and when, so the git history can be
+/// rebuilt from the version-store.
+pub(crate) fn stamp(record: &mut Record, signature: &vcs::Signature) {
+    let author = format!(
+        "{} <{}>",
+        signature.name().unwrap_or_default(),
+        signature.email().unwrap_or_default()
+    );
+    record.set(metadata::AUTHOR, author.as_str());
+    record.set(metadata::TIME, vcs::format_time(&signature.when()).as_str());
+}
+
+/// Like `file_addr`, but the file has to be tracked.
+pub(crate) fn tracked(filesys: &Filesys, file_path: &str) -> Result<(String, u32), ImmutagError> {
+    match file_addr(filesys, file_path)? {
+        Some(tracked) => Ok(tracked),
+        None => {
+            let msg = format!("{} isn't tracked", file_path);
+            Err(ImmutagError::from(Error::new(&msg, ErrorKind::NotTracked)))
+        }
+    }
+}
+
+/// The absolute path of the file. It doesn't have to exist anymore, as long as
+/// its directory does.
+pub(crate) fn canonical<T: AsRef<str>>(file: T) -> Result<String, ImmutagError> {
+    let file = Path::new(file.as_ref());
+    if file.exists() {
+        return Ok(canonicalize(file)?.to_string_lossy().to_string());
+    }
+
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => canonicalize(dir)?,
+        _ => canonicalize(".")?,
+    };
+    let name = file.file_name().unwrap_or_default();
+
+    Ok(dir.join(name).to_string_lossy().to_string())
+}
+
+/// The bytes of a file, or the tar of a directory.
+pub(crate) fn content(file_path: &str) -> Result<Vec<u8>, ImmutagError> {
+    match Path::new(file_path).is_dir() {
+        true => Ok(tardir::pack(file_path)?),
+        false => Ok(read(file_path)?),
+    }
+}
+
+/// The extension, or `file` when there isn't one. A directory is a
+/// `tar-dir`.
+fn file_type<T: AsRef<str>>(file_path: T) -> String {
+    if Path::new(file_path.as_ref()).is_dir() {
+        return tardir::TAR_DIR.to_string();
+    }
+
+    Path::new(file_path.as_ref())
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or_else(|| "file".to_string())
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::test_support::setup;
+    use std::fs::{read_to_string, remove_file};
+
+    #[test]
+    fn add_and_commit() {
+        let path = "/tmp/immutag_version_tests";
+        let file = "/tmp/immutag_version_tests/lemonade_stand.mp4";
+        let mut fixture = setup(path);
+
+        write(file, b"foobar\n").unwrap();
+        let staged = add_content(path, file, Some("lemonade_stand")).unwrap();
+        let version_0 = commit(path, file).unwrap();
+        write(file, b"hello world\n").unwrap();
+        add_content(path, file, None).unwrap();
+        let version_1 = commit(path, file).unwrap();
+        let nothing_staged = commit(path, file);
+
+        let filesys = Filesys::open(path).unwrap();
+        let versionstore = immutag_filesys::path_versionstore(&filesys.path);
+        let file_addr = staged.get(metadata::FILE_ADDR).unwrap().to_string();
+        let records = metadata::records(versionstore::path_metadata_file(versionstore.clone(), file_addr.clone())).unwrap();
+        let versions = versionstore::versions(versionstore::path_versions_file(versionstore, file_addr.clone())).unwrap();
+        let store = filesys.store(path).unwrap();
+        let cid: Cid = records[0].get(metadata::CONTENT_ADDR).unwrap().parse().unwrap();
+        let restored = store.get(&cid).unwrap();
+        let gitignore = read_to_string(filesys.path.clone() + ".gitignore").unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(file_addr, "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9");
+        assert_eq!((version_0, version_1), (0, 1));
+        assert!(nothing_staged.is_err());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].get(metadata::VERSION_ADDR), Some("1QKr8NEjbhe9uW3u1wGVWTDavr5n3HZTx9"));
+        assert_eq!(records[0].get(metadata::CONTENT_ADDR), Some("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"));
+        assert_eq!(records[0].get(metadata::FILE_TYPE), Some("mp4"));
+        assert_eq!(records[1].get(metadata::ALIAS), Some("lemonade_stand"));
+        assert_eq!(versions[1].file_hash, immutag_contentsys::file_hash(b"hello world\n"));
+        assert_eq!(restored, b"foobar\n".to_vec());
+        assert_eq!(gitignore, "objects/\n");
+    }
+
+    #[test]
+    fn commit_after_branching() {
+        let path = "/tmp/immutag_version_branched";
+        let file = "/tmp/immutag_version_branched/menu.txt";
+        let mut fixture = setup(path);
+
+        write(file, b"lemonade\n").unwrap();
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+        let forked = crate::filesys::branch(path, file, 0).unwrap();
+        let filesys = Filesys::open(path).unwrap();
+        let (file_addr, _) = tracked(&filesys, file).unwrap();
+        let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr);
+        let mut flagged = metadata::records(metadata_file.clone()).unwrap();
+        flagged[0].set("conflict_alias", "menu");
+        metadata::write_records(metadata_file.clone(), &flagged).unwrap();
+        write(file, b"lemonade, iced tea\n").unwrap();
+        let staged = add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+        let records = metadata::records(metadata_file).unwrap();
+        let signed = crate::filesys::verify_metadata(path, file).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(staged.get("conflict_alias"), None);
+        assert_eq!(records[0].get(metadata::BRANCHES), Some(forked.branch_addr.as_str()));
+        assert_eq!(records[1].get(metadata::BRANCHES), None);
+        let walked: Vec<(String, usize)> = signed.records.iter().map(|r| (r.file_addr.clone(), r.version)).collect();
+        assert_eq!(walked.len(), 3, "{:?}", walked);
+        assert_eq!(walked[2], (forked.branch_addr, 0));
+    }
+
+    #[test]
+    fn checkout_versions() {
+        let path = "/tmp/immutag_checkout_tests";
+        let file = "/tmp/immutag_checkout_tests/lemonade_stand.mp4";
+        let output = "/tmp/immutag_checkout_tests/old.mp4";
+        let mut fixture = setup(path);
+
+        write(file, b"foobar\n").unwrap();
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+        write(file, b"hello world\n").unwrap();
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+
+        let by_version = checkout(path, file, &Selector::Version(0), None, false).unwrap();
+        let content_0 = read(file).unwrap();
+        let by_hash = checkout(
+            path,
+            file,
+            &Selector::Hash(immutag_contentsys::file_hash(b"hello world\n")),
+            None,
+            false,
+        ).unwrap();
+        let content_1 = read(file).unwrap();
+        let by_addr = checkout(
+            path,
+            file,
+            &Selector::VersionAddr("1QKr8NEjbhe9uW3u1wGVWTDavr5n3HZTx9".to_string()),
+            Some(output),
+            false,
+        ).unwrap();
+        let content_output = read(output).unwrap();
+
+        write(file, b"uncommitted").unwrap();
+        let refused = checkout(path, file, &Selector::Version(0), None, false);
+        let content_refused = read(file).unwrap();
+        let forced = checkout(path, file, &Selector::Version(0), None, true);
+        let missing = checkout(path, file, &Selector::Version(2), None, true);
+        remove_file(file).unwrap();
+        let deleted = checkout(path, file, &Selector::Version(1), None, false);
+        let content_deleted = read(file).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(by_version, (0, file.to_string()));
+        assert_eq!(content_0, b"foobar\n".to_vec());
+        assert_eq!(by_hash.0, 1);
+        assert_eq!(content_1, b"hello world\n".to_vec());
+        assert_eq!(by_addr, (0, output.to_string()));
+        assert_eq!(content_output, b"foobar\n".to_vec());
+        assert!(refused.is_err());
+        assert_eq!(content_refused, b"uncommitted".to_vec());
+        assert!(forced.is_ok());
+        assert!(missing.is_err());
+        assert!(deleted.is_ok());
+        assert_eq!(content_deleted, b"hello world\n".to_vec());
+    }
+
+    #[test]
+    fn tar_directories() {
+        let path = "/tmp/immutag_tardir_tests";
+        let dir = "/tmp/immutag_tardir_tests/stand";
+        let mut fixture = setup(path);
+        create_dir_all(format!("{}/src", dir)).unwrap();
+        write(format!("{}/README.md", dir), "# Lemonade stand").unwrap();
+        write(format!("{}/src/recipe.txt", dir), "lemons, sugar, water").unwrap();
+
+        let staged = add_content(path, dir, None).unwrap();
+        commit(path, dir).unwrap();
+        write(format!("{}/src/recipe.txt", dir), "lemons, honey, water").unwrap();
+        write(format!("{}/menu.txt", dir), "lemonade").unwrap();
+        let restaged = add_content(path, dir, None).unwrap();
+        commit(path, dir).unwrap();
+
+        let checked_out = checkout(path, dir, &Selector::Version(0), None, false).unwrap();
+        let recipe = read_to_string(format!("{}/src/recipe.txt", dir)).unwrap();
+        let menu = Path::new(&format!("{}/menu.txt", dir)).exists();
+        let corrupt = unpack_over(&[b'x'; 512], dir);
+        let kept = read_to_string(format!("{}/src/recipe.txt", dir)).unwrap();
+        let leftovers: Vec<_> = std::fs::read_dir(path)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().starts_with(".stand."))
+            .collect();
+        let repacked = immutag_contentsys::file_hash(&tardir::pack(dir).unwrap());
+        write(format!("{}/README.md", dir), "# Closed").unwrap();
+        let refused = checkout(path, dir, &Selector::Version(1), None, false);
+
+        fixture.teardown(true);
+
+        assert_eq!(staged.get(metadata::FILE_TYPE), Some(tardir::TAR_DIR));
+        assert_ne!(staged.get(metadata::FILE_HASH), restaged.get(metadata::FILE_HASH));
+        assert_eq!(checked_out, (0, dir.to_string()));
+        assert_eq!(recipe, "lemons, sugar, water");
+        assert!(!menu);
+        assert!(leftovers.is_empty());
+        assert!(corrupt.is_err());
+        assert_eq!(kept, "lemons, sugar, water");
+        assert_eq!(Some(repacked.as_str()), staged.get(metadata::FILE_HASH));
+        assert!(refused.is_err());
+    }
+
+    #[test]
+    fn unsupported_contentsys() {
+        let path = "/tmp/immutag_version_contentsys_tests";
+        let file = "/tmp/immutag_version_contentsys_tests/README.md";
+        let mut fixture = setup(path);
+        let immutag_file = "/tmp/immutag_version_contentsys_tests/.immutag/Immutag";
+        let toml = read_to_string(immutag_file).unwrap()
+            .replacen("version = \"0.1.0\"", "version = \"0.1.0\"\ncontentsys = \"dropbox\"", 1);
+        write(immutag_file, toml).unwrap();
+        write(file, b"The README.").unwrap();
+
+        let result = add_content(path, file, None);
+
+        fixture.teardown(true);
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,324 @@
+/*
+A tag view of the tracked files, without FUSE: a tree of symlinks to the
+paths in the path-cache. `tags/<tag>/<name>` has every file with the tag,
+and `tags/<tag>+<tag>/<name>` every file with both. A file's name is its
+alias, or the name of its path, and its tags are those of its latest
+version. The view is rebuilt whenever the metadata changes, touching only
+the links that changed.
+*/
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, read_dir, read_link, remove_dir, remove_file, symlink_metadata};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use immutag_filesys::metadata;
+use immutag_filesys::{pathcache, versionstore};
+
+use crate::err::{Error, ErrorKind, ImmutagError};
+use crate::files;
+use crate::filesys::version::{canonical, Filesys};
+
+pub const TAGS: &str = "tags";
+/// Joins the two tags of an intersection directory.
+pub const PAIR: &str = "+";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct View {
+    pub dir: String,
+    pub links: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// Builds the view in `dir`, and keeps it there. Without `dir`, rebuilds
+/// the view that's kept.
+pub fn build_view<T: AsRef<str>>(path: T, dir: Option<T>) -> Result<View, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    let dir = match dir {
+        Some(dir) => {
+            create_dir_all(dir.as_ref())?;
+            let dir = canonical(dir)?;
+            files::set_view(path.as_ref(), filesys.addr.as_str(), dir.as_str())?;
+            dir
+        }
+        None => match files::get_view(path.as_ref(), filesys.addr.as_str())? {
+            Some(dir) => dir,
+            None => {
+                let err = Error::new("no view has been built", ErrorKind::NoView);
+                return Err(ImmutagError::from(err));
+            }
+        },
+    };
+
+    rebuild(&filesys, dir)
+}
+
+/// Rebuilds the view, if one's been built.
+pub(crate) fn refresh<T: AsRef<str>>(path: T) -> Result<Option<View>, ImmutagError> {
+    let filesys = Filesys::open(path.as_ref())?;
+    match files::get_view(path.as_ref(), filesys.addr.as_str())? {
+        Some(dir) => Ok(Some(rebuild(&filesys, dir)?)),
+        None => Ok(None),
+    }
+}
+
+fn rebuild(filesys: &Filesys, dir: String) -> Result<View, ImmutagError> {
+    let root = Path::new(&dir).join(TAGS);
+    let wanted = links(filesys)?;
+    let mut existing = BTreeMap::new();
+    if root.is_dir() {
+        existing_links(&root, Path::new(""), &mut existing)?;
+    }
+
+    let mut removed = 0;
+    for (link, target) in existing.iter() {
+        if wanted.get(link) != Some(target) {
+            remove_file(root.join(link))?;
+            removed += 1;
+        }
+    }
+    let mut added = 0;
+    for (link, target) in wanted.iter() {
+        if existing.get(link) != Some(target) {
+            let link = root.join(link);
+            if let Some(parent) = link.parent() {
+                create_dir_all(parent)?;
+            }
+            symlink(target, link)?;
+            added += 1;
+        }
+    }
+    if root.is_dir() {
+        prune(&root)?;
+    }
+
+    Ok(View {
+        dir,
+        links: wanted.len(),
+        added,
+        removed,
+    })
+}
+
+/// Where each link goes, by its path under `tags`.
+fn links(filesys: &Filesys) -> Result<BTreeMap<PathBuf, PathBuf>, ImmutagError> {
+    let mut tagged = Vec::new();
+    for (file_addr, file_path) in pathcache::entries(filesys.pathcache())? {
+        let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr.clone());
+        let record = match metadata::records(metadata_file)?.pop() {
+            Some(record) => record,
+            None => continue,
+        };
+        let mut tags = record.tags();
+        tags.sort();
+        tags.dedup();
+        if tags.is_empty() {
+            continue;
+        }
+        let name = match record.get(metadata::ALIAS) {
+            Some(alias) => alias.to_string(),
+            None => Path::new(&file_path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| file_addr.clone()),
+        };
+        tagged.push((file_addr, PathBuf::from(file_path), name, tags));
+    }
+
+    // Names more than one file goes by get the file address.
+    let mut named: HashMap<String, usize> = HashMap::new();
+    for (_, _, name, _) in tagged.iter() {
+        *named.entry(name.clone()).or_default() += 1;
+    }
+
+    let mut links = BTreeMap::new();
+    for (file_addr, file_path, name, tags) in tagged {
+        let name = match named[&name] {
+            1 => entry(&name),
+            _ => format!("{}-{}", entry(&name), file_addr),
+        };
+        for (i, tag) in tags.iter().enumerate() {
+            links.insert(Path::new(&entry(tag)).join(&name), file_path.clone());
+            for other in tags[i + 1..].iter() {
+                let pair = format!("{}{}{}", entry(tag), PAIR, entry(other));
+                links.insert(Path::new(&pair).join(&name), file_path.clone());
+            }
+        }
+    }
+
+    Ok(links)
+}
+
+/// A tag or name as a single path entry, percent-escaped so it can't be
+/// taken for a directory, like `..`, or for a pair of tags.
+fn entry(name: &str) -> String {
+    let escaped = name.replace('%', "%25").replace('/', "%2F").replace(PAIR, "%2B");
+    match escaped.as_str() {
+        "." | ".." => escaped.replace('.', "%2E"),
+        _ => escaped,
+    }
+}
+
+fn existing_links(root: &Path, dir: &Path, links: &mut BTreeMap<PathBuf, PathBuf>) -> Result<(), ImmutagError> {
+    for entry in read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            links.insert(path.clone(), read_link(root.join(&path))?);
+        } else if file_type.is_dir() {
+            existing_links(root, &path, links)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the directories left empty under `dir`, but not `dir` itself.
+fn prune(dir: &Path) -> Result<(), ImmutagError> {
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if symlink_metadata(&path)?.is_dir() {
+            prune(&path)?;
+            if read_dir(&path)?.next().is_none() {
+                remove_dir(&path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::filesys::version::tracked;
+    use crate::test_support::{commit_version, setup};
+
+    /// Commits a version of the file with the tags, since tags carry over
+    /// from the latest version.
+    fn commit_tagged(path: &str, file: &str, content: &str, alias: Option<&str>, tags: &[&str]) {
+        commit_version(path, file, content, alias);
+
+        let filesys = Filesys::open(path).unwrap();
+        let (file_addr, _) = tracked(&filesys, file).unwrap();
+        let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr);
+        let mut records = metadata::records(metadata_file.clone()).unwrap();
+        let latest = records.last_mut().unwrap();
+        for tag in latest.tags() {
+            latest.remove_tag(tag);
+        }
+        for tag in tags {
+            latest.add_tag(*tag);
+        }
+        metadata::write_records(metadata_file, &records).unwrap();
+
+        commit_version(path, file, format!("{}\n", content).as_str(), None);
+    }
+
+    fn view_links(view: &str) -> Vec<(String, String)> {
+        let mut links = BTreeMap::new();
+        existing_links(&Path::new(view).join(TAGS), Path::new(""), &mut links).unwrap();
+
+        links
+            .into_iter()
+            .map(|(l, t)| (l.to_string_lossy().to_string(), t.to_string_lossy().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn tag_view() {
+        let path = "/tmp/immutag_view/";
+        let view = "/tmp/immutag_view/view";
+        let menu = "/tmp/immutag_view/menu.txt";
+        let ledger = "/tmp/immutag_view/ledger.csv";
+        let readme = "/tmp/immutag_view/README.md";
+        let mut fixture = setup(path);
+
+        commit_tagged(path, menu, "lemonade", Some("menu"), &["lemonade", "prices"]);
+        commit_tagged(path, ledger, "cups,10", None, &["prices"]);
+        commit_tagged(path, readme, "# Lemonade stand", None, &[]);
+        let built = build_view(path, Some(view)).unwrap();
+        let links = view_links(view);
+
+        commit_tagged(path, menu, "lemonade, iced tea", Some("menu"), &["prices"]);
+        let refreshed = view_links(view);
+        let pruned = !Path::new(view).join(TAGS).join("lemonade").exists();
+        let rebuilt = build_view(path, None).unwrap();
+
+        fixture.teardown(true);
+
+        assert_eq!(built.links, 4);
+        assert_eq!(built.added, 4);
+        assert_eq!(
+            links,
+            vec![
+                ("lemonade/menu".to_string(), menu.to_string()),
+                ("lemonade+prices/menu".to_string(), menu.to_string()),
+                ("prices/ledger.csv".to_string(), ledger.to_string()),
+                ("prices/menu".to_string(), menu.to_string()),
+            ]
+        );
+        assert_eq!(
+            refreshed,
+            vec![
+                ("prices/ledger.csv".to_string(), ledger.to_string()),
+                ("prices/menu".to_string(), menu.to_string()),
+            ]
+        );
+        assert!(pruned);
+        assert_eq!((rebuilt.links, rebuilt.added, rebuilt.removed), (2, 0, 0));
+    }
+
+    #[test]
+    fn escaped_tags() {
+        let path = "/tmp/immutag_view_tags/";
+        let view = "/tmp/immutag_view_tags/view";
+        let menu = "/tmp/immutag_view_tags/menu.txt";
+        let mut fixture = setup(path);
+
+        commit_tagged(path, menu, "lemonade", None, &["lemonade+ice", "100%"]);
+        build_view(path, Some(view)).unwrap();
+        let links = view_links(view);
+
+        fixture.teardown(true);
+
+        assert_eq!(
+            links,
+            vec![
+                ("100%25/menu.txt".to_string(), menu.to_string()),
+                ("100%25+lemonade%2Bice/menu.txt".to_string(), menu.to_string()),
+                ("lemonade%2Bice/menu.txt".to_string(), menu.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_aliases() {
+        let path = "/tmp/immutag_view_aliases/";
+        let view = "/tmp/immutag_view_aliases/view";
+        let menu = "/tmp/immutag_view_aliases/menu.txt";
+        let ledger = "/tmp/immutag_view_aliases/ledger.csv";
+        let readme = "/tmp/immutag_view_aliases/README.md";
+        let mut fixture = setup(path);
+
+        commit_tagged(path, menu, "lemonade", Some(".."), &["prices"]);
+        commit_tagged(path, ledger, "cups,10", Some("."), &["prices"]);
+        commit_tagged(path, readme, "# Lemonade stand", Some("../README.md"), &["prices"]);
+        build_view(path, Some(view)).unwrap();
+        let links = view_links(view);
+        let escaped = !Path::new(view).join("README.md").exists();
+
+        fixture.teardown(true);
+
+        assert_eq!(
+            links,
+            vec![
+                ("prices/%2E".to_string(), ledger.to_string()),
+                ("prices/%2E%2E".to_string(), menu.to_string()),
+                ("prices/..%2FREADME.md".to_string(), readme.to_string()),
+            ]
+        );
+        assert!(escaped);
+    }
+}
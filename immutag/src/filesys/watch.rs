@@ -0,0 +1,547 @@
+/*
+Watching the tracked files of an immutag directory with inotify. The
+directories holding tracked files are watched, and the whole tree of a
+tracked directory. A change is staged once the file has been quiet for the
+debounce, and a tracked file renamed within the watched directories is moved
+in the path-cache. With `commit_after`, what's staged is committed once
+nothing has changed for that long. What the watcher does is appended to
+`.immutag/<addr>/watch.log`, which stays out of git.
+*/
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{read_to_string, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use immutag_filesys::metadata;
+use immutag_filesys::{pathcache, stage, vcs, versionstore};
+
+use crate::err::ImmutagError;
+use crate::filesys::util::datetime;
+use crate::filesys::version::{add_content, commit, content, Filesys};
+use crate::filesys::view;
+
+pub const WATCH_LOG: &str = "watch.log";
+
+/// How often `watch` looks for events and signals.
+const POLL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchOptions {
+    /// How long a file has to be left alone before it's staged.
+    pub debounce: Duration,
+    /// Commit what's staged after this long without changes.
+    pub commit_after: Option<Duration>,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            debounce: Duration::from_secs(1),
+            commit_after: None,
+        }
+    }
+}
+
+pub struct Watcher {
+    path: String,
+    filesys: Filesys,
+    options: WatchOptions,
+    inotify: Inotify,
+    /// The directory of each watch.
+    dirs: HashMap<WatchDescriptor, String>,
+    /// The path-cache, as of the last rewatch.
+    entries: Vec<(String, String)>,
+    /// Tracked files that changed, and when they last did.
+    changed: BTreeMap<String, Instant>,
+    /// The first half of renames, by cookie.
+    moved: HashMap<u32, (String, Instant)>,
+    last_change: Instant,
+    /// Something was staged or moved since the last commit.
+    uncommitted: bool,
+}
+
+impl Watcher {
+    /// Starts watching the tracked files of the immutag directory at `path`.
+    pub fn new<T: AsRef<str>>(path: T, options: WatchOptions) -> Result<Watcher, ImmutagError> {
+        let filesys = Filesys::open(path.as_ref())?;
+        ignore_log(&filesys)?;
+        let mut watcher = Watcher {
+            path: path.as_ref().to_string(),
+            filesys,
+            options,
+            inotify: Inotify::init()?,
+            dirs: HashMap::new(),
+            entries: Vec::new(),
+            changed: BTreeMap::new(),
+            moved: HashMap::new(),
+            last_change: Instant::now(),
+            uncommitted: false,
+        };
+        watcher.rewatch()?;
+        watcher.log(format!("watching {} directories", watcher.dirs.len()).as_str())?;
+
+        Ok(watcher)
+    }
+
+    /// The directories being watched.
+    pub fn dirs(&self) -> BTreeSet<String> {
+        self.dirs.values().cloned().collect()
+    }
+
+    /// Reads what happened since the last poll, and stages, moves and commits
+    /// what's due. Returns the lines logged.
+    pub fn poll(&mut self) -> Result<Vec<String>, ImmutagError> {
+        self.read()?;
+
+        self.settle(Instant::now(), false)
+    }
+
+    /// Like `poll`, but stages every change without waiting for the debounce,
+    /// and commits if `commit_after` is set. For stopping.
+    pub fn flush(&mut self) -> Result<Vec<String>, ImmutagError> {
+        self.read()?;
+
+        self.settle(Instant::now(), true)
+    }
+
+    fn read(&mut self) -> Result<(), ImmutagError> {
+        let mut buffer = [0; 4096];
+        let mut rewatch = false;
+        loop {
+            let mut events = Vec::new();
+            for event in self.inotify.read_events(&mut buffer)? {
+                events.push((event.wd, event.mask, event.cookie, event.name.map(|n| n.to_owned())));
+            }
+            if events.is_empty() {
+                break;
+            }
+
+            let now = Instant::now();
+            for (wd, mask, cookie, name) in events {
+                if mask.contains(EventMask::IGNORED) {
+                    self.dirs.remove(&wd);
+                    continue;
+                }
+                let file_path = match (self.dirs.get(&wd), name) {
+                    (Some(dir), Some(name)) => Path::new(dir).join(name).to_string_lossy().to_string(),
+                    _ => continue,
+                };
+                self.last_change = now;
+                if mask.contains(EventMask::ISDIR) && mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                    rewatch = true;
+                }
+                if mask.contains(EventMask::MOVED_FROM) {
+                    self.moved.insert(cookie, (file_path, now));
+                } else if mask.contains(EventMask::MOVED_TO) {
+                    match self.moved.remove(&cookie) {
+                        Some((from, _)) => rewatch |= self.renamed(from, file_path)?,
+                        None => self.change(file_path.as_str(), now),
+                    }
+                } else {
+                    self.change(file_path.as_str(), now);
+                }
+            }
+        }
+        if rewatch {
+            self.rewatch()?;
+        }
+
+        Ok(())
+    }
+
+    /// Notes a change to the tracked file at `file_path`, or to the tracked
+    /// directory holding it.
+    fn change(&mut self, file_path: &str, now: Instant) {
+        if let Some(tracked) = owner(&self.entries, file_path) {
+            self.changed.insert(tracked, now);
+        }
+    }
+
+    /// Moves a tracked file in the path-cache. Anything else renamed is a
+    /// change, like an editor saving over a tracked file. Returns whether the
+    /// watches need updating.
+    fn renamed(&mut self, from: String, to: String) -> Result<bool, ImmutagError> {
+        let now = Instant::now();
+        let file_addr = match (self.lookup_addr(from.as_str()), self.lookup_addr(to.as_str())) {
+            (Some(file_addr), None) => file_addr,
+            _ => {
+                self.change(from.as_str(), now);
+                self.change(to.as_str(), now);
+                return Ok(false);
+            }
+        };
+
+        pathcache::set(self.filesys.pathcache(), file_addr.clone(), to.clone())?;
+        self.entries = pathcache::entries(self.filesys.pathcache())?;
+        view::refresh(self.path.as_str())?;
+        if let Some(since) = self.changed.remove(&from) {
+            self.changed.insert(to.clone(), since);
+        }
+        self.uncommitted = true;
+        self.log(format!("moved {} from {} to {}", file_addr, from, to).as_str())?;
+
+        Ok(true)
+    }
+
+    /// Stages the changes that have been quiet for the debounce, or all of
+    /// them when `flush`ing, and then commits if it's time.
+    fn settle(&mut self, now: Instant, flush: bool) -> Result<Vec<String>, ImmutagError> {
+        let mut logged = Vec::new();
+
+        // A rename out of the watched directories never gets its other half.
+        let gone: Vec<u32> = self
+            .moved
+            .iter()
+            .filter(|(_, (_, at))| flush || now.duration_since(*at) >= self.options.debounce)
+            .map(|(cookie, _)| *cookie)
+            .collect();
+        for cookie in gone {
+            if let Some((from, at)) = self.moved.remove(&cookie) {
+                self.change(from.as_str(), at);
+            }
+        }
+
+        let due: Vec<String> = self
+            .changed
+            .iter()
+            .filter(|(_, at)| flush || now.duration_since(**at) >= self.options.debounce)
+            .map(|(file_path, _)| file_path.clone())
+            .collect();
+        for file_path in due {
+            self.changed.remove(&file_path);
+            if let Some(line) = self.stage(file_path.as_str()) {
+                logged.push(self.log(line.as_str())?);
+            }
+        }
+
+        let quiet = match self.options.commit_after {
+            Some(after) => flush || (self.changed.is_empty() && now.duration_since(self.last_change) >= after),
+            None => false,
+        };
+        if quiet && self.uncommitted {
+            for line in self.commit()? {
+                logged.push(self.log(line.as_str())?);
+            }
+            self.uncommitted = false;
+        }
+
+        Ok(logged)
+    }
+
+    /// Stages the file if its content isn't already its latest version or
+    /// what's staged. Failures are logged rather than stopping the watch.
+    fn stage(&mut self, file_path: &str) -> Option<String> {
+        if !Path::new(file_path).exists() {
+            return Some(format!("{} is gone, its versions are left as they are", file_path));
+        }
+        let file_hash = match content(file_path) {
+            Ok(data) => immutag_contentsys::file_hash(&data),
+            Err(e) => return Some(format!("failed to read {}: {:?}", file_path, e)),
+        };
+        match latest_hash(&self.filesys, file_path) {
+            Ok(Some(latest)) if latest == file_hash => return None,
+            Err(e) => return Some(format!("failed to read the versions of {}: {:?}", file_path, e)),
+            _ => {}
+        }
+
+        match add_content(self.path.as_str(), file_path, None) {
+            Ok(_) => {
+                self.uncommitted = true;
+                Some(format!("staged {} {}", file_path, file_hash))
+            }
+            Err(e) => Some(format!("failed to stage {}: {:?}", file_path, e)),
+        }
+    }
+
+    /// Commits everything staged, and the path-cache if files were moved.
+    fn commit(&mut self) -> Result<Vec<String>, ImmutagError> {
+        let mut lines = Vec::new();
+        for record in stage::staged(self.filesys.stage())? {
+            let file_addr = record.get(metadata::FILE_ADDR).unwrap_or_default().to_string();
+            let file_path = match pathcache::lookup_path(self.filesys.pathcache(), file_addr.clone())? {
+                Some(file_path) => file_path,
+                None => continue,
+            };
+            match commit(self.path.as_str(), file_path.as_str()) {
+                Ok(version) => lines.push(format!("committed version {} of {}", version, file_path)),
+                Err(e) => lines.push(format!("failed to commit {}: {:?}", file_path, e)),
+            }
+        }
+        if lines.is_empty() {
+            vcs::commit(self.filesys.path.as_str(), "Move files in the path-cache")?;
+            lines.push("committed the path-cache".to_string());
+        }
+
+        Ok(lines)
+    }
+
+    /// Watches the directories of the tracked files, and the trees of the
+    /// tracked directories, dropping the watches no longer needed.
+    fn rewatch(&mut self) -> Result<(), ImmutagError> {
+        self.entries = pathcache::entries(self.filesys.pathcache())?;
+        let mut wanted = BTreeSet::new();
+        for (_, file_path) in self.entries.iter() {
+            let file_path = Path::new(file_path);
+            if let Some(dir) = file_path.parent().filter(|dir| dir.is_dir()) {
+                wanted.insert(dir.to_string_lossy().to_string());
+            }
+            if file_path.is_dir() {
+                subdirs(file_path, &mut wanted)?;
+            }
+        }
+
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO;
+        let unwanted: Vec<WatchDescriptor> = self
+            .dirs
+            .iter()
+            .filter(|(_, dir)| !wanted.contains(*dir))
+            .map(|(wd, _)| wd.clone())
+            .collect();
+        for wd in unwanted {
+            self.dirs.remove(&wd);
+            // The kernel drops watches of deleted directories by itself.
+            let _ = self.inotify.rm_watch(wd);
+        }
+        for dir in wanted {
+            if !self.dirs.values().any(|d| d == &dir) {
+                let wd = self.inotify.add_watch(dir.as_str(), mask)?;
+                self.dirs.insert(wd, dir);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The address of the tracked file at `file_path`.
+    fn lookup_addr(&self, file_path: &str) -> Option<String> {
+        self.entries
+            .iter()
+            .find(|(_, tracked)| tracked == file_path)
+            .map(|(file_addr, _)| file_addr.clone())
+    }
+
+    /// Appends a timestamped line to the watch log, and returns the line.
+    fn log(&self, line: &str) -> Result<String, ImmutagError> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.filesys.path.clone() + WATCH_LOG)?;
+        writeln!(log, "{} {}", datetime(secs, 0), line)?;
+
+        Ok(line.to_string())
+    }
+}
+
+/// Watches the immutag directory at `path` until `stop` is set, then
+/// flushes what's pending.
+pub fn watch<T: AsRef<str>>(path: T, options: WatchOptions, stop: &AtomicBool) -> Result<(), ImmutagError> {
+    let mut watcher = Watcher::new(path, options)?;
+    while !stop.load(Ordering::Relaxed) {
+        watcher.poll()?;
+        sleep(POLL);
+    }
+    watcher.flush()?;
+    watcher.log("stopped")?;
+
+    Ok(())
+}
+
+/// The tracked file at `file_path`, or the tracked directory holding it.
+fn owner(entries: &[(String, String)], file_path: &str) -> Option<String> {
+    entries
+        .iter()
+        .map(|(_, tracked)| tracked)
+        .filter(|tracked| Path::new(file_path).starts_with(tracked))
+        .max_by_key(|tracked| tracked.len())
+        .cloned()
+}
+
+/// The file hash of what's staged for the file, or of its latest version.
+fn latest_hash(filesys: &Filesys, file_path: &str) -> Result<Option<String>, ImmutagError> {
+    let file_addr = match pathcache::lookup_addr(filesys.pathcache(), file_path.to_string())? {
+        Some(file_addr) => file_addr,
+        None => return Ok(None),
+    };
+    let staged = stage::staged(filesys.stage())?
+        .into_iter()
+        .find(|r| r.get(metadata::FILE_ADDR) == Some(file_addr.as_str()));
+    if let Some(record) = staged {
+        return Ok(record.get(metadata::FILE_HASH).map(String::from));
+    }
+    let versions_file = versionstore::path_versions_file(filesys.versionstore(), file_addr);
+
+    Ok(versionstore::versions(versions_file)?.pop().map(|v| v.file_hash))
+}
+
+fn subdirs(dir: &Path, found: &mut BTreeSet<String>) -> Result<(), ImmutagError> {
+    found.insert(dir.to_string_lossy().to_string());
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            subdirs(&entry.path(), found)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps the log out of the filesystem's git history.
+fn ignore_log(filesys: &Filesys) -> Result<(), ImmutagError> {
+    let gitignore = filesys.path.clone() + ".gitignore";
+    let ignored = read_to_string(&gitignore).unwrap_or_default();
+    if !ignored.lines().any(|line| line == WATCH_LOG) {
+        let mut file = OpenOptions::new().create(true).append(true).open(&gitignore)?;
+        writeln!(file, "{}", WATCH_LOG)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::test_support::setup;
+    use std::fs::{create_dir_all, rename, write};
+    use std::sync::Arc;
+
+    const MENU_ADDR: &str = "1PT41kxwfmmGchF2P92ZSwM1E5xB81Dky9";
+    const RECIPES_ADDR: &str = "13DfKEsGmgrdZ3zgvHzsmP58X9dNW1Vyyy";
+
+    fn track(path: &str, file: &str) {
+        add_content(path, file, None).unwrap();
+        commit(path, file).unwrap();
+    }
+
+    fn versions(filesys: &Filesys, file_addr: &str) -> usize {
+        let versions_file = versionstore::path_versions_file(filesys.versionstore(), file_addr.to_string());
+        versionstore::versions(versions_file).unwrap().len()
+    }
+
+    /// Polls, like `watch` does, until the debounce has passed.
+    fn settled(watcher: &mut Watcher) -> Vec<String> {
+        let mut logged = Vec::new();
+        for _ in 0..8 {
+            sleep(Duration::from_millis(50));
+            logged.extend(watcher.poll().unwrap());
+        }
+
+        logged
+    }
+
+    #[test]
+    fn watch_stage_move_and_commit() {
+        let path = "/tmp/immutag_watch_tests/";
+        let menu = "/tmp/immutag_watch_tests/menu";
+        let moved = "/tmp/immutag_watch_tests/menu.txt";
+        let recipes = "/tmp/immutag_watch_tests/recipes";
+        let mut fixture = setup(path);
+        write(menu, "lemonade").unwrap();
+        track(path, menu);
+        create_dir_all(format!("{}/sour", recipes)).unwrap();
+        write(format!("{}/sour/lemonade", recipes), "lemons").unwrap();
+        track(path, recipes);
+        let filesys = Filesys::open(path).unwrap();
+
+        let options = WatchOptions {
+            debounce: Duration::from_millis(200),
+            commit_after: Some(Duration::from_millis(600)),
+        };
+        let mut watcher = Watcher::new(path, options).unwrap();
+        let dirs = watcher.dirs();
+
+        write(menu, "lemonade and limeade").unwrap();
+        let undebounced = watcher.poll().unwrap();
+        let staged = settled(&mut watcher);
+        let staged_hash = stage::staged(filesys.stage()).unwrap()[0].get(metadata::FILE_HASH).map(String::from);
+        write(menu, "lemonade and limeade").unwrap();
+        let unchanged = settled(&mut watcher);
+
+        rename(menu, moved).unwrap();
+        write(format!("{}/sour/lemonade", recipes), "more lemons").unwrap();
+        let moved_and_staged = settled(&mut watcher);
+        let moved_path = pathcache::lookup_path(filesys.pathcache(), MENU_ADDR.to_string()).unwrap();
+        let not_yet = watcher.poll().unwrap();
+        sleep(Duration::from_millis(700));
+        let committed = watcher.poll().unwrap();
+        let staged_after = stage::staged(filesys.stage()).unwrap().len();
+        let versions = (versions(&filesys, MENU_ADDR), versions(&filesys, RECIPES_ADDR));
+        let log = read_to_string(filesys.path.clone() + WATCH_LOG).unwrap();
+        let gitignore = read_to_string(filesys.path.clone() + ".gitignore").unwrap();
+
+        fixture.teardown(true);
+
+        let expected: BTreeSet<String> = vec![
+            "/tmp/immutag_watch_tests".to_string(),
+            "/tmp/immutag_watch_tests/recipes".to_string(),
+            "/tmp/immutag_watch_tests/recipes/sour".to_string(),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(dirs, expected);
+        assert!(undebounced.is_empty());
+        let hash = immutag_contentsys::file_hash(b"lemonade and limeade");
+        assert_eq!(staged, vec![format!("staged {} {}", menu, hash)]);
+        assert_eq!(staged_hash, Some(hash));
+        assert!(unchanged.is_empty());
+        assert_eq!(moved_and_staged.len(), 1);
+        assert!(moved_and_staged[0].starts_with(format!("staged {} ", recipes).as_str()));
+        assert_eq!(moved_path.as_deref(), Some(moved));
+        assert!(not_yet.is_empty());
+        assert_eq!(
+            committed,
+            vec![
+                format!("committed version 1 of {}", moved),
+                format!("committed version 1 of {}", recipes),
+            ]
+        );
+        assert_eq!(staged_after, 0);
+        assert_eq!(versions, (2, 2));
+        assert!(log.contains(format!("moved {} from {} to {}", MENU_ADDR, menu, moved).as_str()));
+        assert!(log.contains(format!("committed version 1 of {}", recipes).as_str()));
+        assert_eq!(gitignore, "objects/\nwatch.log\n");
+    }
+
+    #[test]
+    fn watch_until_stopped() {
+        let path = "/tmp/immutag_watch_stop_tests/";
+        let menu = "/tmp/immutag_watch_stop_tests/menu";
+        let mut fixture = setup(path);
+        write(menu, "lemonade").unwrap();
+        track(path, menu);
+        let filesys = Filesys::open(path).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = stop.clone();
+        let options = WatchOptions {
+            debounce: Duration::from_secs(60),
+            commit_after: None,
+        };
+        let handle = std::thread::spawn(move || watch(path, options, &stopping));
+        sleep(Duration::from_millis(300));
+        write(menu, "limeade").unwrap();
+        sleep(Duration::from_millis(300));
+        // Staged on the way out, long before the debounce.
+        stop.store(true, Ordering::Relaxed);
+        let watched = handle.join().unwrap();
+        let staged = stage::staged(filesys.stage()).unwrap();
+        let versions = versions(&filesys, MENU_ADDR);
+        let log = read_to_string(filesys.path.clone() + WATCH_LOG).unwrap();
+
+        fixture.teardown(true);
+
+        assert!(watched.is_ok());
+        assert_eq!(staged.len(), 1);
+        assert_eq!(staged[0].get(metadata::FILE_ADDR), Some(MENU_ADDR));
+        assert_eq!(versions, 1);
+        assert!(log.trim_end().ends_with("stopped"));
+    }
+}
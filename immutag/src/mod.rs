@@ -1,3 +1,7 @@
 pub mod bitcoin;
+pub mod contentsys;
+pub mod err;
 pub mod files;
 pub mod filesys;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
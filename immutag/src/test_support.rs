@@ -0,0 +1,66 @@
+/*
+What the integration tests share: a filesystem of one test wallet, and ways
+to commit, tag and broadcast its files. Other crates' tests get it with the
+`test-support` feature.
+*/
+use std::fs::write;
+
+use fixture::Fixture;
+use immutag_bitcoin::tx::{peg_tx, Utxo};
+use immutag_filesys::{metadata, pending, vcs, versionstore};
+use immutag_ledger::{Ledger, MockLedger};
+
+use crate::files;
+use crate::filesys::{add_content, add_filesys, commit, Filesys};
+
+pub const XPRIV: &str = "xprv9s21ZrQH143K29TJGFSiEAAQM8SMBH2V6x5Aaf9bqvXftrs1v274STWWKfz8svukBLGEQgWqkgRhpt2CNFY89CFaqdsA3gicZeqexk2itxf";
+pub const FILESYS_ADDR: &str = "1KCrQzEQ6bsbUDYWzquwEWJNog6bY3rBj";
+
+/// Creates an immutag directory at `path` with the wallet's filesystem.
+pub fn init(path: &str) {
+    files::immutag_file_init(path, "0.1.0").unwrap();
+    files::add_filesystem(path, FILESYS_ADDR, XPRIV).unwrap();
+    add_filesys(path, FILESYS_ADDR);
+}
+
+/// Like `init`, in a fixture to tear down once the test is done.
+pub fn setup(path: &str) -> Fixture {
+    let fixture = Fixture::new().add_dirpath(path.to_string()).build();
+    init(path);
+
+    fixture
+}
+
+/// Writes `content` to `file` and commits it as the file's next version.
+pub fn commit_version(path: &str, file: &str, content: &str, alias: Option<&str>) {
+    write(file, content).unwrap();
+    add_content(path, file, alias).unwrap();
+    commit(path, file).unwrap();
+}
+
+/// Adds `tag` to the latest version of the file, and commits the metadata.
+pub fn tag(path: &str, file_addr: &str, tag: &str) {
+    let filesys = Filesys::open(path).unwrap();
+    let metadata_file = versionstore::path_metadata_file(filesys.versionstore(), file_addr.to_string());
+    let mut records = metadata::records(metadata_file.clone()).unwrap();
+    records.last_mut().unwrap().add_tag(tag);
+    metadata::write_records(metadata_file, &records).unwrap();
+    vcs::commit(filesys.path.as_str(), "Tag").unwrap();
+}
+
+/// Broadcasts the payload of the latest pending op, spending a fresh
+/// output of the wallet's address at `index`.
+pub fn broadcast(ledger: &MockLedger, filesys: &Filesys, index: u32) {
+    let ops = pending::pending(immutag_filesys::path_pending(&filesys.path)).unwrap();
+    let payload = hex::decode(ops.last().unwrap().get(pending::PAYLOAD).unwrap()).unwrap();
+    let addr = immutag_bitcoin::file_addr(XPRIV, index).unwrap();
+    let utxo = Utxo {
+        txid: ledger.fund(addr, 10_000).unwrap(),
+        vout: 0,
+        satoshis: 10_000,
+        chain: 0,
+        index,
+    };
+    let raw = peg_tx(XPRIV, "bitcoinsv".parse().unwrap(), &[utxo], &payload, index, 500).unwrap();
+    ledger.broadcast(&raw).unwrap();
+}